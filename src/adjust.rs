@@ -0,0 +1,125 @@
+//! Manual balance corrections
+//!
+//! An adjustments file has one correction per line: a signed amount moved into
+//! a client's available funds, or out of them when negative, and the code of
+//! the reason it is made, which may not be left out.
+//! ```text
+//! client, amount, reason
+//!      1,    2.5, fee_refund
+//!      2,  -10.0, duplicate_deposit
+//! ```
+//! [crate::Engine::adjust] applies them to accounts that exist, locked ones
+//! included, and records each in the audit trail and as an
+//! [crate::event::Event::AccountAdjusted] event, so every manual change to a
+//! balance can be traced.
+use crate::transaction::{parse_amount, ClientId};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Adjustment {
+    pub client: ClientId,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub amount: Decimal,
+    /// A code such as `fee_refund`, without spaces
+    pub reason: String,
+}
+
+/// Amounts are read from their text so they stay exact, as in transactions
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_amount(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Reads an adjustments file, failing on the first line without a reason
+/// code or with a zero amount so no part of a bad file is applied
+pub fn read_adjustments(csv: impl io::Read) -> Result<Vec<Adjustment>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+    let mut adjustments = Vec::new();
+    for result in rdr.deserialize() {
+        let adjustment: Adjustment = result?;
+        let line = adjustments.len() + 2;
+        if adjustment.reason.is_empty() || adjustment.reason.contains(char::is_whitespace) {
+            return Err(anyhow!(
+                "line {line}: an adjustment needs a reason code without spaces, not '{}'",
+                adjustment.reason
+            ));
+        }
+        if adjustment.amount.is_zero() {
+            return Err(anyhow!("line {line}: an adjustment of 0 changes nothing"));
+        }
+        adjustments.push(adjustment);
+    }
+    Ok(adjustments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::AuditEvent;
+    use crate::event::{replay_events, Event};
+    use crate::snapshot::compare;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_adjust() -> Result<()> {
+        const ADJUSTMENTS: &str = "\
+client, amount, reason
+     1,    2.5, fee_refund
+     2,  -10.0, duplicate_deposit
+";
+        let mut engine = Engine::new();
+        engine.record_events();
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,10.0\ndispute,2,2,\n";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+
+        let adjustments = read_adjustments(ADJUSTMENTS.as_bytes())?;
+        for (tx, adjustment) in (100..).zip(&adjustments) {
+            engine.adjust(adjustment, tx)?;
+        }
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts[0].available, dec!(3.5));
+        assert_eq!(accounts[1].available, dec!(-10.0), "the dispute held it");
+        assert_eq!(accounts[1].total, dec!(0.0));
+
+        let adjusted = &engine.audit_trail()[1];
+        assert_eq!((adjusted.client, adjusted.tx), (2, 101));
+        assert_eq!(
+            adjusted.event,
+            AuditEvent::Adjusted {
+                amount: dec!(-10.0),
+                reason: "duplicate_deposit".to_string(),
+            }
+        );
+        assert_eq!(
+            adjusted.event.to_string(),
+            "adjusted by -10.0: duplicate_deposit"
+        );
+        assert!(matches!(
+            engine.events().last(),
+            Some(Event::AccountAdjusted { tx: 101, .. })
+        ));
+        let replayed = replay_events(engine.events().iter().cloned().map(Ok))?;
+        assert!(compare(&engine.snapshot(), &replayed).is_empty());
+
+        let unknown = Adjustment {
+            client: 3,
+            amount: dec!(1.0),
+            reason: "goodwill".to_string(),
+        };
+        assert!(engine.adjust(&unknown, 102).is_err());
+        assert!(read_adjustments("client,amount,reason\n1,1.0,\n".as_bytes()).is_err());
+        assert!(read_adjustments("client,amount,reason\n1,1.0,a b\n".as_bytes()).is_err());
+        assert!(read_adjustments("client,amount,reason\n1,0,refund\n".as_bytes()).is_err());
+        assert!(read_adjustments("client,amount\n1,1.0\n".as_bytes()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! Anti money laundering rules
+//!
+//! The [Monitor] watches every transaction offered to the engine and keeps a
+//! few counters per client. It never changes a balance. At the end of a run
+//! the counters are checked against the configured [AmlRules] and every client
+//! breaking a rule gets a [Flag].
+//! ```toml
+//! [aml.structuring]
+//! threshold = "10000"
+//! margin = "10"
+//! count = 3
+//!
+//! [aml.cycling]
+//! window_minutes = 60
+//! count = 3
+//!
+//! [aml.chargebacks]
+//! max_rate = "20"
+//! ```
+use crate::transaction::{ClientId, TransType, Transaction};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The rules to flag clients by, all off by default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmlRules {
+    pub structuring: Option<Structuring>,
+    pub cycling: Option<Cycling>,
+    pub chargebacks: Option<ChargebackRate>,
+}
+
+impl AmlRules {
+    pub fn is_empty(&self) -> bool {
+        self.structuring.is_none() && self.cycling.is_none() && self.chargebacks.is_none()
+    }
+}
+
+/// Many deposits just under a reporting threshold
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Structuring {
+    pub threshold: Decimal,
+    /// How far under the threshold, in percent, a deposit counts as just under
+    pub margin: Decimal,
+    /// Deposits just under the threshold needed to flag the client
+    pub count: u64,
+}
+
+/// Deposits withdrawn again soon after. Needs the `timestamp` column.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cycling {
+    /// A withdrawal this soon after a deposit completes a cycle
+    pub window_minutes: i64,
+    /// Cycles needed to flag the client
+    pub count: u64,
+}
+
+/// A high share of deposits charged back
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChargebackRate {
+    /// Chargebacks per deposit, in percent, above which the client is flagged
+    pub max_rate: Decimal,
+    /// Deposits needed before the rate is considered
+    #[serde(default = "one")]
+    pub min_deposits: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    Structuring,
+    Cycling,
+    ChargebackRate,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Structuring => write!(f, "structuring"),
+            Rule::Cycling => write!(f, "cycling"),
+            Rule::ChargebackRate => write!(f, "chargeback_rate"),
+        }
+    }
+}
+
+/// A client breaking a rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flag {
+    pub client: ClientId,
+    pub rule: Rule,
+    /// What was seen, for the reviewer
+    pub detail: String,
+}
+
+/// Per-client counters the rules are checked against
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Monitor {
+    clients: HashMap<ClientId, Activity>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Activity {
+    deposits: u64,
+    under_threshold: u64,
+    /// The latest deposit not yet matched by a withdrawal
+    last_deposit: Option<DateTime<Utc>>,
+    cycles: u64,
+    chargebacks: u64,
+}
+
+impl Monitor {
+    /// Takes in the activity of clients counted by another monitor
+    pub(crate) fn merge(&mut self, other: Monitor) {
+        self.clients.extend(other.clients);
+    }
+
+    /// Counts a transaction towards its client's activity
+    pub fn observe(&mut self, rules: &AmlRules, transaction: &Transaction) {
+        let activity = self.clients.entry(transaction.client).or_default();
+        match transaction.trans {
+            TransType::Deposit => {
+                activity.deposits += 1;
+                if let (Some(rule), Some(amount)) = (&rules.structuring, transaction.amount) {
+                    let floor =
+                        rule.threshold * (Decimal::ONE - rule.margin / Decimal::ONE_HUNDRED);
+                    if amount < rule.threshold && amount >= floor {
+                        activity.under_threshold += 1;
+                    }
+                }
+                if transaction.timestamp.is_some() {
+                    activity.last_deposit = transaction.timestamp;
+                }
+            }
+            TransType::Withdrawal => {
+                if let (Some(rule), Some(deposited), Some(withdrawn)) =
+                    (&rules.cycling, activity.last_deposit, transaction.timestamp)
+                {
+                    if withdrawn - deposited <= Duration::minutes(rule.window_minutes) {
+                        activity.cycles += 1;
+                        activity.last_deposit = None;
+                    }
+                }
+            }
+            TransType::Chargeback => activity.chargebacks += 1,
+            TransType::Dispute
+            | TransType::Resolve
+            | TransType::OpenAccount
+            | TransType::CloseAccount
+            | TransType::Other(_) => {}
+        }
+    }
+
+    /// Every rule broken so far, ordered by client and rule
+    pub fn flags(&self, rules: &AmlRules) -> Vec<Flag> {
+        let mut flags = Vec::new();
+        for (&client, activity) in &self.clients {
+            let mut flag = |rule, detail| {
+                flags.push(Flag {
+                    client,
+                    rule,
+                    detail,
+                })
+            };
+            if let Some(rule) = &rules.structuring {
+                if activity.under_threshold >= rule.count {
+                    flag(
+                        Rule::Structuring,
+                        format!(
+                            "{} deposits within {}% under {}",
+                            activity.under_threshold, rule.margin, rule.threshold
+                        ),
+                    );
+                }
+            }
+            if let Some(rule) = &rules.cycling {
+                if activity.cycles >= rule.count {
+                    flag(
+                        Rule::Cycling,
+                        format!(
+                            "{} deposits withdrawn within {} minutes",
+                            activity.cycles, rule.window_minutes
+                        ),
+                    );
+                }
+            }
+            if let Some(rule) = &rules.chargebacks {
+                if activity.deposits >= rule.min_deposits && activity.deposits > 0 {
+                    let rate = Decimal::from(activity.chargebacks) * Decimal::ONE_HUNDRED
+                        / Decimal::from(activity.deposits);
+                    if rate > rule.max_rate {
+                        flag(
+                            Rule::ChargebackRate,
+                            format!(
+                                "{} chargebacks on {} deposits",
+                                activity.chargebacks, activity.deposits
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        flags.sort_by_key(|flag| (flag.client, flag.rule));
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use anyhow::Result;
+
+    #[test]
+    fn test_aml_flags() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,        9500,     2022-03-21T09:00:00Z
+deposit,         1,     2,        9900,     2022-03-21T10:00:00Z
+deposit,         1,     3,       10000,     2022-03-21T11:00:00Z
+deposit,         2,     4,         100,     2022-03-21T09:00:00Z
+withdrawal,      2,     5,         100,     2022-03-21T09:30:00Z
+deposit,         2,     6,         100,     2022-03-21T10:00:00Z
+withdrawal,      2,     7,         100,     2022-03-21T12:00:00Z
+dispute,         2,     6,            ,     2022-03-21T13:00:00Z
+chargeback,      2,     6,            ,     2022-03-21T14:00:00Z
+";
+        let rules: AmlRules = toml::from_str(
+            r#"
+structuring = { threshold = "10000", margin = "10", count = 2 }
+cycling = { window_minutes = 60, count = 1 }
+chargebacks = { max_rate = "25" }
+"#,
+        )?;
+        let mut monitor = Monitor::default();
+        for transaction in read_csv(DATA.as_bytes()) {
+            monitor.observe(&rules, &transaction?);
+        }
+        let flags = monitor.flags(&rules);
+        let summary: Vec<(ClientId, Rule)> = flags.iter().map(|f| (f.client, f.rule)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, Rule::Structuring),
+                (2, Rule::Cycling),
+                (2, Rule::ChargebackRate),
+            ]
+        );
+        assert_eq!(flags[0].detail, "2 deposits within 10% under 10000");
+        assert_eq!(flags[1].detail, "1 deposits withdrawn within 60 minutes");
+        Ok(())
+    }
+}
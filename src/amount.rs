@@ -0,0 +1,305 @@
+//! The amount type the engine does its arithmetic in
+//!
+//! Amounts are read and reported as [Decimal]. Inside the engine they are an
+//! [Amount], which is [Decimal] itself by default or, with the `fixed-point`
+//! feature, a [Fixed] count of 10^-4 units held in an `i64` that is much
+//! cheaper to add and compare. Converting only happens at the edges, with
+//! [checked_from_decimal] when a transaction is applied and [to_decimal] when
+//! balances are reported. An amount or balance out of range of either
+//! representation turns the transaction down rather than wrapping or
+//! saturating, see [Amount::checked_add].
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+#[cfg(not(feature = "fixed-point"))]
+pub type Amount = Decimal;
+#[cfg(feature = "fixed-point")]
+pub type Amount = Fixed;
+
+/// Converts a configured amount into the engine representation. A limit
+/// beyond what the engine can hold saturates, which is what it means anyway.
+#[cfg(not(feature = "fixed-point"))]
+pub fn from_decimal(amount: Decimal) -> Amount {
+    amount
+}
+
+/// Converts a configured amount into the engine representation. A limit
+/// beyond what the engine can hold saturates, which is what it means anyway.
+#[cfg(feature = "fixed-point")]
+pub fn from_decimal(amount: Decimal) -> Amount {
+    Fixed::saturating_from(amount)
+}
+
+/// Converts an amount read from the input into the engine representation,
+/// `None` when it is out of range
+#[cfg(not(feature = "fixed-point"))]
+pub fn checked_from_decimal(amount: Decimal) -> Option<Amount> {
+    Some(amount)
+}
+
+/// Converts an amount read from the input into the engine representation,
+/// `None` when it is out of range
+#[cfg(feature = "fixed-point")]
+pub fn checked_from_decimal(amount: Decimal) -> Option<Amount> {
+    Fixed::try_from(amount).ok()
+}
+
+/// Converts an engine amount back into a [Decimal] for output
+#[cfg(not(feature = "fixed-point"))]
+pub fn to_decimal(amount: Amount) -> Decimal {
+    amount
+}
+
+/// Converts an engine amount back into a [Decimal] for output
+#[cfg(feature = "fixed-point")]
+pub fn to_decimal(amount: Amount) -> Decimal {
+    amount.to_decimal()
+}
+
+/// The number of decimal places a [Fixed] amount holds
+pub const FIXED_SCALE: u32 = 4;
+const UNITS: i64 = 10_i64.pow(FIXED_SCALE);
+
+/// A fixed-point amount in 10^-4 units
+///
+/// * Inputs with more than four decimal places are rounded to four. Amounts
+///   beyond roughly ±922 billion do not convert, and arithmetic past them
+///   panics like [Decimal]'s does, so callers check with
+///   [Fixed::checked_add] and [Fixed::checked_sub] first.
+/// * The number of decimal places the amount was written with is carried
+///   along the same way [Decimal] does, so `5.0 - 1.5` still prints as `3.5`
+///   and the accounts report is identical in both representations. It plays
+///   no part in comparisons, `2.0 == 2`.
+#[derive(Clone, Copy, Default)]
+pub struct Fixed {
+    units: i64,
+    scale: u8,
+}
+
+impl Fixed {
+    pub fn to_decimal(self) -> Decimal {
+        let mut amount = Decimal::new(self.units, FIXED_SCALE);
+        amount.rescale(u32::from(self.scale));
+        amount
+    }
+
+    /// The amount, saturating at the `i64` limits when out of range
+    pub fn saturating_from(amount: Decimal) -> Fixed {
+        Fixed::try_from(amount).unwrap_or(Fixed {
+            units: if amount.is_sign_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            },
+            scale: amount.scale().min(FIXED_SCALE) as u8,
+        })
+    }
+
+    /// `None` on overflow
+    pub fn checked_add(self, other: Fixed) -> Option<Fixed> {
+        Some(Fixed {
+            units: self.units.checked_add(other.units)?,
+            scale: self.scale.max(other.scale),
+        })
+    }
+
+    /// `None` on overflow
+    pub fn checked_sub(self, other: Fixed) -> Option<Fixed> {
+        Some(Fixed {
+            units: self.units.checked_sub(other.units)?,
+            scale: self.scale.max(other.scale),
+        })
+    }
+
+    /// Stops at the `i64` limits, for comparisons only
+    pub fn saturating_add(self, other: Fixed) -> Fixed {
+        Fixed {
+            units: self.units.saturating_add(other.units),
+            scale: self.scale.max(other.scale),
+        }
+    }
+}
+
+/// An amount beyond what a [Fixed] holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange(Decimal);
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "amount {} is out of range", self.0)
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+impl TryFrom<Decimal> for Fixed {
+    type Error = OutOfRange;
+
+    fn try_from(amount: Decimal) -> Result<Self, Self::Error> {
+        let units = amount
+            .round_dp(FIXED_SCALE)
+            .checked_mul(Decimal::from(UNITS))
+            .and_then(|units| units.to_i64())
+            .ok_or(OutOfRange(amount))?;
+        Ok(Fixed {
+            units,
+            scale: amount.scale().min(FIXED_SCALE) as u8,
+        })
+    }
+}
+
+impl From<Fixed> for Decimal {
+    fn from(amount: Fixed) -> Self {
+        amount.to_decimal()
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_decimal(), f)
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl PartialEq for Fixed {
+    fn eq(&self, other: &Self) -> bool {
+        self.units == other.units
+    }
+}
+
+impl Eq for Fixed {}
+
+impl PartialEq<Decimal> for Fixed {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.to_decimal() == *other
+    }
+}
+
+impl Hash for Fixed {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.units.hash(state)
+    }
+}
+
+impl PartialOrd for Fixed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fixed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.units.cmp(&other.units)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        self.checked_add(other).expect("amount overflow")
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        self.checked_sub(other).expect("amount overflow")
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed {
+            units: self.units.checked_neg().expect("amount overflow"),
+            scale: self.scale,
+        }
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        *self = *self - other;
+    }
+}
+
+/// Serialized exactly like [Decimal] so saved state can be loaded by either
+/// representation
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&self.to_decimal(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fixed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let amount = <Decimal as Deserialize>::deserialize(deserializer)?;
+        Fixed::try_from(amount).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn fixed(amount: Decimal) -> Fixed {
+        Fixed::try_from(amount).unwrap()
+    }
+
+    #[test]
+    fn test_fixed_arithmetic_keeps_scale() {
+        let a = fixed(dec!(5.0));
+        let b = fixed(dec!(1.5));
+        assert_eq!((a - b).to_string(), "3.5");
+        assert_eq!((b - b).to_string(), "0.0");
+        assert_eq!(
+            (Fixed::default() + fixed(dec!(0.0001))).to_string(),
+            "0.0001"
+        );
+        assert_eq!((-a).to_string(), "-5.0");
+        assert_eq!(fixed(dec!(2.0)), fixed(dec!(2)));
+        assert!(b < a);
+    }
+
+    #[test]
+    fn test_fixed_conversion() {
+        assert_eq!(fixed(dec!(1.23456)), dec!(1.2346));
+        assert_eq!(fixed(dec!(-0.5)).to_decimal(), dec!(-0.5));
+        assert_eq!(
+            Fixed::saturating_from(dec!(1e20)),
+            fixed(Decimal::new(i64::MAX, FIXED_SCALE))
+        );
+        assert!(Fixed::try_from(dec!(1e20)).is_err());
+        let max = fixed(Decimal::new(i64::MAX, FIXED_SCALE));
+        assert_eq!(max.checked_add(fixed(dec!(0.0001))), None);
+        assert_eq!((-max).checked_sub(fixed(dec!(0.0002))), None);
+        assert!(serde_json::from_str::<Fixed>("\"100000000000000000000\"").is_err());
+    }
+
+    #[test]
+    fn test_fixed_serde_matches_decimal() {
+        let json = serde_json::to_string(&fixed(dec!(12.50))).unwrap();
+        assert_eq!(json, serde_json::to_string(&dec!(12.50)).unwrap());
+        let fixed: Fixed = serde_json::from_str(&json).unwrap();
+        assert_eq!(fixed.to_string(), "12.50");
+    }
+}
@@ -0,0 +1,179 @@
+//! `tte consume --amqp`, reading transactions from a RabbitMQ queue
+//!
+//! Messages carry the transaction as a JSON object with the CSV columns, as
+//! for JetStream. Up to a batch of deliveries is prefetched, and those never
+//! acknowledged go back to the queue when the connection drops.
+//!
+//! A message that is no valid transaction, or that the engine rejects, is
+//! negatively acknowledged without requeueing. With a dead-letter exchange it
+//! is first republished there under its routing key, with the reason in the
+//! `x-tte-reason` header.
+use crate::consume::{Message, Source};
+use crate::json_fields;
+use crate::runtime;
+use anyhow::{anyhow, Context, Result};
+use futures::{FutureExt, StreamExt};
+use lapin::message::Delivery;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions, BasicQosOptions,
+    ConfirmSelectOptions, QueueDeclareOptions,
+};
+use lapin::types::{AMQPValue, FieldTable, LongString};
+use lapin::{Channel, Connection, ConnectionProperties, Consumer};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long a read waits for new messages before handing back control
+const WAIT: Duration = Duration::from_secs(1);
+const REASON_HEADER: &str = "x-tte-reason";
+
+pub struct Amqp {
+    /// Kept open for as long as the channel is used
+    _connection: Connection,
+    channel: Channel,
+    deliveries: Consumer,
+    dead_letter: Option<String>,
+    queue: String,
+    /// Deliveries handed out but not acknowledged yet, by id
+    unacked: HashMap<String, Delivery>,
+}
+
+impl Amqp {
+    pub fn open(
+        url: &str,
+        queue: &str,
+        consumer: &str,
+        prefetch: u16,
+        dead_letter: Option<&str>,
+    ) -> Result<Self> {
+        runtime().block_on(async {
+            let connection = Connection::connect(url, ConnectionProperties::default())
+                .await
+                .with_context(|| format!("could not connect to {url}"))?;
+            let channel = connection.create_channel().await?;
+            channel
+                .basic_qos(prefetch, BasicQosOptions::default())
+                .await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            let deliveries = channel
+                .basic_consume(
+                    queue,
+                    consumer,
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .with_context(|| format!("could not consume from queue {queue}"))?;
+            Ok(Amqp {
+                _connection: connection,
+                channel,
+                deliveries,
+                dead_letter: dead_letter.map(str::to_string),
+                queue: queue.to_string(),
+                unacked: HashMap::new(),
+            })
+        })
+    }
+
+    fn take(&mut self, message: &Message) -> Result<Delivery> {
+        self.unacked
+            .remove(&message.id)
+            .ok_or_else(|| anyhow!("message {} was already settled", message.id))
+    }
+}
+
+impl Source for Amqp {
+    fn poll(&mut self, max: usize) -> Result<Vec<Message>> {
+        let received = runtime().block_on(async {
+            let mut received = Vec::new();
+            match tokio::time::timeout(WAIT, self.deliveries.next()).await {
+                Err(_) => return Ok(received),
+                Ok(None) => return Err(anyhow!("the broker closed the consumer")),
+                Ok(Some(delivery)) => received.push(delivery?),
+            }
+            // Take whatever else has been prefetched without waiting for more
+            while received.len() < max {
+                match self.deliveries.next().now_or_never() {
+                    Some(Some(delivery)) => received.push(delivery?),
+                    _ => break,
+                }
+            }
+            Ok(received)
+        })?;
+        Ok(received
+            .into_iter()
+            .map(|delivery| {
+                let id = delivery.delivery_tag.to_string();
+                let message = Message {
+                    id: id.clone(),
+                    seq: None,
+                    fields: json_fields(&delivery.data),
+                };
+                self.unacked.insert(id, delivery);
+                message
+            })
+            .collect())
+    }
+
+    fn ack(&mut self, messages: &[Message]) -> Result<()> {
+        for message in messages {
+            let delivery = self.take(message)?;
+            runtime().block_on(delivery.acker.ack(BasicAckOptions::default()))?;
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, message: &Message, reason: &str) -> Result<()> {
+        let delivery = self.take(message)?;
+        runtime().block_on(async {
+            if let Some(exchange) = &self.dead_letter {
+                let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+                headers.insert(
+                    REASON_HEADER.into(),
+                    AMQPValue::LongString(LongString::from(reason)),
+                );
+                let properties = delivery.properties.clone().with_headers(headers);
+                let confirmation = self
+                    .channel
+                    .basic_publish(
+                        exchange,
+                        delivery.routing_key.as_str(),
+                        BasicPublishOptions::default(),
+                        &delivery.data,
+                        properties,
+                    )
+                    .await?
+                    .await?;
+                if confirmation.is_nack() {
+                    return Err(anyhow!(
+                        "exchange {exchange} refused message {}",
+                        message.id
+                    ));
+                }
+            }
+            delivery
+                .acker
+                .nack(BasicNackOptions {
+                    requeue: false,
+                    ..BasicNackOptions::default()
+                })
+                .await?;
+            Ok(())
+        })
+    }
+    /// The messages ready in the queue, those delivered and not yet
+    /// acknowledged aside
+    fn lag(&mut self) -> Result<Option<u64>> {
+        let queue = runtime().block_on(self.channel.queue_declare(
+            &self.queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        ))?;
+        Ok(Some(queue.message_count().into()))
+    }
+}
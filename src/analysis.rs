@@ -0,0 +1,208 @@
+//! Aggregates over a transactions file, for a feel of what is in it
+//!
+//! Everything is counted as offered, before the engine had its say, so a
+//! withdrawal it refused still adds to the withdrawal volume.
+use crate::transaction::{ClientId, TransType, Transaction};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// The upper bounds of the amount buckets, the last bucket taking the rest
+const BUCKETS: [i64; 5] = [1, 10, 100, 1_000, 10_000];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Volume {
+    pub count: u64,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Depositor {
+    pub client: ClientId,
+    pub amount: Decimal,
+}
+
+/// How many deposits and withdrawals moved at least `from`, and less than
+/// `to` if there is one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    pub from: Decimal,
+    pub to: Option<Decimal>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    /// Keyed by transaction type
+    pub volume: BTreeMap<String, Volume>,
+    /// The clients with the most deposited, most first
+    pub top_depositors: Vec<Depositor>,
+    pub amounts: Vec<Bucket>,
+    /// Disputes per deposit, in percent
+    pub dispute_rate: Decimal,
+    /// Chargebacks per deposit, in percent
+    pub chargeback_rate: Decimal,
+}
+
+/// Aggregates `transactions`, keeping the `top` clients by deposit volume
+pub fn analyze<I, E>(transactions: I, top: usize) -> Result<Analysis, E>
+where
+    I: IntoIterator<Item = Result<Transaction, E>>,
+{
+    let mut volume: BTreeMap<String, Volume> = BTreeMap::new();
+    let mut deposited: HashMap<ClientId, Decimal> = HashMap::new();
+    let mut amounts: Vec<Bucket> = BUCKETS
+        .iter()
+        .scan(Decimal::ZERO, |from, &to| {
+            let bucket = Bucket {
+                from: *from,
+                to: Some(Decimal::from(to)),
+                count: 0,
+            };
+            *from = Decimal::from(to);
+            Some(bucket)
+        })
+        .collect();
+    amounts.push(Bucket {
+        from: Decimal::from(BUCKETS[BUCKETS.len() - 1]),
+        to: None,
+        count: 0,
+    });
+    for transaction in transactions {
+        let transaction = transaction?;
+        let entry = volume.entry(transaction.trans.to_string()).or_default();
+        entry.count += 1;
+        let Some(amount) = transaction.amount else {
+            continue;
+        };
+        entry.amount += amount;
+        match transaction.trans {
+            TransType::Deposit => *deposited.entry(transaction.client).or_default() += amount,
+            TransType::Withdrawal => {}
+            _ => continue,
+        }
+        if let Some(bucket) = amounts
+            .iter_mut()
+            .find(|bucket| bucket.to.is_none_or(|to| amount < to))
+        {
+            bucket.count += 1;
+        }
+    }
+
+    let mut top_depositors: Vec<Depositor> = deposited
+        .into_iter()
+        .map(|(client, amount)| Depositor { client, amount })
+        .collect();
+    top_depositors.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.client.cmp(&b.client)));
+    top_depositors.truncate(top);
+
+    let count = |trans: &TransType| volume.get(&trans.to_string()).map_or(0, |v| v.count);
+    let deposits = count(&TransType::Deposit);
+    let rate = |count: u64| match deposits {
+        0 => Decimal::ZERO,
+        deposits => {
+            (Decimal::from(count) * Decimal::ONE_HUNDRED / Decimal::from(deposits)).round_dp(2)
+        }
+    };
+    Ok(Analysis {
+        dispute_rate: rate(count(&TransType::Dispute)),
+        chargeback_rate: rate(count(&TransType::Chargeback)),
+        volume,
+        top_depositors,
+        amounts,
+    })
+}
+
+impl Analysis {
+    /// Writes the aggregates as plain text tables
+    /// ```text
+    /// type, count, amount
+    /// deposit, 2, 105.0
+    /// dispute, 1, 0
+    ///
+    /// client, deposited
+    /// 2, 100.0
+    /// 1, 5.0
+    ///
+    /// amount, count
+    /// 0-1, 0
+    /// 1-10, 1
+    /// ...
+    /// 10000-, 0
+    ///
+    /// dispute rate: 50%
+    /// chargeback rate: 0%
+    /// ```
+    pub fn write_table(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "type, count, amount")?;
+        for (trans, volume) in &self.volume {
+            writeln!(w, "{trans}, {}, {}", volume.count, volume.amount)?;
+        }
+        writeln!(w, "\nclient, deposited")?;
+        for depositor in &self.top_depositors {
+            writeln!(w, "{}, {}", depositor.client, depositor.amount)?;
+        }
+        writeln!(w, "\namount, count")?;
+        for bucket in &self.amounts {
+            let to = bucket.to.map(|to| to.to_string()).unwrap_or_default();
+            writeln!(w, "{}-{to}, {}", bucket.from, bucket.count)?;
+        }
+        writeln!(w, "\ndispute rate: {}%", self.dispute_rate)?;
+        writeln!(w, "chargeback rate: {}%", self.chargeback_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_analyze() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+deposit,         2,     2,       100.0
+deposit,         3,     3,         0.5
+withdrawal,      1,     4,        50.0
+dispute,         2,     2,
+";
+        let analysis = analyze(read_csv(DATA.as_bytes()), 2)?;
+        assert_eq!(
+            analysis.volume["deposit"],
+            Volume {
+                count: 3,
+                amount: dec!(105.5)
+            }
+        );
+        assert_eq!(
+            analysis.volume["withdrawal"].amount,
+            dec!(50.0),
+            "the refused withdrawal counts too"
+        );
+        assert_eq!(
+            analysis.top_depositors,
+            [
+                Depositor {
+                    client: 2,
+                    amount: dec!(100.0)
+                },
+                Depositor {
+                    client: 1,
+                    amount: dec!(5.0)
+                }
+            ]
+        );
+        let counts: Vec<u64> = analysis.amounts.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 1, 1, 1, 0, 0]);
+        assert_eq!(analysis.dispute_rate, dec!(33.33));
+        assert_eq!(analysis.chargeback_rate, dec!(0));
+
+        let json = serde_json::to_value(&analysis)?;
+        assert_eq!(json["amounts"][5]["to"], serde_json::Value::Null);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Idle clients moved out of memory on a long run
+//!
+//! A consumer left running sees more and more clients, most of whom are not
+//! heard from again for a long while. An [Archive] writes the account of a
+//! client idle for too long, records and all, to a file of its own in a
+//! directory and drops it from memory. The client is read back when a
+//! transaction for it comes in, so what stays in memory goes with the clients
+//! active of late rather than every client ever seen.
+//!
+//! A file is left in place when its client is read back and written over when
+//! the client is archived again. A client held in memory, e.g. in the saved
+//! state of the engine, wins over its file, which may be older.
+use crate::client::{Client, Clients};
+use crate::settlement::Position;
+use crate::transaction::ClientId;
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub struct Archive {
+    dir: PathBuf,
+    idle: Duration,
+    /// When each client in memory last had a transaction
+    active: HashMap<ClientId, Instant>,
+    archived: HashSet<ClientId>,
+}
+
+impl Archive {
+    /// An archive in `dir` of clients idle for longer than `idle`, taking in
+    /// the clients archived there by an earlier run unless `clients` holds
+    /// them. Those in `clients` count as active from now.
+    pub(crate) fn open(dir: PathBuf, idle: Duration, clients: &Clients) -> Result<Self> {
+        fs::create_dir_all(&dir).with_context(|| format!("could not create {}", dir.display()))?;
+        let mut archived = HashSet::new();
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|id| id.parse::<ClientId>().ok());
+            if let Some(id) = id.filter(|&id| clients.get(id).is_none()) {
+                archived.insert(id);
+            }
+        }
+        let now = Instant::now();
+        Ok(Archive {
+            dir,
+            idle,
+            active: clients.iter().map(|(id, _)| (id, now)).collect(),
+            archived,
+        })
+    }
+
+    /// How many clients are archived and not in memory
+    pub fn len(&self) -> usize {
+        self.archived.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archived.is_empty()
+    }
+
+    /// Reads `client` back into memory if it is archived, and counts it as
+    /// active. `funds` are the balances of the clients archived, which it no
+    /// longer counts.
+    pub(crate) fn load(
+        &mut self,
+        clients: &mut Clients,
+        client: ClientId,
+        funds: &mut Position,
+    ) -> Result<()> {
+        if self.archived.remove(&client) {
+            let path = self.path(client);
+            debug!("  reading archived client:{client}");
+            let account: Client = serde_json::from_slice(&fs::read(&path)?)
+                .with_context(|| format!("invalid archived client {}", path.display()))?;
+            funds.remove(&account);
+            clients.insert(client, account);
+        }
+        self.active.insert(client, Instant::now());
+        Ok(())
+    }
+
+    /// Reads every archived client back into memory without counting it as
+    /// active, so the next [Archive::archive] puts it back
+    pub(crate) fn load_all(&mut self, clients: &mut Clients, funds: &mut Position) -> Result<()> {
+        for client in std::mem::take(&mut self.archived) {
+            let path = self.path(client);
+            let account: Client = serde_json::from_slice(&fs::read(&path)?)
+                .with_context(|| format!("invalid archived client {}", path.display()))?;
+            funds.remove(&account);
+            clients.insert(client, account);
+        }
+        Ok(())
+    }
+
+    /// Archives the clients idle since before `now` less the idle time,
+    /// returning how many and adding their balances to `funds`. Those with a
+    /// dispute open stay, so it can expire.
+    pub(crate) fn archive(
+        &mut self,
+        clients: &mut Clients,
+        now: Instant,
+        funds: &mut Position,
+    ) -> Result<usize> {
+        let idle = |id: ClientId, client: &Client| {
+            !client.has_open_disputes()
+                && self
+                    .active
+                    .get(&id)
+                    .is_none_or(|&at| now.saturating_duration_since(at) >= self.idle)
+        };
+        let taken = clients.take_where(idle);
+        for (id, client) in &taken {
+            let path = self.path(*id);
+            let partial = path.with_extension("json.partial");
+            fs::write(&partial, serde_json::to_vec(client)?)?;
+            fs::rename(&partial, &path)?;
+            self.active.remove(id);
+            self.archived.insert(*id);
+            funds.add(client);
+        }
+        if !taken.is_empty() {
+            debug!("  archived {} idle clients", taken.len());
+        }
+        Ok(taken.len())
+    }
+
+    fn path(&self, client: ClientId) -> PathBuf {
+        self.dir.join(format!("{client}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        const DATA: &str = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,2.0
+dispute,2,2,
+withdrawal,1,3,1.5
+dispute,1,1,
+";
+        let dir = std::env::temp_dir().join(format!("tte-archive-{}", std::process::id()));
+        let mut plain = Engine::new();
+        plain.replay(read_csv(DATA.as_bytes()), None)?;
+
+        let mut engine = Engine::new();
+        engine.archive_clients(&dir, Duration::ZERO)?;
+        let mut transactions = read_csv(DATA.as_bytes());
+        engine.replay(transactions.by_ref().take(3), None)?;
+        assert_eq!(engine.archive_idle()?, 1, "client 2 has a dispute open");
+        assert_eq!(engine.snapshot().keys().collect::<Vec<_>>(), [&2]);
+        let position = engine.position();
+        assert_eq!(
+            position.liabilities,
+            "7.0".parse()?,
+            "client 1 still counts"
+        );
+        assert_eq!(position.held, "2.0".parse()?);
+
+        // Client 1 is read back, its records with it
+        engine.replay(transactions, None)?;
+        assert_eq!(engine.snapshot(), plain.snapshot());
+        assert_eq!(engine.position(), plain.position());
+
+        // A client in the saved state wins over its older file
+        let mut restored: Engine = serde_json::from_str(&serde_json::to_string(&engine)?)?;
+        restored.archive_clients(&dir, Duration::ZERO)?;
+        assert!(restored.archive.as_ref().is_some_and(|a| a.is_empty()));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
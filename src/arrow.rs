@@ -0,0 +1,278 @@
+//! `report --output-format arrow`, the report and events as Arrow IPC files
+//!
+//! With the `parquet` feature they can be Parquet files instead, with the same
+//! columns. The files load straight into Polars, pandas or DuckDB. Amounts are
+//! `decimal128` columns with the largest scale in the column, so no value is
+//! rounded, and events are one row each with the fields their kind lacks
+//! left null.
+use arrow_array::builder::{BooleanBuilder, StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, Decimal128Array, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use std::io;
+use std::sync::Arc;
+use tte::event::Event;
+use tte::{Engine, TxId};
+
+/// The kind of file the columns are written as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// The Arrow IPC file format
+    Ipc,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Writes `client, available, held, total, locked` for every account, in
+/// client order
+pub fn write_report(engine: &Engine, w: &mut dyn io::Write, layout: Layout) -> io::Result<()> {
+    let snapshot = engine.snapshot();
+    let mut clients = UInt64Builder::new();
+    let mut locked = BooleanBuilder::new();
+    let (mut available, mut held, mut total) = (Vec::new(), Vec::new(), Vec::new());
+    for (&client, account) in &snapshot {
+        clients.append_value(client);
+        available.push(Some(account.available));
+        held.push(Some(account.held));
+        total.push(Some(account.total));
+        locked.append_value(account.locked);
+    }
+    write(
+        w,
+        vec![
+            ("client", Arc::new(clients.finish()) as ArrayRef),
+            ("available", decimals(&available)?),
+            ("held", decimals(&held)?),
+            ("total", decimals(&total)?),
+            ("locked", Arc::new(locked.finish())),
+        ],
+        layout,
+    )
+}
+
+/// Writes `events`, in order, with the columns `event, client, tx, amount,
+/// fee, reason, kind, available, held, total, locked, from`
+pub fn write_events(events: &[Event], w: &mut dyn io::Write, layout: Layout) -> io::Result<()> {
+    let mut columns = EventColumns::default();
+    for event in events {
+        columns.push(event);
+    }
+    columns.write(w, layout)
+}
+
+#[derive(Default)]
+struct EventColumns {
+    event: StringBuilder,
+    client: UInt64Builder,
+    tx: Vec<TxId>,
+    amount: Vec<Option<Decimal>>,
+    fee: Vec<Option<Decimal>>,
+    reason: StringBuilder,
+    kind: StringBuilder,
+    available: Vec<Option<Decimal>>,
+    held: Vec<Option<Decimal>>,
+    total: Vec<Option<Decimal>>,
+    locked: BooleanBuilder,
+    from: UInt64Builder,
+}
+
+impl EventColumns {
+    fn push(&mut self, event: &Event) {
+        let (name, client, tx) = match event {
+            Event::DepositApplied { client, tx, .. } => ("DepositApplied", client, tx),
+            Event::WithdrawalApplied { client, tx, .. } => ("WithdrawalApplied", client, tx),
+            Event::DisputeOpened { client, tx, .. } => ("DisputeOpened", client, tx),
+            Event::DisputeResolved { client, tx, .. } => ("DisputeResolved", client, tx),
+            Event::ChargebackApplied { client, tx, .. } => ("ChargebackApplied", client, tx),
+            Event::DisputeAutoResolved { client, tx, .. } => ("DisputeAutoResolved", client, tx),
+            Event::TransactionRejected { client, tx, .. } => ("TransactionRejected", client, tx),
+            Event::CustomApplied { client, tx, .. } => ("CustomApplied", client, tx),
+            Event::AccountFrozen { client, tx, .. } => ("AccountFrozen", client, tx),
+            Event::AccountOpened { client, tx } => ("AccountOpened", client, tx),
+            Event::AccountClosed { client, tx } => ("AccountClosed", client, tx),
+            Event::AccountLocked { client, tx } => ("AccountLocked", client, tx),
+            Event::AccountUnlocked { client, tx } => ("AccountUnlocked", client, tx),
+            Event::AccountsMerged { client, tx, .. } => ("AccountsMerged", client, tx),
+            Event::AccountAdjusted { client, tx, .. } => ("AccountAdjusted", client, tx),
+        };
+        self.event.append_value(name);
+        self.client.append_value(*client);
+        self.tx.push(*tx);
+        let (amount, fee) = match event {
+            Event::DepositApplied { amount, fee, .. }
+            | Event::WithdrawalApplied { amount, fee, .. } => (Some(*amount), Some(*fee)),
+            Event::DisputeOpened { amount, .. }
+            | Event::DisputeResolved { amount, .. }
+            | Event::ChargebackApplied { amount, .. }
+            | Event::DisputeAutoResolved { amount, .. }
+            | Event::AccountAdjusted { amount, .. } => (Some(*amount), None),
+            _ => (None, None),
+        };
+        self.amount.push(amount);
+        self.fee.push(fee);
+        match event {
+            Event::TransactionRejected { reason, .. }
+            | Event::AccountFrozen { reason, .. }
+            | Event::AccountAdjusted { reason, .. } => self.reason.append_value(reason),
+            _ => self.reason.append_null(),
+        }
+        match event {
+            Event::CustomApplied {
+                kind,
+                available,
+                held,
+                total,
+                locked,
+                ..
+            } => {
+                self.kind.append_value(kind);
+                self.available.push(Some(*available));
+                self.held.push(Some(*held));
+                self.total.push(Some(*total));
+                self.locked.append_value(*locked);
+            }
+            _ => {
+                self.kind.append_null();
+                self.available.push(None);
+                self.held.push(None);
+                self.total.push(None);
+                self.locked.append_null();
+            }
+        }
+        match event {
+            Event::AccountsMerged { from, .. } => self.from.append_value(*from),
+            _ => self.from.append_null(),
+        }
+    }
+
+    fn write(mut self, w: &mut dyn io::Write, layout: Layout) -> io::Result<()> {
+        write(
+            w,
+            vec![
+                ("event", Arc::new(self.event.finish()) as ArrayRef),
+                ("client", Arc::new(self.client.finish())),
+                ("tx", tx_ids(&self.tx)),
+                ("amount", decimals(&self.amount)?),
+                ("fee", decimals(&self.fee)?),
+                ("reason", Arc::new(self.reason.finish())),
+                ("kind", Arc::new(self.kind.finish())),
+                ("available", decimals(&self.available)?),
+                ("held", decimals(&self.held)?),
+                ("total", decimals(&self.total)?),
+                ("locked", Arc::new(self.locked.finish())),
+                ("from", Arc::new(self.from.finish())),
+            ],
+            layout,
+        )
+    }
+}
+
+fn write(w: &mut dyn io::Write, columns: Vec<(&str, ArrayRef)>, layout: Layout) -> io::Result<()> {
+    let batch = RecordBatch::try_from_iter(columns).map_err(io::Error::other)?;
+    match layout {
+        Layout::Ipc => {
+            let mut writer = FileWriter::try_new(w, &batch.schema()).map_err(io::Error::other)?;
+            writer.write(&batch).map_err(io::Error::other)?;
+            writer.finish().map_err(io::Error::other)
+        }
+        #[cfg(feature = "parquet")]
+        Layout::Parquet => {
+            // The Parquet writer wants a writer it can send between threads
+            let mut file = Vec::new();
+            let mut writer =
+                ArrowWriter::try_new(&mut file, batch.schema(), None).map_err(io::Error::other)?;
+            writer.write(&batch).map_err(io::Error::other)?;
+            writer.close().map_err(io::Error::other)?;
+            w.write_all(&file)
+        }
+    }
+}
+
+/// Exact decimals at the largest scale among `values`
+fn decimals(values: &[Option<Decimal>]) -> io::Result<ArrayRef> {
+    let scale = values
+        .iter()
+        .flatten()
+        .map(Decimal::scale)
+        .max()
+        .unwrap_or(0);
+    let array: Decimal128Array = values
+        .iter()
+        .map(|value| {
+            value.map(|mut value| {
+                value.rescale(scale);
+                value.mantissa()
+            })
+        })
+        .collect();
+    let array = array
+        .with_precision_and_scale(38, scale as i8)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(array))
+}
+
+#[cfg(not(feature = "uuid"))]
+fn tx_ids(ids: &[TxId]) -> ArrayRef {
+    Arc::new(arrow_array::UInt64Array::from(ids.to_vec()))
+}
+
+/// UUIDs and other 128 bit ids do not fit an integer column
+#[cfg(feature = "uuid")]
+fn tx_ids(ids: &[TxId]) -> ArrayRef {
+    Arc::new(arrow_array::StringArray::from_iter_values(
+        ids.iter().map(|id| id.to_string()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Decimal128Type;
+    use arrow_ipc::reader::FileReader;
+    use tte::read_csv;
+
+    #[test]
+    fn test_write() -> anyhow::Result<()> {
+        let mut engine = Engine::new();
+        engine.record_events();
+        engine.replay(
+            read_csv(
+                "type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,1,2,2.25\ndispute,1,2,\n"
+                    .as_bytes(),
+            ),
+            None,
+        )?;
+
+        let mut report = Vec::new();
+        write_report(&engine, &mut report, Layout::Ipc)?;
+        let batches =
+            FileReader::try_new(io::Cursor::new(report), None)?.collect::<Result<Vec<_>, _>>()?;
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let clients = batch["client"].as_primitive::<arrow_array::types::UInt64Type>();
+        assert_eq!(clients.values(), &[1, 2]);
+        let held = batch["held"].as_primitive::<Decimal128Type>();
+        assert_eq!(held.value_as_string(0), "2.25");
+        assert_eq!(held.value_as_string(1), "0.00");
+
+        let mut events = Vec::new();
+        write_events(engine.events(), &mut events, Layout::Ipc)?;
+        let batches =
+            FileReader::try_new(io::Cursor::new(events), None)?.collect::<Result<Vec<_>, _>>()?;
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch["event"].as_string::<i32>().value(2), "DisputeOpened");
+        assert!(batch["fee"].is_null(2));
+
+        #[cfg(feature = "parquet")]
+        {
+            let mut report = Vec::new();
+            write_report(&engine, &mut report, Layout::Parquet)?;
+            assert!(report.starts_with(b"PAR1") && report.ends_with(b"PAR1"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Checkpoints for long batch runs
+//!
+//! A checkpoint is the serialized [Engine] together with the position in the
+//! input just past the last transaction applied. Restoring one and seeking the
+//! input to that position carries on the run as if it was never interrupted.
+//! The checkpoint does not record which file it belongs to, so it must only
+//! be resumed against the same input.
+use crate::engine::Engine;
+use crate::transaction::Transactions;
+use anyhow::Result;
+use csv::Position;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// The saved form, generic so that writing can borrow the engine
+#[derive(Deserialize, Serialize)]
+struct Saved<E> {
+    byte: u64,
+    line: u64,
+    record: u64,
+    engine: E,
+}
+
+/// Writes a checkpoint of `engine` at input `position`
+pub fn write_checkpoint(w: impl io::Write, engine: &Engine, position: &Position) -> Result<()> {
+    let saved = Saved {
+        byte: position.byte(),
+        line: position.line(),
+        record: position.record(),
+        engine,
+    };
+    serde_json::to_writer(w, &saved)?;
+    Ok(())
+}
+
+/// Reads a checkpoint written by [write_checkpoint]. The engine comes back
+/// without its [crate::Config], see [Engine::configure].
+pub fn read_checkpoint(r: impl io::Read) -> Result<(Engine, Position)> {
+    let saved: Saved<Engine> = serde_json::from_reader(r)?;
+    let mut position = Position::new();
+    position
+        .set_byte(saved.byte)
+        .set_line(saved.line)
+        .set_record(saved.record);
+    Ok((saved.engine, position))
+}
+
+/// Applies the remaining transactions, handing the engine and input position
+/// to `save` every `every` transactions and once more at the end. `stop` is
+/// checked before each transaction so an interrupted run still ends with a
+/// checkpoint it can be resumed from.
+pub fn replay_checkpointed<R: io::Read>(
+    engine: &mut Engine,
+    transactions: &mut Transactions<R>,
+    every: usize,
+    stop: impl Fn() -> bool,
+    mut save: impl FnMut(&Engine, &Position) -> Result<()>,
+) -> Result<()> {
+    let mut applied = 0;
+    while !stop() {
+        let Some(result) = transactions.next() else {
+            break;
+        };
+        engine.apply(result?)?;
+        applied += 1;
+        if applied % every == 0 {
+            save(engine, &transactions.input_position())?;
+        }
+    }
+    save(engine, &transactions.input_position())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+deposit,         2,     2,         2.0
+withdrawal,      1,     3,         1.5
+dispute,         2,     2,
+chargeback,      2,     2,
+deposit,         1,     4,         1.0
+";
+
+    #[test]
+    fn test_checkpoint_resume() -> Result<()> {
+        let mut full = Engine::new();
+        full.replay(read_csv(DATA.as_bytes()), None)?;
+
+        // Interrupt the run after three transactions
+        let mut engine = Engine::new();
+        let mut transactions = read_csv(Cursor::new(DATA));
+        let offered = Cell::new(0);
+        let mut saves = 0;
+        let mut checkpoint = Vec::new();
+        replay_checkpointed(
+            &mut engine,
+            &mut transactions,
+            2,
+            || {
+                offered.set(offered.get() + 1);
+                offered.get() > 3
+            },
+            |engine, position| {
+                saves += 1;
+                checkpoint.clear();
+                write_checkpoint(&mut checkpoint, engine, position)
+            },
+        )?;
+        // One periodic save after two transactions and the final one
+        assert_eq!(saves, 2);
+        drop(engine);
+
+        let (mut resumed, position) = read_checkpoint(checkpoint.as_slice())?;
+        let mut rest = read_csv(Cursor::new(DATA));
+        rest.seek(position)?;
+        resumed.replay(rest, None)?;
+        assert_eq!(resumed.snapshot(), full.snapshot());
+        Ok(())
+    }
+}
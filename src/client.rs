@@ -0,0 +1,1227 @@
+//! Client account state and the per-client transaction logic
+use crate::amount::{checked_from_decimal, from_decimal, to_decimal, Amount};
+use crate::config::{DisputePolicy, NegativeAvailable};
+use crate::reason::Reason;
+use crate::snapshot::Account;
+use crate::transaction::{ClientId, TransType, Transaction, TxId};
+use ahash::RandomState;
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, error, info, warn};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+
+/// Records can grow to millions of entries keyed by tx id, so they use the
+/// quicker [ahash] rather than the default SipHash
+type Records = HashMap<TxId, Amount, RandomState>;
+
+/// A deposit or withdrawal remembered so a later dispute can refer to it, as
+/// exported with [crate::Engine::write_records]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TxRecord {
+    pub client: ClientId,
+    #[cfg_attr(
+        feature = "uuid",
+        serde(deserialize_with = "crate::transaction::deserialize_tx")
+    )]
+    pub tx: TxId,
+    pub amount: Decimal,
+}
+
+/// Reads tx records from a CSV file with `client`, `tx` and `amount` columns
+/// ```text
+/// client, tx, amount
+///      1,  3,    1.5
+/// ```
+pub fn read_records(csv: impl io::Read) -> Result<Vec<TxRecord>> {
+    let rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+    Ok(rdr.into_deserialize().collect::<csv::Result<_>>()?)
+}
+
+/// Client account data
+///
+/// This is the main structure for holding client acount balances.
+/// * Assumption #1 - If an account is locked no future deposits/withdrawals are
+///   allowed. There is no way to unlock an account once it is locked.
+/// * A client with a credit line may withdraw until `available` reaches
+///   `-credit_limit`.
+/// * A client with a reserve may only withdraw down to `available` of
+///   `reserve`.
+///
+/// The whole state, records included, can be serialized so it can be saved and
+/// restored or sent elsewhere in the same representation.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Client {
+    /// Client records are a simple mapping from transaction id (`tx`) to
+    /// transaction `amount.` They are used by dispute/resolve/chargeback
+    /// transactions that reference `tx` to get an `amount.`
+    records: Records,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    credit_limit: Amount,
+    /// The `tx` ids disputed and not yet resolved or charged back
+    #[serde(default)]
+    disputed: BTreeSet<TxId>,
+    /// The amount held for each `tx` under dispute
+    #[serde(default)]
+    holds: HashMap<TxId, Amount>,
+    /// What is left to dispute of each `tx` disputed before, its amount less
+    /// what is held or was charged back
+    #[serde(default)]
+    disputable: HashMap<TxId, Amount>,
+    /// How many times each `tx` was disputed. Partial disputes adding to an
+    /// open one count with it.
+    #[serde(default)]
+    disputes: HashMap<TxId, u32>,
+    /// The part of each disputed `tx` that could not be held for lack of
+    /// available funds, see [NegativeAvailable::Clamp]
+    #[serde(default)]
+    shortfalls: HashMap<TxId, Amount>,
+    /// The least `available` a withdrawal may leave behind
+    #[serde(default)]
+    reserve: Option<Amount>,
+    /// Opened by an `open_account`, or seeded from a report
+    #[serde(default)]
+    opened: bool,
+    #[serde(default)]
+    closed: bool,
+    /// Transactions that reached the account, applied or not
+    #[serde(default)]
+    transactions: u64,
+    /// What the applied deposits and withdrawals moved, fees aside
+    #[serde(default)]
+    deposited: Amount,
+    #[serde(default)]
+    withdrawn: Amount,
+    /// The last transaction that reached the account
+    #[serde(default)]
+    last_tx: Option<TxId>,
+    #[serde(default)]
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+/// A read-only view of a client account, for inspecting the engine mid-run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountView {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// The `tx` ids under dispute, in ascending order
+    pub open_disputes: Vec<TxId>,
+}
+
+/// Custom [Debug] impl for [Client] so that the fields are shown without the
+/// [Records] HashMap
+/// ```text
+/// Client { available: 24.5  held: 2  total: 26.5  locked: false }
+/// ```
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Client {{ available: {}  held: {}  total: {}  locked: {} }}",
+            report(self.available),
+            report(self.held),
+            report(self.total),
+            self.locked
+        )
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, {}, {}, {}",
+            report(self.available),
+            report(self.held),
+            report(self.total),
+            self.locked
+        )
+    }
+}
+
+/// What [Client::transact] did with a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    /// The transaction moved `amount` and charged `fee`
+    Applied { amount: Amount, fee: Amount },
+    /// The transaction changed nothing, for this reason
+    Ignored(Reason),
+}
+
+impl Outcome {
+    /// A resolve or chargeback that moved the disputed amount
+    fn moved(amount: Result<Amount, Reason>) -> Outcome {
+        match amount {
+            Ok(amount) => Outcome::Applied {
+                amount,
+                fee: Amount::default(),
+            },
+            Err(reason) => Outcome::Ignored(reason),
+        }
+    }
+}
+
+/// An amount as it appears in the accounts report
+fn report(amount: Amount) -> Decimal {
+    to_decimal(amount).round_dp(4)
+}
+
+impl Client {
+    /// A client with a credit line
+    pub(crate) fn with_credit_limit(credit_limit: Amount) -> Client {
+        Client {
+            credit_limit,
+            ..Client::default()
+        }
+    }
+
+    /// The client with room for `records` deposits and withdrawals
+    pub(crate) fn with_records(mut self, records: usize) -> Client {
+        self.records.reserve(records);
+        self
+    }
+
+    /// The client with a minimum balance
+    pub(crate) fn with_reserve(self, reserve: Option<Amount>) -> Client {
+        Client { reserve, ..self }
+    }
+
+    /// A client starting from reported balances, without any records
+    pub(crate) fn from_account(account: &Account, credit_limit: Amount) -> Client {
+        Client {
+            available: from_decimal(account.available),
+            held: from_decimal(account.held),
+            total: from_decimal(account.total),
+            locked: account.locked,
+            credit_limit,
+            opened: true,
+            ..Client::default()
+        }
+    }
+
+    pub(crate) fn credit_limit(&self) -> Decimal {
+        report(self.credit_limit)
+    }
+
+    /// What the client owes for disputed funds that were not there to hold
+    pub(crate) fn shortfall(&self) -> Decimal {
+        report(
+            self.shortfalls
+                .values()
+                .fold(Amount::default(), |sum, &shortfall| sum + shortfall),
+        )
+    }
+
+    pub fn available(&self) -> Decimal {
+        to_decimal(self.available)
+    }
+
+    pub fn held(&self) -> Decimal {
+        to_decimal(self.held)
+    }
+
+    pub fn total(&self) -> Decimal {
+        to_decimal(self.total)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether any transaction of the account is under dispute
+    pub(crate) fn has_open_disputes(&self) -> bool {
+        !self.disputed.is_empty()
+    }
+
+    pub fn is_opened(&self) -> bool {
+        self.opened
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Closed with nothing left in it, which leaves it out of the report
+    pub(crate) fn is_closed_empty(&self) -> bool {
+        self.closed && self.total == Amount::default() && self.held == Amount::default()
+    }
+
+    /// Moves `amount` into the available funds, or out of them when negative.
+    /// Meant for [crate::handler]s, which decide themselves whether a locked
+    /// account or a negative balance is acceptable.
+    pub fn adjust(&mut self, amount: Decimal) {
+        debug!("  adjusting by: {}", amount);
+        let amount = from_decimal(amount);
+        self.available += amount;
+        self.total += amount;
+    }
+
+    /// Locks the account, e.g. when a policy freezes it
+    pub fn lock(&mut self) {
+        info!("locking account");
+        self.locked = true;
+    }
+
+    /// Unlocks the account, e.g. once a chargeback has been looked into
+    pub fn unlock(&mut self) {
+        info!("unlocking account");
+        self.locked = false;
+    }
+
+    /// The most that can be withdrawn, credit line included
+    fn spendable(&self) -> Amount {
+        self.available.saturating_add(self.credit_limit)
+    }
+
+    /// Whether a deposit of `amount` keeps every balance in range
+    fn can_deposit(&self, amount: Amount) -> bool {
+        [self.available, self.total, self.deposited]
+            .iter()
+            .all(|balance| balance.checked_add(amount).is_some())
+    }
+
+    /// Whether a withdrawal of `amount` with `fee` keeps every balance in
+    /// range
+    fn can_withdraw(&self, amount: Amount, fee: Amount) -> bool {
+        amount.checked_add(fee).is_some_and(|due| {
+            [self.available, self.total]
+                .iter()
+                .all(|balance| balance.checked_sub(due).is_some())
+        }) && self.withdrawn.checked_add(amount).is_some()
+    }
+
+    /// The client balances as they appear in the accounts report
+    pub(crate) fn account(&self) -> Account {
+        Account {
+            available: report(self.available),
+            held: report(self.held),
+            total: report(self.total),
+            locked: self.locked,
+        }
+    }
+
+    /// The client balances exactly, without the rounding of the report
+    pub(crate) fn exact_account(&self) -> Account {
+        Account {
+            available: self.available(),
+            held: self.held(),
+            total: self.total(),
+            locked: self.locked,
+        }
+    }
+
+    pub(crate) fn view(&self, client: ClientId) -> AccountView {
+        AccountView {
+            client,
+            available: report(self.available),
+            held: report(self.held),
+            total: report(self.total),
+            locked: self.locked,
+            open_disputes: self.disputed.iter().copied().collect(),
+        }
+    }
+
+    /// Counts a transaction that reached the account
+    pub(crate) fn note(&mut self, transaction: &Transaction) {
+        self.transactions += 1;
+        self.last_tx = Some(transaction.tx);
+        self.last_timestamp = transaction.timestamp;
+    }
+
+    /// Writes the activity columns of the extended report, each led by a
+    /// comma
+    /// ```text
+    /// , 3, 5.0, 1.5, 0, 3, 2022-03-21T10:00:00Z
+    /// ```
+    pub(crate) fn write_activity(&self, w: &mut impl io::Write) -> io::Result<()> {
+        write!(
+            w,
+            ", {}, {}, {}, {}, {}, {}",
+            self.transactions,
+            report(self.deposited),
+            report(self.withdrawn),
+            self.disputed.len(),
+            self.last_tx.map(|tx| tx.to_string()).unwrap_or_default(),
+            self.last_timestamp
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+                .unwrap_or_default()
+        )
+    }
+
+    /// How many times `tx` was disputed, while it is under dispute
+    pub(crate) fn open_dispute(&self, tx: TxId) -> Option<u32> {
+        self.disputed
+            .contains(&tx)
+            .then(|| self.disputes.get(&tx).copied().unwrap_or_default())
+    }
+
+    /// Resolves the dispute of `tx` on the engine's own accord, returning the
+    /// amount released
+    pub(crate) fn auto_resolve(&mut self, tx: TxId) -> Option<Amount> {
+        self.resolve(tx).ok()
+    }
+
+    /// Every record, ordered by `tx`
+    pub(crate) fn records(&self) -> Vec<(TxId, Decimal)> {
+        let mut records: Vec<(TxId, Decimal)> = self
+            .records
+            .iter()
+            .map(|(&tx, &amount)| (tx, to_decimal(amount)))
+            .collect();
+        records.sort_by_key(|&(tx, _)| tx);
+        records
+    }
+
+    pub(crate) fn has_record(&self, tx: TxId) -> bool {
+        self.records.contains_key(&tx)
+    }
+
+    /// Forgets the record of `tx`, e.g. to keep it elsewhere
+    pub(crate) fn take_record(&mut self, tx: TxId) -> Option<Amount> {
+        self.records.remove(&tx)
+    }
+
+    /// Add a mapping entry for a `tx` to an `amount`
+    pub(crate) fn add_record(&mut self, tx: TxId, amount: Amount) -> Result<()> {
+        debug!("  add record tx:{}  amount:{}", tx, amount);
+        self.records.insert(tx, amount);
+        Ok(())
+    }
+
+    /// A `tx` both clients have a record of, which keeps them from merging
+    pub(crate) fn shared_record(&self, other: &Client) -> Option<TxId> {
+        other
+            .records
+            .keys()
+            .find(|tx| self.records.contains_key(tx))
+            .copied()
+    }
+
+    /// Starts the activity counters of the extended report over, for a new
+    /// period
+    pub(crate) fn start_period(&mut self) {
+        self.transactions = 0;
+        self.deposited = Amount::default();
+        self.withdrawn = Amount::default();
+    }
+
+    /// Takes over the balances, records and history of `other`, a duplicate
+    /// of the same customer. The account is locked or closed if either was.
+    pub(crate) fn absorb(&mut self, other: Client) {
+        self.records.extend(other.records);
+        self.available += other.available;
+        self.held += other.held;
+        self.total += other.total;
+        self.locked |= other.locked;
+        self.disputed.extend(other.disputed);
+        self.holds.extend(other.holds);
+        self.disputable.extend(other.disputable);
+        self.disputes.extend(other.disputes);
+        self.shortfalls.extend(other.shortfalls);
+        self.opened |= other.opened;
+        self.closed |= other.closed;
+        self.transactions += other.transactions;
+        self.deposited += other.deposited;
+        self.withdrawn += other.withdrawn;
+        if other.last_timestamp > self.last_timestamp || self.last_tx.is_none() {
+            self.last_tx = other.last_tx;
+            self.last_timestamp = other.last_timestamp;
+        }
+    }
+
+    /// Consumes a transaction provided by [crate::transaction::read_csv] and
+    /// performs the appropriate transaction task
+    ///
+    /// `fee` is taken from the client when a deposit or withdrawal goes
+    /// through. A deposit fee is capped at the deposit amount and a withdrawal
+    /// only goes through if both the amount and the fee are available.
+    /// Disputes follow the `disputes` policy. An amount, or a balance it would
+    /// make, beyond what an [Amount] holds is ignored as an overflow.
+    pub(crate) fn transact(
+        &mut self,
+        transaction: Transaction,
+        fee: Amount,
+        disputes: &DisputePolicy,
+    ) -> Result<Outcome> {
+        self.note(&transaction);
+        let tx = transaction.tx;
+        let amount = transaction.amount.map(checked_from_decimal);
+        let outcome = match transaction.trans {
+            _ if self.closed => Outcome::Ignored(Reason::Closed),
+            TransType::Deposit | TransType::Withdrawal if self.locked => {
+                Outcome::Ignored(Reason::Locked)
+            }
+            TransType::Deposit | TransType::Withdrawal | TransType::Dispute
+                if amount == Some(None) =>
+            {
+                warn!("Amount out of range in tx:{tx}");
+                Outcome::Ignored(Reason::Overflow)
+            }
+            TransType::Deposit => match amount.flatten() {
+                Some(amount) if !self.can_deposit(amount) => {
+                    warn!("Deposit of {amount} would overflow the balances");
+                    Outcome::Ignored(Reason::Overflow)
+                }
+                Some(amount) => {
+                    self.add_record(tx, amount)?;
+                    self.deposit(amount)?;
+                    self.deposited += amount;
+                    let fee = self.charge(fee.min(amount));
+                    Outcome::Applied { amount, fee }
+                }
+                None => {
+                    error!("O_o No amount specified in Deposit transaction");
+                    Outcome::Ignored(Reason::MissingAmount)
+                }
+            },
+            TransType::Withdrawal => match amount.flatten() {
+                Some(amount) if !self.can_withdraw(amount, fee) => {
+                    warn!("Withdrawal of {amount} with fee {fee} would overflow the balances");
+                    Outcome::Ignored(Reason::Overflow)
+                }
+                Some(amount) => {
+                    self.add_record(tx, amount)?;
+                    if self.spendable() < amount + fee {
+                        warn!("Insufficient funds for withdrawal of {amount} with fee {fee}");
+                        Outcome::Ignored(Reason::InsufficientFunds)
+                    } else if self
+                        .reserve
+                        .is_some_and(|reserve| self.available - amount - fee < reserve)
+                    {
+                        warn!("Withdrawal of {amount} with fee {fee} would break the reserve");
+                        Outcome::Ignored(Reason::BelowReserve)
+                    } else {
+                        self.withdrawal(amount)?;
+                        self.withdrawn += amount;
+                        let fee = self.charge(fee);
+                        Outcome::Applied { amount, fee }
+                    }
+                }
+                None => {
+                    error!("O_o No amount in withdrawn");
+                    Outcome::Ignored(Reason::MissingAmount)
+                }
+            },
+            TransType::Dispute => self.dispute(tx, amount.flatten(), disputes),
+            TransType::Resolve => Outcome::moved(self.resolve(tx)),
+            TransType::Chargeback => Outcome::moved(self.chargeback(tx)),
+            TransType::OpenAccount => {
+                self.opened = true;
+                Outcome::Applied {
+                    amount: Amount::default(),
+                    fee: Amount::default(),
+                }
+            }
+            // Funds under dispute could never be released from a closed account
+            TransType::CloseAccount if !self.disputed.is_empty() => {
+                Outcome::Ignored(Reason::OpenDisputes)
+            }
+            TransType::CloseAccount => {
+                info!("closing account");
+                self.closed = true;
+                Outcome::Applied {
+                    amount: Amount::default(),
+                    fee: Amount::default(),
+                }
+            }
+            TransType::Other(_) => Outcome::Ignored(Reason::UnknownType),
+        };
+        Ok(outcome)
+    }
+
+    /// Takes a fee out of the available funds. Returns the fee charged.
+    fn charge(&mut self, fee: Amount) -> Amount {
+        if fee <= Amount::default() {
+            return Amount::default();
+        }
+        debug!("  charging fee: {}", fee);
+        self.available -= fee;
+        self.total -= fee;
+        fee
+    }
+
+    fn deposit(&mut self, amount: Amount) -> io::Result<()> {
+        debug!("  depositing: {}", amount);
+        self.available += amount;
+        self.total += amount;
+        debug!("  {:?}", self);
+        Ok(())
+    }
+
+    fn withdrawal(&mut self, amount: Amount) -> io::Result<()> {
+        if self.spendable() >= amount {
+            debug!("withdrawing: {}", amount);
+            self.available -= amount;
+            self.total -= amount;
+            debug!("{}", self);
+        } else {
+            warn!("Insufficient funds for withdrawal");
+        }
+        Ok(())
+    }
+
+    /// Holds `amount` of a `tx`, or all of it that is left to dispute
+    fn dispute(&mut self, tx: TxId, amount: Option<Amount>, policy: &DisputePolicy) -> Outcome {
+        let Some(&recorded) = self.records.get(&tx) else {
+            warn!("Could not find tx:{tx} to dispute. CSV data error?");
+            return Outcome::Ignored(Reason::UnknownTx);
+        };
+        let disputable = self.disputable.get(&tx).copied().unwrap_or(recorded);
+        let amount = amount.unwrap_or(disputable);
+        if amount <= Amount::default() || amount > disputable {
+            warn!("Cannot dispute {amount} of tx:{tx} with {disputable} left to dispute");
+            return Outcome::Ignored(Reason::NotDisputable);
+        }
+        let opens = !self.disputed.contains(&tx);
+        let count = self.disputes.get(&tx).copied().unwrap_or_default();
+        if opens && policy.max_disputes().is_some_and(|max| count >= max) {
+            warn!("tx:{tx} was disputed {count} times already");
+            return Outcome::Ignored(Reason::Redisputed);
+        }
+        let held = match policy.negative_available {
+            NegativeAvailable::Allow => amount,
+            NegativeAvailable::Reject if amount > self.available => {
+                warn!("Disputing {amount} of tx:{tx} would take available negative");
+                return Outcome::Ignored(Reason::NegativeAvailable);
+            }
+            NegativeAvailable::Reject => amount,
+            NegativeAvailable::Clamp => amount.min(self.available.max(Amount::default())),
+        };
+        if self.held.checked_add(held).is_none() || self.available.checked_sub(held).is_none() {
+            warn!("Disputing {held} of tx:{tx} would overflow the balances");
+            return Outcome::Ignored(Reason::Overflow);
+        }
+        info!("Disputing tx:{tx} amount:{amount} holding:{held}");
+        self.available -= held;
+        self.held += held;
+        if opens {
+            self.disputes.insert(tx, count + 1);
+        }
+        *self.holds.entry(tx).or_default() += held;
+        if held < amount {
+            *self.shortfalls.entry(tx).or_default() += amount - held;
+        }
+        self.disputed.insert(tx);
+        self.disputable.insert(tx, disputable - amount);
+        Outcome::Applied {
+            amount: held,
+            fee: Amount::default(),
+        }
+    }
+
+    /// Ends the dispute of `tx`, returning what it held. A `tx` under dispute
+    /// without a hold, from state saved before holds were kept, held its
+    /// whole amount.
+    fn release(&mut self, tx: TxId, action: &str) -> Result<Amount, Reason> {
+        if !self.disputed.contains(&tx) {
+            warn!("Cannot {action} tx:{tx}, it is not under dispute");
+            return Err(Reason::NotDisputed);
+        }
+        let held = match self.holds.remove(&tx) {
+            Some(held) => held,
+            // Only state saved before holds were kept has a dispute without one
+            None => match self.records.get(&tx) {
+                Some(&amount) => amount,
+                None => {
+                    warn!("Could not find tx:{tx} to {action}. CSV data error?");
+                    return Err(Reason::UnknownTx);
+                }
+            },
+        };
+        self.disputed.remove(&tx);
+        Ok(held)
+    }
+
+    /// Each of these returns the amount moved, or why nothing was
+    fn resolve(&mut self, tx: TxId) -> Result<Amount, Reason> {
+        let amount = self.release(tx, "resolve")?;
+        let shortfall = self.shortfalls.remove(&tx).unwrap_or_default();
+        info!("resolve tx:{tx} amount:{amount}");
+        self.available += amount;
+        self.held -= amount;
+        if let Some(disputable) = self.disputable.get_mut(&tx) {
+            *disputable += amount + shortfall;
+        }
+        Ok(amount)
+    }
+
+    fn chargeback(&mut self, tx: TxId) -> Result<Amount, Reason> {
+        let amount = self.release(tx, "chargeback")?;
+        info!("chargeback tx:{tx} amount:{amount}");
+        self.locked = true;
+        self.held -= amount;
+        self.total -= amount;
+        Ok(amount)
+    }
+}
+
+/// Every [Client], side by side in one arena in the order they were first
+/// seen, with an index from client id to their place in it
+///
+/// The apply loop only hashes the id, and going over every client, e.g. for
+/// the report, walks the arena front to back. Serialized as a map from id to
+/// client, in the same order.
+#[derive(Default)]
+pub(crate) struct Clients {
+    ids: Vec<ClientId>,
+    clients: Vec<Client>,
+    index: HashMap<ClientId, usize, RandomState>,
+}
+
+impl Clients {
+    pub(crate) fn get(&self, id: ClientId) -> Option<&Client> {
+        self.index.get(&id).map(|&i| &self.clients[i])
+    }
+
+    pub(crate) fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.index.get(&id).map(|&i| &mut self.clients[i])
+    }
+
+    /// The client `id`, added at the end with `new` if it was not seen yet
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        id: ClientId,
+        new: impl FnOnce() -> Client,
+    ) -> &mut Client {
+        let i = *self.index.entry(id).or_insert_with(|| {
+            self.ids.push(id);
+            self.clients.push(new());
+            self.clients.len() - 1
+        });
+        &mut self.clients[i]
+    }
+
+    /// Puts `client` in the place of client `id`, keeping its order if it
+    /// was seen before
+    pub(crate) fn insert(&mut self, id: ClientId, client: Client) {
+        *self.get_or_insert_with(id, Client::default) = client;
+    }
+
+    /// Takes out client `id`, keeping the others in order
+    pub(crate) fn remove(&mut self, id: ClientId) -> Option<Client> {
+        let (_, client) = self.take_where(|other, _| other == id).pop()?;
+        Some(client)
+    }
+
+    /// Every client in the order first seen
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (ClientId, &Client)> {
+        self.ids.iter().copied().zip(&self.clients)
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+        self.clients.iter_mut()
+    }
+
+    /// Takes out the clients `take` picks, keeping the others in order
+    pub(crate) fn take_where(
+        &mut self,
+        mut take: impl FnMut(ClientId, &Client) -> bool,
+    ) -> Vec<(ClientId, Client)> {
+        let mut taken = Vec::new();
+        let mut kept = Clients::default();
+        for (id, client) in std::mem::take(self).into_entries() {
+            if take(id, &client) {
+                taken.push((id, client));
+            } else {
+                kept.insert(id, client);
+            }
+        }
+        *self = kept;
+        taken
+    }
+
+    /// The clients seen after `id`, in the order first seen, or None if
+    /// `id` is unknown
+    pub(crate) fn iter_after(
+        &self,
+        id: ClientId,
+    ) -> Option<impl Iterator<Item = (ClientId, &Client)>> {
+        let start = self.index.get(&id)? + 1;
+        Some(self.iter().skip(start))
+    }
+
+    /// Every client, taken out in the order first seen
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (ClientId, Client)> {
+        self.ids.into_iter().zip(self.clients)
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.ids.reserve(additional);
+        self.clients.reserve(additional);
+        self.index.reserve(additional);
+    }
+}
+
+impl Serialize for Clients {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Clients {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Clients;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of client id to client")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Clients, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut clients = Clients::default();
+                clients.reserve(map.size_hint().unwrap_or_default());
+                while let Some((id, client)) = map.next_entry()? {
+                    clients.insert(id, client);
+                }
+                Ok(clients)
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_init;
+    use crate::transaction::read_csv;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_client_defaults() {
+        log_init();
+        let client = Client::default();
+        println!("{:?}", client);
+
+        assert_eq!(client.available, dec!(0.0000));
+        assert_eq!(client.held, dec!(0.0000));
+        assert_eq!(client.total, dec!(0.0000));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_basic_deposit() {
+        log_init();
+        let mut client = Client::default();
+        println!("{:?}", client);
+
+        client.deposit(from_decimal(dec!(3.14))).unwrap();
+        assert_eq!(client.available, dec!(3.14));
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(3.14));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_basic_withdrawal() {
+        log_init();
+        let mut client = Client::default();
+
+        client.deposit(from_decimal(dec!(1.0))).unwrap();
+        client.deposit(from_decimal(dec!(2.0))).unwrap();
+        client.withdrawal(from_decimal(dec!(1.5))).unwrap();
+        assert_eq!(client.available, dec!(1.5));
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(1.5));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        log_init();
+        let mut client = Client::default();
+        client.withdrawal(from_decimal(dec!(1.5))).unwrap();
+    }
+
+    #[test]
+    fn test_withdrawal_credit_limit() {
+        log_init();
+        let mut client = Client::with_credit_limit(from_decimal(dec!(50)));
+
+        client.deposit(from_decimal(dec!(10))).unwrap();
+        client.withdrawal(from_decimal(dec!(40))).unwrap();
+        assert_eq!(client.available, dec!(-30));
+        assert_eq!(client.total, dec!(-30));
+        // Past the credit limit
+        client.withdrawal(from_decimal(dec!(20.01))).unwrap();
+        assert_eq!(client.available, dec!(-30));
+        client.withdrawal(from_decimal(dec!(20))).unwrap();
+        assert_eq!(client.available, dec!(-50));
+    }
+
+    #[test]
+    fn test_partial_dispute() -> Result<()> {
+        log_init();
+        let mut client = Client::default();
+        let dispute = |amount| Transaction::new(TransType::Dispute, 1, 1, amount);
+        let none = Amount::default();
+        client.transact(
+            Transaction::new(TransType::Deposit, 1, 1, Some(dec!(10))),
+            none,
+            &DisputePolicy::default(),
+        )?;
+        client.transact(dispute(Some(dec!(4))), none, &DisputePolicy::default())?;
+        assert_eq!(
+            client.transact(dispute(Some(dec!(7))), none, &DisputePolicy::default())?,
+            Outcome::Ignored(Reason::NotDisputable),
+            "only 6 is left to dispute"
+        );
+        client.transact(dispute(None), none, &DisputePolicy::default())?;
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.held, dec!(10));
+
+        client.transact(
+            Transaction::new(TransType::Resolve, 1, 1, None),
+            none,
+            &DisputePolicy::default(),
+        )?;
+        assert_eq!(client.available, dec!(10));
+        assert_eq!(client.held, dec!(0));
+        client.transact(dispute(Some(dec!(3))), none, &DisputePolicy::default())?;
+        client.transact(
+            Transaction::new(TransType::Chargeback, 1, 1, None),
+            none,
+            &DisputePolicy::default(),
+        )?;
+        assert_eq!(client.available, dec!(7));
+        assert_eq!(client.total, dec!(7));
+        assert_eq!(client.disputable[&1], dec!(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_basic_dispute() -> Result<()> {
+        log_init();
+        let mut client = Client::default();
+        println!("{:#?}", client);
+
+        let amount = from_decimal(dec!(6.62));
+        client.deposit(amount).unwrap();
+        client.add_record(1, amount)?;
+        assert!(matches!(
+            client.dispute(1, None, &DisputePolicy::default()),
+            Outcome::Applied { .. }
+        ));
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.held, amount);
+        assert_eq!(client.total, amount);
+        assert!(!client.locked);
+        assert!(client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_basic_resolve() -> Result<()> {
+        log_init();
+        let mut client = Client::default();
+        print!("{:#?}", client);
+
+        let amount = from_decimal(dec!(6.02));
+        client.deposit(amount).unwrap();
+        client.add_record(1, amount)?;
+        assert!(matches!(
+            client.dispute(1, None, &DisputePolicy::default()),
+            Outcome::Applied { .. }
+        ));
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.held, amount);
+        assert_eq!(client.total, amount);
+        assert!(!client.locked);
+        assert!(client.has_open_disputes());
+
+        client.resolve(1).unwrap();
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.available, amount);
+        assert_eq!(client.total, amount);
+        assert!(!client.locked);
+        assert!(!client.has_open_disputes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_basic_chargeback() -> Result<()> {
+        log_init();
+        let mut client = Client::default();
+        print!("{:#?}", client);
+
+        let amount = from_decimal(dec!(6.28));
+        client.deposit(amount).unwrap();
+        client.deposit(amount).unwrap();
+        client.add_record(1, amount)?;
+        client.add_record(2, amount)?;
+        assert!(matches!(
+            client.dispute(2, None, &DisputePolicy::default()),
+            Outcome::Applied { .. }
+        ));
+        assert_eq!(client.available, amount);
+        assert_eq!(client.held, amount);
+        assert_eq!(client.total, amount + amount);
+        assert!(!client.locked);
+        assert!(client.has_open_disputes());
+
+        client.chargeback(2).unwrap();
+        assert_eq!(client.available, amount);
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, amount);
+        assert!(client.locked);
+        assert!(
+            !client.has_open_disputes(),
+            "the chargeback ends the dispute"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undisputed_resolve_and_chargeback() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,1,1,
+dispute,1,2,
+resolve,1,2,
+";
+        let mut client = Client::default();
+        let policy = DisputePolicy::default();
+        for result in read_csv(DATA.as_bytes()) {
+            client.transact(result?, Amount::default(), &policy)?;
+        }
+        // Resolving one of two open disputes leaves the other
+        assert_eq!(client.held, dec!(10.0));
+        assert!(client.has_open_disputes());
+
+        for trans in [TransType::Resolve, TransType::Chargeback] {
+            let outcome = client.transact(
+                Transaction::new(trans, 1, 2, None),
+                Amount::default(),
+                &policy,
+            )?;
+            assert!(matches!(outcome, Outcome::Ignored(Reason::NotDisputed)));
+        }
+        assert_eq!(client.held, dec!(10.0), "nothing of tx 2 is held");
+        assert!(!client.locked);
+
+        client.transact(
+            Transaction::new(TransType::Chargeback, 1, 1, None),
+            Amount::default(),
+            &policy,
+        )?;
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(5.0));
+        assert!(!client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_without_hold() -> Result<()> {
+        let amount = from_decimal(dec!(4.0));
+        let mut client = Client::default();
+        client.deposit(amount + amount)?;
+        client.add_record(1, amount)?;
+        client.add_record(2, amount)?;
+        // Disputed in state saved before holds were kept
+        client.available -= amount;
+        client.held += amount;
+        client.disputed.insert(1);
+
+        assert_eq!(client.resolve(2), Err(Reason::NotDisputed));
+        assert_eq!(client.held, amount, "a record without a hold moves nothing");
+        assert_eq!(client.resolve(1), Ok(amount));
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.chargeback(1), Err(Reason::NotDisputed));
+        assert_eq!(client.held, dec!(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_serde_round_trip() -> Result<()> {
+        let mut client = Client::default();
+        client.transact(
+            Transaction::new(TransType::Deposit, 1, 1, Some(dec!(2.5))),
+            Amount::default(),
+            &DisputePolicy::default(),
+        )?;
+        client.transact(
+            Transaction::new(TransType::Deposit, 1, 2, Some(dec!(1))),
+            Amount::default(),
+            &DisputePolicy::default(),
+        )?;
+        client.transact(
+            Transaction::new(TransType::Dispute, 1, 2, None),
+            Amount::default(),
+            &DisputePolicy::default(),
+        )?;
+
+        let json = serde_json::to_string(&client)?;
+        let restored: Client = serde_json::from_str(&json)?;
+        assert_eq!(restored.records, client.records);
+        assert_eq!(restored.available, dec!(2.5));
+        assert_eq!(restored.held, dec!(1));
+        assert_eq!(restored.total, dec!(3.5));
+        assert!(restored.has_open_disputes());
+        assert!(!restored.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_chargeback() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2,2.0
+deposit,1,3,100.0
+dispute,1,3,
+deposit,1,4,100.0
+chargeback,1,3,
+";
+        let mut client = Client::default();
+        let transactions = read_csv(DATA.as_bytes());
+        for result in transactions {
+            let transaction: Transaction = result?;
+            client.transact(transaction, Amount::default(), &DisputePolicy::default())?;
+        }
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(103));
+        assert!(client.locked);
+        assert!(!client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transact() -> Result<()> {
+        let mut client = Client::default();
+
+        // Deposit
+        let record = Transaction::new(TransType::Deposit, 1, 1, Some(dec!(10.0)));
+        println!("{:#?}", record);
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+        assert_eq!(client.available, dec!(10));
+
+        // Withdrawl
+        let record = Transaction::new(TransType::Withdrawal, 1, 2, Some(dec!(3.5)));
+        println!("{:#?}", record);
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+        assert_eq!(client.available, dec!(6.5));
+
+        // Dispute a withdrawal
+        let record = Transaction::new(TransType::Dispute, 1, 2, None);
+        println!("{:#?}", record);
+        assert_eq!(client.held, dec!(0));
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+        assert_eq!(client.available, dec!(3));
+        assert_eq!(client.total, dec!(6.5));
+        assert_eq!(client.held, dec!(3.5));
+        assert!(client.has_open_disputes());
+
+        // Resolve the dispute
+        let record = Transaction::new(TransType::Resolve, 1, 2, None);
+        println!("{:?}", client);
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+        assert!(!client.has_open_disputes());
+        assert_eq!(client.available, dec!(6.5));
+        assert_eq!(client.total, dec!(6.5));
+        assert_eq!(client.held, dec!(0));
+
+        // Dispute another
+        let record = Transaction::new(TransType::Dispute, 1, 1, None);
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+
+        // Chargeback
+        let record = Transaction::new(TransType::Chargeback, 1, 1, None);
+        assert!(client
+            .transact(record, Amount::default(), &DisputePolicy::default())
+            .is_ok());
+        println!("{:?}", client);
+        assert!(!client.has_open_disputes());
+        assert!(client.locked);
+        assert_eq!(client.held, dec!(0));
+        // Since the dispute was on a withdrawal the total will be negative
+        assert_eq!(client.total, dec!(-3.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow() -> Result<()> {
+        let policy = DisputePolicy::default();
+        let huge = if cfg!(feature = "fixed-point") {
+            dec!(900000000000000.0)
+        } else {
+            Decimal::MAX
+        };
+        let mut client = Client::default();
+        let deposit = |tx| Transaction::new(TransType::Deposit, 1, tx, Some(huge));
+        let outcome = client.transact(deposit(1), Amount::default(), &policy)?;
+        assert!(matches!(outcome, Outcome::Applied { .. }));
+        let outcome = client.transact(deposit(2), Amount::default(), &policy)?;
+        assert_eq!(outcome, Outcome::Ignored(Reason::Overflow));
+        assert_eq!(client.total(), huge);
+        assert!(!client.records.contains_key(&2), "not disputable either");
+
+        let withdrawal = Transaction::new(TransType::Withdrawal, 1, 3, Some(huge));
+        let outcome = client.transact(withdrawal, from_decimal(huge), &policy)?;
+        assert_eq!(outcome, Outcome::Ignored(Reason::Overflow));
+        assert_eq!(client.total(), huge);
+
+        if cfg!(feature = "fixed-point") {
+            // Past what converts at all, rather than saturating
+            let deposit = Transaction::new(TransType::Deposit, 1, 4, Some(dec!(1e20)));
+            let outcome = client.transact(deposit, Amount::default(), &policy)?;
+            assert_eq!(outcome, Outcome::Ignored(Reason::Overflow));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_clients_first_seen_order() -> Result<()> {
+        let mut clients = Clients::default();
+        clients.reserve(100);
+        assert!(clients.clients.capacity() >= 100);
+        for id in [7, 3, 9] {
+            clients.get_or_insert_with(id, Client::default);
+        }
+        clients.insert(3, Client::with_credit_limit(from_decimal(dec!(5))));
+        clients.get_or_insert_with(7, || unreachable!("seen already"));
+        let ids: Vec<ClientId> = clients.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [7, 3, 9]);
+        assert_eq!(clients.get(3).map(Client::credit_limit), Some(dec!(5)));
+
+        let json = serde_json::to_string(&clients)?;
+        assert!(json.starts_with(r#"{"7":"#), "{json}");
+        let restored: Clients = serde_json::from_str(&json)?;
+        let ids: Vec<ClientId> = restored.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [7, 3, 9]);
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+//! Engine configuration
+//!
+//! Policies that change how the engine applies transactions, as opposed to
+//! [crate::ReaderOptions] which only describe the input. The `tte` binary loads
+//! a [Config] from a TOML file given with `--config`.
+//! ```toml
+//! [fees]
+//! deposit = { flat = "0.10" }
+//! withdrawal = { flat = "0.25", percent = "0.5" }
+//!
+//! [tiers.gold]
+//! clients = [1, 7]
+//! fees.withdrawal = { percent = "0.1" }
+//!
+//! [interest]
+//! rate = "0.25"
+//!
+//! [[credit]]
+//! client = 3
+//! limit = "100"
+//!
+//! [reserve]
+//! min_balance = "10"
+//! clients = [{ client = 4, min_balance = "500" }]
+//!
+//! [disputes]
+//! redisputes = { limit = 2 }
+//! negative_available = "clamp"
+//! expire_after = { days = 30 }
+//! ```
+use crate::aml::AmlRules;
+use crate::risk::RiskLimits;
+use crate::transaction::{ClientId, TransType};
+use crate::{enrich, middleware};
+use anyhow::Result;
+use csv::Trim;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+
+/// All engine policies. Every part is optional and defaults to the plain
+/// behaviour of the engine.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Fees charged to every client not in a tier with its own fees
+    pub fees: FeeSchedule,
+    /// Named groups of clients with their own fees, keyed by tier name
+    pub tiers: BTreeMap<String, Tier>,
+    /// Interest paid by [crate::Engine::accrue_interest]
+    pub interest: Option<Interest>,
+    /// Clients allowed to overdraw their account
+    pub credit: Vec<CreditLine>,
+    /// Balances withdrawals may not go below
+    pub reserve: Reserves,
+    /// How often a transaction may be disputed, and how far
+    pub disputes: DisputePolicy,
+    /// Limits checked before a transaction is applied
+    pub risk: RiskLimits,
+    /// Patterns clients are flagged for, see [crate::aml]
+    pub aml: AmlRules,
+    /// Reject every transaction of a client whose account was not opened
+    /// with an `open_account` first, or seeded from a report
+    pub strict_onboarding: bool,
+    /// Rewrites every transaction first, see [crate::enrich]
+    pub enrich: Vec<enrich::Builtin>,
+    /// Run on every transaction before it is applied, see [crate::middleware]
+    pub middleware: Vec<middleware::Builtin>,
+}
+
+/// Lets a client withdraw until `available` reaches `-limit`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreditLine {
+    pub client: ClientId,
+    pub limit: Decimal,
+}
+
+/// The least `available` a withdrawal may leave, for every client or for
+/// some of them. A client's own reserve replaces the global one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reserves {
+    pub min_balance: Option<Decimal>,
+    pub clients: Vec<ClientReserve>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientReserve {
+    pub client: ClientId,
+    pub min_balance: Decimal,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputePolicy {
+    /// Whether a transaction may be disputed again once its dispute is
+    /// resolved
+    pub redisputes: Redisputes,
+    /// What a dispute of more than is available does
+    pub negative_available: NegativeAvailable,
+    /// When a dispute neither resolved nor charged back is resolved by the
+    /// engine
+    pub expire_after: Option<DisputeExpiry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeExpiry {
+    /// Once this many more transactions were offered to the engine
+    Transactions(u64),
+    /// Once a transaction stamped this many days after the dispute arrives.
+    /// Disputes without a timestamp never expire.
+    Days(i64),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeAvailable {
+    /// Hold it all, taking `available` below zero
+    #[default]
+    Allow,
+    /// Hold only what is available and track the rest as the client's
+    /// shortfall until the dispute is resolved
+    Clamp,
+    /// Reject the dispute
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Redisputes {
+    /// As often as it is resolved
+    #[default]
+    Allow,
+    /// Never
+    Reject,
+    /// At most this many times after the first dispute
+    Limit(u32),
+}
+
+impl DisputePolicy {
+    /// The most disputes a transaction may see, if there is a limit
+    pub fn max_disputes(&self) -> Option<u32> {
+        match self.redisputes {
+            Redisputes::Allow => None,
+            Redisputes::Reject => Some(1),
+            Redisputes::Limit(n) => Some(n.saturating_add(1)),
+        }
+    }
+}
+
+/// Reads credit lines from a CSV file with `client` and `limit` columns
+/// ```text
+/// client, limit
+///      3,   100
+/// ```
+pub fn read_credit_lines(csv: impl io::Read) -> Result<Vec<CreditLine>> {
+    let rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(csv);
+    Ok(rdr.into_deserialize().collect::<csv::Result<_>>()?)
+}
+
+/// Interest paid on available balances
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Interest {
+    /// Percentage of the available balance paid on each accrual
+    pub rate: Decimal,
+}
+
+impl Interest {
+    /// The interest on a `balance`, rounded to four decimal places. Nothing is
+    /// paid on a zero or negative balance.
+    pub fn on(&self, balance: Decimal) -> Decimal {
+        if balance <= Decimal::ZERO {
+            return Decimal::ZERO;
+        }
+        (balance * self.rate / Decimal::ONE_HUNDRED)
+            .round_dp(4)
+            .normalize()
+    }
+}
+
+/// A group of clients sharing the same fees
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tier {
+    pub clients: Vec<ClientId>,
+    /// Fees for the clients in this tier. A transaction type missing here
+    /// falls back to the top level [Config::fees].
+    pub fees: FeeSchedule,
+}
+
+/// The fee charged for each transaction type that moves funds
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub deposit: Option<Fee>,
+    pub withdrawal: Option<Fee>,
+}
+
+impl FeeSchedule {
+    /// The fee for a transaction type, if this schedule sets one
+    pub fn get(&self, trans: &TransType) -> Option<&Fee> {
+        match trans {
+            TransType::Deposit => self.deposit.as_ref(),
+            TransType::Withdrawal => self.withdrawal.as_ref(),
+            TransType::Dispute
+            | TransType::Resolve
+            | TransType::Chargeback
+            | TransType::OpenAccount
+            | TransType::CloseAccount
+            | TransType::Other(_) => None,
+        }
+    }
+}
+
+/// A flat fee plus a percentage of the transaction amount
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fee {
+    pub flat: Decimal,
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// The fee for an `amount`, rounded to four decimal places
+    pub fn on(&self, amount: Decimal) -> Decimal {
+        (self.flat + amount * self.percent / Decimal::ONE_HUNDRED)
+            .round_dp(4)
+            .normalize()
+    }
+}
+
+impl Config {
+    /// The tier name of every client placed in a tier
+    pub fn client_tiers(&self) -> BTreeMap<ClientId, String> {
+        self.tiers
+            .iter()
+            .flat_map(|(name, tier)| tier.clients.iter().map(|&client| (client, name.clone())))
+            .collect()
+    }
+
+    /// The fee a client in `tier` pays on a transaction, zero when none is set
+    pub fn fee(&self, tier: Option<&str>, trans: &TransType, amount: Decimal) -> Decimal {
+        tier.and_then(|name| self.tiers.get(name))
+            .and_then(|tier| tier.fees.get(trans))
+            .or_else(|| self.fees.get(trans))
+            .map_or(Decimal::ZERO, |fee| fee.on(amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    const CONFIG: &str = r#"
+[fees]
+deposit = { flat = "0.10" }
+withdrawal = { flat = "0.25", percent = "0.5" }
+
+[tiers.gold]
+clients = [1, 7]
+fees.withdrawal = { percent = "0.1" }
+"#;
+
+    #[test]
+    fn test_config_fees() -> Result<()> {
+        let config: Config = toml::from_str(CONFIG)?;
+        let tiers = config.client_tiers();
+        assert_eq!(tiers[&7], "gold");
+
+        let fee = |client, trans, amount| {
+            config.fee(tiers.get(&client).map(String::as_str), &trans, amount)
+        };
+        assert_eq!(fee(2, TransType::Deposit, dec!(50)), dec!(0.1));
+        assert_eq!(fee(2, TransType::Withdrawal, dec!(100)), dec!(0.75));
+        // Gold clients get their own withdrawal fee but the standard deposit fee
+        assert_eq!(fee(1, TransType::Withdrawal, dec!(100)), dec!(0.1));
+        assert_eq!(fee(1, TransType::Deposit, dec!(50)), dec!(0.1));
+        assert_eq!(fee(1, TransType::Dispute, dec!(50)), dec!(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_credit_lines() -> Result<()> {
+        let config: Config = toml::from_str("[[credit]]\nclient = 3\nlimit = \"100\"")?;
+        let csv = read_credit_lines("client, limit\n3, 100\n".as_bytes())?;
+        assert_eq!(config.credit, csv);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_unknown_field() {
+        assert!(toml::from_str::<Config>("[fees]\nrefund = { flat = 1 }").is_err());
+    }
+}
@@ -0,0 +1,663 @@
+//! `tte consume`, applying transactions from a message broker as they arrive
+//!
+//! Messages are applied in batches. After each batch the engine state is
+//! saved to the state file, and only then are the messages acknowledged, so
+//! an applied transaction is never lost. Messages redelivered after a crash
+//! between the two are recognised by their sequence in the source and skipped.
+//! Sources without a sequence, or publishers that send a message twice, are
+//! covered by a dedupe window: the keys of the last messages applied, saved
+//! with the state.
+//! Messages that are no valid transaction, or that the engine rejects, are
+//! handed back to the source to dead-letter.
+//!
+//! Consumers scale out by client: each applies the transactions of the
+//! clients its [Shard] owns and acknowledges the others untouched.
+//!
+//! A consumer left running can close a period every day at a set time, see
+//! [Schedule].
+use crate::health::Health;
+use crate::otel::{self, SpanId, Trace, Tracer};
+use crate::shard::Shard;
+use crate::{replace_file, write_period};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use csv::StringRecord;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tte::event::Event;
+use tte::{AccountView, ClientId, Config, Engine, ReaderOptions, Reason, Transaction};
+
+/// One transaction as delivered by a broker
+#[derive(Clone)]
+pub struct Message {
+    /// What the source acknowledges the message by
+    pub id: String,
+    /// Where the message sits in the source, increasing from one message to
+    /// the next, for sources that have such a thing
+    pub seq: Option<(u64, u64)>,
+    /// Field names and values, e.g. `type` and `deposit`, or why the
+    /// message could not be read
+    pub fields: Result<Vec<(String, String)>, String>,
+}
+
+impl Message {
+    /// Reads the transaction the way a CSV row with these columns is read
+    fn decode(&self) -> Result<Transaction, String> {
+        let fields = self.fields.as_ref()?;
+        let headers: StringRecord = fields.iter().map(|(name, _)| name.trim()).collect();
+        let record: StringRecord = fields.iter().map(|(_, value)| value.trim()).collect();
+        ReaderOptions::default()
+            .decode(&record, &headers)
+            .map_err(|e| e.to_string())
+    }
+
+    /// What the dedupe window knows the message by
+    fn key(&self, transaction: &Transaction) -> String {
+        let idempotency_key = self.fields.as_ref().ok().and_then(|fields| {
+            fields
+                .iter()
+                .find(|(name, _)| name.trim() == "idempotency_key")
+                .map(|(_, value)| value.trim())
+                .filter(|value| !value.is_empty())
+        });
+        match idempotency_key {
+            Some(key) => key.to_string(),
+            None => format!("{} {}", transaction.trans, transaction.tx),
+        }
+    }
+}
+
+/// A broker the consumer reads from
+pub trait Source {
+    /// Up to `max` messages, waiting a short while when there are none
+    fn poll(&mut self, max: usize) -> Result<Vec<Message>>;
+    /// Acknowledges messages that are applied and saved
+    fn ack(&mut self, messages: &[Message]) -> Result<()>;
+    /// Gives up on a message that is no valid transaction, or that the engine
+    /// rejected. Brokers without a place for such messages acknowledge it.
+    fn reject(&mut self, message: &Message, reason: &str) -> Result<()> {
+        let _ = reason;
+        self.ack(std::slice::from_ref(message))
+    }
+    /// Hands on the accounts a batch touched, after it is saved and before
+    /// it is acknowledged
+    fn publish(&mut self, _accounts: &[AccountView]) -> Result<()> {
+        Ok(())
+    }
+    /// How many messages the broker holds for this consumer that it has not
+    /// read yet, for brokers that tell
+    fn lag(&mut self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct State<E, W> {
+    last_seq: Option<(u64, u64)>,
+    engine: E,
+    /// The dedupe window, oldest key first
+    #[serde(default)]
+    window: W,
+}
+
+/// The keys of the last `size` messages applied
+#[derive(Default)]
+struct Window {
+    size: usize,
+    order: VecDeque<String>,
+    keys: HashSet<String>,
+}
+
+impl Window {
+    fn new(size: usize, mut order: VecDeque<String>) -> Self {
+        order.drain(..order.len().saturating_sub(size));
+        let keys = order.iter().cloned().collect();
+        Window { size, order, keys }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    fn insert(&mut self, key: String) {
+        if self.size == 0 || !self.keys.insert(key.clone()) {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.size {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn read_state(r: impl io::Read) -> serde_json::Result<State<Engine, VecDeque<String>>> {
+    serde_json::from_reader(io::BufReader::new(r))
+}
+
+/// The engine in a state file saved by a consumer
+pub fn read_engine(r: impl io::Read) -> Result<Engine> {
+    Ok(read_state(r)?.engine)
+}
+
+/// Closes a period every day at a time of day, in UTC, and writes it to a
+/// directory. The period is named by the date it closes on, e.g.
+/// `2026-10-16`. Days the consumer was not running are closed when it starts
+/// again, each as of its own closing time.
+pub struct Schedule {
+    dir: PathBuf,
+    next: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// Starts with the first closing time after the last period the engine
+    /// closed, or after now if it never closed one
+    pub fn new(at: NaiveTime, dir: PathBuf, engine: &Engine) -> Self {
+        let since = engine
+            .last_period_closed_at()
+            .unwrap_or_else(|| SystemTime::now().into());
+        let mut next = since.date_naive().and_time(at).and_utc();
+        if next <= since {
+            next = next + Days::new(1);
+        }
+        Schedule { dir, next }
+    }
+}
+
+/// Handed the engine and the events of a saved batch
+pub type Notify = Box<dyn FnMut(&Engine, &[Event]) -> Result<()>>;
+
+pub struct Consumer {
+    pub engine: Engine,
+    last_seq: Option<(u64, u64)>,
+    shard: Shard,
+    window: Window,
+    /// Each handed the engine and the events of every batch once it is
+    /// saved, if the engine records them. Events saved with a batch but not
+    /// yet handed over when the consumer stopped go out with the next batch.
+    pub notify: Vec<Notify>,
+    /// Told how the consumer keeps up, for the readiness probe
+    pub health: Option<Arc<Health>>,
+    /// Where every batch is traced to
+    pub tracer: Option<Tracer>,
+    /// When periods are closed
+    pub schedule: Option<Schedule>,
+}
+
+impl Consumer {
+    pub fn new(engine: Engine, shard: Shard) -> Self {
+        Consumer {
+            engine,
+            last_seq: None,
+            shard,
+            window: Window::default(),
+            notify: Vec::new(),
+            health: None,
+            tracer: None,
+            schedule: None,
+        }
+    }
+
+    /// Skips messages whose key is among the last `size` applied. The key is
+    /// the message's `idempotency_key` field, or else its type and tx.
+    pub fn dedupe(&mut self, size: usize) {
+        self.window = Window::new(size, std::mem::take(&mut self.window.order));
+    }
+
+    /// Carries on from the state saved by an earlier run
+    pub fn resume(path: &Path, config: Config, shard: Shard) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+        let state =
+            read_state(file).with_context(|| format!("invalid state {}", path.display()))?;
+        let mut engine = state.engine;
+        engine.configure(config);
+        Ok(Consumer {
+            engine,
+            last_seq: state.last_seq,
+            shard,
+            window: Window::new(state.window.len(), state.window),
+            notify: Vec::new(),
+            health: None,
+            tracer: None,
+            schedule: None,
+        })
+    }
+
+    /// Applies batches of up to `batch` messages, saving the state to `path`
+    /// after each, until `stop` says otherwise
+    pub fn run(
+        &mut self,
+        source: &mut dyn Source,
+        path: &Path,
+        batch: usize,
+        stop: impl Fn() -> bool,
+    ) -> Result<()> {
+        if let Some(health) = &self.health {
+            health.set_position(self.engine.position());
+        }
+        while !stop() {
+            self.close_periods(path)?;
+            let polled = SystemTime::now();
+            let messages = source.poll(batch)?;
+            if let Some(health) = &self.health {
+                if health.lag_due() {
+                    // Only the probe goes without it, so the run carries on
+                    let lag = source.lag().unwrap_or_else(|e| {
+                        warn!("could not get the lag: {e:#}");
+                        None
+                    });
+                    health.set_lag(lag);
+                }
+                match messages.len() {
+                    0 => health.caught_up(),
+                    len => health.applying(len),
+                }
+            }
+            if messages.is_empty() {
+                continue;
+            }
+            let mut trace = self.tracer.as_mut().map(|tracer| tracer.begin(polled));
+            let read = messages.len();
+            let stage = |trace: &mut Option<Trace>, name: &str, start: SystemTime| {
+                if let Some(trace) = trace {
+                    let (span, root) = (trace.id(), trace.root);
+                    trace.span(
+                        span,
+                        Some(root),
+                        name,
+                        (start, SystemTime::now()),
+                        Vec::new(),
+                    );
+                }
+            };
+            stage(&mut trace, "poll", polled);
+            let applying = SystemTime::now();
+            let apply_span = trace.as_mut().map(Trace::id);
+            let mut touched = BTreeSet::new();
+            let mut valid = Vec::with_capacity(messages.len());
+            let mut invalid = Vec::new();
+            for message in messages {
+                let sampled = trace.as_mut().is_some_and(Trace::sampled);
+                let traced = trace.as_mut().filter(|_| sampled).zip(apply_span);
+                match self.apply(&message, traced)? {
+                    Delivery::Applied(client) => {
+                        touched.insert(client);
+                        valid.push(message);
+                    }
+                    Delivery::NotOurs => valid.push(message),
+                    Delivery::Invalid(reason) => invalid.push((message, reason)),
+                }
+            }
+            if let (Some(trace), Some(span)) = (&mut trace, apply_span) {
+                let root = trace.root;
+                let during = (applying, SystemTime::now());
+                trace.span(span, Some(root), "apply", during, Vec::new());
+            }
+            let saving = SystemTime::now();
+            self.save(path)?;
+            stage(&mut trace, "save", saving);
+            if let Some(health) = &self.health {
+                health.caught_up();
+                health.set_position(self.engine.position());
+            }
+            let accounts: Vec<AccountView> = touched
+                .into_iter()
+                .filter_map(|client| self.engine.account(client))
+                .collect();
+            source.publish(&accounts)?;
+            // Taken even without anyone to tell, so the saved state stays small
+            let events = self.engine.take_events();
+            for notify in &mut self.notify {
+                // The batch is saved, so a failed notification stops nothing
+                if let Err(e) = notify(&self.engine, &events) {
+                    warn!("{e:#}");
+                }
+            }
+            let acking = SystemTime::now();
+            source.ack(&valid)?;
+            for (message, reason) in &invalid {
+                source.reject(message, reason)?;
+            }
+            stage(&mut trace, "ack", acking);
+            if let (Some(tracer), Some(trace)) = (&mut self.tracer, trace) {
+                let attributes = vec![
+                    ("tte.messages", otel::int(read as u64)),
+                    ("tte.invalid", otel::int(invalid.len() as u64)),
+                ];
+                tracer.finish(trace, attributes);
+            }
+            // Only once the batch is saved, so a client is always in the
+            // saved state or in its archive file
+            self.engine.archive_idle()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        replace_file(path, |w| {
+            let state = State {
+                last_seq: self.last_seq,
+                engine: &self.engine,
+                window: &self.window.order,
+            };
+            Ok(serde_json::to_writer(w, &state)?)
+        })
+    }
+
+    /// Closes the periods whose time has come, writing each before the state
+    /// is saved. Should the consumer stop in between, the period is closed
+    /// again on the next run and found already written with the same
+    /// accounts.
+    fn close_periods(&mut self, path: &Path) -> Result<()> {
+        while let Some(schedule) = &self.schedule {
+            let at = schedule.next;
+            if at > DateTime::<Utc>::from(SystemTime::now()) {
+                break;
+            }
+            let period = self.engine.close_period(&at.date_naive().to_string(), at)?;
+            write_period(&schedule.dir, &period)?;
+            if let Some(schedule) = &mut self.schedule {
+                schedule.next = at + Days::new(1);
+            }
+            self.save(path)?;
+        }
+        Ok(())
+    }
+
+    /// Applies one message, tracing it under `parent` if given. A redelivered
+    /// message changes nothing but still counts as applied, so the update lost
+    /// with the earlier delivery goes out again.
+    fn apply(
+        &mut self,
+        message: &Message,
+        trace: Option<(&mut Trace, SpanId)>,
+    ) -> Result<Delivery> {
+        let start = SystemTime::now();
+        let (transaction, key) = match self.admit(message) {
+            Ok(admitted) => admitted,
+            Err(delivery) => return Ok(delivery),
+        };
+        let admitted = SystemTime::now();
+        let (client, tx) = (transaction.client, transaction.tx);
+        let rejections = self.engine.rejections().len();
+        self.engine.apply(transaction)?;
+        if let Some((trace, parent)) = trace {
+            let span = trace.id();
+            let attributes = vec![
+                ("tte.client", otel::int(client)),
+                ("tte.tx", otel::string(tx)),
+            ];
+            let end = SystemTime::now();
+            trace.span(span, Some(parent), "transaction", (start, end), attributes);
+            let validate = trace.id();
+            trace.span(
+                validate,
+                Some(span),
+                "validate",
+                (start, admitted),
+                Vec::new(),
+            );
+            let engine = trace.id();
+            trace.span(engine, Some(span), "engine", (admitted, end), Vec::new());
+        }
+        Ok(match self.engine.rejections().get(rejections) {
+            Some(rejection) => Delivery::Invalid(rejection.violation.to_string()),
+            None => {
+                self.window.insert(key);
+                Delivery::Applied(client)
+            }
+        })
+    }
+
+    /// The transaction of a message for the engine to apply, and its dedupe
+    /// key, or what became of a message it is not for
+    fn admit(&mut self, message: &Message) -> Result<(Transaction, String), Delivery> {
+        let redelivered = message.seq.is_some() && message.seq <= self.last_seq;
+        if message.seq.is_some() {
+            self.last_seq = self.last_seq.max(message.seq);
+        }
+        let transaction = match message.decode() {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                let reason = format!("{}: {e}", Reason::Malformed);
+                warn!("message {}: {reason}", message.id);
+                return Err(Delivery::Invalid(reason));
+            }
+        };
+        let client = transaction.client;
+        if !self.shard.owns(client) {
+            return Err(Delivery::NotOurs);
+        }
+        if redelivered {
+            return Err(Delivery::Applied(client));
+        }
+        let key = message.key(&transaction);
+        if self.window.contains(&key) {
+            warn!("message {}: {key} was applied already", message.id);
+            return Err(Delivery::Applied(client));
+        }
+        Ok((transaction, key))
+    }
+}
+
+/// What became of a message
+enum Delivery {
+    /// Applied to the account of this client
+    Applied(ClientId),
+    /// For a client of another shard
+    NotOurs,
+    /// Not a transaction, or rejected by the engine for this reason
+    Invalid(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Hands out its messages a batch at a time, then asks to stop
+    struct Queue {
+        batches: VecDeque<Vec<Message>>,
+        acked: Vec<String>,
+        rejected: Vec<String>,
+        published: Vec<(ClientId, String)>,
+    }
+
+    impl Source for Queue {
+        fn poll(&mut self, _max: usize) -> Result<Vec<Message>> {
+            Ok(self.batches.pop_front().unwrap_or_default())
+        }
+
+        fn ack(&mut self, messages: &[Message]) -> Result<()> {
+            self.acked.extend(messages.iter().map(|m| m.id.clone()));
+            Ok(())
+        }
+
+        fn reject(&mut self, message: &Message, _reason: &str) -> Result<()> {
+            self.rejected.push(message.id.clone());
+            Ok(())
+        }
+
+        fn publish(&mut self, accounts: &[AccountView]) -> Result<()> {
+            self.published
+                .extend(accounts.iter().map(|a| (a.client, a.total.to_string())));
+            Ok(())
+        }
+    }
+
+    fn message(seq: u64, row: &str) -> Message {
+        let fields = ["type", "client", "tx", "amount"]
+            .iter()
+            .zip(row.split(','))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Message {
+            id: seq.to_string(),
+            seq: Some((seq, 0)),
+            fields: Ok(fields),
+        }
+    }
+
+    #[test]
+    fn test_consume() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tte-consume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("state.json");
+        let mut queue = Queue {
+            batches: VecDeque::from([
+                vec![message(1, "deposit,1,1,2.0"), message(2, "deposit,2,2,5.0")],
+                vec![message(3, "withdrawal,1,3,0.5"), message(4, "bogus,x,4,")],
+            ]),
+            acked: Vec::new(),
+            rejected: Vec::new(),
+            published: Vec::new(),
+        };
+        let mut consumer = Consumer::new(Engine::new(), "1/2".parse()?);
+        consumer.dedupe(10);
+        let notified = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = notified.clone();
+        consumer.engine.record_events();
+        consumer.notify.push(Box::new(move |_, events| {
+            counter.set(counter.get() + events.len());
+            Ok(())
+        }));
+        let batches = std::cell::Cell::new(0);
+        let stop = || {
+            batches.set(batches.get() + 1);
+            batches.get() > 2
+        };
+        consumer.run(&mut queue, &path, 10, stop)?;
+        assert_eq!(queue.acked, ["1", "2", "3"]);
+        assert_eq!(queue.rejected, ["4"]);
+        assert_eq!(queue.published, [(1, "2.0".into()), (1, "1.5".into())]);
+        assert_eq!(notified.get(), 2, "the events of client 1's transactions");
+        let snapshot = consumer.engine.snapshot();
+        assert_eq!(
+            snapshot.keys().collect::<Vec<_>>(),
+            [&1],
+            "client 2 is shard 0's"
+        );
+        assert_eq!(snapshot[&1].total.to_string(), "1.5");
+
+        // A crash before the ack redelivers the last batch
+        let mut consumer = Consumer::resume(&path, Config::default(), "1/2".parse()?)?;
+        queue.batches = VecDeque::from([vec![message(3, "withdrawal,1,3,0.5")]]);
+        batches.set(0);
+        consumer.run(&mut queue, &path, 10, stop)?;
+        assert_eq!(consumer.engine.snapshot()[&1].total.to_string(), "1.5");
+        assert_eq!(queue.published.last(), Some(&(1, "1.5".into())));
+
+        // Without a sequence it takes the saved dedupe window to catch it
+        let mut consumer = Consumer::resume(&path, Config::default(), "1/2".parse()?)?;
+        let mut unsequenced = message(5, "withdrawal,1,3,0.5");
+        unsequenced.seq = None;
+        queue.batches = VecDeque::from([vec![unsequenced]]);
+        batches.set(0);
+        consumer.run(&mut queue, &path, 10, stop)?;
+        assert_eq!(consumer.engine.snapshot()[&1].total.to_string(), "1.5");
+        assert_eq!(queue.acked.last().map(String::as_str), Some("5"));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_close_periods() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tte-periods-{}", std::process::id()));
+        let path = dir.join("state.json");
+        let mut consumer = Consumer::new(Engine::new(), "0/1".parse()?);
+        let long_ago = DateTime::<Utc>::from(SystemTime::now()) - Days::new(2);
+        consumer.engine.close_period("first", long_ago)?;
+        let at = long_ago.time();
+        consumer.schedule = Some(Schedule::new(at, dir.join("periods"), &consumer.engine));
+        let mut queue = Queue {
+            batches: VecDeque::from([vec![message(1, "deposit,1,1,2.0")]]),
+            acked: Vec::new(),
+            rejected: Vec::new(),
+            published: Vec::new(),
+        };
+        let batches = std::cell::Cell::new(0);
+        let stop = || {
+            batches.set(batches.get() + 1);
+            batches.get() > 2
+        };
+        consumer.run(&mut queue, &path, 10, stop)?;
+        let written = std::fs::read_dir(dir.join("periods"))?.count();
+        assert_eq!(written, 2, "the two days missed");
+        let today = (long_ago + Days::new(2)).date_naive().to_string();
+        let period = std::fs::File::open(dir.join("periods").join(format!("{today}.json")))?;
+        let period = tte::period::read_period(period)?;
+        assert_eq!(period.transactions, 0, "closed before the deposit");
+        assert_eq!(
+            consumer.engine.last_period_closed_at(),
+            Some(long_ago + Days::new(2))
+        );
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn test_dedupe_faulty_delivery() -> Result<()> {
+        let messages: Vec<Message> = (1..=60)
+            .map(|tx| {
+                let mut message = message(tx, &format!("deposit,{},{tx},1.0", tx % 4));
+                message.seq = None;
+                message
+            })
+            .collect();
+        let mut clean = Engine::new();
+        for message in &messages {
+            clean.apply(message.decode().map_err(anyhow::Error::msg)?)?;
+        }
+
+        let faults = tte::fault::Faults {
+            seed: 3,
+            duplicate: 0.3,
+            max_delay: 8,
+            ..Default::default()
+        };
+        let delivered: Vec<Message> = faults.stream(messages.into_iter()).collect();
+        assert!(delivered.len() > 60);
+        let path = std::env::temp_dir().join(format!("tte-faulty-{}.json", std::process::id()));
+        let mut queue = Queue {
+            batches: delivered.chunks(7).map(<[Message]>::to_vec).collect(),
+            acked: Vec::new(),
+            rejected: Vec::new(),
+            published: Vec::new(),
+        };
+        let mut consumer = Consumer::new(Engine::new(), "0/1".parse()?);
+        consumer.dedupe(16);
+        let batches = std::cell::Cell::new(0);
+        let count = queue.batches.len();
+        consumer.run(&mut queue, &path, 7, || {
+            batches.set(batches.get() + 1);
+            batches.get() > count
+        })?;
+        assert_eq!(consumer.engine.snapshot(), clean.snapshot());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[cfg(any(feature = "nats", feature = "amqp"))]
+    #[test]
+    fn test_json_fields() {
+        assert_eq!(
+            crate::json_fields(br#"{"type": "deposit", "client": 1, "amount": "1.5", "tx": null}"#),
+            Ok(vec![
+                ("amount".into(), "1.5".into()),
+                ("client".into(), "1".into()),
+                ("tx".into(), "".into()),
+                ("type".into(), "deposit".into())
+            ])
+        );
+        assert!(crate::json_fields(br#"{"client": [1]}"#).is_err());
+        assert!(crate::json_fields(b"[1]").is_err());
+    }
+}
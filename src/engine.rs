@@ -0,0 +1,2287 @@
+//! The transaction engine that owns every client account
+//!
+//! The engine is a deterministic fold over the transaction stream: replaying
+//! the same input always produces the same accounts, so the balances at any
+//! earlier point can be recovered by replaying the stream up to that point.
+use crate::adjust::Adjustment;
+use crate::aml::{Flag, Monitor};
+use crate::amount::{from_decimal, to_decimal, Amount};
+use crate::archive::Archive;
+use crate::client::{AccountView, Client, Clients, Outcome, TxRecord};
+use crate::config::{Config, DisputeExpiry, NegativeAvailable};
+use crate::enrich::{self, Enricher, Metadata};
+use crate::event::Event;
+use crate::handler::TransactionHandler;
+use crate::ledger::Ledger;
+use crate::middleware::{self, Context, Decision, Middleware};
+use crate::period::{self, Period, Periods};
+use crate::risk::{Rejection, RiskState, Violation};
+use crate::score::Scores;
+use crate::settlement::Position;
+use crate::snapshot::{Account, Snapshot};
+use crate::source::TransactionSource;
+use crate::spill::Spill;
+use crate::transaction::{parse_tx_id, ClientId, TransType, Transaction, TxId};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration as StdDuration, Instant};
+
+/// A point in the transaction stream at which [Engine::replay] stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Stop once the deposit or withdrawal with this `tx` id has been applied
+    Tx(TxId),
+    /// Stop before the first transaction stamped later than this time.
+    /// Transactions without a timestamp are always applied.
+    Timestamp(DateTime<Utc>),
+}
+
+/// Parses either a numeric `tx` id or an RFC 3339 timestamp
+/// ```text
+/// 1234
+/// 2022-03-21T12:00:00Z
+/// ```
+impl FromStr for AsOf {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(tx) = parse_tx_id(s) {
+            Ok(AsOf::Tx(tx))
+        } else if let Ok(timestamp) = s.parse::<DateTime<Utc>>() {
+            Ok(AsOf::Timestamp(timestamp))
+        } else {
+            Err(anyhow!(
+                "'{s}' is neither a tx id nor an RFC 3339 timestamp"
+            ))
+        }
+    }
+}
+
+/// Something done to an account other than by applying a transaction as given
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum AuditEvent {
+    /// The freeze policy locked the account, for the given reason
+    Frozen(String),
+    /// The dispute of the transaction expired and was resolved
+    DisputeExpired,
+    /// An [crate::enrich::Enricher] changed these columns, as `old -> new`,
+    /// or attached these fields
+    Enriched(Metadata),
+    /// The account of this client was merged into it, see
+    /// [Engine::merge_accounts]
+    Merged(ClientId),
+    /// A manual correction moved `amount` into the available funds, see
+    /// [Engine::adjust]
+    Adjusted { amount: Decimal, reason: String },
+    /// Locked by an administrative operation, see [Engine::lock]
+    Locked,
+    /// Unlocked by an administrative operation, see [Engine::unlock]
+    Unlocked,
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditEvent::Frozen(reason) => write!(f, "frozen: {reason}"),
+            AuditEvent::DisputeExpired => write!(f, "dispute expired"),
+            AuditEvent::Enriched(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{name} {value}"))
+                    .collect();
+                write!(f, "enriched: {}", fields.join("; "))
+            }
+            AuditEvent::Merged(from) => write!(f, "merged from client {from}"),
+            AuditEvent::Adjusted { amount, reason } => write!(f, "adjusted by {amount}: {reason}"),
+            AuditEvent::Locked => write!(f, "locked"),
+            AuditEvent::Unlocked => write!(f, "unlocked"),
+        }
+    }
+}
+
+/// An [AuditEvent] with the client and the transaction, or operation, that
+/// triggered it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub event: AuditEvent,
+}
+
+/// The log target decisions for traced clients and transactions are logged
+/// under, see [Engine::trace_client]
+pub const TRACE: &str = "tte::trace";
+
+fn trace(client: ClientId, tx: TxId, decision: fmt::Arguments) {
+    info!(target: TRACE, "client:{client} tx:{tx}: {decision}");
+}
+
+/// Which invariant of [Engine::check_invariants] a transaction broke, going
+/// from the account `before` it to the one `after`
+fn broken_invariant(trans: &TransType, before: &Account, after: &Account) -> Option<&'static str> {
+    if after.total != after.available + after.held {
+        Some("total is not available plus held")
+    } else if after.held < Decimal::ZERO {
+        Some("held is negative")
+    } else if before.locked && !after.locked {
+        Some("a locked account was unlocked")
+    } else if before.locked
+        && matches!(trans, TransType::Deposit | TransType::Withdrawal)
+        && before != after
+    {
+        Some("a deposit or withdrawal changed a locked account")
+    } else {
+        None
+    }
+}
+
+/// A dispute as it was opened, see [crate::config::DisputeExpiry]
+#[derive(Debug, Deserialize, Serialize)]
+struct OpenDispute {
+    client: ClientId,
+    tx: TxId,
+    /// Which dispute of the transaction this is
+    round: u32,
+    offered: u64,
+    at: Option<DateTime<Utc>>,
+}
+
+/// Holds all of the [Client] accounts keyed by client id
+///
+/// Serializing the engine captures the complete state needed to carry on
+/// processing later, not just the balances in the report. The [Config] is not
+/// part of that state and has to be given again with [Engine::configure].
+#[derive(Default, Deserialize, Serialize)]
+pub struct Engine {
+    clients: Clients,
+    /// The fees account every charged fee is moved into
+    #[serde(default)]
+    fees: Amount,
+    /// Everything chargebacks took back, see [Engine::position]
+    #[serde(default)]
+    chargeback_losses: Amount,
+    /// Running totals for the risk limits
+    #[serde(default)]
+    risk: RiskState,
+    #[serde(default)]
+    rejections: Vec<Rejection>,
+    /// Activity counters for the AML rules
+    #[serde(default)]
+    aml: Monitor,
+    #[serde(default)]
+    audit: Vec<AuditEntry>,
+    /// Transactions offered so far, what dispute expiry counts in
+    #[serde(default)]
+    offered: u64,
+    /// Disputes in the order they were opened, kept for expiry
+    #[serde(default)]
+    open_disputes: VecDeque<OpenDispute>,
+    /// Only kept once [Engine::record_events] is called
+    #[serde(default)]
+    events: Option<Vec<Event>>,
+    /// Only kept once [Engine::keep_journal] is called
+    #[serde(default)]
+    ledger: Option<Ledger>,
+    /// Only kept once [Engine::score_risk] is called
+    #[serde(default)]
+    scores: Option<Scores>,
+    #[serde(skip)]
+    config: Config,
+    #[serde(skip)]
+    client_tiers: BTreeMap<ClientId, String>,
+    #[serde(skip)]
+    credit_limits: HashMap<ClientId, Amount>,
+    #[serde(skip)]
+    reserves: HashMap<ClientId, Amount>,
+    /// Room made for the records of every new client, see [Engine::reserve_for]
+    #[serde(skip)]
+    records_per_client: usize,
+    /// Where records go once too many are in memory, see
+    /// [Engine::spill_records]
+    #[serde(skip)]
+    pub(crate) spill: Option<Spill>,
+    /// Where idle clients go, see [Engine::archive_clients]
+    #[serde(skip)]
+    pub(crate) archive: Option<Archive>,
+    /// Whose decisions are logged, see [Engine::trace_client]
+    #[serde(skip)]
+    traced_clients: HashSet<ClientId>,
+    #[serde(skip)]
+    traced_txs: HashSet<TxId>,
+    /// Whether every transaction is checked, see [Engine::check_invariants]
+    #[serde(skip)]
+    invariants: bool,
+    /// Whether the report has the activity columns, see [Engine::extend_report]
+    #[serde(skip)]
+    extended: bool,
+    /// How many transactions of each custom type had no handler
+    #[serde(default)]
+    unknown_types: BTreeMap<String, u64>,
+    /// Clients merged away, with the client whose transactions they now are,
+    /// see [Engine::merge_accounts]
+    #[serde(default)]
+    merged: BTreeMap<ClientId, ClientId>,
+    /// See [Engine::close_period]
+    #[serde(default)]
+    periods: Periods,
+    /// The balances of the clients in the archive rather than in memory
+    #[serde(default)]
+    archived: Position,
+    /// The registry of custom transaction types
+    #[serde(skip)]
+    handlers: HashMap<String, Box<dyn TransactionHandler + Send>>,
+    /// Built from [Config::enrich]
+    #[serde(skip)]
+    configured_enrichers: Vec<Box<dyn Enricher + Send>>,
+    /// Added with [Engine::add_enricher], run after the configured ones
+    #[serde(skip)]
+    enrichers: Vec<Box<dyn Enricher + Send>>,
+    /// Built from [Config::middleware]
+    #[serde(skip)]
+    configured: Vec<Box<dyn Middleware + Send>>,
+    /// Added with [Engine::add_middleware], run after the configured ones
+    #[serde(skip)]
+    middleware: Vec<Box<dyn Middleware + Send>>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// An engine that applies the policies in `config`
+    pub fn with_config(config: Config) -> Engine {
+        let mut engine = Engine::default();
+        engine.configure(config);
+        engine
+    }
+
+    /// Replaces the policies, e.g. on an engine restored from saved state.
+    /// Credit lines and reserves only apply to clients created from now on.
+    pub fn configure(&mut self, config: Config) {
+        self.client_tiers = config.client_tiers();
+        self.credit_limits = config
+            .credit
+            .iter()
+            .map(|line| (line.client, from_decimal(line.limit)))
+            .collect();
+        self.reserves = config
+            .reserve
+            .clients
+            .iter()
+            .map(|reserve| (reserve.client, from_decimal(reserve.min_balance)))
+            .collect();
+        self.configured_enrichers = config.enrich.iter().map(enrich::Builtin::build).collect();
+        self.configured = config
+            .middleware
+            .iter()
+            .map(middleware::Builtin::build)
+            .collect();
+        self.config = config;
+    }
+
+    /// Applies transactions of type `name` with `handler` from now on,
+    /// replacing any handler registered for it before. The built in types
+    /// cannot be overridden.
+    pub fn register_handler(
+        &mut self,
+        name: impl Into<String>,
+        handler: impl TransactionHandler + Send + 'static,
+    ) {
+        self.handlers.insert(name.into(), Box::new(handler));
+    }
+
+    /// Has `enricher` rewrite every transaction before the middleware, after
+    /// the enrichers added before and any from [Config::enrich]
+    pub fn add_enricher(&mut self, enricher: impl Enricher + Send + 'static) {
+        self.enrichers.push(Box::new(enricher));
+    }
+
+    /// Runs every transaction through `middleware` before it is applied, after
+    /// the middleware added before and any from [Config::middleware]
+    pub fn add_middleware(&mut self, middleware: impl Middleware + Send + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Starts the clients in `accounts` from their reported balances, e.g.
+    /// yesterday's report, replacing any state they had. Their earlier
+    /// transactions are unknown, so those cannot be disputed.
+    pub fn seed(&mut self, accounts: &Snapshot) -> Result<()> {
+        for (&id, account) in accounts {
+            if account.available + account.held != account.total {
+                return Err(anyhow!(
+                    "client {id}: total {} is not available {} plus held {}",
+                    account.total,
+                    account.available,
+                    account.held
+                ));
+            }
+            if let Some(ledger) = &mut self.ledger {
+                ledger.open(id, account.available, account.held);
+            }
+            let credit_limit = self.credit_limits.get(&id).copied();
+            let client = Client::from_account(account, credit_limit.unwrap_or_default());
+            self.clients
+                .insert(id, client.with_reserve(self.reserve(id)));
+        }
+        Ok(())
+    }
+
+    /// Takes in everything `other` found out, e.g. from a file of its own
+    /// applied alongside this engine. Meant for engines with the same config
+    /// that saw different clients, so a client in both is an error. Records
+    /// `other` spilled to disk are not taken in.
+    pub fn merge(&mut self, other: Engine) -> Result<()> {
+        if let Some((id, _)) = other
+            .clients
+            .iter()
+            .find(|(id, _)| self.clients.get(*id).is_some())
+        {
+            return Err(anyhow!("client {id} is in both engines"));
+        }
+        for (id, client) in other.clients.into_entries() {
+            self.clients.insert(id, client);
+        }
+        self.fees += other.fees;
+        self.chargeback_losses += other.chargeback_losses;
+        self.risk.merge(other.risk);
+        self.rejections.extend(other.rejections);
+        self.aml.merge(other.aml);
+        self.audit.extend(other.audit);
+        self.offered += other.offered;
+        // Expiry goes from the front, so the oldest stay first
+        self.open_disputes.extend(other.open_disputes);
+        self.open_disputes
+            .make_contiguous()
+            .sort_by_key(|open| open.offered);
+        if let (Some(events), Some(more)) = (&mut self.events, other.events) {
+            events.extend(more);
+        }
+        if let (Some(ledger), Some(more)) = (&mut self.ledger, other.ledger) {
+            ledger.merge(more);
+        }
+        if let (Some(scores), Some(more)) = (&mut self.scores, other.scores) {
+            scores.merge(more);
+        }
+        for (name, count) in other.unknown_types {
+            *self.unknown_types.entry(name).or_default() += count;
+        }
+        self.merged.extend(other.merged);
+        Ok(())
+    }
+
+    /// Merges the account of client `from` into that of client `into`, for
+    /// duplicate records of one customer. The balances are summed and the
+    /// records of both kept, so either's transactions can still be disputed,
+    /// and the account is locked or closed if either was. Any later
+    /// transaction of `from` goes to `into`. The merge goes by `tx` in the
+    /// audit trail and the events.
+    ///
+    /// Fails, changing nothing, if either client has no account or a dispute
+    /// open, or both have a record of the same `tx`.
+    pub fn merge_accounts(&mut self, into: ClientId, from: ClientId, tx: TxId) -> Result<()> {
+        if into == from {
+            bail!("client {into} cannot be merged into itself");
+        }
+        if self.spill.is_some() {
+            bail!("accounts cannot be merged while records are spilled to disk");
+        }
+        if let Some(archive) = &mut self.archive {
+            archive.load(&mut self.clients, into, &mut self.archived)?;
+            archive.load(&mut self.clients, from, &mut self.archived)?;
+        }
+        let (target, source) = match (self.clients.get(into), self.clients.get(from)) {
+            (Some(target), Some(source)) => (target, source),
+            (None, _) => bail!("client {into} has no account"),
+            (_, None) => bail!("client {from} has no account"),
+        };
+        for (id, client) in [(into, target), (from, source)] {
+            if client.has_open_disputes() {
+                bail!("client {id} has disputes open, settle them before merging");
+            }
+        }
+        if let Some(shared) = target.shared_record(source) {
+            bail!("clients {into} and {from} both have tx {shared}");
+        }
+        let source = self.clients.remove(from).expect("the client is there");
+        self.clients
+            .get_mut(into)
+            .expect("the client is there")
+            .absorb(source);
+        for merged in self.merged.values_mut() {
+            if *merged == from {
+                *merged = into;
+            }
+        }
+        self.merged.insert(from, into);
+        info!("Merged client:{from} into client:{into}");
+        self.audit.push(AuditEntry {
+            client: into,
+            tx,
+            event: AuditEvent::Merged(from),
+        });
+        self.emit(|| Event::AccountsMerged {
+            client: into,
+            tx,
+            from,
+        });
+        Ok(())
+    }
+
+    /// Remembers deposits and withdrawals from an earlier run, exported with
+    /// [Engine::write_records], so the transactions can still be disputed.
+    /// Balances are left alone, see [Engine::seed] for those.
+    pub fn import_records(&mut self, records: impl IntoIterator<Item = TxRecord>) -> Result<()> {
+        for record in records {
+            let credit_limit = self.credit_limits.get(&record.client).copied();
+            let reserve = self.reserve(record.client);
+            self.clients
+                .get_or_insert_with(record.client, || {
+                    Client::with_credit_limit(credit_limit.unwrap_or_default())
+                        .with_reserve(reserve)
+                        .with_records(self.records_per_client)
+                })
+                .add_record(record.tx, from_decimal(record.amount))?;
+        }
+        Ok(())
+    }
+
+    /// Makes room up front for about `clients` clients and `txs` deposits
+    /// and withdrawals spread evenly between them, so a big input does not
+    /// keep growing and rehashing the maps
+    pub fn reserve_for(&mut self, clients: usize, txs: usize) {
+        self.clients.reserve(clients);
+        self.records_per_client = txs.div_ceil(clients.max(1));
+    }
+
+    /// Keeps an [Event] for every transaction from now on
+    pub fn record_events(&mut self) {
+        self.events.get_or_insert_with(Vec::new);
+    }
+
+    /// Posts every balance change to a double-entry [Ledger] from now on. The
+    /// books open with the balances the clients have so far, e.g. seeded or
+    /// restored ones.
+    pub fn keep_journal(&mut self) {
+        if self.ledger.is_some() {
+            return;
+        }
+        let mut ledger = Ledger::default();
+        for (id, client) in self.clients.iter() {
+            ledger.open(id, client.available(), client.held());
+        }
+        self.ledger = Some(ledger);
+    }
+
+    /// Keeps the deposit and withdrawal records in memory under about
+    /// `max_memory` bytes from now on, moving the least recently referenced
+    /// to disk and loading them back when a dispute refers to them. Spilled
+    /// records are left out of [Engine::write_records] and of the serialized
+    /// state.
+    pub fn spill_records(&mut self, max_memory: u64) -> Result<()> {
+        if self.archive.is_some() {
+            return Err(anyhow!(
+                "clients are archived, so records cannot be spilled"
+            ));
+        }
+        let mut spill = Spill::new(max_memory)?;
+        let mut known: Vec<(ClientId, TxId)> = Vec::new();
+        for (id, client) in self.clients.iter() {
+            known.extend(client.records().into_iter().map(|(tx, _)| (id, tx)));
+        }
+        for (id, tx) in known {
+            spill.added(&mut self.clients, id, tx)?;
+        }
+        self.spill = Some(spill);
+        Ok(())
+    }
+
+    /// Lets [Engine::archive_idle] move the clients idle for longer than
+    /// `idle` out of memory to files in `dir`, from where a transaction for
+    /// one reads it back. Clients archived there by an earlier run are taken
+    /// in. Archived clients are left out of the reports and of the serialized
+    /// state, and the flag goes without [Engine::spill_records].
+    pub fn archive_clients(&mut self, dir: impl Into<PathBuf>, idle: StdDuration) -> Result<()> {
+        if self.spill.is_some() {
+            return Err(anyhow!(
+                "records are spilled, so clients cannot be archived"
+            ));
+        }
+        self.archive = Some(Archive::open(dir.into(), idle, &self.clients)?);
+        Ok(())
+    }
+
+    /// Archives the clients idle for too long, see [Engine::archive_clients],
+    /// returning how many
+    pub fn archive_idle(&mut self) -> Result<usize> {
+        match &mut self.archive {
+            Some(archive) => archive.archive(&mut self.clients, Instant::now(), &mut self.archived),
+            None => Ok(0),
+        }
+    }
+
+    /// Logs every decision made for the transactions of `client` at info
+    /// level under the [TRACE] target, with the account before and after
+    pub fn trace_client(&mut self, client: ClientId) {
+        self.traced_clients.insert(client);
+    }
+
+    /// Logs every decision made for the transactions with id `tx`, like
+    /// [Engine::trace_client]
+    pub fn trace_tx(&mut self, tx: TxId) {
+        self.traced_txs.insert(tx);
+    }
+
+    fn traced(&self, client: ClientId, tx: TxId) -> bool {
+        self.traced_clients.contains(&client) || self.traced_txs.contains(&tx)
+    }
+
+    /// Checks the account after every transaction from now on, failing
+    /// [Engine::apply] with the transaction and the account before and after
+    /// once `total` is not `available` plus `held`, `held` is negative, or a
+    /// locked account is unlocked or has a deposit or withdrawal go through.
+    /// Meant for CI and debugging.
+    pub fn check_invariants(&mut self) {
+        self.invariants = true;
+    }
+
+    /// Adds each client's transaction count, deposit and withdrawal totals,
+    /// open disputes and last transaction to the report
+    pub fn extend_report(&mut self) {
+        self.extended = true;
+    }
+
+    /// Keeps the activity of every client from now on, for a `risk_score`
+    /// column in the report
+    pub fn score_risk(&mut self) {
+        self.scores.get_or_insert_with(Scores::default);
+    }
+
+    /// The client activity kept since [Engine::score_risk]
+    pub fn risk_scores(&self) -> Option<&Scores> {
+        self.scores.as_ref()
+    }
+
+    fn emit(&mut self, event: impl FnOnce() -> Event) {
+        if self.events.is_none() && self.ledger.is_none() {
+            return;
+        }
+        let event = event();
+        if let Some(ledger) = &mut self.ledger {
+            ledger.post(&event);
+        }
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+    }
+
+    /// Records a transaction refused before reaching its client
+    fn reject(&mut self, client: ClientId, tx: TxId, violation: Violation) {
+        if self.traced(client, tx) {
+            trace(client, tx, format_args!("rejected: {violation}"));
+        }
+        warn!("Rejected tx:{tx} of client:{client}: {violation}");
+        self.rejections.push(Rejection {
+            client,
+            tx,
+            violation,
+        });
+        if let Some(scores) = &mut self.scores {
+            scores.reject(client);
+        }
+        self.emit(|| Event::TransactionRejected {
+            client,
+            tx,
+            reason: violation.reason().name().to_string(),
+        });
+    }
+
+    /// Runs `transaction` through the enrichers, recording what they did in
+    /// the audit trail
+    fn enrich(&mut self, mut transaction: Transaction) -> Transaction {
+        let before = transaction.clone();
+        let mut metadata = Metadata::new();
+        for enricher in self
+            .configured_enrichers
+            .iter_mut()
+            .chain(&mut self.enrichers)
+        {
+            enricher.enrich(&mut transaction, &mut metadata);
+        }
+        let changes = enrich::changes(&before, &transaction, metadata);
+        if !changes.is_empty() {
+            debug!("Enriched to {:?}", transaction);
+            self.audit.push(AuditEntry {
+                client: transaction.client,
+                tx: transaction.tx,
+                event: AuditEvent::Enriched(changes),
+            });
+        }
+        transaction
+    }
+
+    /// Runs `transaction` through the middleware, returning it unless it is
+    /// skipped or rejected
+    fn intercept(&mut self, mut transaction: Transaction) -> Option<Transaction> {
+        for middleware in self.configured.iter_mut().chain(&mut self.middleware) {
+            let (client, tx) = (transaction.client, transaction.tx);
+            let mut context = Context {
+                account: self.clients.get(client),
+                offered: self.offered,
+            };
+            match middleware.check(transaction, &mut context) {
+                Decision::Continue(checked) => transaction = checked,
+                Decision::Skip => {
+                    if self.traced(client, tx) {
+                        trace(client, tx, format_args!("skipped by a middleware"));
+                    }
+                    return None;
+                }
+                Decision::Reject(reason) => {
+                    self.reject(client, tx, Violation::Refused(reason));
+                    return None;
+                }
+            }
+        }
+        Some(transaction)
+    }
+
+    /// Applies a single transaction, creating the client on first reference.
+    /// A transaction breaking a risk limit, of a custom type without a
+    /// handler, or for an account never opened under
+    /// [Config::strict_onboarding], is not applied but recorded in
+    /// [Engine::rejections], as is one a middleware rejects.
+    pub fn apply(&mut self, mut transaction: Transaction) -> Result<()> {
+        debug!("{:?}", transaction);
+        if !self.configured_enrichers.is_empty() || !self.enrichers.is_empty() {
+            transaction = self.enrich(transaction);
+        }
+        if let Some(&into) = self.merged.get(&transaction.client) {
+            transaction.client = into;
+        }
+        let traced = self.traced(transaction.client, transaction.tx);
+        if traced {
+            trace(
+                transaction.client,
+                transaction.tx,
+                format_args!("offered {transaction:?}"),
+            );
+        }
+        if let Some(archive) = &mut self.archive {
+            archive.load(&mut self.clients, transaction.client, &mut self.archived)?;
+        }
+        self.offered += 1;
+        let Some(transaction) = self.intercept(transaction) else {
+            return Ok(());
+        };
+        let expiry = self.config.disputes.expire_after;
+        if let Some(expiry) = expiry {
+            self.expire_disputes(expiry, transaction.timestamp);
+        }
+        if !self.config.aml.is_empty() {
+            self.aml.observe(&self.config.aml, &transaction);
+        }
+        if let Some(scores) = &mut self.scores {
+            scores.observe(&transaction);
+        }
+        if let Err(violation) = self.risk.check(&self.config.risk, &transaction) {
+            self.reject(transaction.client, transaction.tx, violation);
+            return Ok(());
+        }
+        if traced {
+            trace(
+                transaction.client,
+                transaction.tx,
+                format_args!("within the risk limits"),
+            );
+        }
+        if self.config.strict_onboarding
+            && transaction.trans != TransType::OpenAccount
+            && !self
+                .clients
+                .get(transaction.client)
+                .is_some_and(Client::is_opened)
+        {
+            self.reject(transaction.client, transaction.tx, Violation::NotOpened);
+            return Ok(());
+        }
+        let handler = match &transaction.trans {
+            TransType::Other(name) => match self.handlers.get(name) {
+                Some(handler) => Some(handler),
+                None => {
+                    *self.unknown_types.entry(name.clone()).or_default() += 1;
+                    self.reject(transaction.client, transaction.tx, Violation::UnknownType);
+                    return Ok(());
+                }
+            },
+            _ => None,
+        };
+        let fee = self.fee(&transaction);
+        // Counted towards the daily total and the freeze policy once applied
+        let counted = match transaction.trans {
+            TransType::Withdrawal => self.config.risk.max_daily_withdrawal.is_some(),
+            TransType::Deposit | TransType::Chargeback => self.config.risk.freeze.is_some(),
+            _ => false,
+        }
+        .then(|| transaction.clone());
+        let mut freeze = None;
+        let mut recorded = false;
+        if let Some(spill) = &mut self.spill {
+            match transaction.trans {
+                TransType::Dispute | TransType::Resolve | TransType::Chargeback => {
+                    spill.load(&mut self.clients, transaction.client, transaction.tx)?
+                }
+                TransType::Deposit | TransType::Withdrawal => {
+                    recorded = !self
+                        .clients
+                        .get(transaction.client)
+                        .is_some_and(|client| client.has_record(transaction.tx))
+                }
+                _ => {}
+            }
+        }
+        let (id, tx) = (transaction.client, transaction.tx);
+        let recording = self.events.is_some() || self.ledger.is_some();
+        let credit_limit = self.credit_limits.get(&transaction.client).copied();
+        let reserve = self.reserve(transaction.client);
+        let records_per_client = self.records_per_client;
+        let client = self.clients.get_or_insert_with(transaction.client, || {
+            debug!("  Adding new client: {}", transaction.client);
+            Client::with_credit_limit(credit_limit.unwrap_or_default())
+                .with_reserve(reserve)
+                .with_records(records_per_client)
+        });
+        let checked = self
+            .invariants
+            .then(|| (transaction.clone(), client.exact_account()));
+        if traced {
+            let before = client.exact_account();
+            trace(id, tx, format_args!("fee {fee}, before {before:?}"));
+        }
+        let event = match handler {
+            Some(handler) => {
+                let before = recording.then(|| client.account());
+                client.note(&transaction);
+                handler.apply(client, &transaction)?;
+                if traced {
+                    let kind = &transaction.trans;
+                    trace(id, tx, format_args!("applied by the {kind} handler"));
+                }
+                before.map(|before| Event::CustomApplied {
+                    client: id,
+                    tx,
+                    kind: transaction.trans.to_string(),
+                    available: client.available() - before.available,
+                    held: client.held() - before.held,
+                    total: client.total() - before.total,
+                    locked: client.is_locked(),
+                })
+            }
+            None => {
+                let trans = recording.then(|| transaction.trans.clone());
+                let opening = transaction.trans == TransType::Dispute
+                    && client.open_dispute(tx).is_none()
+                    && match expiry {
+                        Some(DisputeExpiry::Days(_)) => transaction.timestamp.is_some(),
+                        Some(DisputeExpiry::Transactions(_)) => true,
+                        None => false,
+                    };
+                let at = transaction.timestamp;
+                let chargeback = transaction.trans == TransType::Chargeback;
+                let outcome = client.transact(transaction, fee, &self.config.disputes)?;
+                if traced {
+                    trace(id, tx, format_args!("{outcome:?}"));
+                }
+                if let Some(round) = client.open_dispute(tx).filter(|_| opening) {
+                    self.open_disputes.push_back(OpenDispute {
+                        client: id,
+                        tx,
+                        round,
+                        offered: self.offered,
+                        at,
+                    });
+                }
+                match outcome {
+                    Outcome::Applied { amount, fee } => {
+                        self.fees += fee;
+                        if chargeback {
+                            self.chargeback_losses += amount;
+                        }
+                        if let Some(counted) = &counted {
+                            self.risk.withdrew(counted);
+                            freeze = self.risk.freeze(&self.config.risk, counted);
+                        }
+                    }
+                    Outcome::Ignored(reason) => {
+                        info!("Ignored tx:{tx} of client:{id}: {reason}");
+                        if let Some(scores) = &mut self.scores {
+                            scores.reject(id);
+                        }
+                    }
+                }
+                trans.map(|trans| event(id, tx, trans, outcome))
+            }
+        };
+        if let Some(reason) = &freeze {
+            warn!("Freezing client:{id}: {reason}");
+            client.lock();
+        }
+        if traced {
+            let after = client.exact_account();
+            trace(id, tx, format_args!("after {after:?}"));
+        }
+        if let Some((transaction, before)) = checked {
+            let after = client.exact_account();
+            if let Some(broken) = broken_invariant(&transaction.trans, &before, &after) {
+                error!("Invariant broken: {broken}");
+                return Err(anyhow!(
+                    "{broken} after {transaction:?}\n  before: {before:?}\n  after:  {after:?}"
+                ));
+            }
+        }
+        if let Some(event) = event {
+            self.emit(|| event);
+        }
+        if let Some(spill) = self.spill.as_mut().filter(|_| recorded) {
+            if self
+                .clients
+                .get(id)
+                .is_some_and(|client| client.has_record(tx))
+            {
+                spill.added(&mut self.clients, id, tx)?;
+            }
+        }
+        if let Some(reason) = freeze {
+            self.emit(|| Event::AccountFrozen {
+                client: id,
+                tx,
+                reason: reason.clone(),
+            });
+            self.audit.push(AuditEntry {
+                client: id,
+                tx,
+                event: AuditEvent::Frozen(reason),
+            });
+        }
+        Ok(())
+    }
+
+    /// Resolves the disputes open since before `expiry`, in the order they
+    /// were opened. Those resolved, charged back or disputed again since are
+    /// passed over.
+    fn expire_disputes(&mut self, expiry: DisputeExpiry, now: Option<DateTime<Utc>>) {
+        while let Some(open) = self.open_disputes.front() {
+            let expired = match (expiry, open.at, now) {
+                (DisputeExpiry::Transactions(n), _, _) => self.offered > open.offered + n,
+                (DisputeExpiry::Days(days), Some(at), Some(now)) => now > at + Duration::days(days),
+                (DisputeExpiry::Days(_), _, _) => false,
+            };
+            if !expired {
+                return;
+            }
+            let OpenDispute {
+                client: id,
+                tx,
+                round,
+                ..
+            } = self.open_disputes.pop_front().expect("just looked");
+            let Some(client) = self.clients.get_mut(id) else {
+                continue;
+            };
+            if client.open_dispute(tx) != Some(round) {
+                continue;
+            }
+            let Some(amount) = client.auto_resolve(tx) else {
+                continue;
+            };
+            warn!("The dispute of tx:{tx} of client:{id} expired. Resolving");
+            if self.traced(id, tx) {
+                let amount = to_decimal(amount);
+                trace(id, tx, format_args!("dispute expired, released {amount}"));
+            }
+            self.emit(|| Event::DisputeAutoResolved {
+                client: id,
+                tx,
+                amount: to_decimal(amount),
+            });
+            self.audit.push(AuditEntry {
+                client: id,
+                tx,
+                event: AuditEvent::DisputeExpired,
+            });
+        }
+    }
+
+    /// The configured minimum balance of a client, its own or the global one
+    fn reserve(&self, client: ClientId) -> Option<Amount> {
+        self.reserves
+            .get(&client)
+            .copied()
+            .or_else(|| self.config.reserve.min_balance.map(from_decimal))
+    }
+
+    /// The fee the configured schedule sets for a transaction
+    fn fee(&self, transaction: &Transaction) -> Amount {
+        let tier = self.client_tiers.get(&transaction.client);
+        let amount = transaction.amount.unwrap_or_default();
+        from_decimal(
+            self.config
+                .fee(tier.map(String::as_str), &transaction.trans, amount),
+        )
+    }
+
+    /// Every event recorded since [Engine::record_events], in input order
+    pub fn events(&self) -> &[Event] {
+        self.events.as_deref().unwrap_or_default()
+    }
+
+    /// The books kept since [Engine::keep_journal]
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// Hands over the events recorded so far and keeps recording, for
+    /// long-running engines that pass their events on as they go
+    pub fn take_events(&mut self) -> Vec<Event> {
+        self.events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Everything the engine did on its own accord, in input order
+    pub fn audit_trail(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    /// How many transactions of each type without a handler were rejected
+    pub fn unknown_types(&self) -> &BTreeMap<String, u64> {
+        &self.unknown_types
+    }
+
+    /// Every transaction refused by a risk limit or for its unknown type, in
+    /// input order
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    /// Clients breaking the configured AML rules. Transactions refused by a
+    /// risk limit count as well.
+    pub fn flags(&self) -> Vec<Flag> {
+        self.aml.flags(&self.config.aml)
+    }
+
+    /// The total of every fee charged so far
+    pub fn fees(&self) -> Decimal {
+        to_decimal(self.fees)
+    }
+
+    /// What the engine owes its clients as a whole, archived ones included,
+    /// and what chargebacks cost it, see [Position]
+    pub fn position(&self) -> Position {
+        let mut position = self.archived;
+        for (_, client) in self.clients.iter() {
+            position.add(client);
+        }
+        position.chargeback_losses = to_decimal(self.chargeback_losses);
+        position
+    }
+
+    /// Applies every transaction in the stream in order. When `as_of` is given
+    /// the replay stops at that point so the engine holds the balances as they
+    /// stood then.
+    pub fn replay<I, E>(&mut self, transactions: I, as_of: Option<AsOf>) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<Transaction, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        for result in transactions {
+            if self.replay_one(result?, as_of)? {
+                return Ok(());
+            }
+        }
+        if let Some(AsOf::Tx(tx)) = as_of {
+            warn!("tx:{tx} was never applied. Reporting final balances");
+        }
+        Ok(())
+    }
+
+    /// Same as [Engine::replay] with the transactions of `source`, until it
+    /// is exhausted or `stop` is true before a batch
+    pub fn replay_source(
+        &mut self,
+        source: &mut dyn TransactionSource,
+        as_of: Option<AsOf>,
+        stop: impl Fn() -> bool,
+    ) -> Result<()> {
+        while !stop() {
+            let batch = source.next_batch()?;
+            if batch.is_empty() {
+                break;
+            }
+            for transaction in batch {
+                if self.replay_one(transaction, as_of)? {
+                    return Ok(());
+                }
+            }
+        }
+        if let Some(AsOf::Tx(tx)) = as_of {
+            warn!("tx:{tx} was never applied. Reporting final balances");
+        }
+        Ok(())
+    }
+
+    /// Applies `transaction` unless it is past `as_of`, returning whether the
+    /// replay is at `as_of`
+    fn replay_one(&mut self, transaction: Transaction, as_of: Option<AsOf>) -> Result<bool> {
+        match as_of {
+            Some(AsOf::Timestamp(time)) if transaction.timestamp.is_some_and(|t| t > time) => {
+                Ok(true)
+            }
+            Some(AsOf::Tx(tx))
+                if transaction.tx == tx
+                    && matches!(
+                        transaction.trans,
+                        TransType::Deposit | TransType::Withdrawal
+                    ) =>
+            {
+                self.apply(transaction)?;
+                Ok(true)
+            }
+            _ => self.apply(transaction).map(|_| false),
+        }
+    }
+
+    /// Closes the current period as of `at` under `id`, which must not have
+    /// been closed before, and starts the next, see [crate::period]. Archived
+    /// clients are read back so the period has every account.
+    pub fn close_period(&mut self, id: &str, at: DateTime<Utc>) -> Result<Period> {
+        period::check_id(id)?;
+        if self.periods.closed.contains(id) {
+            bail!("period {id} is closed already");
+        }
+        if let Some(archive) = &mut self.archive {
+            archive.load_all(&mut self.clients, &mut self.archived)?;
+        }
+        let period = Period {
+            id: id.to_string(),
+            closed_at: at,
+            transactions: self.offered - self.periods.offered,
+            rejections: self.rejections.len() - self.periods.rejections,
+            accounts: self.snapshot(),
+        };
+        self.periods.closed.insert(period.id.clone());
+        self.periods.last_closed_at = Some(at);
+        self.periods.offered = self.offered;
+        self.periods.rejections = self.rejections.len();
+        self.risk.start_period();
+        self.clients.iter_mut().for_each(Client::start_period);
+        self.unknown_types.clear();
+        info!("Closed period {id}");
+        Ok(period)
+    }
+
+    /// When the last period was closed, if one was
+    pub fn last_period_closed_at(&self) -> Option<DateTime<Utc>> {
+        self.periods.last_closed_at
+    }
+
+    /// Applies a manual correction to the account of an existing client, going
+    /// by `tx` in the audit trail and the events. The amount moves into the
+    /// available funds, or out of them when negative, even if that leaves
+    /// them below zero or the account is locked.
+    pub fn adjust(&mut self, adjustment: &Adjustment, tx: TxId) -> Result<()> {
+        let Adjustment {
+            client: id,
+            amount,
+            reason,
+        } = adjustment;
+        if let Some(archive) = &mut self.archive {
+            archive.load(&mut self.clients, *id, &mut self.archived)?;
+        }
+        let client = self
+            .clients
+            .get_mut(*id)
+            .ok_or_else(|| anyhow!("client {id} has no account to adjust"))?;
+        client.adjust(*amount);
+        info!("Adjusted client:{id} by {amount}: {reason}");
+        self.audit.push(AuditEntry {
+            client: *id,
+            tx,
+            event: AuditEvent::Adjusted {
+                amount: *amount,
+                reason: reason.clone(),
+            },
+        });
+        self.emit(|| Event::AccountAdjusted {
+            client: *id,
+            tx,
+            amount: *amount,
+            reason: reason.clone(),
+        });
+        Ok(())
+    }
+
+    /// Locks the account of an existing client by an administrative
+    /// operation going by `tx` in the audit trail and the events, so it takes
+    /// no more deposits or withdrawals
+    pub fn lock(&mut self, client: ClientId, tx: TxId) -> Result<()> {
+        self.set_locked(client, tx, true)
+    }
+
+    /// Unlocks the account of an existing client, e.g. once a chargeback has
+    /// been looked into, going by `tx` in the audit trail and the events
+    pub fn unlock(&mut self, client: ClientId, tx: TxId) -> Result<()> {
+        self.set_locked(client, tx, false)
+    }
+
+    fn set_locked(&mut self, id: ClientId, tx: TxId, locked: bool) -> Result<()> {
+        if let Some(archive) = &mut self.archive {
+            archive.load(&mut self.clients, id, &mut self.archived)?;
+        }
+        let client = self
+            .clients
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("client {id} has no account"))?;
+        let (event, audit) = if locked {
+            client.lock();
+            info!("Locked client:{id}");
+            (Event::AccountLocked { client: id, tx }, AuditEvent::Locked)
+        } else {
+            client.unlock();
+            info!("Unlocked client:{id}");
+            (
+                Event::AccountUnlocked { client: id, tx },
+                AuditEvent::Unlocked,
+            )
+        };
+        self.audit.push(AuditEntry {
+            client: id,
+            tx,
+            event: audit,
+        });
+        self.emit(|| event);
+        Ok(())
+    }
+
+    /// Pays the configured interest on every available balance as of `at`.
+    ///
+    /// Each payment is an ordinary deposit stamped with `at`, numbered upwards
+    /// from `first_tx`, which must not clash with any `tx` id in the input.
+    /// The payments are returned so they can be added to the transactions
+    /// file. Replaying that file then gives the same balances without accruing
+    /// again. Locked accounts earn nothing.
+    pub fn accrue_interest(
+        &mut self,
+        at: DateTime<Utc>,
+        first_tx: TxId,
+    ) -> Result<Vec<Transaction>> {
+        let Some(interest) = self.config.interest.clone() else {
+            return Err(anyhow!("no interest rate is configured"));
+        };
+        let mut tx = first_tx;
+        let mut payments = Vec::new();
+        for (client, account) in self.snapshot() {
+            let amount = interest.on(account.available);
+            if account.locked || amount.is_zero() {
+                continue;
+            }
+            let mut payment = Transaction::new(TransType::Deposit, client, tx, Some(amount));
+            payment.timestamp = Some(at);
+            self.apply(payment.clone())?;
+            payments.push(payment);
+            tx += 1;
+        }
+        Ok(payments)
+    }
+
+    /// The client whose account now takes the transactions of `client`, if it
+    /// was merged away
+    pub fn merged_into(&self, client: ClientId) -> Option<ClientId> {
+        self.merged.get(&client).copied()
+    }
+
+    /// A view of one client's account, if the client has been seen
+    pub fn account(&self, client: ClientId) -> Option<AccountView> {
+        self.clients.get(client).map(|c| c.view(client))
+    }
+
+    /// Views of every client account, in the order the clients were first
+    /// seen
+    pub fn iter_accounts(&self) -> impl Iterator<Item = AccountView> + '_ {
+        self.reported().map(|(id, client)| client.view(id))
+    }
+
+    /// Up to `limit` client accounts from `cursor` on, in the order the
+    /// clients were first seen, and the cursor of the next page if there is
+    /// more. A page starts from the beginning without a cursor. Clients seen
+    /// after a page was taken come on a later page.
+    pub fn accounts_page(
+        &self,
+        cursor: Option<ClientId>,
+        limit: usize,
+    ) -> Result<(Vec<AccountView>, Option<ClientId>)> {
+        let clients: Box<dyn Iterator<Item = (ClientId, &Client)>> = match cursor {
+            Some(cursor) => Box::new(
+                self.clients
+                    .iter_after(cursor)
+                    .ok_or_else(|| anyhow!("unknown cursor {cursor}"))?,
+            ),
+            None => Box::new(self.clients.iter()),
+        };
+        let mut clients = clients.filter(|(_, client)| !client.is_closed_empty());
+        let page: Vec<AccountView> = clients
+            .by_ref()
+            .take(limit)
+            .map(|(id, client)| client.view(id))
+            .collect();
+        let next = match (page.last(), clients.next()) {
+            (Some(last), Some(_)) => Some(last.client),
+            _ => None,
+        };
+        Ok((page, next))
+    }
+
+    /// The current balances of every client
+    pub fn snapshot(&self) -> Snapshot {
+        self.reported()
+            .map(|(id, client)| (id, client.account()))
+            .collect()
+    }
+
+    /// Every client but those whose account was closed empty, in the order
+    /// first seen
+    fn reported(&self) -> impl Iterator<Item = (ClientId, &Client)> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| !client.is_closed_empty())
+    }
+
+    /// Writes the account balances report, ordered by client id. A
+    /// `credit_limit` column is added when credit lines are configured, and a
+    /// `shortfall` column when disputes are clamped to what is available.
+    /// ```text
+    /// client, available, held, total, locked
+    /// 1, 1.5, 0, 1.5, false
+    /// ```
+    pub fn write_report(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "client, available, held, total, locked{}",
+            self.extra_columns()
+        )?;
+        self.write_report_rows(&mut w, "")
+    }
+
+    /// The header of the report columns past `locked`, each led by a comma
+    pub(crate) fn extra_columns(&self) -> String {
+        let mut columns = String::new();
+        if self.has_credit_lines() {
+            columns.push_str(", credit_limit");
+        }
+        if self.tracks_shortfalls() {
+            columns.push_str(", shortfall");
+        }
+        if self.scores.is_some() {
+            columns.push_str(", risk_score");
+        }
+        if self.extended {
+            columns.push_str(
+                ", transactions, deposited, withdrawn, open_disputes, last_tx, last_timestamp",
+            );
+        }
+        columns
+    }
+
+    /// Whether the report has a `credit_limit` column
+    fn has_credit_lines(&self) -> bool {
+        !self.credit_limits.is_empty()
+    }
+
+    /// Whether the report has a `shortfall` column
+    fn tracks_shortfalls(&self) -> bool {
+        self.config.disputes.negative_available == NegativeAvailable::Clamp
+    }
+
+    /// The rows of [Engine::write_report], each starting with `prefix`
+    pub(crate) fn write_report_rows(&self, w: &mut impl io::Write, prefix: &str) -> io::Result<()> {
+        let credit = self.has_credit_lines();
+        let shortfalls = self.tracks_shortfalls();
+        let mut clients: Vec<(ClientId, &Client)> = self.reported().collect();
+        clients.sort_by_key(|&(id, _)| id);
+        for (id, client) in clients {
+            write!(w, "{}{}, {}", prefix, id, client)?;
+            if credit {
+                write!(w, ", {}", client.credit_limit())?;
+            }
+            if shortfalls {
+                write!(w, ", {}", client.shortfall())?;
+            }
+            if let Some(scores) = &self.scores {
+                write!(w, ", {}", scores.score(id))?;
+            }
+            if self.extended {
+                client.write_activity(w)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the totals that do not belong to any one client
+    /// ```text
+    /// fees collected: 1.25
+    /// customer liabilities: 3.5
+    /// held: 0
+    /// chargeback losses: 2.0
+    /// transactions rejected: 0
+    /// ```
+    pub fn write_summary(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "fees collected: {}", self.fees())?;
+        self.position().write(&mut w)?;
+        writeln!(w, "transactions rejected: {}", self.rejections.len())?;
+        for (name, count) in &self.unknown_types {
+            writeln!(w, "unknown type {name}: {count}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes every deposit and withdrawal a later dispute could refer to,
+    /// ordered by client and tx. Read back with [crate::client::read_records].
+    /// ```text
+    /// client, tx, amount
+    /// 1, 3, 1.5
+    /// ```
+    pub fn write_records(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "client, tx, amount")?;
+        let mut clients: Vec<(ClientId, &Client)> = self.clients.iter().collect();
+        clients.sort_by_key(|&(id, _)| id);
+        for (id, client) in clients {
+            for (tx, amount) in client.records() {
+                writeln!(w, "{id}, {tx}, {amount}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the AML flags, one row per client and rule broken
+    /// ```text
+    /// client, rule, detail
+    /// 2, cycling, 3 deposits withdrawn within 60 minutes
+    /// ```
+    pub fn write_flags(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "client, rule, detail")?;
+        for flag in self.flags() {
+            writeln!(w, "{}, {}, {}", flag.client, flag.rule, flag.detail)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the audit trail
+    /// ```text
+    /// client, tx, event
+    /// 2, 14, frozen: 3 chargebacks
+    /// ```
+    pub fn write_audit_trail(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "client, tx, event")?;
+        for entry in &self.audit {
+            writeln!(w, "{}, {}, {}", entry.client, entry.tx, entry.event)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the recorded events as JSON lines, see [crate::event]
+    pub fn write_events(&self, mut w: impl io::Write) -> io::Result<()> {
+        for event in self.events() {
+            serde_json::to_writer(&mut w, event)?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rejected transactions with their [crate::Reason] codes and
+    /// names
+    /// ```text
+    /// client, tx, code, reason
+    /// 1, 7, R013, max_amount
+    /// ```
+    pub fn write_rejections(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "client, tx, code, reason")?;
+        for rejection in &self.rejections {
+            let reason = rejection.violation.reason();
+            writeln!(
+                w,
+                "{}, {}, {}, {}",
+                rejection.client,
+                rejection.tx,
+                reason.code(),
+                reason.name()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The event for what a client did with a transaction
+fn event(client: ClientId, tx: TxId, trans: TransType, outcome: Outcome) -> Event {
+    let (amount, fee) = match outcome {
+        Outcome::Applied { amount, fee } => (to_decimal(amount), to_decimal(fee)),
+        Outcome::Ignored(reason) => {
+            return Event::TransactionRejected {
+                client,
+                tx,
+                reason: reason.name().to_string(),
+            }
+        }
+    };
+    match trans {
+        TransType::Deposit => Event::DepositApplied {
+            client,
+            tx,
+            amount,
+            fee,
+        },
+        TransType::Withdrawal => Event::WithdrawalApplied {
+            client,
+            tx,
+            amount,
+            fee,
+        },
+        TransType::Dispute => Event::DisputeOpened { client, tx, amount },
+        TransType::Resolve => Event::DisputeResolved { client, tx, amount },
+        TransType::Chargeback => Event::ChargebackApplied { client, tx, amount },
+        TransType::OpenAccount => Event::AccountOpened { client, tx },
+        TransType::CloseAccount => Event::AccountClosed { client, tx },
+        TransType::Other(_) => unreachable!("custom types go to their handler"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_init;
+    use crate::snapshot::read_snapshot;
+    use crate::transaction::read_csv;
+    use rust_decimal_macros::dec;
+
+    const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         5.0,     2022-03-21T09:00:00Z
+deposit,         2,     2,         2.0,     2022-03-21T10:00:00Z
+withdrawal,      1,     3,         1.5,     2022-03-21T11:00:00Z
+dispute,         2,     2,            ,     2022-03-21T12:00:00Z
+chargeback,      2,     2,            ,     2022-03-21T13:00:00Z
+";
+
+    fn report(as_of: Option<AsOf>) -> Result<String> {
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), as_of)?;
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_reserve_for() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.reserve_for(1000, 50_000);
+        assert_eq!(engine.records_per_client, 50);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        assert_eq!(String::from_utf8(out)?, report(None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_accounts_page() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let (page, next) = engine.accounts_page(None, 1)?;
+        assert_eq!(page.iter().map(|a| a.client).collect::<Vec<_>>(), [1]);
+        assert_eq!(next, Some(1));
+        let (page, next) = engine.accounts_page(next, 1)?;
+        assert_eq!(page.iter().map(|a| a.client).collect::<Vec<_>>(), [2]);
+        assert_eq!(next, None, "the last page");
+        assert!(engine.accounts_page(Some(99), 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let header = DATA.lines().next().expect("a header");
+        let only = |client: &str| -> Result<Engine> {
+            let data: String = DATA
+                .lines()
+                .filter(|line| line.split(',').nth(1).map(str::trim) == Some(client))
+                .fold(format!("{header}\n"), |data, line| data + line + "\n");
+            let mut engine = Engine::new();
+            engine.replay(read_csv(data.as_bytes()), None)?;
+            Ok(engine)
+        };
+        let mut engine = only("1")?;
+        engine.merge(only("2")?)?;
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        assert_eq!(String::from_utf8(out)?, report(None)?);
+        assert!(engine.merge(only("2")?).is_err(), "client 2 is in both");
+        Ok(())
+    }
+
+    #[test]
+    fn test_engine_serde_round_trip() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), Some(AsOf::Tx(3)))?;
+
+        let json = serde_json::to_string(&engine)?;
+        let mut restored: Engine = serde_json::from_str(&json)?;
+        assert_eq!(restored.snapshot(), engine.snapshot());
+
+        // The restored records still back later disputes
+        let rest = read_csv(DATA.as_bytes()).skip(3);
+        restored.replay(rest, None)?;
+        assert!(restored.snapshot()[&2].locked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_events() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+withdrawal,      1,     2,         9.0
+dispute,         1,     1,
+resolve,         1,     1,
+chargeback,      1,     7,
+";
+        let mut engine = Engine::new();
+        engine.record_events();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let amount = Decimal::new(50, 1);
+        assert_eq!(
+            engine.events(),
+            [
+                Event::DepositApplied {
+                    client: 1,
+                    tx: 1,
+                    amount,
+                    fee: Decimal::ZERO,
+                },
+                Event::TransactionRejected {
+                    client: 1,
+                    tx: 2,
+                    reason: "insufficient_funds".to_string(),
+                },
+                Event::DisputeOpened {
+                    client: 1,
+                    tx: 1,
+                    amount,
+                },
+                Event::DisputeResolved {
+                    client: 1,
+                    tx: 1,
+                    amount,
+                },
+                Event::TransactionRejected {
+                    client: 1,
+                    tx: 7,
+                    reason: "not_disputed".to_string(),
+                },
+            ]
+        );
+        let mut out = Vec::new();
+        engine.write_events(&mut out)?;
+        let first = String::from_utf8(out)?.lines().next().map(str::to_string);
+        assert_eq!(
+            first.as_deref(),
+            Some(r#"{"event":"DepositApplied","client":1,"tx":1,"amount":"5.0","fee":"0"}"#)
+        );
+        assert_eq!(engine.take_events().len(), 5);
+        assert!(engine.events().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_handler() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+bonus,           1,     2,         2.5
+";
+        // Without a handler the type is rejected and counted
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        assert_eq!(engine.snapshot()[&1].available, Decimal::new(50, 1));
+        assert_eq!(engine.rejections()[0].violation, Violation::UnknownType);
+        assert_eq!(engine.unknown_types()["bonus"], 1);
+        let mut summary = Vec::new();
+        engine.write_summary(&mut summary)?;
+        assert!(String::from_utf8(summary)?.ends_with("unknown type bonus: 1\n"));
+
+        let mut engine = Engine::new();
+        engine.register_handler(
+            "bonus",
+            |account: &mut Client, transaction: &Transaction| {
+                account.adjust(transaction.amount.unwrap_or_default());
+                Ok(())
+            },
+        );
+        engine.record_events();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        assert_eq!(engine.snapshot()[&1].available, Decimal::new(75, 1));
+        assert!(matches!(
+            &engine.events()[1],
+            Event::CustomApplied { kind, total, .. } if kind == "bonus" && *total == Decimal::new(25, 1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed() -> Result<()> {
+        let accounts = read_snapshot(
+            "client, available, held, total, locked\n1, 10, 0, 10, false\n2, 1, 1, 2, true\n"
+                .as_bytes(),
+        )?;
+        let mut engine = Engine::new();
+        engine.seed(&accounts)?;
+        engine.replay(read_csv(DATA.as_bytes()).take(3), None)?;
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot[&1].available, Decimal::new(135, 1));
+        // Locked accounts stay locked
+        assert_eq!(snapshot[&2], accounts[&2]);
+
+        let broken = read_snapshot(
+            "client, available, held, total, locked\n1, 1, 1, 1, false\n".as_bytes(),
+        )?;
+        assert!(Engine::new().seed(&broken).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_round_trip() -> Result<()> {
+        let mut today = Engine::new();
+        today.replay(read_csv(DATA.as_bytes()).take(3), None)?;
+        let mut accounts = Vec::new();
+        today.write_report(&mut accounts)?;
+        let mut records = Vec::new();
+        today.write_records(&mut records)?;
+        assert_eq!(
+            String::from_utf8(records.clone())?,
+            "client, tx, amount\n1, 1, 5.0\n1, 3, 1.5\n2, 2, 2.0\n"
+        );
+
+        // Tomorrow's disputes can reach today's deposits
+        let mut tomorrow = Engine::new();
+        tomorrow.seed(&read_snapshot(accounts.as_slice())?)?;
+        tomorrow.import_records(crate::client::read_records(records.as_slice())?)?;
+        tomorrow.replay(read_csv(DATA.as_bytes()).skip(3), None)?;
+        assert_eq!(tomorrow.snapshot(), {
+            let mut full = Engine::new();
+            full.replay(read_csv(DATA.as_bytes()), None)?;
+            full.snapshot()
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_view() -> Result<()> {
+        let mut engine = Engine::new();
+        let transactions = read_csv(DATA.as_bytes()).take(4);
+        engine.replay(transactions, None)?;
+
+        let view = engine.account(2).expect("client 2 exists");
+        assert_eq!(view.available, Decimal::ZERO);
+        assert_eq!(view.held, Decimal::TWO);
+        assert_eq!(view.open_disputes, vec![2]);
+        assert_eq!(engine.account(3), None);
+
+        engine.replay(read_csv(DATA.as_bytes()).skip(4), None)?;
+        let view = engine.account(2).expect("client 2 exists");
+        assert!(view.locked);
+        assert!(view.open_disputes.is_empty());
+        assert_eq!(engine.iter_accounts().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fees() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str(
+            r#"
+[fees]
+deposit = { flat = "0.1" }
+withdrawal = { percent = "1" }
+
+[tiers.free]
+clients = [2]
+fees.deposit = { flat = "0" }
+"#,
+        )?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), Some(AsOf::Tx(3)))?;
+        // 5.0 - 0.1 deposit fee - 1.5 - 0.015 withdrawal fee
+        assert_eq!(engine.snapshot()[&1].available, "3.385".parse()?);
+        assert_eq!(engine.snapshot()[&2].available, "2.0".parse()?);
+        assert_eq!(engine.fees(), "0.115".parse()?);
+
+        let mut out = Vec::new();
+        engine.write_summary(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "fees collected: 0.115\n\
+             customer liabilities: 5.385\n\
+             held: 0\n\
+             chargeback losses: 0\n\
+             transactions rejected: 0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_accrue_interest() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str("[interest]\nrate = \"10\"")?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let at = "2022-03-31T00:00:00Z".parse()?;
+        let payments = engine.accrue_interest(at, 4)?;
+
+        // Client 2 is locked after the chargeback
+        let mut expected = Transaction::new(TransType::Deposit, 1, 4, Some("0.35".parse()?));
+        expected.timestamp = Some(at);
+        assert_eq!(payments, vec![expected]);
+        assert_eq!(engine.snapshot()[&1].available, "3.85".parse()?);
+
+        // Replaying the input with the payments gives the same balances
+        let mut replayed = Engine::new();
+        replayed.replay(read_csv(DATA.as_bytes()), None)?;
+        replayed.replay(payments.into_iter().map(Ok::<_, io::Error>), None)?;
+        assert_eq!(replayed.snapshot(), engine.snapshot());
+
+        assert!(Engine::new().accrue_interest(at, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_credit_limit_report() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str("[[credit]]\nclient = 1\nlimit = \"10\"")?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        engine.apply(Transaction::new(
+            TransType::Withdrawal,
+            1,
+            9,
+            Some("12".parse()?),
+        ))?;
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, available, held, total, locked, credit_limit\n\
+             1, -8.5, 0, -8.5, false, 10\n\
+             2, 0.0, 0.0, 0.0, true, 0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str(
+            "[reserve]\nmin_balance = \"1\"\nclients = [{ client = 1, min_balance = \"4\" }]",
+        )?;
+        let mut engine = Engine::with_config(config);
+        engine.record_events();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        engine.apply(Transaction::new(TransType::Deposit, 3, 6, Some(dec!(3))))?;
+        engine.apply(Transaction::new(TransType::Withdrawal, 3, 7, Some(dec!(2))))?;
+        // Client 1 may not go below 4 and client 3 below the global 1
+        assert_eq!(engine.snapshot()[&1].available, dec!(5.0));
+        assert_eq!(engine.snapshot()[&3].available, dec!(1));
+        assert_eq!(
+            engine.events()[2],
+            Event::TransactionRejected {
+                client: 1,
+                tx: 3,
+                reason: "below_reserve".to_string()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_redisputes() -> Result<()> {
+        log_init();
+        const DISPUTES: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+dispute,         1,     1,
+resolve,         1,     1,
+dispute,         1,     1,
+resolve,         1,     1,
+dispute,         1,     1,
+";
+        let held = |policy: &str| -> Result<Vec<&'static str>> {
+            let mut engine = Engine::with_config(toml::from_str(policy)?);
+            engine.record_events();
+            engine.replay(read_csv(DISPUTES.as_bytes()), None)?;
+            Ok(engine
+                .events()
+                .iter()
+                .filter_map(|event| match event {
+                    Event::DisputeOpened { .. } => Some("opened"),
+                    Event::TransactionRejected { reason, .. } if reason == "redisputed" => {
+                        Some("rejected")
+                    }
+                    _ => None,
+                })
+                .collect())
+        };
+        assert_eq!(held("")?, ["opened", "opened", "opened"]);
+        assert_eq!(
+            held("disputes.redisputes = \"reject\"")?,
+            ["opened", "rejected", "rejected"]
+        );
+        assert_eq!(
+            held("disputes.redisputes = { limit = 1 }")?,
+            ["opened", "opened", "rejected"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_available() -> Result<()> {
+        log_init();
+        const WITHDRAWN: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+withdrawal,      1,     2,         4.0
+dispute,         1,     1,
+";
+        let report = |policy: &str| -> Result<String> {
+            let mut engine = Engine::with_config(toml::from_str(policy)?);
+            engine.replay(read_csv(WITHDRAWN.as_bytes()), None)?;
+            let mut out = Vec::new();
+            engine.write_report(&mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert_eq!(
+            report("")?,
+            "client, available, held, total, locked\n1, -4.0, 5.0, 1.0, false\n"
+        );
+        assert_eq!(
+            report("disputes.negative_available = \"clamp\"")?,
+            "client, available, held, total, locked, shortfall\n1, 0.0, 1.0, 1.0, false, 4.0\n"
+        );
+        assert_eq!(
+            report("disputes.negative_available = \"reject\"")?,
+            "client, available, held, total, locked\n1, 1.0, 0, 1.0, false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dispute_expiry() -> Result<()> {
+        log_init();
+        const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         5.0,     2022-03-01T10:00:00Z
+deposit,         2,     2,         3.0,     2022-03-01T10:00:00Z
+dispute,         1,     1,            ,     2022-03-02T10:00:00Z
+dispute,         2,     2,            ,     2022-03-02T10:00:00Z
+resolve,         2,     2,            ,     2022-03-03T10:00:00Z
+dispute,         2,     2,            ,     2022-03-20T10:00:00Z
+deposit,         1,     3,         1.0,     2022-04-02T10:00:00Z
+";
+        let replay = |policy: &str| -> Result<Engine> {
+            let mut engine = Engine::with_config(toml::from_str(policy)?);
+            engine.record_events();
+            engine.replay(read_csv(DATA.as_bytes()), None)?;
+            Ok(engine)
+        };
+        let engine = replay("disputes.expire_after = { days = 30 }")?;
+        let accounts = engine.snapshot();
+        assert_eq!(accounts[&1].available, dec!(6.0));
+        assert_eq!(accounts[&1].held, dec!(0));
+        assert_eq!(
+            accounts[&2].held,
+            dec!(3.0),
+            "disputed again after the resolve, 13 days ago"
+        );
+        assert_eq!(
+            engine.audit_trail(),
+            [AuditEntry {
+                client: 1,
+                tx: 1,
+                event: AuditEvent::DisputeExpired,
+            }]
+        );
+        assert!(engine.events().contains(&Event::DisputeAutoResolved {
+            client: 1,
+            tx: 1,
+            amount: dec!(5.0)
+        }));
+
+        let engine = replay("disputes.expire_after = { transactions = 3 }")?;
+        let accounts = engine.snapshot();
+        assert_eq!(accounts[&1].held, dec!(0));
+        assert_eq!(accounts[&2].held, dec!(3.0), "disputed 1 transaction ago");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_report() -> Result<()> {
+        log_init();
+        const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         5.0,     2022-03-21T10:00:00Z
+withdrawal,      1,     2,         1.5,
+withdrawal,      1,     3,        10.0,
+dispute,         1,     1,            ,     2022-03-22T10:00:00Z
+deposit,         2,     4,         2.0,
+";
+        let mut engine = Engine::new();
+        engine.extend_report();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, available, held, total, locked, transactions, deposited, withdrawn, open_disputes, last_tx, last_timestamp\n\
+             1, -1.5, 5.0, 3.5, false, 4, 5.0, 1.5, 1, 1, 2022-03-22T10:00:00Z\n\
+             2, 2.0, 0, 2.0, false, 1, 2.0, 0, 0, 4, \n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invariants() -> Result<()> {
+        log_init();
+        let mut engine = Engine::new();
+        engine.check_invariants();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+
+        let account = |available, held, total, locked| Account {
+            available,
+            held,
+            total,
+            locked,
+        };
+        let open = account(dec!(1), dec!(1), dec!(2), false);
+        let locked = account(dec!(1), dec!(1), dec!(2), true);
+        assert_eq!(broken_invariant(&TransType::Dispute, &open, &open), None);
+        assert_eq!(
+            broken_invariant(
+                &TransType::Deposit,
+                &open,
+                &account(dec!(2), dec!(1), dec!(2), false)
+            ),
+            Some("total is not available plus held")
+        );
+        assert_eq!(
+            broken_invariant(
+                &TransType::Chargeback,
+                &open,
+                &account(dec!(3), dec!(-1), dec!(2), false)
+            ),
+            Some("held is negative")
+        );
+        assert_eq!(
+            broken_invariant(&TransType::Resolve, &locked, &open),
+            Some("a locked account was unlocked")
+        );
+        assert_eq!(
+            broken_invariant(
+                &TransType::Deposit,
+                &locked,
+                &account(dec!(2), dec!(1), dec!(3), true)
+            ),
+            Some("a deposit or withdrawal changed a locked account")
+        );
+        assert_eq!(
+            broken_invariant(
+                &TransType::Resolve,
+                &locked,
+                &account(dec!(2), dec!(0), dec!(2), true)
+            ),
+            None,
+            "disputes still settle on a locked account"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace() -> Result<()> {
+        log_init();
+        let mut engine = Engine::new();
+        engine.trace_client(1);
+        engine.trace_tx(5);
+        assert!(engine.traced(1, 9));
+        assert!(engine.traced(2, 5));
+        assert!(!engine.traced(2, 6));
+        let mut plain = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        plain.replay(read_csv(DATA.as_bytes()), None)?;
+        assert_eq!(
+            engine.snapshot(),
+            plain.snapshot(),
+            "tracing changes nothing"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_risk_rejections() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str("[risk]\nmax_daily_withdrawal = \"1\"")?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        // The 1.5 withdrawal is refused, the rest goes through
+        assert_eq!(engine.snapshot()[&1].available, "5.0".parse()?);
+        assert!(engine.snapshot()[&2].locked);
+
+        let mut out = Vec::new();
+        engine.write_rejections(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, tx, code, reason\n1, 3, R014, max_daily_withdrawal\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aml_flags_leave_balances() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str("[aml.chargebacks]\nmax_rate = \"50\"")?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let mut plain = Engine::new();
+        plain.replay(read_csv(DATA.as_bytes()), None)?;
+        assert_eq!(engine.snapshot(), plain.snapshot());
+
+        let mut out = Vec::new();
+        engine.write_flags(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, rule, detail\n2, chargeback_rate, 1 chargebacks on 1 deposits\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_freeze_policy() -> Result<()> {
+        log_init();
+        let config: Config = toml::from_str("[risk.freeze]\nmax_chargebacks = 0")?;
+        let mut engine = Engine::with_config(config);
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        // A chargeback for a tx of client 1 that was never disputed is
+        // ignored, so it does not count
+        engine.apply(Transaction::new(TransType::Chargeback, 1, 1, None))?;
+        assert!(!engine.snapshot()[&1].locked);
+        assert_eq!(engine.snapshot()[&1].available, "3.5".parse()?);
+
+        let mut out = Vec::new();
+        engine.write_audit_trail(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, tx, event\n2, 2, frozen: 1 chargebacks\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_lifecycle() -> Result<()> {
+        log_init();
+        const LIFECYCLE: &str = "\
+type,            client,     tx,     amount
+open_account,         1,     1,
+deposit,              1,     2,         5.0
+deposit,              2,     3,         1.0
+open_account,         3,     4,
+deposit,              3,     5,         2.0
+close_account,        1,     6,
+withdrawal,           3,     7,         2.0
+close_account,        3,     8,
+deposit,              3,     9,         1.0
+";
+        let config: Config = toml::from_str("strict_onboarding = true")?;
+        let mut engine = Engine::with_config(config);
+        engine.record_events();
+        engine.replay(read_csv(LIFECYCLE.as_bytes()), None)?;
+
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, available, held, total, locked
+1, 5.0, 0, 5.0, false
+",
+            "client 2 was never opened and client 3 was closed empty"
+        );
+        assert!(engine.account(3).is_some_and(|view| view.total.is_zero()));
+        assert_eq!(engine.rejections()[0].violation, Violation::NotOpened);
+        let reasons: Vec<&str> = engine
+            .events()
+            .iter()
+            .filter_map(|event| match event {
+                Event::TransactionRejected { reason, .. } => Some(reason.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons, ["not_opened", "closed"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_accounts() -> Result<()> {
+        log_init();
+        const DUPLICATES: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,        10.0
+deposit,         2,     2,         4.0
+dispute,         2,     2,
+";
+        let mut engine = Engine::new();
+        engine.record_events();
+        engine.replay(read_csv(DUPLICATES.as_bytes()), None)?;
+        let refused = engine.merge_accounts(1, 2, 100).unwrap_err();
+        assert_eq!(
+            refused.to_string(),
+            "client 2 has disputes open, settle them before merging"
+        );
+        assert!(engine.merge_accounts(1, 3, 100).is_err());
+        assert!(engine.merge_accounts(1, 1, 100).is_err());
+
+        engine.replay(
+            read_csv("type,client,tx,amount\nresolve,2,2,\n".as_bytes()),
+            None,
+        )?;
+        engine.merge_accounts(1, 2, 100)?;
+        engine.replay(
+            read_csv("type,client,tx,amount\ndispute,1,2,\nwithdrawal,2,3,1.0\n".as_bytes()),
+            None,
+        )?;
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].client, 1);
+        assert_eq!(accounts[0].available, dec!(9.0));
+        assert_eq!(accounts[0].held, dec!(4.0), "tx 2 came with client 2");
+        assert_eq!(engine.merged_into(2), Some(1));
+        let merged = &engine.audit_trail()[0];
+        assert_eq!((merged.client, merged.tx), (1, 100));
+        assert_eq!(merged.event.to_string(), "merged from client 2");
+
+        let replayed = crate::event::replay_events(engine.events().iter().cloned().map(Ok))?;
+        assert!(crate::snapshot::compare(&engine.snapshot(), &replayed).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        log_init();
+        const CHARGEBACK: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,        10.0
+deposit,         1,     2,         4.0
+dispute,         1,     2,
+chargeback,      1,     2,
+";
+        let mut engine = Engine::new();
+        engine.record_events();
+        engine.replay(read_csv(CHARGEBACK.as_bytes()), None)?;
+        assert!(engine.unlock(2, 100).is_err());
+        engine.unlock(1, 100)?;
+        engine.replay(
+            read_csv(
+                "type,client,tx,amount
+withdrawal,1,3,1.0
+"
+                .as_bytes(),
+            ),
+            None,
+        )?;
+        engine.lock(1, 101)?;
+        engine.replay(
+            read_csv(
+                "type,client,tx,amount
+deposit,1,4,1.0
+"
+                .as_bytes(),
+            ),
+            None,
+        )?;
+        let account = engine.account(1).expect("client 1");
+        assert_eq!(
+            account.available,
+            dec!(9.0),
+            "only the withdrawal went through"
+        );
+        assert!(account.locked);
+        let trail: Vec<String> = engine
+            .audit_trail()
+            .iter()
+            .map(|entry| format!("{} {}", entry.tx, entry.event))
+            .collect();
+        assert_eq!(trail, ["100 unlocked", "101 locked"]);
+        assert!(engine
+            .events()
+            .contains(&Event::AccountUnlocked { client: 1, tx: 100 }));
+
+        let replayed = crate::event::replay_events(engine.events().iter().cloned().map(Ok))?;
+        assert!(crate::snapshot::compare(&engine.snapshot(), &replayed).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_as_of_parse() -> Result<()> {
+        assert_eq!("42".parse::<AsOf>()?, AsOf::Tx(42));
+        assert_eq!(
+            "2022-03-21T12:00:00Z".parse::<AsOf>()?,
+            AsOf::Timestamp("2022-03-21T12:00:00Z".parse()?)
+        );
+        assert!("yesterday".parse::<AsOf>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_final_state() -> Result<()> {
+        log_init();
+        assert_eq!(
+            report(None)?,
+            "client, available, held, total, locked\n\
+             1, 3.5, 0, 3.5, false\n\
+             2, 0.0, 0.0, 0.0, true\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_as_of_tx() -> Result<()> {
+        log_init();
+        assert_eq!(
+            report(Some(AsOf::Tx(2)))?,
+            "client, available, held, total, locked\n\
+             1, 5.0, 0, 5.0, false\n\
+             2, 2.0, 0, 2.0, false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_as_of_timestamp() -> Result<()> {
+        log_init();
+        assert_eq!(
+            report(Some("2022-03-21T12:30:00Z".parse()?))?,
+            "client, available, held, total, locked\n\
+             1, 3.5, 0, 3.5, false\n\
+             2, 0.0, 2.0, 2.0, false\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Rewriting transactions before they are applied
+//!
+//! An [Enricher] gets every transaction first, before the middleware and the
+//! accounts see it, to put it in the engine's terms: map an external merchant
+//! id to a client id, convert an amount, or attach metadata. Whatever it
+//! changes or attaches goes to the audit trail as [crate::AuditEvent::Enriched],
+//! so the report can be traced back to the input. Library users add their own
+//! with [crate::Engine::add_enricher]:
+//! ```
+//! use tte::enrich::Metadata;
+//! use tte::{Engine, Transaction};
+//!
+//! let mut engine = Engine::new();
+//! engine.add_enricher(|transaction: &mut Transaction, metadata: &mut Metadata| {
+//!     metadata.insert("source".to_string(), "bank".to_string());
+//! });
+//! ```
+//! The `tte` tool builds [Builtin]s from the `[[enrich]]` tables of its
+//! config, which run ahead of any added in code:
+//! ```toml
+//! [[enrich]]
+//! kind = "map_clients"
+//! clients = [{ from = 9001, to = 1 }]
+//!
+//! [[enrich]]
+//! kind = "convert"
+//! currency = "USD"
+//! rate = "0.92"
+//! ```
+use crate::transaction::{ClientId, Transaction};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Fields attached to a transaction by name, only kept in the audit trail
+pub type Metadata = BTreeMap<String, String>;
+
+/// Rewrites a transaction in place
+pub trait Enricher {
+    fn enrich(&mut self, transaction: &mut Transaction, metadata: &mut Metadata);
+}
+
+impl<F> Enricher for F
+where
+    F: FnMut(&mut Transaction, &mut Metadata),
+{
+    fn enrich(&mut self, transaction: &mut Transaction, metadata: &mut Metadata) {
+        self(transaction, metadata)
+    }
+}
+
+/// The enrichers that can be configured without code
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Builtin {
+    /// Gives the transactions of the `from` clients to the `to` ones
+    MapClients { clients: Vec<ClientMapping> },
+    /// Multiplies every amount by `rate`, e.g. from `currency` into the one
+    /// the accounts are kept in
+    Convert { currency: String, rate: Decimal },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientMapping {
+    pub from: ClientId,
+    pub to: ClientId,
+}
+
+impl Builtin {
+    pub fn build(&self) -> Box<dyn Enricher + Send> {
+        match self.clone() {
+            Builtin::MapClients { clients } => {
+                let clients: BTreeMap<ClientId, ClientId> = clients
+                    .into_iter()
+                    .map(|mapping| (mapping.from, mapping.to))
+                    .collect();
+                boxed(move |transaction, _| {
+                    if let Some(&to) = clients.get(&transaction.client) {
+                        transaction.client = to;
+                    }
+                })
+            }
+            Builtin::Convert { currency, rate } => boxed(move |transaction, metadata| {
+                if let Some(amount) = &mut transaction.amount {
+                    *amount = (*amount * rate).round_dp(4);
+                    metadata.insert("currency".to_string(), currency.clone());
+                    metadata.insert("rate".to_string(), rate.to_string());
+                }
+            }),
+        }
+    }
+}
+
+/// Gives a closure the signature the blanket [Enricher] impl wants
+fn boxed<F>(enricher: F) -> Box<dyn Enricher + Send>
+where
+    F: FnMut(&mut Transaction, &mut Metadata) + Send + 'static,
+{
+    Box::new(enricher)
+}
+
+/// The columns `after` changed from `before`, as `old -> new`, along with the
+/// `metadata` attached
+pub(crate) fn changes(before: &Transaction, after: &Transaction, metadata: Metadata) -> Metadata {
+    fn or_none<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map_or("none".to_string(), T::to_string)
+    }
+    let mut changes = metadata;
+    let mut changed = |column: &str, old: String, new: String| {
+        if old != new {
+            changes.insert(column.to_string(), format!("{old} -> {new}"));
+        }
+    };
+    changed("type", before.trans.to_string(), after.trans.to_string());
+    changed(
+        "client",
+        before.client.to_string(),
+        after.client.to_string(),
+    );
+    changed("tx", before.tx.to_string(), after.tx.to_string());
+    changed("amount", or_none(&before.amount), or_none(&after.amount));
+    changed(
+        "timestamp",
+        or_none(&before.timestamp),
+        or_none(&after.timestamp),
+    );
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::engine::AuditEvent;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_enrich() -> Result<()> {
+        const CONFIG: &str = r#"
+            [[enrich]]
+            kind = "map_clients"
+            clients = [{ from = 9001, to = 1 }]
+
+            [[enrich]]
+            kind = "convert"
+            currency = "USD"
+            rate = "0.5"
+        "#;
+        let config: Config = toml::from_str(CONFIG)?;
+        let mut engine = Engine::with_config(config);
+        engine.add_enricher(|transaction: &mut Transaction, metadata: &mut Metadata| {
+            if transaction.tx == 3 {
+                metadata.insert("note".to_string(), "refund".to_string());
+            }
+        });
+        let csv = "type,client,tx,amount\ndeposit,9001,1,10.0\ndispute,9001,1,\ndeposit,2,3,4.0\n";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].held, dec!(5.0), "the dispute is mapped too");
+        assert_eq!(accounts[1].total, dec!(2.0));
+
+        let trail = engine.audit_trail();
+        assert_eq!(trail.len(), 3, "every transaction changed");
+        assert_eq!(trail[0].client, 1);
+        let AuditEvent::Enriched(fields) = &trail[0].event else {
+            panic!("{:?} is not enriched", trail[0].event);
+        };
+        assert_eq!(fields["client"], "9001 -> 1");
+        assert_eq!(fields["amount"], "10.0 -> 5.00");
+        assert_eq!(fields["currency"], "USD");
+        assert_eq!(
+            trail[2].event.to_string(),
+            "enriched: amount 4.0 -> 2.00; currency USD; note refund; rate 0.5"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+//! The decisions the engine made, as a stream of events
+//!
+//! Every transaction offered to an engine recording events ends up as exactly
+//! one [Event]: the change it applied, or why it changed nothing. A freeze
+//! adds an [Event::AccountFrozen] right after, and a dispute expiring an
+//! [Event::DisputeAutoResolved] before the transaction it expired at.
+//! Downstream systems can consume these instead of re-deriving the engine's
+//! decisions from the input.
+//! Written as JSON lines by [crate::Engine::write_events].
+//!
+//! The events carry everything needed to rebuild the accounts, which
+//! [replay_events] does to check an event log against the engine's report.
+//! ```text
+//! {"event":"DepositApplied","client":1,"tx":1,"amount":"1.5","fee":"0"}
+//! {"event":"TransactionRejected","client":2,"tx":5,"reason":"insufficient_funds"}
+//! ```
+use crate::reason::Reason;
+use crate::snapshot::{Account, Snapshot};
+use crate::transaction::{ClientId, TxId};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event", try_from = "Fields")]
+pub enum Event {
+    DepositApplied {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+        fee: Decimal,
+    },
+    WithdrawalApplied {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+        fee: Decimal,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    ChargebackApplied {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    /// A dispute left open past its expiry, resolved by the engine
+    DisputeAutoResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    /// A transaction that changed nothing. The reason is the name of a
+    /// [crate::Reason], such as `max_amount` or `insufficient_funds`.
+    TransactionRejected {
+        client: ClientId,
+        tx: TxId,
+        reason: String,
+    },
+    /// A custom transaction type applied by its handler, with the changes it
+    /// made to the balances and whether the account is now locked
+    CustomApplied {
+        client: ClientId,
+        tx: TxId,
+        kind: String,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    },
+    /// The freeze policy locked the account after transaction `tx`
+    AccountFrozen {
+        client: ClientId,
+        tx: TxId,
+        reason: String,
+    },
+    AccountOpened {
+        client: ClientId,
+        tx: TxId,
+    },
+    AccountClosed {
+        client: ClientId,
+        tx: TxId,
+    },
+    /// The account of client `from` was merged into this one by an
+    /// administrative operation going by `tx`, see
+    /// [crate::Engine::merge_accounts]
+    AccountsMerged {
+        client: ClientId,
+        tx: TxId,
+        from: ClientId,
+    },
+    /// An administrative operation going by `tx` locked the account, see
+    /// [crate::Engine::lock]
+    AccountLocked {
+        client: ClientId,
+        tx: TxId,
+    },
+    /// An administrative operation going by `tx` unlocked the account, see
+    /// [crate::Engine::unlock]
+    AccountUnlocked {
+        client: ClientId,
+        tx: TxId,
+    },
+    /// A manual correction moved `amount` into the available funds, for the
+    /// reason with this code, see [crate::Engine::adjust]
+    AccountAdjusted {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+        reason: String,
+    },
+}
+
+/// Every field of any event. Deserializing the tagged enum directly buffers
+/// the fields first, which cannot hold the `u128` tx ids of the `uuid` feature.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fields {
+    event: String,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    fee: Option<Decimal>,
+    reason: Option<String>,
+    kind: Option<String>,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+    from: Option<ClientId>,
+}
+
+impl TryFrom<Fields> for Event {
+    type Error = String;
+
+    fn try_from(f: Fields) -> Result<Self, Self::Error> {
+        let (client, tx) = (f.client, f.tx);
+        let missing = |field| format!("{} event without {field}", f.event);
+        let amount = f.amount.ok_or_else(|| missing("amount"));
+        let reason = f.reason.clone().ok_or_else(|| missing("reason"));
+        Ok(match f.event.as_str() {
+            "DepositApplied" => Event::DepositApplied {
+                client,
+                tx,
+                amount: amount?,
+                fee: f.fee.unwrap_or_default(),
+            },
+            "WithdrawalApplied" => Event::WithdrawalApplied {
+                client,
+                tx,
+                amount: amount?,
+                fee: f.fee.unwrap_or_default(),
+            },
+            "DisputeOpened" => Event::DisputeOpened {
+                client,
+                tx,
+                amount: amount?,
+            },
+            "DisputeResolved" => Event::DisputeResolved {
+                client,
+                tx,
+                amount: amount?,
+            },
+            "ChargebackApplied" => Event::ChargebackApplied {
+                client,
+                tx,
+                amount: amount?,
+            },
+            "DisputeAutoResolved" => Event::DisputeAutoResolved {
+                client,
+                tx,
+                amount: amount?,
+            },
+            "TransactionRejected" => Event::TransactionRejected {
+                client,
+                tx,
+                reason: reason?,
+            },
+            "CustomApplied" => Event::CustomApplied {
+                client,
+                tx,
+                kind: f.kind.clone().ok_or_else(|| missing("kind"))?,
+                available: f.available.ok_or_else(|| missing("available"))?,
+                held: f.held.ok_or_else(|| missing("held"))?,
+                total: f.total.ok_or_else(|| missing("total"))?,
+                locked: f.locked.ok_or_else(|| missing("locked"))?,
+            },
+            "AccountFrozen" => Event::AccountFrozen {
+                client,
+                tx,
+                reason: reason?,
+            },
+            "AccountOpened" => Event::AccountOpened { client, tx },
+            "AccountClosed" => Event::AccountClosed { client, tx },
+            "AccountLocked" => Event::AccountLocked { client, tx },
+            "AccountUnlocked" => Event::AccountUnlocked { client, tx },
+            "AccountsMerged" => Event::AccountsMerged {
+                client,
+                tx,
+                from: f.from.ok_or_else(|| missing("from"))?,
+            },
+            "AccountAdjusted" => Event::AccountAdjusted {
+                client,
+                tx,
+                amount: amount?,
+                reason: reason?,
+            },
+            other => return Err(format!("unknown event {other}")),
+        })
+    }
+}
+
+impl Event {
+    pub fn client(&self) -> ClientId {
+        match self {
+            Event::DepositApplied { client, .. }
+            | Event::WithdrawalApplied { client, .. }
+            | Event::DisputeOpened { client, .. }
+            | Event::DisputeResolved { client, .. }
+            | Event::ChargebackApplied { client, .. }
+            | Event::DisputeAutoResolved { client, .. }
+            | Event::TransactionRejected { client, .. }
+            | Event::CustomApplied { client, .. }
+            | Event::AccountFrozen { client, .. }
+            | Event::AccountOpened { client, .. }
+            | Event::AccountClosed { client, .. }
+            | Event::AccountLocked { client, .. }
+            | Event::AccountUnlocked { client, .. }
+            | Event::AccountsMerged { client, .. }
+            | Event::AccountAdjusted { client, .. } => *client,
+        }
+    }
+
+    pub fn tx(&self) -> TxId {
+        match self {
+            Event::DepositApplied { tx, .. }
+            | Event::WithdrawalApplied { tx, .. }
+            | Event::DisputeOpened { tx, .. }
+            | Event::DisputeResolved { tx, .. }
+            | Event::ChargebackApplied { tx, .. }
+            | Event::DisputeAutoResolved { tx, .. }
+            | Event::TransactionRejected { tx, .. }
+            | Event::CustomApplied { tx, .. }
+            | Event::AccountFrozen { tx, .. }
+            | Event::AccountOpened { tx, .. }
+            | Event::AccountClosed { tx, .. }
+            | Event::AccountLocked { tx, .. }
+            | Event::AccountUnlocked { tx, .. }
+            | Event::AccountsMerged { tx, .. }
+            | Event::AccountAdjusted { tx, .. } => *tx,
+        }
+    }
+}
+
+/// Reads events written by [crate::Engine::write_events]
+pub fn read_events(r: impl io::Read) -> impl Iterator<Item = Result<Event>> {
+    serde_json::Deserializer::from_reader(r)
+        .into_iter()
+        .map(|result| Ok(result?))
+}
+
+/// Rebuilds the accounts from events alone. The result matches the engine's
+/// [crate::Engine::snapshot] for the run that wrote them.
+pub fn replay_events(events: impl IntoIterator<Item = Result<Event>>) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::new();
+    let mut closed = BTreeSet::new();
+    let empty = || Account {
+        available: Decimal::ZERO,
+        held: Decimal::ZERO,
+        total: Decimal::ZERO,
+        locked: false,
+    };
+    for event in events {
+        let event = event?;
+        // These are refused before the client is created
+        if let Event::TransactionRejected { reason, .. } = &event {
+            if reason
+                .parse()
+                .is_ok_and(|reason: Reason| reason.before_account())
+            {
+                continue;
+            }
+        }
+        if let Event::AccountsMerged { client, from, .. } = event {
+            let merged = snapshot.remove(&from).unwrap_or_else(empty);
+            let account = snapshot.entry(client).or_insert_with(empty);
+            account.available += merged.available;
+            account.held += merged.held;
+            account.total += merged.total;
+            account.locked |= merged.locked;
+            if closed.remove(&from) {
+                closed.insert(client);
+            }
+            continue;
+        }
+        let account = snapshot.entry(event.client()).or_insert_with(empty);
+        match event {
+            Event::DepositApplied { amount, fee, .. } => {
+                account.available += amount - fee;
+                account.total += amount - fee;
+            }
+            Event::WithdrawalApplied { amount, fee, .. } => {
+                account.available -= amount + fee;
+                account.total -= amount + fee;
+            }
+            Event::DisputeOpened { amount, .. } => {
+                account.available -= amount;
+                account.held += amount;
+            }
+            Event::DisputeResolved { amount, .. } | Event::DisputeAutoResolved { amount, .. } => {
+                account.available += amount;
+                account.held -= amount;
+            }
+            Event::ChargebackApplied { amount, .. } => {
+                account.held -= amount;
+                account.total -= amount;
+                account.locked = true;
+            }
+            Event::CustomApplied {
+                available,
+                held,
+                total,
+                locked,
+                ..
+            } => {
+                account.available += available;
+                account.held += held;
+                account.total += total;
+                account.locked = locked;
+            }
+            Event::AccountAdjusted { amount, .. } => {
+                account.available += amount;
+                account.total += amount;
+            }
+            Event::AccountFrozen { .. } | Event::AccountLocked { .. } => account.locked = true,
+            Event::AccountUnlocked { .. } => account.locked = false,
+            Event::AccountClosed { client, .. } => {
+                closed.insert(client);
+            }
+            Event::AccountOpened { .. }
+            | Event::TransactionRejected { .. }
+            | Event::AccountsMerged { .. } => {}
+        }
+    }
+    // Accounts closed empty are left out, as in the report
+    snapshot.retain(|client, account| {
+        !(closed.contains(client) && account.total.is_zero() && account.held.is_zero())
+    });
+    for account in snapshot.values_mut() {
+        account.available = account.available.round_dp(4);
+        account.held = account.held.round_dp(4);
+        account.total = account.total.round_dp(4);
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::{Config, Engine};
+
+    #[test]
+    fn test_replay_events() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+deposit,         2,     2,         2.0
+withdrawal,      1,     3,         1.5
+withdrawal,      3,     4,       500.0
+dispute,         2,     2,
+chargeback,      2,     2,
+dispute,         1,     9,
+open_account,    4,    10,
+close_account,   4,    11,
+";
+        let config: Config = toml::from_str(
+            r#"
+fees.withdrawal = { flat = "0.25" }
+risk.max_amount = "100"
+risk.freeze = { max_chargebacks = 0 }
+"#,
+        )?;
+        let mut engine = Engine::with_config(config);
+        engine.record_events();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let mut log = Vec::new();
+        engine.write_events(&mut log)?;
+
+        let replayed = replay_events(read_events(log.as_slice()))?;
+        assert_eq!(replayed, engine.snapshot());
+        assert!(!replayed.contains_key(&3), "client 3 was only ever refused");
+        assert!(!replayed.contains_key(&4), "client 4 was closed empty");
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Fault injection for tests
+//!
+//! Built with the `fault-injection` feature. [Faults] wraps an input so its
+//! reads fail now and then, or a stream of records so some arrive late or
+//! twice, the way a flaky disk or an at-least-once broker would. Everything
+//! is drawn from the seed, so a failing run can be repeated exactly.
+use std::collections::VecDeque;
+use std::io;
+
+/// How often each fault happens, as a chance from 0 to 1. None by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    pub seed: u64,
+    /// Of a read failing
+    pub io_error: f64,
+    /// Of a record being held back
+    pub delay: f64,
+    /// Of a record arriving a second time, later
+    pub duplicate: f64,
+    /// Most records a delayed or duplicated one arrives after
+    pub max_delay: usize,
+}
+
+impl Faults {
+    /// `inner` with reads that fail, and come up short, at random
+    pub fn reader<R>(&self, inner: R) -> FaultyReader<R> {
+        FaultyReader {
+            inner,
+            rng: Rng::new(self.seed),
+            io_error: self.io_error,
+        }
+    }
+
+    /// The records of `inner` with some held back and some repeated
+    pub fn stream<I>(&self, inner: I) -> FaultyStream<I>
+    where
+        I: Iterator,
+        I::Item: Clone,
+    {
+        FaultyStream {
+            inner,
+            rng: Rng::new(self.seed),
+            faults: *self,
+            position: 0,
+            held: VecDeque::new(),
+        }
+    }
+}
+
+/// A xorshift generator, good enough for picking faults and without
+/// dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// True with a chance of `p`
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// From 1 to `max`, or 1 if `max` is 0
+    fn up_to(&mut self, max: usize) -> usize {
+        1 + (self.next() % max.max(1) as u64) as usize
+    }
+}
+
+pub struct FaultyReader<R> {
+    inner: R,
+    rng: Rng,
+    io_error: f64,
+}
+
+impl<R: io::Read> io::Read for FaultyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rng.chance(self.io_error) {
+            return Err(io::Error::other("injected fault"));
+        }
+        let len = self.rng.up_to(buf.len());
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl<R: io::Seek> io::Seek for FaultyReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+pub struct FaultyStream<I: Iterator> {
+    inner: I,
+    rng: Rng,
+    faults: Faults,
+    position: usize,
+    /// Records waiting for the position they arrive at
+    held: VecDeque<(usize, I::Item)>,
+}
+
+impl<I> FaultyStream<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    fn hold(&mut self, item: I::Item) {
+        let due = self.position + self.rng.up_to(self.faults.max_delay);
+        let at = self.held.partition_point(|(held, _)| *held <= due);
+        self.held.insert(at, (due, item));
+    }
+}
+
+impl<I> Iterator for FaultyStream<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        loop {
+            self.position += 1;
+            if self
+                .held
+                .front()
+                .is_some_and(|(due, _)| *due <= self.position)
+            {
+                return self.held.pop_front().map(|(_, item)| item);
+            }
+            let Some(item) = self.inner.next() else {
+                // Nothing new comes, so the held records are all due
+                return self.held.pop_front().map(|(_, item)| item);
+            };
+            if self.rng.chance(self.faults.duplicate) {
+                self.hold(item.clone());
+            }
+            if self.rng.chance(self.faults.delay) {
+                self.hold(item);
+                continue;
+            }
+            return Some(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::{read_checkpoint, replay_checkpointed, write_checkpoint};
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_resume_after_faults() -> Result<()> {
+        let mut data = String::from("type,client,tx,amount\n");
+        for tx in 1..=200 {
+            let row = match tx % 10 {
+                3 => format!("withdrawal,{},{tx},1.5\n", tx % 7),
+                5 => format!("dispute,{},{},\n", (tx - 2) % 7, tx - 2),
+                9 => format!("resolve,{},{},\n", (tx - 6) % 7, tx - 6),
+                _ => format!("deposit,{},{tx},2.0\n", tx % 7),
+            };
+            data.push_str(&row);
+        }
+        let mut clean = Engine::new();
+        clean.replay(read_csv(data.as_bytes()), None)?;
+
+        let mut checkpoint = Vec::new();
+        let mut failures = 0;
+        for seed in 0.. {
+            let faults = Faults {
+                seed,
+                io_error: 0.3,
+                ..Faults::default()
+            };
+            let (mut engine, mut transactions) = if checkpoint.is_empty() {
+                (Engine::new(), read_csv(faults.reader(Cursor::new(&data))))
+            } else {
+                let (engine, position) = read_checkpoint(checkpoint.as_slice())?;
+                let mut transactions = read_csv(faults.reader(Cursor::new(&data)));
+                transactions.seek(position)?;
+                (engine, transactions)
+            };
+            let run = replay_checkpointed(
+                &mut engine,
+                &mut transactions,
+                5,
+                || false,
+                |engine, position| {
+                    checkpoint.clear();
+                    write_checkpoint(&mut checkpoint, engine, position)
+                },
+            );
+            match run {
+                Ok(()) => {
+                    assert!(failures > 0, "no fault was injected");
+                    assert_eq!(engine.snapshot(), clean.snapshot());
+                    return Ok(());
+                }
+                Err(_) => failures += 1,
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_faulty_stream() {
+        let faults = Faults {
+            seed: 7,
+            delay: 0.2,
+            duplicate: 0.2,
+            max_delay: 3,
+            ..Faults::default()
+        };
+        let stream: Vec<u32> = faults.stream(0..100).collect();
+        let again: Vec<u32> = faults.stream(0..100).collect();
+        assert_eq!(stream, again, "the same seed, the same faults");
+        assert!(stream.len() > 100, "some arrive twice");
+        assert!(stream.windows(2).any(|w| w[0] > w[1]), "some are late");
+        let mut seen: Vec<u32> = stream.clone();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, (0..100).collect::<Vec<_>>(), "none is lost");
+    }
+}
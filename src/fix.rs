@@ -0,0 +1,148 @@
+//! FIX drop copy logs, for `tte import --format fix`
+//!
+//! Every line holding a FIX message is read, from its `8=FIX` on, so the
+//! timestamps and directions logging engines put in front do not matter.
+//! Fields are separated by SOH or `|`. Execution reports (`35=8`) that are
+//! fills move the fill's value, `GrossTradeAmt` or else `LastQty` times
+//! `LastPx`, on the order's `Account`: a sell deposits it and a buy withdraws
+//! it. A trade cancel moves it back. Every other message is skipped.
+use crate::import::Entry;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tte::TransType;
+
+const ACCOUNT: u32 = 1;
+const CURRENCY: u32 = 15;
+const LAST_PX: u32 = 31;
+const LAST_QTY: u32 = 32;
+const MSG_TYPE: u32 = 35;
+const SIDE: u32 = 54;
+const TRANSACT_TIME: u32 = 60;
+const EXEC_TYPE: u32 = 150;
+const GROSS_TRADE_AMT: u32 = 381;
+
+pub fn read(log: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in log.lines().enumerate() {
+        let Some(start) = line.find("8=FIX") else {
+            continue;
+        };
+        let entry = fields(&line[start..])
+            .and_then(|fields| fill(&fields))
+            .with_context(|| format!("line {}", number + 1))?;
+        entries.extend(entry);
+    }
+    Ok(entries)
+}
+
+fn fields(message: &str) -> Result<HashMap<u32, &str>> {
+    message
+        .split(['\x01', '|'])
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("field {field} is not TAG=VALUE"))?;
+            let tag = tag.parse().map_err(|_| anyhow!("invalid tag {tag}"))?;
+            Ok((tag, value))
+        })
+        .collect()
+}
+
+/// The money a fill moves, if the message is one
+fn fill(fields: &HashMap<u32, &str>) -> Result<Option<Entry>> {
+    if fields.get(&MSG_TYPE) != Some(&"8") {
+        return Ok(None);
+    }
+    let field = |tag| {
+        fields
+            .get(&tag)
+            .copied()
+            .ok_or_else(|| anyhow!("execution report without tag {tag}"))
+    };
+    let number = |tag| -> Result<Decimal> {
+        let value = field(tag)?;
+        value
+            .parse()
+            .map_err(|_| anyhow!("invalid number {value} in tag {tag}"))
+    };
+    // F is a trade from FIX 4.3 on, 1 and 2 partial and full fills before
+    let cancel = match field(EXEC_TYPE)? {
+        "F" | "1" | "2" => false,
+        "H" => true,
+        _ => return Ok(None),
+    };
+    let sell = match field(SIDE)? {
+        "1" | "3" => false,
+        "2" | "4" | "5" | "6" => true,
+        side => bail!("side {side} is neither a buy nor a sell"),
+    };
+    let amount = if fields.contains_key(&GROSS_TRADE_AMT) {
+        number(GROSS_TRADE_AMT)?
+    } else {
+        number(LAST_QTY)? * number(LAST_PX)?
+    };
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(Entry {
+        account: field(ACCOUNT)?.to_string(),
+        trans: if sell != cancel {
+            TransType::Deposit
+        } else {
+            TransType::Withdrawal
+        },
+        amount: amount.abs().normalize(),
+        currency: fields.get(&CURRENCY).map(|ccy| ccy.to_string()),
+        timestamp: fields
+            .get(&TRANSACT_TIME)
+            .map(|time| timestamp(time))
+            .transpose()?,
+    }))
+}
+
+/// UTCTimestamp, `YYYYMMDD-HH:MM:SS` with optional fractions of a second
+fn timestamp(value: &str) -> Result<DateTime<Utc>> {
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%d-%H:%M:%S%.f")
+        .map_err(|_| anyhow!("invalid TransactTime {value}"))?;
+    Ok(time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let log = "\
+20220321-10:00:00.000 : 8=FIX.4.4|9=100|35=8|1=ACC7|54=1|150=F|32=100|31=1.25|15=USD|60=20220321-10:00:00.123|10=000|
+20220321-10:00:01.000 : 8=FIX.4.4|9=100|35=0|10=000|
+8=FIX.4.2\x019=100\x0135=8\x011=ACC7\x0154=2\x01150=2\x0132=10\x0131=3\x01381=30.50\x0110=000\x01
+8=FIX.4.4|9=100|35=8|1=ACC7|54=1|150=0|32=0|31=0|10=000|
+8=FIX.4.4|9=100|35=8|1=ACC7|54=1|150=H|32=100|31=1.25|10=000|
+";
+        let entries = read(log)?;
+        assert_eq!(entries.len(), 3, "heartbeats and new orders are skipped");
+        assert_eq!(entries[0].account, "ACC7");
+        assert_eq!(entries[0].trans, TransType::Withdrawal, "a buy");
+        assert_eq!(entries[0].amount, dec!(125));
+        assert_eq!(entries[0].currency.as_deref(), Some("USD"));
+        assert_eq!(
+            entries[0].timestamp.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2022-03-21T10:00:00.123+00:00")
+        );
+        assert_eq!(entries[1].trans, TransType::Deposit, "a sell");
+        assert_eq!(entries[1].amount, dec!(30.50), "the gross trade amount");
+        assert_eq!(entries[2].trans, TransType::Deposit, "the buy cancelled");
+        assert_eq!(entries[2].amount, dec!(125));
+
+        assert!(
+            read("8=FIX.4.4|35=8|150=F|54=1|32=1|31=1|").is_err(),
+            "no account"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! `tte follow`, answering account queries from a consumer's saved state
+//!
+//! A follower takes reporting queries off a running `consume`. It reads the
+//! state file the consumer replaces after every batch, locally or from object
+//! storage, and so is never behind it by more than the last batch, plus up to
+//! `max_staleness` during which a state already loaded is answered from
+//! without looking again. A state that cannot be read, say while the object
+//! is being replaced, leaves the last one in use.
+use crate::consume::read_engine;
+use anyhow::Result;
+use log::warn;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use tte::Engine;
+
+pub struct Follower {
+    path: PathBuf,
+    max_staleness: Duration,
+    engine: Engine,
+    loaded: Instant,
+    /// When the loaded state was saved, for local files
+    modified: Option<SystemTime>,
+}
+
+impl Follower {
+    pub fn open(path: PathBuf, max_staleness: Duration) -> Result<Self> {
+        let modified = modified(&path);
+        let engine = read_engine(crate::open(&path)?)?;
+        Ok(Follower {
+            path,
+            max_staleness,
+            engine,
+            loaded: Instant::now(),
+            modified,
+        })
+    }
+
+    /// The engine as last saved, loaded again once `max_staleness` has passed
+    /// and the state changed since
+    pub fn engine(&mut self) -> &Engine {
+        if self.loaded.elapsed() >= self.max_staleness {
+            let modified = modified(&self.path);
+            if modified.is_none() || modified != self.modified {
+                match crate::open(&self.path).and_then(read_engine) {
+                    Ok(engine) => {
+                        self.engine = engine;
+                        self.modified = modified;
+                    }
+                    Err(e) => warn!("answering from the last state, {e:#}"),
+                }
+            }
+            self.loaded = Instant::now();
+        }
+        &self.engine
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tte::read_csv;
+
+    fn save(path: &PathBuf, csv: &str, modified: SystemTime) -> Result<()> {
+        let mut engine = Engine::new();
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+        let state = serde_json::json!({ "last_seq": null, "engine": engine });
+        std::fs::write(path, state.to_string())?;
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_follower() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tte-follow-{}.json", std::process::id()));
+        let first = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        save(&path, "type,client,tx,amount\ndeposit,1,1,5\n", first)?;
+        let mut follower = Follower::open(path.clone(), Duration::ZERO)?;
+        assert_eq!(follower.engine().snapshot().len(), 1);
+
+        let second = first + Duration::from_secs(1);
+        save(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,5\ndeposit,2,2,1\n",
+            second,
+        )?;
+        assert_eq!(follower.engine().snapshot().len(), 2, "saved again");
+
+        follower.max_staleness = Duration::from_secs(3600);
+        follower.loaded = Instant::now();
+        save(
+            &path,
+            "type,client,tx,amount\n",
+            second + Duration::from_secs(1),
+        )?;
+        assert_eq!(
+            follower.engine().snapshot().len(),
+            2,
+            "within the staleness"
+        );
+
+        std::fs::remove_file(&path)?;
+        follower.max_staleness = Duration::ZERO;
+        assert_eq!(follower.engine().snapshot().len(), 2, "the last state kept");
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+//! Custom transaction types
+//!
+//! A `type` the engine does not know, such as `bonus` or `adjustment`, is read
+//! as [crate::TransType::Other]. Library users give such types meaning by
+//! registering a [TransactionHandler] for them with
+//! [crate::Engine::register_handler]. The handler gets the client's account to
+//! change as it sees fit, after the risk limits have passed the transaction.
+//! ```
+//! use tte::{Client, Engine, Transaction};
+//!
+//! let mut engine = Engine::new();
+//! engine.register_handler("bonus", |account: &mut Client, transaction: &Transaction| {
+//!     account.adjust(transaction.amount.unwrap_or_default());
+//!     Ok(())
+//! });
+//! ```
+use crate::client::Client;
+use crate::transaction::Transaction;
+use anyhow::Result;
+
+/// Applies transactions of one custom type to an account
+pub trait TransactionHandler {
+    /// An error aborts the run like any other engine error
+    fn apply(&self, account: &mut Client, transaction: &Transaction) -> Result<()>;
+}
+
+impl<F> TransactionHandler for F
+where
+    F: Fn(&mut Client, &Transaction) -> Result<()>,
+{
+    fn apply(&self, account: &mut Client, transaction: &Transaction) -> Result<()> {
+        self(account, transaction)
+    }
+}
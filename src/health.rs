@@ -0,0 +1,315 @@
+//! `/healthz` and `/readyz` for a running `consume`
+//!
+//! Given `--health-addr`, a consumer answers HTTP GETs on that address from a
+//! thread of its own, for the probes of an orchestrator. `/healthz` is
+//! 200 for as long as the process is up. `/readyz` is 200 while the consumer
+//! keeps up and 503 otherwise, with what it went by as JSON:
+//! ```text
+//! {"ready":true,"lag":12,"in_flight":0,"state_age_secs":0.4}
+//! ```
+//! `lag` is how many messages the broker holds for the consumer, if it tells,
+//! and `in_flight` how many of the batch being applied are not saved yet.
+//! What is applied is only durable once the state is saved after the batch,
+//! so `state_age_secs` is how long ago the saved state last caught up with
+//! everything read, by a save or by a poll that found nothing new.
+//!
+//! `/metrics` has the settlement position of the engine as last saved, see
+//! [tte::settlement::Position], in the Prometheus text format:
+//! ```text
+//! # TYPE tte_customer_liabilities gauge
+//! tte_customer_liabilities 1234.5
+//! # TYPE tte_held gauge
+//! tte_held 20.0
+//! # TYPE tte_chargeback_losses counter
+//! tte_chargeback_losses 5.0
+//! ```
+//!
+//! The server is hyper's, on a runtime of its own. A probe has [READ_TIMEOUT]
+//! to send its request, of no more than [MAX_REQUEST] bytes, and at most
+//! [MAX_CONNECTIONS] are answered at once, so a stuck or hostile client cannot
+//! tie the consumer up.
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use log::warn;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tte::settlement::Position;
+
+/// How often the broker is asked for the lag
+const LAG_EVERY: Duration = Duration::from_secs(5);
+
+/// How long a probe may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a probe may take altogether
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes of request line and headers read, the least hyper allows
+const MAX_REQUEST: usize = 8192;
+
+/// Probes answered at once, any more are turned away
+const MAX_CONNECTIONS: usize = 16;
+
+/// What the consumer last reported, shared with the thread answering probes
+pub struct Health {
+    max_staleness: Duration,
+    max_lag: Option<u64>,
+    state: Mutex<State>,
+}
+
+struct State {
+    lag: Option<u64>,
+    lag_checked: Option<Instant>,
+    in_flight: usize,
+    caught_up: Instant,
+    position: Option<Position>,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    lag: Option<u64>,
+    in_flight: usize,
+    state_age_secs: f64,
+}
+
+impl Health {
+    /// Ready while the saved state is no older than `max_staleness` and the
+    /// lag, when known, no more than `max_lag`
+    pub fn new(max_staleness: Duration, max_lag: Option<u64>) -> Self {
+        Health {
+            max_staleness,
+            max_lag,
+            state: Mutex::new(State {
+                lag: None,
+                lag_checked: None,
+                in_flight: 0,
+                caught_up: Instant::now(),
+                position: None,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the lag should be asked for again
+    pub fn lag_due(&self) -> bool {
+        self.state()
+            .lag_checked
+            .is_none_or(|at| at.elapsed() >= LAG_EVERY)
+    }
+
+    pub fn set_lag(&self, lag: Option<u64>) {
+        let mut state = self.state();
+        state.lag = lag;
+        state.lag_checked = Some(Instant::now());
+    }
+
+    /// A batch of `len` messages was read and is being applied
+    pub fn applying(&self, len: usize) {
+        self.state().in_flight = len;
+    }
+
+    /// The saved state holds everything read so far
+    pub fn caught_up(&self) {
+        let mut state = self.state();
+        state.in_flight = 0;
+        state.caught_up = Instant::now();
+    }
+
+    /// The position of the engine as saved
+    pub fn set_position(&self, position: Position) {
+        self.state().position = Some(position);
+    }
+
+    /// The position as metrics, none before it is known
+    fn metrics(&self) -> String {
+        let Some(position) = self.state().position else {
+            return String::new();
+        };
+        [
+            ("tte_customer_liabilities", "gauge", position.liabilities),
+            ("tte_held", "gauge", position.held),
+            (
+                "tte_chargeback_losses",
+                "counter",
+                position.chargeback_losses,
+            ),
+        ]
+        .iter()
+        .map(|(name, kind, value)| format!("# TYPE {name} {kind}\n{name} {value}\n"))
+        .collect()
+    }
+
+    fn readiness(&self) -> Readiness {
+        let state = self.state();
+        let age = state.caught_up.elapsed();
+        let lagging = matches!((state.lag, self.max_lag), (Some(lag), Some(max)) if lag > max);
+        Readiness {
+            ready: age <= self.max_staleness && !lagging,
+            lag: state.lag,
+            in_flight: state.in_flight,
+            state_age_secs: age.as_secs_f64(),
+        }
+    }
+
+    /// Answers the probes on `addr` from a thread of its own, returning the
+    /// address bound, e.g. for port 0
+    pub fn serve(self: &Arc<Self>, addr: &str) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("could not listen on {addr}"))?;
+        listener.set_nonblocking(true)?;
+        let bound = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let health = Arc::clone(self);
+        thread::spawn(move || {
+            runtime.block_on(async {
+                match TcpListener::from_std(listener) {
+                    Ok(listener) => health.accept(listener).await,
+                    Err(e) => warn!("health probes: {e}"),
+                }
+            })
+        });
+        Ok(bound)
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("health probe: {e}");
+                    continue;
+                }
+            };
+            let Ok(permit) = Arc::clone(&connections).try_acquire_owned() else {
+                warn!("health probe: {MAX_CONNECTIONS} probes at once already");
+                continue;
+            };
+            let health = Arc::clone(&self);
+            tokio::spawn(async move {
+                let service = service_fn(|request| {
+                    let response = health.answer(&request);
+                    async move { Ok::<_, Infallible>(response) }
+                });
+                let connection = http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(READ_TIMEOUT)
+                    .max_buf_size(MAX_REQUEST)
+                    .keep_alive(false)
+                    .serve_connection(TokioIo::new(stream), service);
+                match tokio::time::timeout(CONNECTION_TIMEOUT, connection).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("health probe: {e}"),
+                    Err(_) => warn!("health probe: no answer in {CONNECTION_TIMEOUT:?}"),
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    fn answer<B>(&self, request: &Request<B>) -> Response<Full<Bytes>> {
+        let (status, content_type, body) = match request.uri().path() {
+            "/healthz" => (StatusCode::OK, "text/plain", "ok\n".to_string()),
+            "/readyz" => {
+                let readiness = self.readiness();
+                let status = if readiness.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                let body = serde_json::to_string(&readiness).unwrap_or_default() + "\n";
+                (status, "application/json", body)
+            }
+            "/metrics" => (StatusCode::OK, "text/plain; version=0.0.4", self.metrics()),
+            _ => (
+                StatusCode::NOT_FOUND,
+                "text/plain",
+                "not found\n".to_string(),
+            ),
+        };
+        let mut response = Response::new(Full::new(Bytes::from(body)));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, content_type.parse().expect("a valid header"));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn get(addr: SocketAddr, path: &str) -> Result<String> {
+        get_with(addr, path, "")
+    }
+
+    fn get_with(addr: SocketAddr, path: &str, headers: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n"
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_probes() -> Result<()> {
+        let health = Arc::new(Health::new(Duration::from_secs(60), Some(100)));
+        let addr = health.serve("127.0.0.1:0")?;
+        assert!(get(addr, "/healthz")?.starts_with("HTTP/1.1 200 OK"));
+
+        health.applying(7);
+        health.set_lag(Some(12));
+        let ready = get(addr, "/readyz")?;
+        assert!(ready.starts_with("HTTP/1.1 200 OK"));
+        assert!(ready.contains(r#""lag":12,"in_flight":7"#), "{ready}");
+
+        health.set_lag(Some(101));
+        assert!(get(addr, "/readyz")?.starts_with("HTTP/1.1 503"));
+        assert!(!health.lag_due(), "just asked");
+        assert!(
+            get(addr, "/metrics")?.ends_with("\r\n\r\n"),
+            "not known yet"
+        );
+        health.set_position(Position {
+            liabilities: "1234.5".parse()?,
+            ..Position::default()
+        });
+        let metrics = get(addr, "/metrics")?;
+        assert!(
+            metrics.contains("\ntte_customer_liabilities 1234.5\n"),
+            "{metrics}"
+        );
+        assert!(metrics.contains("# TYPE tte_chargeback_losses counter\n"));
+        assert!(get(addr, "/nope")?.starts_with("HTTP/1.1 404"));
+        let huge = format!("X-Padding: {}\r\n", "x".repeat(MAX_REQUEST));
+        assert!(
+            get_with(addr, "/healthz", &huge)?.starts_with("HTTP/1.1 431"),
+            "headers past the limit are turned away"
+        );
+        Ok(())
+    }
+}
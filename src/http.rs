@@ -0,0 +1,194 @@
+//! `http://` and `https://` URLs as input files
+//!
+//! The body is streamed straight into the reader. A connection that drops or
+//! a server error mid-download is retried with a `Range` request for the rest,
+//! so a long download survives transient failures. A bearer token for the
+//! server is taken from the `TTE_HTTP_TOKEN` environment variable, which keeps
+//! it out of the process list.
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// Attempts at each point of the download before giving up
+const RETRIES: u32 = 5;
+const TOKEN_VAR: &str = "TTE_HTTP_TOKEN";
+
+pub fn is_url(path: &std::path::Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+type Body = Box<dyn Read + Send + Sync>;
+
+/// Streams a download, resuming from where it broke off. Seeking starts a new
+/// ranged download, which lets checkpointed runs resume on a URL.
+pub struct HttpReader {
+    url: String,
+    token: Option<String>,
+    body: Body,
+    position: u64,
+}
+
+impl HttpReader {
+    pub fn open(url: &str) -> io::Result<Self> {
+        let token = std::env::var(TOKEN_VAR).ok();
+        let body = retry(|| get(url, token.as_deref(), 0))?;
+        Ok(HttpReader {
+            url: url.to_string(),
+            token,
+            body,
+            position: 0,
+        })
+    }
+}
+
+/// Calls `f` until it succeeds or fails for good, backing off in between
+fn retry<T>(mut f: impl FnMut() -> Result<T, Failure>) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(Failure::Transient(e)) if attempt < RETRIES => {
+                log::warn!("{e}, retrying");
+                thread::sleep(Duration::from_millis(100 << attempt));
+                attempt += 1;
+            }
+            Err(Failure::Transient(e) | Failure::Permanent(e)) => return Err(e),
+        }
+    }
+}
+
+enum Failure {
+    Transient(io::Error),
+    Permanent(io::Error),
+}
+
+/// Requests the body from `offset` on
+fn get(url: &str, token: Option<&str>, offset: u64) -> Result<Body, Failure> {
+    let mut request = ureq::get(url);
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={offset}-"));
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) if status == 429 || status >= 500 => {
+            return Err(Failure::Transient(io::Error::other(format!(
+                "{url}: status {status}"
+            ))))
+        }
+        Err(ureq::Error::Status(status, _)) => {
+            return Err(Failure::Permanent(io::Error::other(format!(
+                "{url}: status {status}"
+            ))))
+        }
+        Err(e @ ureq::Error::Transport(_)) => return Err(Failure::Transient(io::Error::other(e))),
+    };
+    let partial = response.status() == 206;
+    let mut body = response.into_reader();
+    if offset > 0 && !partial {
+        // The server ignored the range and sent everything again
+        io::copy(&mut body.by_ref().take(offset), &mut io::sink()).map_err(Failure::Transient)?;
+    }
+    Ok(body)
+}
+
+impl io::Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 1;
+        loop {
+            match self.body.read(buf) {
+                Ok(n) => {
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if attempt < RETRIES => {
+                    log::warn!("{}: {e} at byte {}, resuming", self.url, self.position);
+                    thread::sleep(Duration::from_millis(100 << attempt));
+                    self.body = retry(|| get(&self.url, self.token.as_deref(), self.position))?;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl io::Seek for HttpReader {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let io::SeekFrom::Start(offset) = pos else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "URLs can only be sought from the start",
+            ));
+        };
+        self.body = retry(|| get(&self.url, self.token.as_deref(), offset))?;
+        self.position = offset;
+        Ok(offset)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_resume() -> io::Result<()> {
+        const BODY: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/transactions.csv", listener.local_addr()?);
+        let server = thread::spawn(move || -> io::Result<Vec<String>> {
+            let mut ranges = Vec::new();
+            for (i, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream?;
+                let mut range = String::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line?;
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = value.to_string();
+                        }
+                    }
+                }
+                ranges.push(range);
+                if i == 0 {
+                    // Promise the whole body but drop the connection halfway
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        BODY.len(),
+                        &BODY[..30]
+                    )?;
+                } else {
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\r\n{}",
+                        BODY.len() - 30,
+                        &BODY[30..]
+                    )?;
+                }
+            }
+            Ok(ranges)
+        });
+
+        let mut body = String::new();
+        HttpReader::open(&url)?.read_to_string(&mut body)?;
+        assert_eq!(body, BODY);
+        assert_eq!(server.join().unwrap()?, ["", "bytes=30-"]);
+        assert!(is_url(url.as_ref()));
+        assert!(!is_url("transactions.csv".as_ref()));
+        Ok(())
+    }
+}
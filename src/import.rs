@@ -0,0 +1,146 @@
+//! `tte import`, turning bank, personal finance and brokerage files into
+//! transactions the engine reads
+//!
+//! Each format is read into [Entry]s naming the account they move money on.
+//! Accounts are mapped to clients by a CSV file with `account,client` columns,
+//! and an account without a mapping that is a number is taken as the client id
+//! itself. The transactions are numbered in file order.
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use tte::{ClientId, TransType, Transaction, TxId};
+
+/// The formats `tte import` reads
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// FIX drop copy logs, whose fills move money on the order's account
+    Fix,
+    /// ISO 20022 XML, camt.053 statements or pain.001 payment initiations
+    #[cfg(feature = "iso20022")]
+    Iso20022,
+    /// OFX bank and credit card statements
+    Ofx,
+    /// QIF exports, whose file name is the account unless they name it
+    Qif,
+}
+
+/// A deposit or withdrawal found in an imported file
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// The account the money is credited to or debited from, as the file
+    /// names it, e.g. an IBAN
+    pub account: String,
+    /// [TransType::Deposit] or [TransType::Withdrawal]
+    pub trans: TransType,
+    pub amount: Decimal,
+    pub currency: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct Mapping {
+    account: String,
+    client: ClientId,
+}
+
+/// Which client each account of the imported files belongs to
+#[derive(Default)]
+pub struct Clients(HashMap<String, ClientId>);
+
+impl Clients {
+    pub fn read(csv: impl io::Read) -> Result<Self> {
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(csv);
+        let mut clients = HashMap::new();
+        for mapping in rdr.into_deserialize() {
+            let Mapping { account, client } = mapping?;
+            clients.insert(account, client);
+        }
+        Ok(Clients(clients))
+    }
+
+    fn client(&self, account: &str) -> Result<ClientId> {
+        match self.0.get(account) {
+            Some(&client) => Ok(client),
+            None => account
+                .parse()
+                .map_err(|_| anyhow!("no client for account {account}, map it with --clients")),
+        }
+    }
+}
+
+/// The entries as transactions numbered from `first_tx`. Every entry must be
+/// in the same currency, since balances have none.
+pub fn transactions(
+    entries: Vec<Entry>,
+    clients: &Clients,
+    first_tx: TxId,
+) -> Result<Vec<Transaction>> {
+    let mut currency = None;
+    let mut transactions = Vec::with_capacity(entries.len());
+    for (tx, entry) in (first_tx..).zip(entries) {
+        if let Some(ccy) = entry.currency {
+            match &currency {
+                None => currency = Some(ccy),
+                Some(seen) if *seen != ccy => bail!("amounts in both {seen} and {ccy}"),
+                Some(_) => {}
+            }
+        }
+        let client = clients
+            .client(&entry.account)
+            .with_context(|| format!("entry {tx}"))?;
+        let mut transaction = Transaction::new(entry.trans, client, tx, Some(entry.amount));
+        transaction.timestamp = entry.timestamp;
+        transactions.push(transaction);
+    }
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn entry(account: &str, trans: TransType, currency: &str) -> Entry {
+        Entry {
+            account: account.to_string(),
+            trans,
+            amount: dec!(1.5),
+            currency: Some(currency.to_string()),
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_transactions() -> Result<()> {
+        let clients = Clients::read("account,client\nDE89370400440532013000,7\n".as_bytes())?;
+        let transactions = transactions(
+            vec![
+                entry("DE89370400440532013000", TransType::Deposit, "EUR"),
+                entry("42", TransType::Withdrawal, "EUR"),
+            ],
+            &clients,
+            10,
+        )?;
+        assert_eq!(
+            transactions,
+            [
+                Transaction::new(TransType::Deposit, 7, 10, Some(dec!(1.5))),
+                Transaction::new(TransType::Withdrawal, 42, 11, Some(dec!(1.5))),
+            ]
+        );
+
+        let unmapped = vec![entry("GB33BUKB20201555555555", TransType::Deposit, "EUR")];
+        assert!(super::transactions(unmapped, &clients, 1).is_err());
+        let mixed = vec![
+            entry("1", TransType::Deposit, "EUR"),
+            entry("1", TransType::Deposit, "USD"),
+        ];
+        assert!(super::transactions(mixed, &clients, 1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! ISO 20022 XML bank files, for `tte import --format iso20022`
+//!
+//! The message is told apart by its root element:
+//! * camt.053 account statements. Booked entries become deposits when they
+//!   credit the statement's account and withdrawals when they debit it.
+//!   Pending entries are left out.
+//! * pain.001 payment initiations. Every credit transfer becomes a withdrawal
+//!   from the debtor account, stamped with the requested execution date.
+//!
+//! Elements are matched by their local name, so any version of either message
+//! is read.
+use crate::import::Entry;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use roxmltree::{Document, Node};
+use rust_decimal::Decimal;
+use tte::TransType;
+
+pub fn read(xml: &str) -> Result<Vec<Entry>> {
+    let document = Document::parse(xml)?;
+    let message = document
+        .root_element()
+        .first_element_child()
+        .context("empty document")?;
+    match message.tag_name().name() {
+        "BkToCstmrStmt" => statement(message),
+        "CstmrCdtTrfInitn" => payment_initiation(message),
+        name => bail!("{name} is neither a camt.053 statement nor a pain.001 payment initiation"),
+    }
+}
+
+/// camt.053
+fn statement(message: Node) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for statement in children(message, "Stmt") {
+        let account = account(child(statement, "Acct")?)?;
+        for entry in children(statement, "Ntry") {
+            if !booked(entry) {
+                continue;
+            }
+            let (amount, currency) = amount(child(entry, "Amt")?)?;
+            let trans = match text(child(entry, "CdtDbtInd")?) {
+                "CRDT" => TransType::Deposit,
+                "DBIT" => TransType::Withdrawal,
+                indicator => bail!("unknown credit/debit indicator {indicator}"),
+            };
+            let timestamp = child(entry, "BookgDt").ok().map(date).transpose()?;
+            entries.push(Entry {
+                account: account.clone(),
+                trans,
+                amount,
+                currency,
+                timestamp,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether the status, a code of its own or inside `Cd` from camt.053.001.08
+/// on, is booked
+fn booked(entry: Node) -> bool {
+    child(entry, "Sts").is_ok_and(|status| {
+        let code = child(status, "Cd").unwrap_or(status);
+        text(code) == "BOOK"
+    })
+}
+
+/// pain.001
+fn payment_initiation(message: Node) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for payment in children(message, "PmtInf") {
+        let account = account(child(payment, "DbtrAcct")?)?;
+        let timestamp = child(payment, "ReqdExctnDt").ok().map(date).transpose()?;
+        for transfer in children(payment, "CdtTrfTxInf") {
+            let (amount, currency) = amount(child(child(transfer, "Amt")?, "InstdAmt")?)?;
+            entries.push(Entry {
+                account: account.clone(),
+                trans: TransType::Withdrawal,
+                amount,
+                currency,
+                timestamp,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn children<'a, 'i>(node: Node<'a, 'i>, name: &'static str) -> impl Iterator<Item = Node<'a, 'i>> {
+    node.children()
+        .filter(move |child| child.tag_name().name() == name)
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Result<Node<'a, 'i>> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .ok_or_else(|| {
+            anyhow!(
+                "no {name} in {} at byte {}",
+                node.tag_name().name(),
+                node.range().start
+            )
+        })
+}
+
+fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or_default().trim()
+}
+
+/// The IBAN of an account, or its other identification
+fn account(node: Node) -> Result<String> {
+    let id = child(node, "Id")?;
+    let id = child(id, "IBAN").or_else(|_| child(child(id, "Othr")?, "Id"))?;
+    Ok(text(id).to_string())
+}
+
+fn amount(node: Node) -> Result<(Decimal, Option<String>)> {
+    let amount = text(node)
+        .parse()
+        .with_context(|| format!("invalid amount {}", text(node)))?;
+    Ok((amount, node.attribute("Ccy").map(str::to_string)))
+}
+
+/// A date or date and time, either on its own or inside `Dt` or `DtTm`.
+/// Times without an offset are taken as UTC, as are dates at midnight.
+fn date(node: Node) -> Result<DateTime<Utc>> {
+    let node = child(node, "DtTm")
+        .or_else(|_| child(node, "Dt"))
+        .unwrap_or(node);
+    let value = text(node);
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.to_utc());
+    }
+    if let Ok(timestamp) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Ok(timestamp.and_utc());
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("invalid date {value}"))?;
+    Ok(date.and_time(Default::default()).and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let camt = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>STMT-1</MsgId></GrpHdr>
+    <Stmt>
+      <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+      <Ntry>
+        <Amt Ccy="EUR">100.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2022-03-21</Dt></BookgDt>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">20</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><DtTm>2022-03-22T10:30:00+01:00</DtTm></BookgDt>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">5</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>PDNG</Sts>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+        let entries = read(camt)?;
+        assert_eq!(entries.len(), 2, "the pending entry is left out");
+        assert_eq!(entries[0].account, "DE89370400440532013000");
+        assert_eq!(entries[0].trans, TransType::Deposit);
+        assert_eq!(entries[0].amount, dec!(100.50));
+        assert_eq!(entries[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(
+            entries[0].timestamp.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2022-03-21T00:00:00+00:00")
+        );
+        assert_eq!(entries[1].trans, TransType::Withdrawal);
+        assert_eq!(
+            entries[1].timestamp.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2022-03-22T09:30:00+00:00")
+        );
+
+        let pain = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <PmtInf>
+      <ReqdExctnDt><Dt>2022-03-23</Dt></ReqdExctnDt>
+      <DbtrAcct><Id><Othr><Id>7</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf><Amt><InstdAmt Ccy="EUR">1.25</InstdAmt></Amt></CdtTrfTxInf>
+      <CdtTrfTxInf><Amt><InstdAmt Ccy="EUR">2</InstdAmt></Amt></CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+        let entries = read(pain)?;
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|e| e.account == "7" && e.trans == TransType::Withdrawal));
+        assert_eq!(entries[1].amount, dec!(2));
+
+        assert!(read("<Document><Other/></Document>").is_err());
+        Ok(())
+    }
+}
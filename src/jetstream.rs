@@ -0,0 +1,136 @@
+//! `tte consume --nats`, reading transactions from NATS JetStream
+//!
+//! Messages carry the transaction as a JSON object with the CSV columns:
+//! ```text
+//! nats pub tx.eu '{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}'
+//! ```
+//! They are read through a durable pull consumer on the stream holding the
+//! subject, created if it does not exist yet, so messages never acknowledged
+//! are delivered again. With a publish subject, the balances of the accounts
+//! each batch touched go out as JSON before the batch is acknowledged, so
+//! every update is published at least once.
+use crate::consume::{Message, Source};
+use crate::json_fields;
+use crate::runtime;
+use anyhow::{anyhow, Context, Result};
+use async_nats::jetstream::consumer::{pull, AckPolicy, PullConsumer};
+use async_nats::jetstream::{self, Message as JetStreamMessage};
+use futures::StreamExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tte::AccountView;
+
+/// How long a read waits for new messages before handing back control
+const EXPIRES: Duration = Duration::from_secs(1);
+
+pub struct JetStream {
+    client: async_nats::Client,
+    consumer: PullConsumer,
+    publish: Option<String>,
+    /// Messages handed out but not acknowledged yet, by id
+    unacked: HashMap<String, JetStreamMessage>,
+}
+
+impl JetStream {
+    pub fn open(url: &str, subject: &str, durable: &str, publish: Option<&str>) -> Result<Self> {
+        runtime().block_on(async {
+            let client = async_nats::connect(url)
+                .await
+                .with_context(|| format!("could not connect to {url}"))?;
+            let context = jetstream::new(client.clone());
+            let stream = context
+                .stream_by_subject(subject)
+                .await
+                .with_context(|| format!("no stream holds {subject}"))?;
+            let consumer = context
+                .get_stream(&stream)
+                .await?
+                .get_or_create_consumer(
+                    durable,
+                    pull::Config {
+                        durable_name: Some(durable.to_string()),
+                        filter_subject: subject.to_string(),
+                        ack_policy: AckPolicy::Explicit,
+                        ..pull::Config::default()
+                    },
+                )
+                .await
+                .with_context(|| format!("could not create consumer {durable}"))?;
+            Ok(JetStream {
+                client,
+                consumer,
+                publish: publish.map(str::to_string),
+                unacked: HashMap::new(),
+            })
+        })
+    }
+}
+
+impl Source for JetStream {
+    fn poll(&mut self, max: usize) -> Result<Vec<Message>> {
+        let received: Vec<JetStreamMessage> = runtime().block_on(async {
+            let batch = self
+                .consumer
+                .batch()
+                .max_messages(max)
+                .expires(EXPIRES)
+                .messages()
+                .await?;
+            let received: Result<_, _> = batch.collect::<Vec<_>>().await.into_iter().collect();
+            received.map_err(|e| anyhow!(e))
+        })?;
+        let mut messages = Vec::with_capacity(received.len());
+        for message in received {
+            let sequence = message.info().map_err(|e| anyhow!(e))?.stream_sequence;
+            let id = sequence.to_string();
+            messages.push(Message {
+                id: id.clone(),
+                seq: Some((sequence, 0)),
+                fields: json_fields(&message.payload),
+            });
+            self.unacked.insert(id, message);
+        }
+        Ok(messages)
+    }
+
+    fn ack(&mut self, messages: &[Message]) -> Result<()> {
+        runtime().block_on(async {
+            for message in messages {
+                if let Some(message) = self.unacked.remove(&message.id) {
+                    message.ack().await.map_err(|e| anyhow!(e))?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn publish(&mut self, accounts: &[AccountView]) -> Result<()> {
+        let Some(subject) = &self.publish else {
+            return Ok(());
+        };
+        runtime().block_on(async {
+            for account in accounts {
+                let update = json!({
+                    "client": account.client,
+                    "available": account.available.to_string(),
+                    "held": account.held.to_string(),
+                    "total": account.total.to_string(),
+                    "locked": account.locked,
+                });
+                self.client
+                    .publish(subject.clone(), update.to_string().into())
+                    .await?;
+            }
+            self.client.flush().await?;
+            Ok(())
+        })
+    }
+
+    fn lag(&mut self) -> Result<Option<u64>> {
+        let info = runtime()
+            .block_on(self.consumer.info())
+            .map_err(|e| anyhow!(e))?;
+        Ok(Some(info.num_pending))
+    }
+}
@@ -0,0 +1,419 @@
+//! Double-entry bookkeeping of every balance change
+//!
+//! Once [crate::Engine::keep_journal] is called, every change the engine makes
+//! to a balance is posted to a [Ledger] as a [JournalEntry] whose postings sum
+//! to zero, so the books as a whole always do too. Each client has an
+//! `available` and a `held` account, next to the engine's own:
+//!
+//! * `bank_settlement` -- the money moved through the bank by deposits,
+//!   withdrawals, adjustments and custom types, carrying everything else with
+//!   the opposite sign
+//! * `fees` -- every fee charged
+//! * `chargeback_loss` -- what chargebacks took back from held funds
+//! * `opening_balances` -- what the accounts held when the books were opened,
+//!   e.g. seeded from an earlier report, with the opposite sign
+//!
+//! A deposit of 10.0 with a fee of 1.0, say, posts 9.0 to the client's
+//! `available`, 1.0 to `fees` and -10.0 to `bank_settlement`. The entries are
+//! posted from the same [Event]s the engine emits, and the client accounts of
+//! the ledger match the report, see [Ledger::unbalanced].
+use crate::event::Event;
+use crate::snapshot::Snapshot;
+use crate::transaction::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// An account of the ledger, written e.g. `client 1 available` or `fees`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum LedgerAccount {
+    Available(ClientId),
+    Held(ClientId),
+    BankSettlement,
+    Fees,
+    ChargebackLoss,
+    OpeningBalances,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client) => write!(f, "client {client} available"),
+            LedgerAccount::Held(client) => write!(f, "client {client} held"),
+            LedgerAccount::BankSettlement => write!(f, "bank_settlement"),
+            LedgerAccount::Fees => write!(f, "fees"),
+            LedgerAccount::ChargebackLoss => write!(f, "chargeback_loss"),
+            LedgerAccount::OpeningBalances => write!(f, "opening_balances"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let client = |client: &str| {
+            client
+                .parse()
+                .map_err(|_| format!("'{s}' is no ledger account"))
+        };
+        match s.split(' ').collect::<Vec<_>>()[..] {
+            ["bank_settlement"] => Ok(LedgerAccount::BankSettlement),
+            ["fees"] => Ok(LedgerAccount::Fees),
+            ["chargeback_loss"] => Ok(LedgerAccount::ChargebackLoss),
+            ["opening_balances"] => Ok(LedgerAccount::OpeningBalances),
+            ["client", id, "available"] => Ok(LedgerAccount::Available(client(id)?)),
+            ["client", id, "held"] => Ok(LedgerAccount::Held(client(id)?)),
+            _ => Err(format!("'{s}' is no ledger account")),
+        }
+    }
+}
+
+impl From<LedgerAccount> for String {
+    fn from(account: LedgerAccount) -> String {
+        account.to_string()
+    }
+}
+
+impl TryFrom<String> for LedgerAccount {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The postings of one balance change, by the event that made it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub client: ClientId,
+    /// None for an opening balance, which no transaction made
+    pub tx: Option<TxId>,
+    /// The name of the event, e.g. `DepositApplied`
+    pub event: String,
+    /// How much each account went up, or down when negative, summing to zero
+    pub postings: Vec<(LedgerAccount, Decimal)>,
+}
+
+/// The balances of every account and the journal of how they came about
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    balances: BTreeMap<LedgerAccount, Decimal>,
+    journal: Vec<JournalEntry>,
+}
+
+impl Ledger {
+    /// Posts the balance change of `event`, if it made one
+    pub fn post(&mut self, event: &Event) {
+        use LedgerAccount::*;
+        let (name, postings) = match *event {
+            Event::DepositApplied {
+                client,
+                amount,
+                fee,
+                ..
+            } => (
+                "DepositApplied",
+                vec![
+                    (Available(client), amount - fee),
+                    (Fees, fee),
+                    (BankSettlement, -amount),
+                ],
+            ),
+            Event::WithdrawalApplied {
+                client,
+                amount,
+                fee,
+                ..
+            } => (
+                "WithdrawalApplied",
+                vec![
+                    (Available(client), -amount - fee),
+                    (Fees, fee),
+                    (BankSettlement, amount),
+                ],
+            ),
+            Event::DisputeOpened { client, amount, .. } => (
+                "DisputeOpened",
+                vec![(Available(client), -amount), (Held(client), amount)],
+            ),
+            Event::DisputeResolved { client, amount, .. } => (
+                "DisputeResolved",
+                vec![(Held(client), -amount), (Available(client), amount)],
+            ),
+            Event::DisputeAutoResolved { client, amount, .. } => (
+                "DisputeAutoResolved",
+                vec![(Held(client), -amount), (Available(client), amount)],
+            ),
+            Event::ChargebackApplied { client, amount, .. } => (
+                "ChargebackApplied",
+                vec![(Held(client), -amount), (ChargebackLoss, amount)],
+            ),
+            Event::CustomApplied {
+                client,
+                available,
+                held,
+                ..
+            } => (
+                "CustomApplied",
+                vec![
+                    (Available(client), available),
+                    (Held(client), held),
+                    (BankSettlement, -available - held),
+                ],
+            ),
+            Event::AccountAdjusted { client, amount, .. } => (
+                "AccountAdjusted",
+                vec![(Available(client), amount), (BankSettlement, -amount)],
+            ),
+            Event::AccountsMerged { client, from, .. } => {
+                let available = self.balance(Available(from));
+                let held = self.balance(Held(from));
+                (
+                    "AccountsMerged",
+                    vec![
+                        (Available(from), -available),
+                        (Available(client), available),
+                        (Held(from), -held),
+                        (Held(client), held),
+                    ],
+                )
+            }
+            Event::TransactionRejected { .. }
+            | Event::AccountFrozen { .. }
+            | Event::AccountOpened { .. }
+            | Event::AccountClosed { .. }
+            | Event::AccountLocked { .. }
+            | Event::AccountUnlocked { .. } => return,
+        };
+        self.record(event.client(), Some(event.tx()), name, postings);
+    }
+
+    /// Opens the books of `client` at the balances given, e.g. seeded from a
+    /// report, posting the difference from what the books hold against
+    /// `opening_balances`
+    pub fn open(&mut self, client: ClientId, available: Decimal, held: Decimal) {
+        use LedgerAccount::*;
+        let available = available - self.balance(Available(client));
+        let held = held - self.balance(Held(client));
+        let postings = vec![
+            (Available(client), available),
+            (Held(client), held),
+            (OpeningBalances, -available - held),
+        ];
+        self.record(client, None, "OpeningBalance", postings);
+    }
+
+    fn record(
+        &mut self,
+        client: ClientId,
+        tx: Option<TxId>,
+        name: &str,
+        postings: Vec<(LedgerAccount, Decimal)>,
+    ) {
+        let postings: Vec<_> = postings
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .collect();
+        if postings.is_empty() {
+            return;
+        }
+        for &(account, amount) in &postings {
+            *self.balances.entry(account).or_default() += amount;
+        }
+        self.journal.push(JournalEntry {
+            client,
+            tx,
+            event: name.to_string(),
+            postings,
+        });
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Every account with its balance, in account order
+    pub fn balances(&self) -> &BTreeMap<LedgerAccount, Decimal> {
+        &self.balances
+    }
+
+    /// The sum of every balance, which is zero unless the books are broken
+    pub fn sum(&self) -> Decimal {
+        self.balances.values().sum()
+    }
+
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    /// Takes in the books kept by another engine
+    pub fn merge(&mut self, other: Ledger) {
+        for (account, amount) in other.balances {
+            *self.balances.entry(account).or_default() += amount;
+        }
+        self.journal.extend(other.journal);
+    }
+
+    /// Where the books and `snapshot`, the accounts report, disagree: a
+    /// client whose available or held funds differ, or books that do not sum
+    /// to zero
+    pub fn unbalanced(&self, snapshot: &Snapshot) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.sum().is_zero() {
+            problems.push(format!("the books sum to {}", self.sum()));
+        }
+        for (&client, account) in snapshot {
+            let books = [
+                (
+                    "available",
+                    LedgerAccount::Available(client),
+                    account.available,
+                ),
+                ("held", LedgerAccount::Held(client), account.held),
+            ];
+            for (name, ledger, reported) in books {
+                let booked = self.balance(ledger).round_dp(4);
+                if booked != reported {
+                    problems.push(format!(
+                        "client {client}: {name} is {reported}, the books say {booked}"
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Writes a line per posting, those of an entry together. An opening
+    /// balance has no tx.
+    /// ```text
+    /// client, tx, event, account, amount
+    /// 1, 1, DepositApplied, client 1 available, 9.0
+    /// 1, 1, DepositApplied, fees, 1.0
+    /// 1, 1, DepositApplied, bank_settlement, -10.0
+    /// 2, , OpeningBalance, client 2 available, 5.0
+    /// 2, , OpeningBalance, opening_balances, -5.0
+    /// ```
+    pub fn write_journal(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "client, tx, event, account, amount")?;
+        for entry in &self.journal {
+            let tx = entry.tx.map(|tx| tx.to_string()).unwrap_or_default();
+            for (account, amount) in &entry.postings {
+                writeln!(
+                    w,
+                    "{}, {tx}, {}, {account}, {amount}",
+                    entry.client, entry.event
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the balance of every account
+    /// ```text
+    /// account, balance
+    /// client 1 available, 9.0
+    /// ```
+    pub fn write_balances(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "account, balance")?;
+        for (account, balance) in &self.balances {
+            writeln!(w, "{account}, {balance}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::read_snapshot;
+    use crate::transaction::read_csv;
+    use crate::{Config, Engine};
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ledger() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,        10.0
+deposit,         2,     2,         5.0
+withdrawal,      1,     3,         4.0
+dispute,         2,     2,
+chargeback,      2,     2,
+deposit,         3,     6,         2.0
+";
+        const MORE: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     4,         3.0
+dispute,         1,     4,
+withdrawal,      1,     5,       100.0
+";
+        let config: Config = toml::from_str("fees.withdrawal = { flat = \"0.5\" }")?;
+        let mut engine = Engine::with_config(config);
+        engine.keep_journal();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        engine.merge_accounts(1, 3, 100)?;
+        engine.replay(read_csv(MORE.as_bytes()), None)?;
+
+        let ledger = engine.ledger().expect("the journal is kept");
+        assert_eq!(ledger.sum(), dec!(0));
+        assert!(ledger.unbalanced(&engine.snapshot()).is_empty());
+        assert_eq!(ledger.balance(LedgerAccount::Fees), engine.fees());
+        assert_eq!(ledger.balance(LedgerAccount::ChargebackLoss), dec!(5.0));
+        assert_eq!(ledger.balance(LedgerAccount::BankSettlement), dec!(-16.0));
+        assert_eq!(ledger.balance(LedgerAccount::Held(1)), dec!(3.0));
+        assert_eq!(
+            ledger.journal().len(),
+            9,
+            "the rejected withdrawal posts nothing"
+        );
+
+        let mut journal = Vec::new();
+        ledger.write_journal(&mut journal)?;
+        let journal = String::from_utf8(journal)?;
+        assert!(journal.contains("1, 3, WithdrawalApplied, client 1 available, -4.5\n"));
+        assert!(journal.contains("1, 3, WithdrawalApplied, fees, 0.5\n"));
+
+        let mut books = engine.snapshot();
+        books.get_mut(&1).expect("client 1").available += dec!(1);
+        assert_eq!(
+            ledger.unbalanced(&books),
+            ["client 1: available is 8.5, the books say 7.5"]
+        );
+        let saved = serde_json::to_string(ledger)?;
+        let restored: Ledger = serde_json::from_str(&saved)?;
+        assert_eq!(restored.balances(), ledger.balances());
+
+        // Seeded balances open the books, whenever the journal is started
+        let accounts = "client,available,held,total,locked\n1,10.0,1.0,11.0,false\n";
+        let accounts = read_snapshot(accounts.as_bytes())?;
+        for journal_first in [true, false] {
+            let mut engine = Engine::new();
+            if journal_first {
+                engine.keep_journal();
+            }
+            engine.seed(&accounts)?;
+            engine.keep_journal();
+            engine.replay(
+                read_csv("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes()),
+                None,
+            )?;
+            let ledger = engine.ledger().expect("the journal is kept");
+            assert!(ledger.unbalanced(&engine.snapshot()).is_empty());
+            assert_eq!(ledger.balance(LedgerAccount::OpeningBalances), dec!(-11.0));
+            let mut journal = Vec::new();
+            ledger.write_journal(&mut journal)?;
+            let journal = String::from_utf8(journal)?;
+            assert!(
+                journal.contains("1, , OpeningBalance, client 1 held, 1"),
+                "{journal}"
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! TTE
+//!
+//! TTE is a command line tool that reads a CSV file containing a series of
+//! transactions and generates an accounts balance output file also in CSV.
+//!
+//! The engine itself lives in this library so it can be driven by the `tte`
+//! binary or embedded elsewhere.
+pub mod adjust;
+pub mod aml;
+pub mod amount;
+pub mod analysis;
+pub mod archive;
+pub mod checkpoint;
+pub mod client;
+pub mod config;
+pub mod engine;
+pub mod enrich;
+pub mod event;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod handler;
+pub mod ledger;
+pub mod middleware;
+pub mod output;
+pub mod period;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod query;
+pub mod reason;
+pub mod risk;
+#[cfg(feature = "rules")]
+pub mod rules;
+pub mod scan;
+pub mod score;
+pub mod settlement;
+pub mod snapshot;
+pub mod source;
+pub mod spill;
+pub mod tenant;
+pub mod transaction;
+pub mod validate;
+
+pub use client::{AccountView, Client};
+pub use config::Config;
+pub use engine::{AsOf, AuditEntry, AuditEvent, Engine};
+pub use handler::TransactionHandler;
+pub use reason::Reason;
+pub use snapshot::{read_snapshot, Snapshot};
+pub use tenant::Tenants;
+pub use transaction::{
+    read_csv, read_csv_with, ClientId, ReaderOptions, TransType, Transaction, TxId,
+};
+
+#[cfg(test)]
+pub(crate) fn log_init() {
+    let _ = env_logger::builder()
+        .format_timestamp(None)
+        .is_test(true)
+        .try_init();
+}
@@ -8,544 +8,2376 @@
 //! ```bash
 //! cargo build
 //! cargo run -- transactions.csv > accounts.csv
+//! cargo run -- report --as-of 1234 transactions.csv > accounts.csv
+//! cargo run -- report --config fees.toml transactions.csv > accounts.csv
+//! cargo run -- report --repl transactions.csv
+//! cargo run -- report --risk-score transactions.csv > accounts.csv
+//! cargo run -- report --extended transactions.csv > accounts.csv
+//! cargo run -- report --check-invariants transactions.csv > accounts.csv
+//! cargo run -- report --trace-client 42 --trace-tx 1234 transactions.csv > accounts.csv
+//! cargo run -- report --adjustments corrections.csv --adjustments-first-tx 900001 --audit audit.csv transactions.csv > accounts.csv
+//! cargo run -- report --checkpoint-every 100000 --checkpoint-file state.json transactions.csv
+//! cargo run --release -- report --fast-parse transactions.csv > accounts.csv
+//! cargo run --release -- report --expect-clients 100000 --expect-txs 50000000 transactions.csv > accounts.csv
+//! cargo run --release -- report --max-memory 2G transactions.csv > accounts.csv
+//! cargo run --release -- report --assume-disjoint-clients eu_transactions.csv us_transactions.csv > accounts.csv
+//! cargo run -- report --max-duration 30m --checkpoint-file state.json transactions.csv
+//! gunzip -c transactions.csv.gz | cargo run -- report - > accounts.csv
+//! cargo run --release -- report "generate://?count=1000000&clients=1000&seed=7" > accounts.csv
+//! cargo run -- accrue-interest --config interest.toml --as-of 2022-03-31T23:59:59Z transactions.csv
+//! cargo run -- reconcile transactions.csv expected_accounts.csv
+//! cargo run -- whatif --state state.json --tx 'withdrawal,42,9999,500.0'
+//! cargo run -- merge --state state.json --into 42 --from 4242 --tx 100001
+//! cargo run -- close-period --state state.json --period 2026-10-16 --period-dir periods
+//! cargo run -- replay events.jsonl expected_accounts.csv
+//! cargo run -- report --journal journal.csv transactions.csv > accounts.csv
+//! cargo run -- net transactions.csv > settlement.csv
+//! cargo run -- analyze --top 10 --format json transactions.csv
+//! cargo run -- diff accounts_before.csv accounts_after.csv
+//! cargo run -- validate transactions.csv
+//! cargo run -- tenants --tenant-pattern "{tenant}_transactions.csv" --output-dir reports acme_transactions.csv globex_transactions.csv
+//! cargo run --release -- shard --workers 8 --shard-by client transactions.csv > accounts.csv
+//! cargo run --release -- shard --verify-parallel transactions.csv > accounts.csv
+//! cargo run -- import --format ofx --clients accounts.csv statement.ofx > transactions.csv
+//! cargo run -- import --format fix --clients accounts.csv dropcopy.log > transactions.csv
+//! cargo run --features iso20022 -- import --format iso20022 --clients accounts.csv camt053.xml > transactions.csv
+//! cargo run --features object-store -- report --output s3://reports/accounts.csv s3://inbox/transactions.csv
+//! TTE_HTTP_TOKEN=secret cargo run --features http -- https://example.com/transactions.csv
+//! cargo run --features postgres -- report --sink postgres://localhost/reporting transactions.csv
+//! cargo run --features postgres,parquet -- report --sink accounts.parquet --sink postgres://localhost/reporting --sink summary transactions.csv
+//! cargo run --features plugins -- report --plugin policy.wasm transactions.csv
+//! cargo run --features rules -- report --rules rules.rhai transactions.csv
+//! cargo run --features redis -- consume --redis redis://localhost --state state.json
+//! cargo run --features redis -- consume --redis redis://localhost --health-addr 0.0.0.0:8080 --state state.json
+//! cargo run --features redis -- consume --redis redis://localhost --otlp-endpoint http://localhost:4318 --state state.json
+//! cargo run --features redis -- consume --redis redis://localhost --archive-after 24h --archive-dir archive --state state.json
+//! cargo run --features redis -- consume --redis redis://localhost --close-period-at 23:59 --period-dir periods --state state.json
+//! cargo run --features arrow -- report --output-format arrow --output accounts.arrow --events events.arrow transactions.csv
+//! cargo run --features templates -- report --output-template eod.tera transactions.csv
+//! cargo run --features amqp -- consume --amqp amqp://localhost --queue transactions --dead-letter rejected --state state.json
+//! cargo run --features nats -- consume --nats nats://localhost --subject "tx.>" --publish accounts.updates --state state.json
+//! cargo run --features redis -- follow --max-staleness 5 state.json
+//! cargo run --features server -- serve --addr 0.0.0.0:8080 --initial-accounts accounts.csv
+//! cargo run --features server -- serve --api-keys keys.csv --addr 0.0.0.0:8080
+//! cargo run --features server -- serve --rate-limit 100 --global-rate-limit 5000 --queue 10000
+//! TTE_WEBHOOK_SECRET=secret cargo run --features webhooks -- report --webhook https://example.com/hooks/tte transactions.csv
+//! cargo run --release --features io-uring -- report --io-uring transactions.csv > accounts.csv
+//! cargo run --features alerts -- report --alerts alerts.toml transactions.csv
+//! cargo run --features tui -- tui transactions.csv
+//! cargo run --features sql -- sql "SELECT client, total FROM accounts WHERE locked" transactions.csv
 //! ```
-use anyhow::Result;
-use csv::Trim;
-use log::LevelFilter;
-use log::{debug, error, info, warn};
-use rust_decimal::prelude::*;
-use serde::Deserialize;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::env;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use log::{warn, LevelFilter};
+use std::cell::Cell;
 use std::ffi::OsString;
-use std::fmt;
 use std::fs::File;
 use std::io;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tte::adjust::read_adjustments;
+use tte::checkpoint::{read_checkpoint, replay_checkpointed, write_checkpoint};
+use tte::client::read_records;
+use tte::config::read_credit_lines;
+use tte::event::Event;
+use tte::event::{read_events, replay_events};
+use tte::ledger::Ledger;
+use tte::output::{EventSink, JsonLines, ReportSink, Summary};
+use tte::period::{read_period, Period};
+use tte::query::Query;
+use tte::settlement::{movements, write_movements};
+use tte::snapshot::{compare, Difference, Snapshot};
+use tte::source::{Generator, TransactionSource};
+use tte::tenant::{tenant_from_name, Tenants};
+use tte::transaction::{parse_tx_id, read_schema, write_csv, DEFAULT_MAX_PRECISION};
+use tte::validate::{validate, Severity};
+use tte::{
+    read_csv_with, read_snapshot, AsOf, ClientId, Config, Engine, ReaderOptions, Transaction, TxId,
+};
 
-type Records = HashMap<u32, Decimal>;
-
-/// Client account data
-///
-/// This is the main structure for holding client acount balances.
-/// * Assumption #1 - If an account is locked no future deposits/withdrawals are
-/// allowed. There is no way to unlock an account once it is locked.
-#[derive(Default)]
-struct Client {
-    /// Client records are a simple mapping from transaction id (`tx`) to
-    /// transaction `amount.` They are used by dispute/resolve/chargeback
-    /// transactions that reference `tx` to get an `amount.`
-    records: Records,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
-    in_dispute: bool,
-}
-
-/// Custom [Debug] impl for [Client] so that the fields are shown without the
-/// [Records] HashMap
-/// ```
-/// Client { available: 24.5  held: 2  total: 26.5  locked: false }
-/// ```
-impl fmt::Debug for Client {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Client {{ available: {}  held: {}  total: {}  locked: {} }}",
-            self.available.round_dp(4),
-            self.held.round_dp(4),
-            self.total.round_dp(4),
-            self.locked
-        )
-    }
-}
-
-impl fmt::Display for Client {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}, {}, {}, {}",
-            self.available.round_dp(4),
-            self.held.round_dp(4),
-            self.total.round_dp(4),
-            self.locked
-        )
-    }
-}
-
-impl Client {
-    /// Add a mapping entry for a `tx` to an `amount`
-    fn add_record(&mut self, tx: u32, amount: Decimal) -> Result<()> {
-        debug!("  add record tx:{}  amount:{}", tx, amount);
-        self.records.insert(tx, amount);
+#[cfg(feature = "alerts")]
+mod alert;
+#[cfg(feature = "amqp")]
+mod amqp;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+mod consume;
+mod fix;
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+mod follow;
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+mod health;
+#[cfg(feature = "http")]
+mod http;
+mod import;
+#[cfg(feature = "iso20022")]
+mod iso20022;
+#[cfg(feature = "nats")]
+mod jetstream;
+mod ofx;
+#[cfg(feature = "server")]
+mod openapi;
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+mod otel;
+mod qif;
+#[cfg(feature = "redis")]
+mod redis_stream;
+#[cfg(feature = "object-store")]
+mod remote;
+#[cfg(feature = "server")]
+mod server;
+mod shard;
+#[cfg(feature = "postgres")]
+mod sink;
+#[cfg(feature = "sql")]
+mod sql;
+#[cfg(feature = "templates")]
+mod template;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "webhooks")]
+mod webhook;
+mod whatif;
+
+/// The object store, NATS and AMQP clients are async, so every call into them
+/// blocks on this runtime
+#[cfg(any(feature = "object-store", feature = "nats", feature = "amqp"))]
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("could not start the async runtime")
+    })
+}
+
+/// The fields of a JSON object such as
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`, for
+/// brokers whose messages are JSON and for `tte serve`
+#[cfg(any(feature = "nats", feature = "amqp", feature = "server"))]
+fn json_fields(payload: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(payload).map_err(|e| format!("invalid JSON: {e}"))?;
+    object
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => Ok((name, value)),
+            serde_json::Value::Number(value) => Ok((name, value.to_string())),
+            serde_json::Value::Null => Ok((name, String::new())),
+            _ => Err(format!("field {name} is neither a string nor a number")),
+        })
+        .collect()
+}
+
+/// Set when SIGINT or SIGTERM arrives so a run can stop between transactions
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn stopping() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
+
+/// The exit status of a run cut short by `--max-duration`, as with timeout(1)
+const TIMED_OUT: i32 = 124;
+
+#[derive(Parser)]
+#[command(version, about)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Transactions CSV file. Shorthand for `tte report <FILE>`
+    file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process a transactions file and print the account balances
+    Report(Box<ReportArgs>),
+    /// Process a transactions file and compare the balances against an
+    /// expected accounts file, exiting non-zero on any discrepancy
+    Reconcile {
+        /// Transactions CSV file
+        transactions: PathBuf,
+        /// Accounts CSV file holding the expected balances
+        expected: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Rebuild the balances from an event stream written by `report --events`
+    /// and compare them against an accounts file, exiting non-zero on any
+    /// discrepancy
+    Replay {
+        /// JSON lines event file
+        events: PathBuf,
+        /// Accounts CSV file holding the expected balances
+        expected: PathBuf,
+    },
+    /// Pay the configured interest on every available balance as of a time
+    /// and print the interest deposits as transactions CSV
+    AccrueInterest {
+        /// Transactions CSV file
+        file: PathBuf,
+
+        /// Apply the transactions up to this RFC 3339 timestamp and stamp the
+        /// interest deposits with it
+        #[arg(long, value_name = "TIMESTAMP")]
+        as_of: DateTime<Utc>,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Print the net movement of funds per client, deposits less
+    /// withdrawals and chargebacks, for settling the batch upstream
+    Net {
+        /// Transactions CSV file
+        file: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Print aggregates over a transactions file: the volume of each
+    /// transaction type, the top clients by deposit volume, how the amounts
+    /// are spread and the dispute and chargeback rates
+    Analyze {
+        /// Transactions CSV file
+        file: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// How many of the clients with the most deposited to list
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        top: usize,
+
+        #[arg(long, value_enum, default_value = "table")]
+        format: AnalysisFormat,
+    },
+    /// Show the per-client changes between two accounts files
+    Diff {
+        /// Accounts CSV file to compare from
+        a: PathBuf,
+        /// Accounts CSV file to compare to
+        b: PathBuf,
+    },
+    /// Check every row of a transactions file and report problems by line
+    /// number without producing an accounts report
+    Validate {
+        /// Transactions CSV file
+        file: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Process a transactions file and print the rows a SQL query returns
+    /// from the `accounts` table, and the `transactions` table if loaded
+    #[cfg(feature = "sql")]
+    Sql {
+        /// SQLite query, e.g. "SELECT client, total FROM accounts WHERE locked"
+        query: String,
+        /// Transactions CSV file
+        file: PathBuf,
+
+        /// Also load the transactions read into the `transactions` table
+        #[arg(long)]
+        with_transactions: bool,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Convert a bank, personal finance or FIX drop copy file into a
+    /// transactions CSV file, printed to standard output
+    Import {
+        /// File to convert
+        file: PathBuf,
+
+        /// What the file is
+        #[arg(long)]
+        format: import::Format,
+
+        /// CSV file with `account,client` columns naming the client of each
+        /// account. Accounts not in it must be client ids themselves
+        #[arg(long, value_name = "FILE")]
+        clients: Option<PathBuf>,
+
+        /// Number the transactions from this tx id on
+        #[arg(long, value_name = "TX", default_value = "1")]
+        first_tx: tte::TxId,
+    },
+    /// Process the transactions of many tenants, keeping each tenant's
+    /// accounts apart, and print the balances led by a tenant column
+    Tenants {
+        /// Transactions CSV files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        #[command(flatten)]
+        tenant: TenantArgs,
+
+        /// Write each tenant's report to DIR/TENANT.csv instead, in a
+        /// directory that exists
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Process a transactions file with several worker processes, each
+    /// applying the transactions of its share of the clients, and print the
+    /// merged account balances
+    Shard {
+        /// Transactions CSV file
+        file: PathBuf,
+
+        /// Worker processes to run, by default one per core
+        #[arg(long, value_name = "N")]
+        workers: Option<NonZeroUsize>,
+
+        /// What the transactions are split by
+        #[arg(long, value_enum, default_value = "client")]
+        shard_by: ShardBy,
+
+        /// Write the report to this file instead of standard output
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Also run the input through a single engine in this process and
+        /// fail unless its report is the same as the sharded one
+        #[arg(long)]
+        verify_parallel: bool,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Apply transactions from a message broker as they arrive until
+    /// interrupted, then print the account balances
+    #[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+    Consume(Box<ConsumeArgs>),
+    /// Apply transactions posted over HTTP until interrupted, then print the
+    /// account balances
+    #[cfg(feature = "server")]
+    Serve {
+        /// The address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        addr: String,
+
+        /// Requests waiting for the engine, past which they are answered
+        /// with 503
+        #[arg(long, value_name = "N", default_value_t = 1024)]
+        queue: usize,
+
+        /// Requests a second taken on one connection, past which they are
+        /// answered with 429
+        #[arg(long, value_name = "N")]
+        rate_limit: Option<u32>,
+
+        /// Requests a second taken over all connections, past which they are
+        /// answered with 429
+        #[arg(long, value_name = "N")]
+        global_rate_limit: Option<u32>,
+
+        /// CSV file of the API keys requests need, with `key` and `role`
+        /// columns, the role one of submitter, auditor or admin. Without it
+        /// anyone may do anything.
+        #[arg(long, value_name = "FILE")]
+        api_keys: Option<PathBuf>,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Answer account queries from the state a running consume saves, read
+    /// only, to keep reporting off the consumer
+    #[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+    Follow {
+        /// State file written by `consume --state`, or an object URL it is
+        /// copied to
+        state: PathBuf,
+
+        /// Answer from the loaded state for up to this many seconds before
+        /// looking for a newer one
+        #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+        max_staleness: u64,
+    },
+    /// Process a transactions file or stream while showing live throughput,
+    /// the top accounts, recently locked accounts and a client lookup
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Transactions CSV file, or - to read standard input
+        file: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Apply one proposed transaction to a saved state and print what it
+    /// would do to the account, without saving anything
+    Whatif {
+        /// Checkpoint written by `report --checkpoint-file`, or state file
+        /// written by `consume --state`
+        #[arg(long, value_name = "FILE")]
+        state: PathBuf,
+
+        /// The transaction as a CSV row without a header, e.g.
+        /// "withdrawal,42,9999,500.0", maybe with a timestamp after the amount
+        #[arg(long, value_name = "ROW", value_parser = whatif::proposed)]
+        tx: Transaction,
+
+        #[arg(long, value_enum, default_value = "table")]
+        format: AnalysisFormat,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Merge the account of one client into another's in a checkpoint, for
+    /// duplicate records of one customer
+    Merge {
+        /// Checkpoint written by `report --checkpoint-file`, rewritten in place
+        #[arg(long, value_name = "FILE")]
+        state: PathBuf,
+
+        /// The client that keeps the account
+        #[arg(long, value_name = "CLIENT")]
+        into: ClientId,
+
+        /// The client whose account is merged away
+        #[arg(long, value_name = "CLIENT")]
+        from: ClientId,
+
+        /// The id the merge goes by in the audit trail and the events
+        #[arg(long, value_name = "TX", value_parser = tx_id)]
+        tx: TxId,
+    },
+    /// Close the period of a checkpoint: write its accounts to DIR/PERIOD.json
+    /// and start the period-scoped counters over, e.g. from an end-of-day job
+    ClosePeriod {
+        /// Checkpoint written by `report --checkpoint-file`, rewritten in place
+        #[arg(long, value_name = "FILE")]
+        state: PathBuf,
+
+        /// The id of the period, e.g. 2026-10-16, which may only be closed
+        /// once
+        #[arg(long, value_name = "PERIOD")]
+        period: String,
+
+        /// Where the period is written
+        #[arg(long, value_name = "DIR")]
+        period_dir: PathBuf,
+
+        /// When the period closed, the current time if not given
+        #[arg(long, value_name = "TIMESTAMP")]
+        closed_at: Option<DateTime<Utc>>,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum AnalysisFormat {
+    /// Plain text tables
+    Table,
+    Json,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ShardBy {
+    /// The client id, which keeps all of a client's transactions in one
+    /// worker
+    Client,
+}
+
+/// Where the tenant of a transaction comes from. Without any of these it is
+/// the name of the file without its extension
+#[derive(Args)]
+#[group(multiple = false)]
+struct TenantArgs {
+    /// The tenant of every transaction
+    #[arg(long, value_name = "NAME")]
+    tenant: Option<String>,
+
+    /// Take the tenant of each transaction from this column
+    #[arg(long, value_name = "NAME")]
+    tenant_column: Option<String>,
+
+    /// Take the tenant from the file name, e.g. {tenant}_transactions.csv
+    #[arg(long, value_name = "PATTERN")]
+    tenant_pattern: Option<String>,
+}
+
+impl TenantArgs {
+    /// The tenant of every transaction in `file`, unless it is per row
+    fn of_file(&self, file: &Path) -> Result<Option<String>> {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        Ok(match self {
+            TenantArgs {
+                tenant: Some(tenant),
+                ..
+            } => Some(tenant.clone()),
+            TenantArgs {
+                tenant_pattern: Some(pattern),
+                ..
+            } => {
+                let tenant = tenant_from_name(pattern, &name)
+                    .with_context(|| format!("{name} does not match {pattern}"))?;
+                Some(tenant.to_string())
+            }
+            TenantArgs {
+                tenant_column: Some(_),
+                ..
+            } => None,
+            _ => Some(
+                file.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        })
+    }
+}
+
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+#[derive(Parser)]
+struct ConsumeArgs {
+    #[command(flatten)]
+    broker: BrokerArgs,
+
+    /// Redis stream to read
+    #[cfg(feature = "redis")]
+    #[arg(long, default_value = "transactions")]
+    stream: String,
+
+    /// JetStream subject to read, wildcards allowed
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value = "transactions.>")]
+    subject: String,
+
+    /// JetStream subject to publish the balances of updated accounts to
+    #[cfg(feature = "nats")]
+    #[arg(long, value_name = "SUBJECT")]
+    publish: Option<String>,
+
+    /// RabbitMQ queue to read
+    #[cfg(feature = "amqp")]
+    #[arg(long, default_value = "transactions")]
+    queue: String,
+
+    /// RabbitMQ exchange to republish invalid and rejected messages to, with
+    /// the reason in the x-tte-reason header, before they are nacked
+    #[cfg(feature = "amqp")]
+    #[arg(long, value_name = "EXCHANGE")]
+    dead_letter: Option<String>,
+
+    /// Consumer group to read through, or the durable JetStream consumer
+    #[cfg(any(feature = "redis", feature = "nats"))]
+    #[arg(long, default_value = "tte")]
+    group: String,
+
+    /// Name of this consumer in the Redis group, or its RabbitMQ tag
+    #[cfg(any(feature = "redis", feature = "amqp"))]
+    #[arg(long, default_value = "tte")]
+    consumer: String,
+
+    /// Only apply the transactions of clients whose id modulo COUNT is INDEX,
+    /// acknowledging the rest. Each shard reads through its own group.
+    #[arg(long, value_name = "INDEX/COUNT", default_value = "0/1")]
+    shard: shard::Shard,
+
+    /// Where the engine state is saved after every batch. An existing state
+    /// is carried on from.
+    #[arg(long, value_name = "FILE")]
+    state: PathBuf,
+
+    /// Messages applied between saves
+    #[arg(long, value_name = "N", default_value = "100")]
+    batch: NonZeroUsize,
+
+    /// Skip messages whose `idempotency_key` field, or else type and tx, is
+    /// among the last N applied, such as redeliveries after a restart. The
+    /// window is saved with the state.
+    #[arg(long, value_name = "N", default_value = "0")]
+    dedupe_window: usize,
+
+    /// Answer /healthz and /readyz on this address, e.g. 0.0.0.0:8080, with
+    /// the lag, the messages in flight and the age of the saved state
+    #[arg(long, value_name = "ADDR")]
+    health_addr: Option<String>,
+
+    /// Not ready once the saved state is older than this, e.g. 30s or 5m
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration,
+        default_value = "60s",
+        requires = "health_addr"
+    )]
+    ready_max_staleness: Duration,
+
+    /// Not ready once the broker holds more than N messages for the consumer
+    #[arg(long, value_name = "N", requires = "health_addr")]
+    ready_max_lag: Option<u64>,
+
+    /// Send a trace of every batch to this OpenTelemetry collector over
+    /// OTLP/HTTP, e.g. http://localhost:4318
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// The share of transactions traced one by one, from 0 to 1
+    #[arg(
+        long,
+        value_name = "RATIO",
+        default_value = "0.01",
+        requires = "otlp_endpoint"
+    )]
+    trace_sample: f64,
+
+    /// Move the clients without a transaction for this long, e.g. 24h, out
+    /// of memory into --archive-dir, reading one back when it has a
+    /// transaction again
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration,
+        requires = "archive_dir"
+    )]
+    archive_after: Option<Duration>,
+
+    /// Where clients are archived to, one JSON file each, next to the state
+    #[arg(long, value_name = "DIR", requires = "archive_after")]
+    archive_dir: Option<PathBuf>,
+
+    /// Close a period every day at this time, e.g. 23:59 (UTC), writing it
+    /// to --period-dir
+    #[arg(
+        long,
+        value_name = "HH:MM",
+        value_parser = time_of_day,
+        requires = "period_dir"
+    )]
+    close_period_at: Option<chrono::NaiveTime>,
+
+    /// Where closed periods are written, one JSON file each named by the
+    /// period
+    #[arg(long, value_name = "DIR", requires = "close_period_at")]
+    period_dir: Option<PathBuf>,
+
+    /// POST the chargebacks and locks of every batch to this URL, which may
+    /// be given more than once
+    #[cfg(feature = "webhooks")]
+    #[arg(long = "webhook", value_name = "URL")]
+    webhooks: Vec<String>,
+
+    /// TOML file of alert rules, such as any account locked, and the Slack
+    /// channel or mailboxes to alert, checked after every batch
+    #[cfg(feature = "alerts")]
+    #[arg(long, value_name = "FILE")]
+    alerts: Option<PathBuf>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+/// The broker to consume from
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct BrokerArgs {
+    /// Redis server to read a stream from, e.g. redis://localhost
+    #[cfg(feature = "redis")]
+    #[arg(long, value_name = "URL")]
+    redis: Option<String>,
+
+    /// RabbitMQ server to read a queue from, e.g. amqp://localhost
+    #[cfg(feature = "amqp")]
+    #[arg(long, value_name = "URL")]
+    amqp: Option<String>,
+
+    /// NATS server to read JetStream from, e.g. nats://localhost
+    #[cfg(feature = "nats")]
+    #[arg(long, value_name = "URL")]
+    nats: Option<String>,
+}
+
+/// Options describing the layout of a transactions CSV file
+#[derive(Parser)]
+struct InputArgs {
+    /// The transactions file has no header line. Columns are taken to be
+    /// `type,client,tx,amount` unless `--columns` says otherwise
+    #[arg(long)]
+    no_header: bool,
+
+    /// Column names by position, overriding the header line. Columns not
+    /// named type, client, tx, amount or timestamp are ignored
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    columns: Option<Vec<String>>,
+
+    /// Field delimiter, e.g. ';' for semicolon separated exports
+    #[arg(long, default_value_t = ',', value_parser = ascii_char)]
+    delimiter: char,
+
+    /// Quote character
+    #[arg(long, default_value_t = '"', value_parser = ascii_char)]
+    quote: char,
+
+    /// Treat quote characters as ordinary data
+    #[arg(long)]
+    no_quoting: bool,
+
+    /// Amounts use a comma as the decimal separator, e.g. "1,50"
+    #[arg(long)]
+    decimal_comma: bool,
+
+    /// Reject client ids above 65535, the range of the original u16 ids
+    #[arg(long)]
+    legacy_ids: bool,
+
+    /// Only accept the canonical lowercase transaction types, rejecting
+    /// spellings such as `Deposit` or `withdraw` as unknown types
+    #[arg(long)]
+    strict_types: bool,
+
+    /// Reject amounts with more than this many decimal places
+    #[arg(long, default_value_t = DEFAULT_MAX_PRECISION, value_name = "DIGITS")]
+    max_precision: u32,
+
+    /// Scan a file with the plain `type,client,tx,amount` header without the
+    /// csv parser. Other files are read as usual
+    #[arg(long)]
+    fast_parse: bool,
+}
+
+impl InputArgs {
+    fn reader_options(&self) -> ReaderOptions {
+        ReaderOptions {
+            has_headers: !self.no_header,
+            columns: self.columns.clone(),
+            delimiter: self.delimiter as u8,
+            quote: self.quote as u8,
+            quoting: !self.no_quoting,
+            decimal_comma: self.decimal_comma,
+            max_precision: Some(self.max_precision),
+            legacy_ids: self.legacy_ids,
+            strict_types: self.strict_types,
+            fast_parse: self.fast_parse,
+        }
+    }
+
+    /// The options that still apply to a file [shard::split] wrote, as
+    /// command line arguments for its worker
+    fn decode_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "--max-precision".into(),
+            self.max_precision.to_string().into(),
+        ];
+        for (set, flag) in [
+            (self.decimal_comma, "--decimal-comma"),
+            (self.legacy_ids, "--legacy-ids"),
+            (self.strict_types, "--strict-types"),
+            (self.fast_parse, "--fast-parse"),
+        ] {
+            if set {
+                args.push(flag.into());
+            }
+        }
+        args
+    }
+}
+
+impl Default for InputArgs {
+    fn default() -> Self {
+        InputArgs::parse_from([""])
+    }
+}
+
+/// Options changing how the engine applies transactions
+#[derive(Parser)]
+struct EngineArgs {
+    /// TOML file of engine policies such as fees
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// CSV file of per-client credit lines with `client` and `limit` columns,
+    /// added to any given in the config
+    #[arg(long, value_name = "FILE")]
+    credit_limits: Option<PathBuf>,
+
+    /// Accounts CSV file, e.g. an earlier report, whose balances the clients
+    /// start from
+    #[arg(long, value_name = "FILE")]
+    initial_accounts: Option<PathBuf>,
+
+    /// CSV file of deposits and withdrawals exported by an earlier run with
+    /// `--export-records`, so they can be disputed in this one
+    #[arg(long, value_name = "FILE")]
+    import_records: Option<PathBuf>,
+
+    /// WebAssembly module deciding on every transaction before it is
+    /// applied, after any middleware in the config. May be repeated.
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", value_name = "FILE")]
+    plugins: Vec<PathBuf>,
+
+    /// Rhai script allowing, changing or rejecting every transaction before
+    /// it is applied, after any plugins
+    #[cfg(feature = "rules")]
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+}
+
+impl EngineArgs {
+    /// The same options as command line arguments, for worker processes
+    fn args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        for (path, flag) in [
+            (&self.config, "--config"),
+            (&self.credit_limits, "--credit-limits"),
+            (&self.initial_accounts, "--initial-accounts"),
+            (&self.import_records, "--import-records"),
+        ] {
+            if let Some(path) = path {
+                args.extend([flag.into(), path.into()]);
+            }
+        }
+        #[cfg(feature = "plugins")]
+        for path in &self.plugins {
+            args.extend(["--plugin".into(), path.into()]);
+        }
+        #[cfg(feature = "rules")]
+        if let Some(path) = &self.rules {
+            args.extend(["--rules".into(), path.into()]);
+        }
+        args
+    }
+
+    fn engine(&self) -> Result<Engine> {
+        let mut engine = Engine::with_config(self.config()?);
+        self.load_middleware(&mut engine)?;
+        if let Some(path) = &self.initial_accounts {
+            let accounts = read_snapshot(open(path)?)
+                .with_context(|| format!("invalid accounts {}", path.display()))?;
+            engine.seed(&accounts)?;
+        }
+        if let Some(path) = &self.import_records {
+            engine.import_records(
+                read_records(open(path)?)
+                    .with_context(|| format!("invalid records {}", path.display()))?,
+            )?;
+        }
+        Ok(engine)
+    }
+
+    /// Adds the `--plugin` modules and `--rules` to an engine, which a
+    /// restored one needs again like its config
+    #[cfg_attr(
+        not(any(feature = "plugins", feature = "rules")),
+        allow(unused_variables)
+    )]
+    fn load_middleware(&self, engine: &mut Engine) -> Result<()> {
+        #[cfg(feature = "plugins")]
+        for path in &self.plugins {
+            engine.add_middleware(tte::plugin::Plugin::load(path)?);
+        }
+        #[cfg(feature = "rules")]
+        if let Some(path) = &self.rules {
+            engine.add_middleware(tte::rules::Rules::load(path)?);
+        }
         Ok(())
     }
 
-    /// Consumes a transaction provided by [read_csv] and performs the appropriate
-    /// transaction task
-    fn transact(&mut self, transaction: Transaction) -> Result<()> {
-        match transaction.trans {
-            TransType::Deposit => {
-                if !self.locked {
-                    if let Some(amount) = transaction.amount {
-                        self.add_record(transaction.tx, amount)?;
-                        self.deposit(amount)?;
-                    } else {
-                        error!("O_o No amount specified in Deposit transaction");
-                    }
-                }
+    fn config(&self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("could not read {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("invalid config {}", path.display()))?
             }
-            TransType::Withdrawal => {
-                if !self.locked {
-                    if let Some(amount) = transaction.amount {
-                        self.add_record(transaction.tx, amount)?;
-                        self.withdrawal(amount)?;
-                    } else {
-                        error!("O_o No amount in withdrawn");
-                    }
+            None => Config::default(),
+        };
+        if let Some(path) = &self.credit_limits {
+            config.credit.extend(
+                read_credit_lines(open(path)?)
+                    .with_context(|| format!("invalid credit limits {}", path.display()))?,
+            );
+        }
+        Ok(config)
+    }
+}
+
+impl Default for EngineArgs {
+    fn default() -> Self {
+        EngineArgs::parse_from([""])
+    }
+}
+
+/// CSV delimiters and quotes are single bytes
+fn ascii_char(s: &str) -> Result<char, String> {
+    match s.parse::<char>() {
+        Ok(c) if c.is_ascii() => Ok(c),
+        _ => Err(format!("'{s}' is not a single ASCII character")),
+    }
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// Transactions CSV file, `-` for standard input, or made up
+    /// transactions such as `generate://?count=100000&clients=50&seed=7`
+    file: PathBuf,
+
+    /// More transactions files with clients of their own, see
+    /// --assume-disjoint-clients
+    #[arg(value_name = "MORE_FILES", requires = "assume_disjoint_clients")]
+    more_files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Stop applying transactions at a tx id or an RFC 3339 timestamp and
+    /// report the balances as they stood then
+    #[arg(long, value_name = "TX|TIMESTAMP")]
+    as_of: Option<AsOf>,
+
+    /// Apply the manual corrections in this CSV file, with `client`, `amount`
+    /// and `reason` columns, after the transactions
+    #[arg(long, value_name = "FILE", requires = "adjustments_first_tx")]
+    adjustments: Option<PathBuf>,
+
+    /// Number the adjustments in the audit trail and the events from this tx
+    /// id on, past any in the input
+    #[arg(long, value_name = "TX", value_parser = tx_id, requires = "adjustments")]
+    adjustments_first_tx: Option<TxId>,
+
+    /// Write the report to this file instead of standard output
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// How the report and the events are written
+    #[cfg(feature = "arrow")]
+    #[arg(long, value_enum, default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// Render the report through this Tera template instead of writing CSV,
+    /// e.g. for a printable summary or fixed-width records
+    #[cfg(feature = "templates")]
+    #[arg(long, value_name = "FILE")]
+    output_template: Option<PathBuf>,
+
+    /// Log every decision made for this client's transactions, with its
+    /// account before and after, without raising the log level. May be given
+    /// more than once
+    #[arg(long = "trace-client", value_name = "CLIENT")]
+    trace_clients: Vec<ClientId>,
+
+    /// Log every decision made for the transactions with this id, like
+    /// --trace-client
+    #[arg(long = "trace-tx", value_name = "TX", value_parser = tx_id)]
+    trace_txs: Vec<TxId>,
+
+    /// Check every account after each transaction and stop with the
+    /// offending transaction once its balances no longer add up, its held
+    /// funds go negative or a locked account changes
+    #[arg(long)]
+    check_invariants: bool,
+
+    /// Add each client's transaction count, deposit and withdrawal totals,
+    /// open dispute count and last transaction to the report
+    #[arg(long)]
+    extended: bool,
+
+    /// Add a `risk_score` column to the report, from 0 to 100, scoring each
+    /// client's disputes, chargebacks, rejections and busiest day
+    #[arg(long)]
+    risk_score: bool,
+
+    /// Write the transactions refused by risk limits, with their reason
+    /// codes, to this CSV file
+    #[arg(long, value_name = "FILE")]
+    rejections: Option<PathBuf>,
+
+    /// Write the clients flagged by the AML rules to this CSV file
+    #[arg(long, value_name = "FILE")]
+    flags: Option<PathBuf>,
+
+    /// Write the audit trail of changes not made by the transactions as
+    /// given, such as freezes, enrichments and adjustments, to this CSV file
+    #[arg(long, value_name = "FILE")]
+    audit: Option<PathBuf>,
+
+    /// Write the deposits and withdrawals a later dispute could refer to, for
+    /// `--import-records` in the next run, to this CSV file
+    #[arg(long, value_name = "FILE")]
+    export_records: Option<PathBuf>,
+
+    /// Write every decision the engine made, one JSON event per line, to
+    /// this file
+    #[arg(long, value_name = "FILE")]
+    events: Option<PathBuf>,
+
+    /// Keep double-entry books of every balance change and write their
+    /// journal to this CSV file. The run fails if the books do not sum to
+    /// zero or disagree with the report
+    #[arg(long, value_name = "FILE")]
+    journal: Option<PathBuf>,
+
+    /// Save the engine state and the position in the input to the checkpoint
+    /// file every N transactions
+    #[arg(long, value_name = "N", requires = "checkpoint_file")]
+    checkpoint_every: Option<NonZeroUsize>,
+
+    /// Where checkpoints are saved. The last one is always saved when the run
+    /// ends or is interrupted
+    #[arg(long, value_name = "FILE", conflicts_with = "as_of")]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Carry on from the checkpoint file instead of starting over. The input
+    /// and config must be the same as for the run that saved it
+    #[arg(
+        long,
+        requires = "checkpoint_file",
+        conflicts_with = "initial_accounts"
+    )]
+    resume: bool,
+
+    /// Stop applying transactions after this long, e.g. 90s, 30m or 2h, then
+    /// save a checkpoint if --checkpoint-file is given, write the report of
+    /// what was applied, marked as incomplete, and exit with status 124
+    #[arg(long, value_name = "DURATION", value_parser = duration)]
+    max_duration: Option<Duration>,
+
+    /// Keep the deposit and withdrawal records in memory under this many
+    /// bytes, e.g. 512M or 2G, moving the least recently used to a temporary
+    /// directory and back when a dispute refers to them
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = bytes,
+        conflicts_with_all = ["checkpoint_file", "export_records"]
+    )]
+    max_memory: Option<u64>,
+
+    /// About how many clients the input has, to make room for them up front
+    #[arg(long, value_name = "N")]
+    expect_clients: Option<usize>,
+
+    /// About how many deposits and withdrawals the input has, to make room
+    /// for their records up front
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "expect_clients",
+        conflicts_with = "max_memory"
+    )]
+    expect_txs: Option<usize>,
+
+    /// Read the input through io_uring, keeping reads queued while it is
+    /// parsed. Elsewhere than Linux, or for a URL, it is read as usual
+    #[cfg(feature = "io-uring")]
+    #[arg(long)]
+    io_uring: bool,
+
+    /// The files share no client, so each is applied by an engine of its own
+    /// in parallel and the engines are merged for the outputs. A client found
+    /// in two files is an error.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "checkpoint_file",
+            "max_duration",
+            "max_memory",
+            "initial_accounts",
+            "import_records"
+        ]
+    )]
+    assume_disjoint_clients: bool,
+
+    /// Preview the effect of the input: write the report and the other
+    /// outputs as usual but leave the checkpoint file untouched
+    #[arg(long, conflicts_with = "checkpoint_every")]
+    dry_run: bool,
+
+    /// After the run, read queries such as `account 42`, `locked`,
+    /// `top 10 by total` or `disputes open` from standard input and answer
+    /// them from the final state
+    #[arg(long)]
+    repl: bool,
+
+    /// Also send the report here, which may be given more than once: `-` for
+    /// CSV on standard output, `summary` for the fees and rejections on
+    /// standard error, a postgres:// database to upsert the balances into,
+    /// e.g. postgres://user@host/reporting, skipped by --dry-run, or a file,
+    /// written as Arrow or Parquet by a .arrow or .parquet extension and as
+    /// CSV otherwise
+    #[arg(long = "sink", value_name = "SINK", value_parser = sink_spec)]
+    sinks: Vec<SinkSpec>,
+
+    /// Table the balances are upserted into, which needs a unique `client`
+    /// column
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        value_name = "TABLE",
+        default_value = "accounts",
+        requires = "sinks"
+    )]
+    sink_table: String,
+
+    /// POST the chargebacks and locks to this URL once the run is done,
+    /// which may be given more than once. Skipped by --dry-run
+    #[cfg(feature = "webhooks")]
+    #[arg(long = "webhook", value_name = "URL")]
+    webhooks: Vec<String>,
+
+    /// TOML file of alert rules, such as any account locked, and the Slack
+    /// channel or mailboxes to alert. Skipped by --dry-run
+    #[cfg(feature = "alerts")]
+    #[arg(long, value_name = "FILE")]
+    alerts: Option<PathBuf>,
+}
+
+#[cfg(feature = "arrow")]
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    /// CSV for the report and JSON lines for the events
+    Csv,
+    /// Arrow IPC files, e.g. for Polars or pandas
+    Arrow,
+    /// Parquet files with the same columns as the Arrow ones
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[cfg(feature = "arrow")]
+impl OutputFormat {
+    /// The columnar layout, or None for CSV and JSON lines
+    fn layout(self) -> Option<arrow::Layout> {
+        match self {
+            OutputFormat::Csv => None,
+            OutputFormat::Arrow => Some(arrow::Layout::Ipc),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => Some(arrow::Layout::Parquet),
+        }
+    }
+}
+
+/// A time of day such as `23:59`
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+fn time_of_day(s: &str) -> Result<chrono::NaiveTime, String> {
+    chrono::NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| format!("'{s}' is not a time such as 23:59"))
+}
+
+/// A duration such as `90s`, `30m` or `2h`, in seconds without a unit
+fn duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return Err(format!("'{unit}' is not s, m or h")),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{s}' is not a duration such as 30m"))?;
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// A size such as `512M` or `2G`, in bytes without a unit
+fn bytes(s: &str) -> Result<u64, String> {
+    let (number, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return Err(format!("'{unit}' is not K, M or G")),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{s}' is not a size such as 2G"))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("'{s}' is too big"))
+}
+
+fn tx_id(s: &str) -> Result<TxId, String> {
+    parse_tx_id(s).ok_or_else(|| format!("'{s}' is not a tx id"))
+}
+
+/// A further place for the report of a run, see `--sink`
+#[derive(Clone)]
+enum SinkSpec {
+    Summary,
+    #[cfg(feature = "postgres")]
+    Postgres(String),
+    /// A file, or standard output without a path
+    File(Option<PathBuf>, ReportFormat),
+}
+
+fn sink_spec(s: &str) -> Result<SinkSpec, String> {
+    if s == "summary" {
+        return Ok(SinkSpec::Summary);
+    }
+    if s == "-" {
+        return Ok(SinkSpec::File(None, ReportFormat::Csv));
+    }
+    if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        return Ok(SinkSpec::Postgres(s.to_string()));
+        #[cfg(not(feature = "postgres"))]
+        return Err("postgres:// sinks need the postgres feature".to_string());
+    }
+    let extension = Path::new(s).extension().and_then(|e| e.to_str());
+    #[cfg(not(feature = "arrow"))]
+    if extension == Some("arrow") {
+        return Err(".arrow sinks need the arrow feature".to_string());
+    }
+    #[cfg(not(feature = "parquet"))]
+    if extension == Some("parquet") {
+        return Err(".parquet sinks need the parquet feature".to_string());
+    }
+    let format = match extension {
+        #[cfg(feature = "arrow")]
+        Some("arrow") => ReportFormat::Columnar(arrow::Layout::Ipc),
+        #[cfg(feature = "parquet")]
+        Some("parquet") => ReportFormat::Columnar(arrow::Layout::Parquet),
+        _ => ReportFormat::Csv,
+    };
+    Ok(SinkSpec::File(Some(PathBuf::from(s)), format))
+}
+
+impl SinkSpec {
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    fn open(&self, args: &ReportArgs, incomplete: Option<&'static str>) -> Box<dyn ReportSink> {
+        match self {
+            SinkSpec::Summary => Box::new(Summary(io::stderr())),
+            #[cfg(feature = "postgres")]
+            SinkSpec::Postgres(url) => Box::new(sink::Postgres {
+                url: url.clone(),
+                table: args.sink_table.clone(),
+            }),
+            SinkSpec::File(path, format) => Box::new(ReportFile {
+                path: path.clone(),
+                format: format.clone(),
+                incomplete,
+            }),
+        }
+    }
+}
+
+/// How a report file is written
+#[derive(Clone)]
+enum ReportFormat {
+    Csv,
+    #[cfg(feature = "arrow")]
+    Columnar(arrow::Layout),
+    #[cfg(feature = "templates")]
+    Template(std::sync::Arc<template::Template>),
+}
+
+/// The report as a file, or on standard output without a path
+struct ReportFile {
+    path: Option<PathBuf>,
+    format: ReportFormat,
+    /// Why the run stopped before the end of its input, if it did
+    incomplete: Option<&'static str>,
+}
+
+impl ReportSink for ReportFile {
+    fn write_report(&mut self, engine: &Engine) -> Result<()> {
+        let write = |w: &mut dyn io::Write| match &self.format {
+            ReportFormat::Csv => {
+                engine.write_report(&mut *w)?;
+                match self.incomplete {
+                    Some(reason) => writeln!(w, "# incomplete: {reason}"),
+                    None => Ok(()),
                 }
             }
-            TransType::Dispute => {
-                self.dispute(transaction.tx)?;
+            #[cfg(feature = "arrow")]
+            ReportFormat::Columnar(layout) => arrow::write_report(engine, w, *layout),
+            #[cfg(feature = "templates")]
+            ReportFormat::Template(template) => template.render(engine, w),
+        };
+        match &self.path {
+            Some(path) => write_output(path, write),
+            None => Ok(write(&mut io::stdout().lock())?),
+        }
+    }
+}
+
+/// The events as a file, JSON lines unless columnar
+struct EventFile {
+    path: PathBuf,
+    #[cfg(feature = "arrow")]
+    layout: Option<arrow::Layout>,
+}
+
+impl EventSink for EventFile {
+    fn write_events(&mut self, events: &[Event]) -> Result<()> {
+        write_output(&self.path, |w| {
+            #[cfg(feature = "arrow")]
+            if let Some(layout) = self.layout {
+                return arrow::write_events(events, w, layout);
             }
-            TransType::Resolve => {
-                if self.in_dispute {
-                    self.resolve(transaction.tx)?;
-                } else {
-                    error!("client not in dispute");
+            JsonLines(w).write_events(events).map_err(io::Error::other)
+        })
+    }
+}
+
+fn report(args: ReportArgs) -> Result<()> {
+    #[cfg(feature = "templates")]
+    let template = match &args.output_template {
+        Some(path) => Some(
+            template::Template::new(&io::read_to_string(open(path)?)?)
+                .with_context(|| format!("in {}", path.display()))?,
+        ),
+        None => None,
+    };
+    #[cfg(feature = "alerts")]
+    let alerts = args
+        .alerts
+        .as_deref()
+        .map(alert::Alerts::read)
+        .transpose()?;
+    let options = args.input.reader_options();
+    let (mut engine, position) = match &args.checkpoint_file {
+        Some(path) if args.resume => {
+            let (mut engine, position) = read_checkpoint(open(path)?)
+                .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+            engine.configure(args.engine.config()?);
+            args.engine.load_middleware(&mut engine)?;
+            (engine, Some(position))
+        }
+        _ => (args.engine.engine()?, None),
+    };
+    prepare(&mut engine, &args)?;
+    let deadline = args.max_duration.map(|duration| Instant::now() + duration);
+    let timed_out = Cell::new(false);
+    let stop = || {
+        timed_out.set(deadline.is_some_and(|deadline| Instant::now() >= deadline));
+        stopping() || timed_out.get()
+    };
+    let more = thread::scope(|scope| -> Result<Vec<Engine>> {
+        // One engine per further file, while this thread applies the first
+        let workers: Vec<_> = args
+            .more_files
+            .iter()
+            .map(|path| {
+                let (args, options) = (&args, &options);
+                scope.spawn(move || -> Result<Engine> {
+                    let mut engine = args.engine.engine()?;
+                    prepare(&mut engine, args)?;
+                    let mut transactions = source(args, path, options)?;
+                    engine.replay_source(transactions.as_mut(), args.as_of, stopping)?;
+                    Ok(engine)
+                })
+            })
+            .collect();
+        match &args.checkpoint_file {
+            // Checkpoints are positions in a file, so take it as one
+            Some(path) => {
+                let mut transactions = read_csv_with(open_input(&args, &args.file)?, &options);
+                if let Some(position) = position {
+                    transactions.seek(position)?;
                 }
-            }
-            TransType::Chargeback => {
-                if self.in_dispute {
-                    self.chargeback(transaction.tx)?;
+                if args.dry_run {
+                    engine.replay_source(&mut transactions, args.as_of, stop)?
                 } else {
-                    error!("client not in dispute");
+                    let every = args.checkpoint_every.map_or(usize::MAX, NonZeroUsize::get);
+                    replay_checkpointed(
+                        &mut engine,
+                        &mut transactions,
+                        every,
+                        stop,
+                        |engine, position| save_checkpoint(path, engine, position),
+                    )?
                 }
             }
-        };
-        Ok(())
+            None => engine.replay_source(
+                source(&args, &args.file, &options)?.as_mut(),
+                args.as_of,
+                stop,
+            )?,
+        }
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })?;
+    for (other, path) in more.into_iter().zip(&args.more_files) {
+        engine
+            .merge(other)
+            .with_context(|| format!("{} shares a client with another file", path.display()))?;
     }
-
-    fn deposit(&mut self, amount: Decimal) -> io::Result<()> {
-        debug!("  depositing: {}", amount);
-        self.available += amount;
-        self.total += amount;
-        debug!("  {:?}", self);
-        Ok(())
+    let interrupted = stopping();
+    let out_of_time = !interrupted && timed_out.get();
+    let incomplete = match (interrupted, out_of_time) {
+        (true, _) => Some("interrupted"),
+        (_, true) => Some("out of time"),
+        _ => None,
+    };
+    if let (Some(path), Some(first_tx)) = (&args.adjustments, args.adjustments_first_tx) {
+        if let Some(reason) = incomplete {
+            warn!(
+                "{reason}, so the adjustments in {} are not applied",
+                path.display()
+            );
+        } else {
+            let adjustments = read_adjustments(open(path)?)
+                .with_context(|| format!("invalid adjustments {}", path.display()))?;
+            for (tx, adjustment) in (first_tx..).zip(&adjustments) {
+                engine.adjust(adjustment, tx)?;
+            }
+        }
+    }
+    if let Some(ledger) = engine.ledger() {
+        balanced(ledger, &engine.snapshot())?;
+    }
+    if args.dry_run {
+        warn!("Dry run: nothing was persisted and the report is not authoritative");
     }
 
-    fn withdrawal(&mut self, amount: Decimal) -> io::Result<()> {
-        if self.available >= amount {
-            debug!("withdrawing: {}", amount);
-            self.available -= amount;
-            self.total -= amount;
-            debug!("{}", self);
-        } else {
-            warn!("Insufficient funds for withdrawal");
+    // Print out all the clients and their account info
+    #[allow(unused_mut)]
+    let mut format = ReportFormat::Csv;
+    #[cfg(feature = "arrow")]
+    if let Some(layout) = args.output_format.layout() {
+        format = ReportFormat::Columnar(layout);
+    }
+    #[cfg(feature = "templates")]
+    if let Some(template) = template {
+        format = ReportFormat::Template(std::sync::Arc::new(template));
+    }
+    let mut reports: Vec<Box<dyn ReportSink>> = vec![Box::new(ReportFile {
+        path: args.output.clone(),
+        format,
+        incomplete,
+    })];
+    reports.extend(args.sinks.iter().map(|spec| spec.open(&args, incomplete)));
+    // Keep stdout a plain accounts CSV
+    let summarized = args
+        .sinks
+        .iter()
+        .any(|spec| matches!(spec, SinkSpec::Summary));
+    if !summarized && (args.engine.config.is_some() || !engine.unknown_types().is_empty()) {
+        reports.push(Box::new(Summary(io::stderr())));
+    }
+    #[cfg(feature = "alerts")]
+    if let Some(alerts) = alerts {
+        reports.push(Box::new(alerts));
+    }
+    let mut events: Vec<Box<dyn EventSink>> = Vec::new();
+    if let Some(path) = &args.events {
+        events.push(Box::new(EventFile {
+            path: path.clone(),
+            #[cfg(feature = "arrow")]
+            layout: args.output_format.layout(),
+        }));
+    }
+    #[cfg(feature = "webhooks")]
+    if !args.webhooks.is_empty() {
+        events.push(Box::new(webhook::Webhooks::new(args.webhooks.clone())));
+    }
+    let (reports, mut persisting): (Vec<_>, Vec<_>) =
+        reports.into_iter().partition(|sink| !sink.persists());
+    for mut sink in reports {
+        sink.write_report(&engine)?;
+    }
+    if let Some(path) = &args.rejections {
+        write_output(path, |w| engine.write_rejections(w))?;
+    }
+    if let Some(path) = &args.flags {
+        write_output(path, |w| engine.write_flags(w))?;
+    }
+    if let Some(path) = &args.audit {
+        write_output(path, |w| engine.write_audit_trail(w))?;
+    }
+    if let (Some(path), Some(ledger)) = (&args.journal, engine.ledger()) {
+        write_output(path, |w| ledger.write_journal(w))?;
+    }
+    if let Some(path) = &args.export_records {
+        write_output(path, |w| engine.write_records(w))?;
+    }
+    let (events, mut persisting_events): (Vec<_>, Vec<_>) =
+        events.into_iter().partition(|sink| !sink.persists());
+    for mut sink in events {
+        sink.write_events(engine.events())?;
+    }
+    if interrupted {
+        warn!("Interrupted. The report only covers the transactions applied so far");
+        process::exit(1);
+    }
+    if out_of_time {
+        warn!(
+            "Out of time. The report is incomplete, it only covers the transactions applied so far"
+        );
+        process::exit(TIMED_OUT);
+    }
+    if !args.dry_run {
+        for sink in &mut persisting {
+            sink.write_report(&engine)?;
         }
-        Ok(())
+        for sink in &mut persisting_events {
+            sink.write_events(engine.events())?;
+        }
+    }
+    if args.repl {
+        repl(|query| query.answer(&engine, io::stdout().lock()))?;
     }
+    Ok(())
+}
 
-    fn dispute(&mut self, tx: u32) -> io::Result<()> {
-        if let Some(amount) = self.records.get(&tx) {
-            info!("Disputing tx:{tx} amount:{amount}");
-            self.available -= amount;
-            self.held += amount;
-            self.in_dispute = true;
-        } else {
-            warn!("Could not find tx:{tx} to dispute. CSV data error?");
-        };
-        Ok(())
+const QUERIES: &str = "\
+account ID         one client's account
+locked             every locked account
+top N [by BALANCE] the N largest accounts by available, held or total
+disputes open      every transaction under dispute
+quit               leave, as does end of input";
+
+/// Answers queries read from standard input until it ends. The prompt and
+/// errors go to stderr so stdout only has the answers
+fn repl(mut answer: impl FnMut(Query) -> io::Result<()>) -> Result<()> {
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        eprint!("tte> ");
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+        match line.trim() {
+            "" => {}
+            "quit" | "exit" => return Ok(()),
+            "help" => eprintln!("{QUERIES}"),
+            query => match query.parse::<Query>() {
+                Ok(query) => answer(query)?,
+                Err(e) => eprintln!("{e}, try help"),
+            },
+        }
     }
+}
 
-    fn resolve(&mut self, tx: u32) -> io::Result<()> {
-        if let Some(amount) = self.records.get(&tx) {
-            info!("resolve tx:{tx} amount:{amount}");
-            self.available += amount;
-            self.held -= amount;
-            self.in_dispute = false;
-        } else {
-            warn!("Could not find tx:{tx} to resolve. CSV data error?");
-        };
-        Ok(())
+fn save_checkpoint(path: &Path, engine: &Engine, position: &csv::Position) -> Result<()> {
+    replace_file(path, |w| write_checkpoint(w, engine, position))
+}
+
+/// Writes next to `path` first so an interruption never leaves a torn
+/// file behind
+fn replace_file(path: &Path, write: impl FnOnce(&mut dyn io::Write) -> Result<()>) -> Result<()> {
+    let partial = path.with_extension("partial");
+    let mut file = io::BufWriter::new(create(&partial)?);
+    write(&mut file)?;
+    file.into_inner()?.sync_all()?;
+    std::fs::rename(&partial, path).with_context(|| format!("could not replace {}", path.display()))
+}
+
+fn accrue_interest(
+    file: PathBuf,
+    as_of: DateTime<Utc>,
+    input: InputArgs,
+    engine: EngineArgs,
+) -> Result<()> {
+    let mut engine = engine.engine()?;
+    let options = input.reader_options();
+    engine.replay(
+        read_csv_with(open(&file)?, &options),
+        Some(AsOf::Timestamp(as_of)),
+    )?;
+    // Number the payments after every tx id in the file, not just those
+    // applied so far
+    let last_tx = read_csv_with(open(&file)?, &options)
+        .filter_map(|result| result.ok().map(|transaction| transaction.tx))
+        .max();
+    let payments = engine.accrue_interest(as_of, last_tx.map_or(1, |tx| tx + 1))?;
+    write_csv(io::stdout().lock(), &payments)?;
+    Ok(())
+}
+
+fn net(file: PathBuf, input: InputArgs, engine: EngineArgs) -> Result<()> {
+    let mut engine = engine.engine()?;
+    engine.record_events();
+    engine.replay(read_csv_with(open(&file)?, &input.reader_options()), None)?;
+    write_movements(io::stdout().lock(), &movements(engine.events()))?;
+    Ok(())
+}
+
+fn analyze(file: PathBuf, input: InputArgs, top: usize, format: AnalysisFormat) -> Result<()> {
+    let transactions = read_csv_with(open(&file)?, &input.reader_options());
+    let analysis = tte::analysis::analyze(transactions, top)?;
+    let mut out = io::stdout().lock();
+    match format {
+        AnalysisFormat::Table => analysis.write_table(out)?,
+        AnalysisFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &analysis)?;
+            io::Write::write_all(&mut out, b"\n")?;
+        }
     }
+    Ok(())
+}
 
-    fn chargeback(&mut self, tx: u32) -> io::Result<()> {
-        if let Some(amount) = self.records.get(&tx) {
-            info!("chargeback tx:{tx} amount:{amount}");
-            self.locked = true;
-            self.held -= amount;
-            self.total -= amount;
-        } else {
-            warn!("Could not find tx:{tx} to chargeback. CSV data error?");
-        };
-        Ok(())
+/// Anything an input file is read from
+trait Input: io::Read + io::Seek {}
+
+impl<T: io::Read + io::Seek> Input for T {}
+
+fn open(path: &PathBuf) -> Result<Box<dyn Input>> {
+    #[cfg(feature = "http")]
+    if http::is_url(path) {
+        let body = http::HttpReader::open(&path.to_string_lossy())
+            .with_context(|| format!("could not open {}", path.display()))?;
+        return Ok(Box::new(body));
+    }
+    #[cfg(feature = "object-store")]
+    if remote::is_remote(path) {
+        let object = remote::ObjectReader::open(&path.to_string_lossy())
+            .with_context(|| format!("could not open {}", path.display()))?;
+        return Ok(Box::new(object));
     }
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    Ok(Box::new(file))
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-enum TransType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
+/// Sets `engine` up for a report with the options in `args`
+fn prepare(engine: &mut Engine, args: &ReportArgs) -> Result<()> {
+    if args.events.is_some() {
+        engine.record_events();
+    }
+    if args.journal.is_some() {
+        engine.keep_journal();
+    }
+    for &client in &args.trace_clients {
+        engine.trace_client(client);
+    }
+    for &tx in &args.trace_txs {
+        engine.trace_tx(tx);
+    }
+    if args.check_invariants {
+        engine.check_invariants();
+    }
+    if args.extended {
+        engine.extend_report();
+    }
+    if args.risk_score {
+        engine.score_risk();
+    }
+    if let Some(clients) = args.expect_clients {
+        engine.reserve_for(clients, args.expect_txs.unwrap_or_default());
+    }
+    if let Some(max_memory) = args.max_memory {
+        engine.spill_records(max_memory)?;
+    }
+    #[cfg(feature = "webhooks")]
+    if !args.webhooks.is_empty() {
+        engine.record_events();
+    }
+    Ok(())
 }
 
-/// [Transaction] is a struct used by [serde] and [csv] to deserialize the
-/// input CSV data into fields that can be acted upon.
-#[derive(Debug, Deserialize, PartialEq)]
-struct Transaction {
-    #[serde(rename = "type")]
-    trans: TransType,
-    client: u16,
-    tx: u32,
-    amount: Option<Decimal>,
+/// The transactions of a report by the scheme of `path`: `-` is standard
+/// input, `generate://` made up transactions, and anything else a file or URL
+/// as [open_input] takes it. New kinds of input go here.
+fn source(
+    args: &ReportArgs,
+    path: &PathBuf,
+    options: &ReaderOptions,
+) -> Result<Box<dyn TransactionSource>> {
+    let name = path.to_string_lossy();
+    if name == "-" {
+        return Ok(Box::new(read_csv_with(io::stdin(), options)));
+    }
+    if name.starts_with(Generator::SCHEME) {
+        let generator: Generator = name.parse()?;
+        return Ok(Box::new(generator.source()));
+    }
+    Ok(Box::new(read_csv_with(open_input(args, path)?, options)))
 }
 
-/// Currently only used by the unit tests
-#[allow(dead_code)]
-impl Transaction {
-    fn new(trans: TransType, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
-        Transaction {
-            trans,
-            client,
-            tx,
-            amount,
+/// Opens the transactions file of a report, through io_uring if asked to
+#[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+fn open_input(args: &ReportArgs, path: &PathBuf) -> Result<Box<dyn Input>> {
+    #[cfg(feature = "io-uring")]
+    if args.io_uring && path.is_file() {
+        #[cfg(target_os = "linux")]
+        match uring::UringFile::open(path) {
+            Ok(file) => return Ok(Box::new(file)),
+            Err(e) => warn!("io_uring is not available, reading as usual: {e}"),
         }
+        #[cfg(not(target_os = "linux"))]
+        warn!("io_uring is only on Linux, reading as usual");
     }
+    open(path)
 }
-/// Taken from <https://docs.rs/csv/latest/csv/tutorial/index.html#reading-csv>
-/// Returns the first positional argument sent to this process. If there are no
-/// positional arguments, then this returns an error.
-fn get_first_arg() -> Option<OsString> {
-    env::args_os().nth(1)
+
+fn create(path: &PathBuf) -> Result<File> {
+    File::create(path).with_context(|| format!("could not create {}", path.display()))
 }
 
-fn read_csv(csv: impl io::Read) -> csv::DeserializeRecordsIntoIter<impl io::Read, Transaction> {
-    let rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(csv);
-    rdr.into_deserialize()
+/// Writes an output file, or uploads it when built with the `object-store`
+/// feature and `path` is an object URL
+fn write_output(
+    path: &PathBuf,
+    write: impl FnOnce(&mut dyn io::Write) -> io::Result<()>,
+) -> Result<()> {
+    let failed = || format!("could not write {}", path.display());
+    #[cfg(feature = "object-store")]
+    if remote::is_remote(path) {
+        let mut object = remote::ObjectWriter::create(&path.to_string_lossy())
+            .with_context(|| format!("could not create {}", path.display()))?;
+        write(&mut object).with_context(failed)?;
+        return object.finish().with_context(failed);
+    }
+    let mut file = io::BufWriter::new(create(path)?);
+    write(&mut file).with_context(failed)?;
+    io::Write::flush(&mut file).with_context(failed)
 }
 
-fn usage() {
-    println!("Usage");
-    println!("    cargo run -- transactions.cv > account.csv");
-    process::exit(1);
+fn reconcile(
+    transactions: PathBuf,
+    expected: PathBuf,
+    input: InputArgs,
+    engine: EngineArgs,
+) -> Result<()> {
+    let mut engine = engine.engine()?;
+    let options = input.reader_options();
+    engine.replay(read_csv_with(open(&transactions)?, &options), None)?;
+    let actual = engine.snapshot();
+    let expected = read_snapshot(open(&expected)?)?;
+    check(&expected, &actual, "engine output")
 }
 
-fn main() -> Result<()> {
-    env_logger::builder()
-        .format_timestamp(None)
-        .filter_level(LevelFilter::Info)
-        .init();
+fn replay(events: PathBuf, expected: PathBuf) -> Result<()> {
+    let mut ledger = Ledger::default();
+    let read = read_events(io::BufReader::new(open(&events)?)).inspect(|event| {
+        if let Ok(event) = event {
+            ledger.post(event);
+        }
+    });
+    let actual =
+        replay_events(read).with_context(|| format!("invalid events {}", events.display()))?;
+    balanced(&ledger, &actual)?;
+    let expected = read_snapshot(open(&expected)?)?;
+    check(&expected, &actual, "event replay")
+}
 
-    let mut clients: HashMap<u16, Client> = HashMap::new();
-
-    if let Some(filename) = get_first_arg() {
-        match File::open(filename) {
-            Ok(open_file) => {
-                let transactions = read_csv(open_file);
-                for result in transactions {
-                    let transaction: Transaction = result?;
-                    debug!("{:?}", transaction);
-
-                    if let Entry::Vacant(e) = clients.entry(transaction.client) {
-                        debug!("  Adding new client: {}", transaction.client);
-                        e.insert(Client::default());
-                    } else {
-                        debug!("  Client {} exists", transaction.client);
-                    }
-
-                    if let Some(client) = clients.get_mut(&transaction.client) {
-                        client.transact(transaction)?;
-                    }
-                }
+/// Fails with every way the books disagree with the accounts, if any
+fn balanced(ledger: &Ledger, accounts: &Snapshot) -> Result<()> {
+    let problems = ledger.unbalanced(accounts);
+    if !problems.is_empty() {
+        bail!("the books do not balance:\n  {}", problems.join("\n  "));
+    }
+    Ok(())
+}
+
+/// Prints every discrepancy between the expected balances and the `actual`
+/// ones from `source`, exiting non-zero if there are any
+fn check(expected: &Snapshot, actual: &Snapshot, source: &str) -> Result<()> {
+    let discrepancies = compare(expected, actual);
+    for discrepancy in &discrepancies {
+        match discrepancy {
+            Difference::OnlyLeft(client) => {
+                println!("client {client}: expected but not in {source}")
             }
-            Err(e) => {
-                error!("{}", e);
-                usage();
+            Difference::OnlyRight(client) => {
+                println!("client {client}: in {source} but not expected")
             }
-        };
-
-        // Print out all the clients and their account info
-        println!("client, available, held, total, locked");
-        for (id, client) in clients {
-            println!("{}, {}", id, client);
+            Difference::Changed {
+                client,
+                field,
+                left,
+                right,
+            } => println!("client {client}: {field} expected {left} got {right}"),
         }
-    } else {
-        usage();
     }
+    if !discrepancies.is_empty() {
+        println!("discrepancies found: {}", discrepancies.len());
+        process::exit(1);
+    }
+    println!("all {} clients reconcile", actual.len());
+    Ok(())
+}
 
+fn diff(a: PathBuf, b: PathBuf) -> Result<()> {
+    let a = read_snapshot(open(&a)?)?;
+    let b = read_snapshot(open(&b)?)?;
+    let differences = compare(&a, &b);
+    for difference in &differences {
+        println!("{difference}");
+    }
+    if differences.is_empty() {
+        println!("no differences");
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use rust_decimal_macros::dec;
-
-    const DATA_SPACES: &'static str = "\
-type,       client,     tx,     amount
-deposit,         1,     1,         1.0
-deposit,         2,     2,         2.0
-deposit,         1,     3,         2.0
-withdrawal,      1,     4,         1.5
-withdrawal,      2,     5,         3.0
-";
-
-    const DATA_NO_SPACES: &'static str = "\
-type,client,tx,amount
-deposit,1,1,1.0
-deposit,2,2,2.0
-deposit,1,3,2.0
-withdrawal,1,4,1.5
-withdrawal,2,5,3.0
-";
-
-    fn log_init() {
-        let _ = env_logger::builder()
-            .format_timestamp(None)
-            .is_test(true)
-            .try_init();
-    }
-
-    #[test]
-    fn test_client_defaults() {
-        log_init();
-        let client = Client::default();
-        println!("{:?}", client);
-
-        assert_eq!(client.available, dec!(0.0000));
-        assert_eq!(client.held, dec!(0.0000));
-        assert_eq!(client.total, dec!(0.0000));
-        assert_eq!(client.locked, false);
-    }
-
-    #[test]
-    fn test_basic_deposit() {
-        log_init();
-        let mut client = Client::default();
-        println!("{:?}", client);
-
-        client.deposit(dec!(3.14)).unwrap();
-        assert_eq!(client.available, dec!(3.14));
-        assert_eq!(client.held, dec!(0));
-        assert_eq!(client.total, dec!(3.14));
-        assert_eq!(client.locked, false);
-    }
-
-    #[test]
-    fn test_basic_withdrawal() {
-        log_init();
-        let mut client = Client::default();
-
-        client.deposit(dec!(1.0)).unwrap();
-        client.deposit(dec!(2.0)).unwrap();
-        client.withdrawal(dec!(1.5)).unwrap();
-        assert_eq!(client.available, dec!(1.5));
-        assert_eq!(client.held, dec!(0));
-        assert_eq!(client.total, dec!(1.5));
-        assert_eq!(client.locked, false);
-    }
-
-    #[test]
-    fn test_withdrawal_insufficient_funds() {
-        log_init();
-        let mut client = Client::default();
-        client.withdrawal(dec!(1.5)).unwrap();
-    }
-
-    #[test]
-    fn test_basic_dispute() -> Result<()> {
-        log_init();
-        let mut client = Client::default();
-        println!("{:#?}", client);
-
-        let amount: Decimal = dec!(6.62);
-        client.deposit(amount).unwrap();
-        client.add_record(1, dec!(6.62))?;
-        client.dispute(1).unwrap();
-        assert_eq!(client.available, dec!(0));
-        assert_eq!(client.held, amount);
-        assert_eq!(client.total, amount);
-        assert_eq!(client.locked, false);
-        assert_eq!(client.in_dispute, true);
-        Ok(())
+fn tenants(
+    files: Vec<PathBuf>,
+    tenant: TenantArgs,
+    output_dir: Option<PathBuf>,
+    input: InputArgs,
+    engine: EngineArgs,
+) -> Result<()> {
+    if engine.initial_accounts.is_some() || engine.import_records.is_some() {
+        anyhow::bail!("starting balances and imported records are not kept per tenant");
+    }
+    let options = input.reader_options();
+    let mut tenants = Tenants::new(engine.config()?);
+    for file in &files {
+        let of_file = tenant.of_file(file)?;
+        let mut input = io::BufReader::new(open(file)?);
+        let schema = read_schema(&mut input)?;
+        let mut rdr = options.schema_reader(input, schema);
+        let headers = rdr.headers()?.clone();
+        schema.unwrap_or_default().check(&headers)?;
+        let column = match &tenant.tenant_column {
+            Some(name) => Some(
+                headers
+                    .iter()
+                    .position(|header| header == name)
+                    .with_context(|| format!("{} has no {name} column", file.display()))?,
+            ),
+            None => None,
+        };
+        for record in rdr.records() {
+            if stopping() {
+                break;
+            }
+            let record = record?;
+            let transaction = options.decode(&record, &headers)?;
+            let tenant = match column {
+                Some(column) => record.get(column).unwrap_or_default(),
+                None => of_file.as_deref().unwrap_or_default(),
+            };
+            if tenant.is_empty() {
+                let line = record.position().map_or(0, csv::Position::line);
+                anyhow::bail!("{}:{line}: no tenant", file.display());
+            }
+            tenants.apply(tenant, transaction)?;
+        }
     }
+    match &output_dir {
+        Some(dir) => {
+            for (tenant, engine) in tenants.iter() {
+                // The tenant names a file, so it must not lead out of the directory
+                if tenant.contains(['/', '\\']) || tenant.starts_with('.') {
+                    anyhow::bail!("tenant {tenant} cannot name a report file");
+                }
+                write_output(&dir.join(format!("{tenant}.csv")), |w| {
+                    engine.write_report(w)
+                })?;
+            }
+        }
+        None => tenants.write_report(io::stdout().lock())?,
+    }
+    if stopping() {
+        warn!("Interrupted. The report only covers the transactions applied so far");
+        process::exit(1);
+    }
+    Ok(())
+}
 
-    #[test]
-    fn test_basic_resolve() -> Result<()> {
-        log_init();
-        let mut client = Client::default();
-        print!("{:#?}", client);
-
-        let amount: Decimal = dec!(6.02);
-        client.deposit(amount).unwrap();
-        client.add_record(1, amount)?;
-        client.dispute(1).unwrap();
-        assert_eq!(client.available, dec!(0));
-        assert_eq!(client.held, amount);
-        assert_eq!(client.total, amount);
-        assert_eq!(client.locked, false);
-        assert_eq!(client.in_dispute, true);
-
-        client.resolve(1).unwrap();
-        assert_eq!(client.held, dec!(0));
-        assert_eq!(client.available, amount);
-        assert_eq!(client.total, amount);
-        assert_eq!(client.locked, false);
-        assert_eq!(client.in_dispute, false);
+fn shard(
+    file: PathBuf,
+    workers: Option<NonZeroUsize>,
+    output: Option<PathBuf>,
+    verify_parallel: bool,
+    input: InputArgs,
+    engine: EngineArgs,
+) -> Result<()> {
+    let workers = match workers {
+        Some(workers) => workers,
+        None => std::thread::available_parallelism()?,
+    };
+    let dir = std::env::temp_dir().join(format!("tte-shard-{}", process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let report = shard::split(
+        open(&file)?,
+        &input.reader_options(),
+        &dir,
+        workers.get() as u64,
+    )
+    .and_then(|files| {
+        let mut args = input.decode_args();
+        args.extend(engine.args());
+        shard::run(&files, &args)
+    });
+    std::fs::remove_dir_all(&dir)?;
+    let report = report?;
+    if verify_parallel {
+        let mut single = engine.engine()?;
+        single.replay(read_csv_with(open(&file)?, &input.reader_options()), None)?;
+        let mut out = Vec::new();
+        single.write_report(&mut out)?;
+        shard::verify(&report, &String::from_utf8(out)?)?;
+    }
+    match &output {
+        Some(path) => write_output(path, |w| w.write_all(report.as_bytes()))?,
+        None => io::Write::write_all(&mut io::stdout().lock(), report.as_bytes())?,
+    }
+    Ok(())
+}
 
-        Ok(())
+fn validate_file(file: PathBuf, input: InputArgs) -> Result<()> {
+    let issues = validate(open(&file)?, &input.reader_options())?;
+    for issue in &issues {
+        println!(
+            "{}:{}: {}: {}",
+            file.display(),
+            issue.line,
+            issue.severity,
+            issue.message
+        );
     }
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    println!("{errors} error(s), {} warning(s)", issues.len() - errors);
+    if errors > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
 
-    #[test]
-    fn test_basic_chargeback() -> Result<()> {
-        log_init();
-        let mut client = Client::default();
-        print!("{:#?}", client);
-
-        let amount: Decimal = dec!(6.28);
-        client.deposit(amount).unwrap();
-        client.deposit(amount).unwrap();
-        client.add_record(1, amount)?;
-        client.add_record(2, amount)?;
-        client.dispute(2).unwrap();
-        assert_eq!(client.available, amount);
-        assert_eq!(client.held, amount);
-        assert_eq!(client.total, amount + amount);
-        assert_eq!(client.locked, false);
-        assert_eq!(client.in_dispute, true);
-
-        client.chargeback(2).unwrap();
-        assert_eq!(client.available, amount);
-        assert_eq!(client.held, dec!(0));
-        assert_eq!(client.total, amount);
-        assert_eq!(client.locked, true);
-        assert_eq!(client.in_dispute, true);
+#[cfg(feature = "sql")]
+fn sql_query(
+    query: String,
+    file: PathBuf,
+    with_transactions: bool,
+    input: InputArgs,
+    engine: EngineArgs,
+) -> Result<()> {
+    let mut engine = engine.engine()?;
+    let mut transactions = Vec::new();
+    engine.replay(
+        read_csv_with(open(&file)?, &input.reader_options()).inspect(|result| {
+            if let (true, Ok(transaction)) = (with_transactions, result) {
+                transactions.push(transaction.clone());
+            }
+        }),
+        None,
+    )?;
+    let db = sql::load(&engine, &transactions)?;
+    sql::query(&db, &query, io::stdout().lock())
+}
 
-        Ok(())
+fn import(
+    file: PathBuf,
+    format: import::Format,
+    clients: Option<PathBuf>,
+    first_tx: tte::TxId,
+) -> Result<()> {
+    let clients = match clients {
+        Some(path) => import::Clients::read(open(&path)?)
+            .with_context(|| format!("invalid clients file {}", path.display()))?,
+        None => import::Clients::default(),
+    };
+    let content = io::read_to_string(open(&file)?)?;
+    let entries = match format {
+        import::Format::Fix => fix::read(&content),
+        #[cfg(feature = "iso20022")]
+        import::Format::Iso20022 => iso20022::read(&content),
+        import::Format::Ofx => ofx::read(&content),
+        import::Format::Qif => {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy();
+            qif::read(&content, &name)
+        }
     }
+    .with_context(|| format!("could not import {}", file.display()))?;
+    let transactions = import::transactions(entries, &clients, first_tx)?;
+    write_csv(io::stdout().lock(), &transactions)?;
+    Ok(())
+}
 
-    #[test]
-    fn test_parse_csv_spaces() {
-        read_csv(DATA_SPACES.as_bytes());
-    }
-
-    #[test]
-    fn test_parse_csv_no_spaces() {
-        read_csv(DATA_NO_SPACES.as_bytes());
-    }
-
-    #[test]
-    fn test_transaction_chargeback() -> Result<()> {
-        const DATA: &'static str = "\
-type,client,tx,amount
-deposit,1,1,1.0
-deposit,1,2,2.0
-deposit,1,3,100.0
-dispute,1,3,
-deposit,1,4,100.0
-chargeback,1,3,
-";
-        let mut client = Client::default();
-        let transactions = read_csv(DATA.as_bytes());
-        for result in transactions {
-            let transaction: Transaction = result?;
-            client.transact(transaction)?;
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+fn consume(args: ConsumeArgs) -> Result<()> {
+    let mut consumer = if args.state.exists() {
+        let mut consumer =
+            consume::Consumer::resume(&args.state, args.engine.config()?, args.shard)?;
+        args.engine.load_middleware(&mut consumer.engine)?;
+        consumer
+    } else {
+        consume::Consumer::new(args.engine.engine()?, args.shard)
+    };
+    consumer.dedupe(args.dedupe_window);
+    if let (Some(after), Some(dir)) = (args.archive_after, &args.archive_dir) {
+        consumer.engine.archive_clients(dir, after)?;
+    }
+    if let (Some(at), Some(dir)) = (args.close_period_at, &args.period_dir) {
+        consumer.schedule = Some(consume::Schedule::new(at, dir.clone(), &consumer.engine));
+    }
+    if let Some(addr) = &args.health_addr {
+        let health = std::sync::Arc::new(health::Health::new(
+            args.ready_max_staleness,
+            args.ready_max_lag,
+        ));
+        log::info!("Answering health probes on {}", health.serve(addr)?);
+        consumer.health = Some(health);
+    }
+    if let Some(endpoint) = &args.otlp_endpoint {
+        consumer.tracer = Some(otel::Tracer::new(endpoint, args.trace_sample)?);
+    }
+    #[cfg(feature = "webhooks")]
+    if !args.webhooks.is_empty() {
+        let webhooks = webhook::Webhooks::new(args.webhooks.clone());
+        consumer.engine.record_events();
+        consumer
+            .notify
+            .push(Box::new(move |_, events| webhooks.notify(events)));
+    }
+    #[cfg(feature = "alerts")]
+    if let Some(path) = &args.alerts {
+        let mut alerts = alert::Alerts::read(path)?;
+        consumer
+            .notify
+            .push(Box::new(move |engine, _| alerts.check(engine)));
+    }
+    #[cfg(any(feature = "redis", feature = "nats"))]
+    let group = match args.shard.count {
+        1 => args.group.clone(),
+        _ => format!("{}-{}", args.group, args.shard.index),
+    };
+    let mut source: Box<dyn consume::Source> = match args.broker {
+        #[cfg(feature = "redis")]
+        BrokerArgs {
+            redis: Some(url), ..
+        } => Box::new(redis_stream::RedisStream::open(
+            &url,
+            &args.stream,
+            &group,
+            &args.consumer,
+        )?),
+        #[cfg(feature = "amqp")]
+        BrokerArgs {
+            amqp: Some(url), ..
+        } => Box::new(amqp::Amqp::open(
+            &url,
+            &args.queue,
+            &args.consumer,
+            args.batch.get().try_into().unwrap_or(u16::MAX),
+            args.dead_letter.as_deref(),
+        )?),
+        #[cfg(feature = "nats")]
+        BrokerArgs {
+            nats: Some(url), ..
+        } => Box::new(jetstream::JetStream::open(
+            &url,
+            &args.subject,
+            &group,
+            args.publish.as_deref(),
+        )?),
+        _ => unreachable!("clap requires a broker"),
+    };
+    consumer.run(source.as_mut(), &args.state, args.batch.get(), stopping)?;
+    consumer.engine.write_report(io::stdout().lock())?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(
+    addr: String,
+    limits: server::Limits,
+    api_keys: Option<PathBuf>,
+    args: EngineArgs,
+) -> Result<()> {
+    let keys = match &api_keys {
+        Some(path) => Some(
+            server::read_api_keys(open(path)?)
+                .with_context(|| format!("invalid API keys {}", path.display()))?,
+        ),
+        None => {
+            warn!("No --api-keys given, anyone may submit, read the audit trail and lock accounts");
+            None
         }
-        assert_eq!(client.held, dec!(0));
-        assert_eq!(client.total, dec!(103));
-        assert_eq!(client.locked, true);
-        assert_eq!(client.in_dispute, true);
-        Ok(())
+    };
+    let server = server::Server::start(args.engine()?, &addr, limits, keys)?;
+    log::info!("Serving on {}", server.addr);
+    while !stopping() {
+        thread::sleep(Duration::from_millis(100));
     }
+    let engine = server.stop()?;
+    engine.write_report(io::stdout().lock())?;
+    Ok(())
+}
 
-    #[test]
-    fn test_parse_csv_file() {
-        let _ = OsString::from_str("transactions.csv").unwrap();
+fn what_if(
+    state: PathBuf,
+    transaction: Transaction,
+    format: AnalysisFormat,
+    args: EngineArgs,
+) -> Result<()> {
+    if args.initial_accounts.is_some() || args.import_records.is_some() {
+        bail!("--initial-accounts and --import-records start a new engine, not a saved state");
     }
+    let mut engine = whatif::read_engine(open(&state)?)
+        .with_context(|| format!("invalid state {}", state.display()))?;
+    engine.configure(args.config()?);
+    args.load_middleware(&mut engine)?;
+    let projection = whatif::what_if(&mut engine, transaction)?;
+    let out = io::stdout().lock();
+    match format {
+        AnalysisFormat::Table => projection.write_text(out)?,
+        AnalysisFormat::Json => projection.write_json(out)?,
+    }
+    Ok(())
+}
 
-    #[test]
-    fn test_csv_to_transactions() -> Result<()> {
-        let mut transactions = read_csv(DATA_SPACES.as_bytes());
+fn merge(state: PathBuf, into: ClientId, from: ClientId, tx: TxId) -> Result<()> {
+    let (mut engine, position) = read_checkpoint(open(&state)?)
+        .with_context(|| format!("invalid checkpoint {}", state.display()))?;
+    engine.merge_accounts(into, from, tx)?;
+    replace_file(&state, |w| write_checkpoint(w, &engine, &position))?;
+    Ok(())
+}
 
-        if let Some(result) = transactions.next() {
-            let record: Transaction = result?;
-            assert_eq!(
-                record,
-                Transaction {
-                    trans: TransType::Deposit,
-                    client: 1,
-                    tx: 1,
-                    amount: Some(dec!(1.0)),
-                }
+fn close_period(
+    state: PathBuf,
+    id: String,
+    dir: PathBuf,
+    closed_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let (mut engine, position) = read_checkpoint(open(&state)?)
+        .with_context(|| format!("invalid checkpoint {}", state.display()))?;
+    let at = closed_at.unwrap_or_else(|| SystemTime::now().into());
+    let period = engine.close_period(&id, at)?;
+    write_period(&dir, &period)?;
+    replace_file(&state, |w| write_checkpoint(w, &engine, &position))
+}
+
+/// Writes `period` to `DIR/ID.json`. A period is never written over with
+/// other accounts, but one written already with the same is left as it is,
+/// as when it is closed again after a crash before the state was saved.
+fn write_period(dir: &Path, period: &Period) -> Result<()> {
+    let path = dir.join(format!("{}.json", period.id));
+    if path.exists() {
+        let written = read_period(open(&path)?)
+            .with_context(|| format!("invalid period {}", path.display()))?;
+        if written.accounts != period.accounts {
+            bail!(
+                "period {} was written already, with other accounts",
+                period.id
             );
         }
-        Ok(())
+        return Ok(());
     }
+    std::fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+    replace_file(&path, |w| period.write(w))
+}
 
-    #[test]
-    fn test_transact() -> Result<()> {
-        //        const DATA: &'static str = "\
-        //    type,       client,    tx,     amount
-        //    deposit,         1,     1,       10.0
-        //    withdrawal,      1,     2,        3.5
-        //    dispute,         1,     2,
-        //    resolve,         1,     2,
-        //    ";
-        //        let mut transactions = read_csv(DATA.as_bytes());
-        let mut client = Client::default();
-
-        // Deposit
-        let record = Transaction::new(TransType::Deposit, 1, 1, Some(dec!(10.0)));
-        println!("{:#?}", record);
-        assert!(client.transact(record).is_ok());
-        assert_eq!(client.available, dec!(10));
-
-        // Withdrawl
-        let record = Transaction::new(TransType::Withdrawal, 1, 2, Some(dec!(3.5)));
-        println!("{:#?}", record);
-        assert!(client.transact(record).is_ok());
-        assert_eq!(client.available, dec!(6.5));
-
-        // Dispute a withdrawal
-        let record = Transaction::new(TransType::Dispute, 1, 2, None);
-        println!("{:#?}", record);
-        assert_eq!(client.held, dec!(0));
-        assert!(client.transact(record).is_ok());
-        assert_eq!(client.available, dec!(3));
-        assert_eq!(client.total, dec!(6.5));
-        assert_eq!(client.held, dec!(3.5));
-        assert!(client.in_dispute);
-
-        // Resolve the dispute
-        let record = Transaction::new(TransType::Resolve, 1, 2, None);
-        println!("{:?}", client);
-        assert!(client.transact(record).is_ok());
-        assert!(!client.in_dispute);
-        assert_eq!(client.available, dec!(6.5));
-        assert_eq!(client.total, dec!(6.5));
-        assert_eq!(client.held, dec!(0));
-
-        // Dispute another
-        let record = Transaction::new(TransType::Dispute, 1, 1, None);
-        assert!(client.transact(record).is_ok());
-
-        // Chargeback
-        let record = Transaction::new(TransType::Chargeback, 1, 1, None);
-        assert!(client.transact(record).is_ok());
-        println!("{:?}", client);
-        assert!(client.in_dispute);
-        assert!(client.locked);
-        assert_eq!(client.held, dec!(0));
-        // Since the dispute was on a withdrawal the total will be negative
-        assert_eq!(client.total, dec!(-3.5));
+#[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+fn follow(state: PathBuf, max_staleness: u64) -> Result<()> {
+    let mut follower = follow::Follower::open(state, Duration::from_secs(max_staleness))?;
+    repl(|query| query.answer(follower.engine(), io::stdout().lock()))
+}
 
-        Ok(())
+#[cfg(feature = "tui")]
+fn dashboard(file: PathBuf, input: InputArgs, engine: EngineArgs) -> Result<()> {
+    let engine = engine.engine()?;
+    let options = input.reader_options();
+    let csv: Box<dyn io::Read> = if file.as_os_str() == "-" {
+        Box::new(io::stdin())
+    } else {
+        open(&file)?
+    };
+    tui::run(engine, read_csv_with(io::BufReader::new(csv), &options))
+}
+
+fn main() -> Result<()> {
+    env_logger::builder()
+        .format_timestamp(None)
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    // Finish the transaction being applied and still write out the report
+    // instead of dying mid-run, e.g. during a rollout
+    ctrlc::set_handler(|| STOP.store(true, Ordering::Relaxed))?;
+
+    let cli = Cli::parse();
+    let command = match (cli.command, cli.file) {
+        (Some(command), _) => command,
+        (None, Some(file)) => Command::Report(Box::new(ReportArgs {
+            file,
+            more_files: Vec::new(),
+            input: InputArgs::default(),
+            engine: EngineArgs::default(),
+            as_of: None,
+            adjustments: None,
+            adjustments_first_tx: None,
+            output: None,
+            #[cfg(feature = "arrow")]
+            output_format: OutputFormat::Csv,
+            #[cfg(feature = "templates")]
+            output_template: None,
+            trace_clients: Vec::new(),
+            trace_txs: Vec::new(),
+            check_invariants: false,
+            extended: false,
+            risk_score: false,
+            rejections: None,
+            flags: None,
+            audit: None,
+            export_records: None,
+            events: None,
+            journal: None,
+            checkpoint_every: None,
+            checkpoint_file: None,
+            resume: false,
+            max_duration: None,
+            max_memory: None,
+            expect_clients: None,
+            expect_txs: None,
+            #[cfg(feature = "io-uring")]
+            io_uring: false,
+            assume_disjoint_clients: false,
+            dry_run: false,
+            repl: false,
+            sinks: Vec::new(),
+            #[cfg(feature = "postgres")]
+            sink_table: "accounts".to_string(),
+            #[cfg(feature = "webhooks")]
+            webhooks: Vec::new(),
+            #[cfg(feature = "alerts")]
+            alerts: None,
+        })),
+        (None, None) => {
+            Cli::command().print_help()?;
+            process::exit(1);
+        }
+    };
+
+    match command {
+        Command::Report(args) => report(*args),
+        Command::Reconcile {
+            transactions,
+            expected,
+            input,
+            engine,
+        } => reconcile(transactions, expected, input, engine),
+        Command::Replay { events, expected } => replay(events, expected),
+        Command::AccrueInterest {
+            file,
+            as_of,
+            input,
+            engine,
+        } => accrue_interest(file, as_of, input, engine),
+        Command::Net {
+            file,
+            input,
+            engine,
+        } => net(file, input, engine),
+        Command::Analyze {
+            file,
+            input,
+            top,
+            format,
+        } => analyze(file, input, top, format),
+        Command::Diff { a, b } => diff(a, b),
+        Command::Validate { file, input } => validate_file(file, input),
+        Command::Tenants {
+            files,
+            tenant,
+            output_dir,
+            input,
+            engine,
+        } => tenants(files, tenant, output_dir, input, engine),
+        Command::Shard {
+            file,
+            workers,
+            shard_by: ShardBy::Client,
+            output,
+            verify_parallel,
+            input,
+            engine,
+        } => shard(file, workers, output, verify_parallel, input, engine),
+        #[cfg(feature = "sql")]
+        Command::Sql {
+            query,
+            file,
+            with_transactions,
+            input,
+            engine,
+        } => sql_query(query, file, with_transactions, input, engine),
+        Command::Import {
+            file,
+            format,
+            clients,
+            first_tx,
+        } => import(file, format, clients, first_tx),
+        #[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+        Command::Consume(args) => consume(*args),
+        #[cfg(feature = "server")]
+        Command::Serve {
+            addr,
+            queue,
+            rate_limit,
+            global_rate_limit,
+            api_keys,
+            engine,
+        } => {
+            let limits = server::Limits {
+                queue,
+                rate: rate_limit,
+                global_rate: global_rate_limit,
+            };
+            serve(addr, limits, api_keys, engine)
+        }
+        #[cfg(any(feature = "redis", feature = "nats", feature = "amqp"))]
+        Command::Follow {
+            state,
+            max_staleness,
+        } => follow(state, max_staleness),
+        #[cfg(feature = "tui")]
+        Command::Tui {
+            file,
+            input,
+            engine,
+        } => dashboard(file, input, engine),
+        Command::Whatif {
+            state,
+            tx,
+            format,
+            engine,
+        } => what_if(state, tx, format, engine),
+        Command::Merge {
+            state,
+            into,
+            from,
+            tx,
+        } => merge(state, into, from, tx),
+        Command::ClosePeriod {
+            state,
+            period,
+            period_dir,
+            closed_at,
+        } => close_period(state, period, period_dir, closed_at),
     }
 }
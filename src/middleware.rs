@@ -0,0 +1,226 @@
+//! Checks and changes made to transactions before the engine applies them
+//!
+//! Policies that cut across transaction types, such as validation, enrichment,
+//! filtering and rate limiting, go in a chain of [Middleware] rather than into
+//! the accounts. Every transaction offered to the engine goes through the
+//! chain in order, and each link passes it on, maybe changed, skips it, or
+//! rejects it with a [Reason], recorded like a broken risk limit. Library users
+//! add their own with [crate::Engine::add_middleware]:
+//! ```
+//! use tte::middleware::{Context, Decision};
+//! use tte::{Engine, Reason, Transaction};
+//!
+//! let mut engine = Engine::new();
+//! engine.add_middleware(|transaction: Transaction, _: &mut Context| {
+//!     match transaction.client {
+//!         0 => Decision::Reject(Reason::Refused),
+//!         _ => Decision::Continue(transaction),
+//!     }
+//! });
+//! ```
+//! The `tte` tool builds a chain of [Builtin]s from the `[[middleware]]`
+//! tables of its config, which run ahead of any added in code:
+//! ```toml
+//! [[middleware]]
+//! kind = "filter"
+//! types = ["bonus"]
+//! clients = [99]
+//!
+//! [[middleware]]
+//! kind = "stamp"
+//!
+//! [[middleware]]
+//! kind = "rate_limit"
+//! max = 100
+//! per_seconds = 60
+//! ```
+use crate::client::Client;
+use crate::reason::Reason;
+use crate::transaction::{ClientId, Transaction};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+
+/// What becomes of a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// On to the next middleware, and then the engine
+    Continue(Transaction),
+    /// Dropped without a trace in the results, as if never offered
+    Skip,
+    /// Refused before reaching the account, with a reason such as
+    /// [Reason::Refused]. Event replay takes the reasons accounts give
+    /// themselves, e.g. `insufficient_funds`, to mean the account exists, so
+    /// prefer those for which [Reason::before_account] is true.
+    Reject(Reason),
+}
+
+/// What the engine knows when a transaction reaches a middleware
+pub struct Context<'a> {
+    /// The client's account, unless this is its first transaction
+    pub account: Option<&'a Client>,
+    /// How many transactions were offered to the engine, this one included
+    pub offered: u64,
+}
+
+/// One link of the chain
+pub trait Middleware {
+    fn check(&mut self, transaction: Transaction, context: &mut Context) -> Decision;
+}
+
+impl<F> Middleware for F
+where
+    F: FnMut(Transaction, &mut Context) -> Decision,
+{
+    fn check(&mut self, transaction: Transaction, context: &mut Context) -> Decision {
+        self(transaction, context)
+    }
+}
+
+/// The middleware that can be configured without code
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Builtin {
+    /// Skips the transactions of any of `types` or `clients`
+    Filter {
+        #[serde(default)]
+        types: Vec<String>,
+        #[serde(default)]
+        clients: Vec<ClientId>,
+    },
+    /// Rejects transactions without a timestamp as malformed
+    RequireTimestamp,
+    /// Gives transactions without a timestamp the time they are applied, so
+    /// the limits going by timestamps see them
+    Stamp,
+    /// Rejects a client's transactions past `max` within `per_seconds`, going
+    /// by their timestamps. Transactions without one are not counted, and
+    /// the counts start over when the engine is restored from saved state.
+    RateLimit { max: usize, per_seconds: u64 },
+}
+
+impl Builtin {
+    pub fn build(&self) -> Box<dyn Middleware + Send> {
+        match self.clone() {
+            Builtin::Filter { types, clients } => boxed(move |transaction, _| {
+                let trans = transaction.trans.as_str();
+                match types.iter().any(|t| t == trans) || clients.contains(&transaction.client) {
+                    true => Decision::Skip,
+                    false => Decision::Continue(transaction),
+                }
+            }),
+            Builtin::RequireTimestamp => {
+                boxed(|transaction, _| match transaction.timestamp.is_some() {
+                    true => Decision::Continue(transaction),
+                    false => Decision::Reject(Reason::Malformed),
+                })
+            }
+            Builtin::Stamp => boxed(|mut transaction, _| {
+                transaction
+                    .timestamp
+                    .get_or_insert_with(|| SystemTime::now().into());
+                Decision::Continue(transaction)
+            }),
+            Builtin::RateLimit { max, per_seconds } => Box::new(RateLimit {
+                max,
+                window: Duration::seconds(per_seconds as i64),
+                seen: HashMap::new(),
+            }),
+        }
+    }
+}
+
+/// Gives a closure the signature the blanket [Middleware] impl wants
+fn boxed<F>(middleware: F) -> Box<dyn Middleware + Send>
+where
+    F: FnMut(Transaction, &mut Context) -> Decision + Send + 'static,
+{
+    Box::new(middleware)
+}
+
+struct RateLimit {
+    max: usize,
+    window: Duration,
+    /// The times of each client's transactions within the window
+    seen: HashMap<ClientId, VecDeque<DateTime<Utc>>>,
+}
+
+impl Middleware for RateLimit {
+    fn check(&mut self, transaction: Transaction, _: &mut Context) -> Decision {
+        let Some(at) = transaction.timestamp else {
+            return Decision::Continue(transaction);
+        };
+        let seen = self.seen.entry(transaction.client).or_default();
+        while seen.front().is_some_and(|&first| first <= at - self.window) {
+            seen.pop_front();
+        }
+        if seen.len() >= self.max {
+            return Decision::Reject(Reason::RateLimited);
+        }
+        seen.push_back(at);
+        Decision::Continue(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+
+    #[test]
+    fn test_middleware() -> Result<()> {
+        const CONFIG: &str = r#"
+            [[middleware]]
+            kind = "filter"
+            types = ["bonus"]
+            clients = [9]
+
+            [[middleware]]
+            kind = "rate_limit"
+            max = 2
+            per_seconds = 60
+        "#;
+        const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         1.0,     2022-03-21T10:00:00Z
+deposit,         1,     2,         1.0,     2022-03-21T10:00:30Z
+deposit,         1,     3,         1.0,     2022-03-21T10:00:59Z
+deposit,         1,     4,         1.0,     2022-03-21T10:01:01Z
+bonus,           1,     5,         1.0,     2022-03-21T10:02:00Z
+deposit,         9,     6,         1.0,     2022-03-21T10:02:00Z
+deposit,         2,     7,         2.0,
+";
+        let config: Config = toml::from_str(CONFIG)?;
+        let mut engine = Engine::with_config(config);
+        engine.add_middleware(|mut transaction: Transaction, context: &mut Context| {
+            if transaction.client == 2 {
+                assert!(context.account.is_none(), "the first of client 2");
+                transaction.amount = transaction
+                    .amount
+                    .map(|amount| amount * rust_decimal::Decimal::TWO);
+            }
+            Decision::Continue(transaction)
+        });
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts.len(), 2, "client 9 is filtered out");
+        assert_eq!(accounts[0].total, rust_decimal_macros::dec!(3.0));
+        assert_eq!(accounts[1].total, rust_decimal_macros::dec!(4.0));
+        assert_eq!(engine.rejections().len(), 1);
+        assert_eq!(engine.rejections()[0].tx, 3);
+        assert_eq!(
+            engine.rejections()[0].violation.reason(),
+            Reason::RateLimited
+        );
+        assert!(engine.unknown_types().is_empty(), "the bonus is skipped");
+
+        let bad = toml::from_str::<Config>("[[middleware]]\nkind = \"filter\"\nclient = [1]\n");
+        assert!(bad.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! OFX statement downloads, for `tte import --format ofx`
+//!
+//! Both the SGML of OFX 1.x, where values are not closed, and the XML of OFX
+//! 2.x are read. Every `STMTTRN` of a bank or credit card statement becomes a
+//! deposit when its amount is positive and a withdrawal when it is negative, on
+//! the account the statement is for.
+use crate::import::Entry;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, Utc};
+use rust_decimal::Decimal;
+use tte::TransType;
+
+pub fn read(ofx: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut account = None;
+    let mut currency = None;
+    // The transaction being read, if inside a STMTTRN
+    let mut transaction: Option<Transaction> = None;
+    for (tag, value) in tags(ofx) {
+        match (tag, &mut transaction) {
+            ("STMTTRN", None) => transaction = Some(Transaction::default()),
+            ("/STMTTRN", Some(_)) => {
+                let Transaction { amount, posted } = transaction.take().unwrap_or_default();
+                let amount = amount.context("STMTTRN without a TRNAMT")?;
+                let account = account.clone().context("STMTTRN outside a statement")?;
+                if amount.is_zero() {
+                    continue;
+                }
+                entries.push(Entry {
+                    account,
+                    trans: if amount.is_sign_positive() {
+                        TransType::Deposit
+                    } else {
+                        TransType::Withdrawal
+                    },
+                    amount: amount.abs(),
+                    currency: currency.clone(),
+                    timestamp: posted,
+                });
+            }
+            ("TRNAMT", Some(transaction)) => {
+                transaction.amount = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid TRNAMT {value}"))?,
+                )
+            }
+            ("DTPOSTED", Some(transaction)) => transaction.posted = Some(date(value)?),
+            // The account a transfer went to, not the statement's
+            ("ACCTID", Some(_)) => {}
+            ("ACCTID", None) => account = Some(value.to_string()),
+            ("CURDEF", _) => currency = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if transaction.is_some() {
+        bail!("STMTTRN not closed");
+    }
+    Ok(entries)
+}
+
+#[derive(Default)]
+struct Transaction {
+    amount: Option<Decimal>,
+    posted: Option<DateTime<Utc>>,
+}
+
+/// Every tag with the text up to the next tag, skipping the header and
+/// processing instructions. Closing tags keep their `/`.
+fn tags(ofx: &str) -> impl Iterator<Item = (&str, &str)> {
+    ofx.split('<').skip(1).filter_map(|part| {
+        let (tag, value) = part.split_once('>')?;
+        (!tag.starts_with(['?', '!'])).then(|| (tag.trim(), value.trim()))
+    })
+}
+
+/// `YYYYMMDD[HHMMSS[.XXX]][[gmt offset[:tz name]]]`, in UTC unless an offset
+/// says otherwise
+fn date(value: &str) -> Result<DateTime<Utc>> {
+    let invalid = || anyhow!("invalid date {value}");
+    let (stamp, zone) = match value.split_once('[') {
+        Some((stamp, zone)) => (stamp, Some(zone.trim_end_matches(']'))),
+        None => (value, None),
+    };
+    let date = NaiveDate::parse_from_str(stamp.get(..8).ok_or_else(invalid)?, "%Y%m%d")
+        .map_err(|_| invalid())?;
+    let time = match stamp.get(8..14) {
+        Some(time) => NaiveTime::parse_from_str(time, "%H%M%S").map_err(|_| invalid())?,
+        None => NaiveTime::MIN,
+    };
+    let hours: f64 = match zone {
+        Some(zone) => zone
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| invalid())?,
+        None => 0.0,
+    };
+    let offset = FixedOffset::east_opt((hours * 3600.0) as i32).ok_or_else(invalid)?;
+    let local = date
+        .and_time(time)
+        .and_local_timezone(offset)
+        .single()
+        .ok_or_else(invalid)?;
+    Ok(local.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let sgml = "OFXHEADER:100
+DATA:OFXSGML
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>USD
+<BANKACCTFROM><BANKID>121000248<ACCTID>4001<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20220321120000[-5:EST]<TRNAMT>100.50<FITID>1</STMTTRN>
+<STMTTRN><TRNTYPE>XFER<DTPOSTED>20220322<TRNAMT>-20.00<FITID>2
+<BANKACCTTO><BANKID>121000248<ACCTID>4002<ACCTTYPE>SAVINGS</BANKACCTTO></STMTTRN>
+</BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>";
+        let entries = read(sgml)?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].account, "4001");
+        assert_eq!(entries[0].trans, TransType::Deposit);
+        assert_eq!(entries[0].amount, dec!(100.50));
+        assert_eq!(entries[0].currency.as_deref(), Some("USD"));
+        assert_eq!(
+            entries[0].timestamp.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2022-03-21T17:00:00+00:00")
+        );
+        assert_eq!(entries[1].account, "4001", "not the transfer's account");
+        assert_eq!(entries[1].trans, TransType::Withdrawal);
+        assert_eq!(entries[1].amount, dec!(20));
+
+        let xml = r#"<?xml version="1.0"?><?OFX OFXHEADER="200" VERSION="220"?>
+<OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS><CURDEF>EUR</CURDEF>
+<CCACCTFROM><ACCTID>5555</ACCTID></CCACCTFROM>
+<BANKTRANLIST><STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20220323</DTPOSTED>
+<TRNAMT>-9.99</TRNAMT></STMTTRN></BANKTRANLIST>
+</CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>"#;
+        let entries = read(xml)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].account, "5555");
+        assert_eq!(entries[0].amount, dec!(9.99));
+
+        assert!(read("<OFX><STMTTRN><TRNAMT>1</STMTTRN></OFX>").is_err());
+        Ok(())
+    }
+}
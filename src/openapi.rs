@@ -0,0 +1,254 @@
+//! The OpenAPI document of `tte serve`, served at `/openapi.json`
+//!
+//! Client SDKs can be generated from it. The reason codes and names come from
+//! [Reason::ALL], so a new reason shows up in the document without touching
+//! it. The server's tests check its routes against the document.
+use serde_json::{json, Value};
+use tte::Reason;
+
+/// The OpenAPI 3.1 document of every route
+pub fn document() -> Value {
+    let codes: Vec<&str> = Reason::ALL.iter().map(Reason::code).collect();
+    let names: Vec<&str> = Reason::ALL.iter().map(Reason::name).collect();
+    let reasons: Vec<String> = Reason::ALL
+        .iter()
+        .map(|reason| format!("- `{reason}`"))
+        .collect();
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}
+        })
+    };
+    let busy = json!({
+        "description": "Over a rate limit (429) or the queue to the engine is full (503)",
+        "headers": {"Retry-After": {"schema": {"type": "integer"}}},
+        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}
+    });
+    let refused = json!({
+        "401": error("No valid API key"),
+        "403": error("Not allowed for the role of the API key"),
+        "429": busy,
+        "503": busy,
+    });
+    let with = |responses: Value| {
+        let mut responses = responses;
+        for (status, response) in refused.as_object().expect("an object") {
+            responses[status] = response.clone();
+        }
+        responses
+    };
+    let lines = |schema: &str| {
+        json!({
+            "description": "One JSON object a line, cut short by the connection closing if the engine cannot be asked",
+            "content": {"application/x-ndjson": {"schema": {"$ref": format!("#/components/schemas/{schema}")}}}
+        })
+    };
+    let lock = |summary: &str| {
+        json!({
+            "post": {
+                "summary": summary,
+                "description": "Only for the admin role. The operation goes by tx in the audit trail and the events.",
+                "parameters": [
+                    {"name": "client", "in": "path", "required": true, "schema": {"type": "integer"}},
+                    {"name": "tx", "in": "query", "required": true, "schema": {"$ref": "#/components/schemas/TxId"}}
+                ],
+                "responses": with(json!({
+                    "200": {
+                        "description": "The account after the operation",
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Account"}}}
+                    },
+                    "400": error("No valid tx"),
+                    "404": error("The client has no account")
+                }))
+            }
+        })
+    };
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "tte",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Applies transactions posted over HTTP and answers account queries, see `tte serve`."
+        },
+        "security": [{"apiKey": []}],
+        "paths": {
+            "/transactions": {
+                "post": {
+                    "summary": "Apply a transaction",
+                    "description": "For the submitter and admin roles.",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Transaction"}}}
+                    },
+                    "responses": with(json!({
+                        "200": {
+                            "description": "Applied, with the events it made",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Submitted"}}}
+                        },
+                        "422": {
+                            "description": "Turned down by the engine, with the reason",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Submitted"}}}
+                        },
+                        "400": error("No valid transaction, with reason R016 malformed"),
+                        "413": error("Over 4 KiB")
+                    }))
+                }
+            },
+            "/accounts": {
+                "get": {
+                    "summary": "A page of accounts, in the order the clients were first seen",
+                    "parameters": [
+                        {
+                            "name": "cursor",
+                            "in": "query",
+                            "description": "The next cursor of the page before, none for the first page",
+                            "schema": {"type": "integer"}
+                        },
+                        {
+                            "name": "limit",
+                            "in": "query",
+                            "schema": {"type": "integer", "minimum": 1, "maximum": 1000, "default": 100}
+                        }
+                    ],
+                    "responses": with(json!({
+                        "200": {
+                            "description": "The page",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Page"}}}
+                        },
+                        "400": error("An unknown cursor or a limit out of range")
+                    }))
+                }
+            },
+            "/accounts/stream": {
+                "get": {
+                    "summary": "Every account, in the order the clients were first seen",
+                    "responses": with(json!({"200": lines("Account")}))
+                }
+            },
+            "/audit": {
+                "get": {
+                    "summary": "The audit trail from an entry on",
+                    "description": "Only for the auditor role.",
+                    "parameters": [{
+                        "name": "from",
+                        "in": "query",
+                        "description": "The index of the first entry, counting from 0",
+                        "schema": {"type": "integer", "minimum": 0, "default": 0}
+                    }],
+                    "responses": with(json!({"200": lines("AuditEntry"), "400": error("No valid from")}))
+                }
+            },
+            "/accounts/{client}/lock": lock("Lock an account"),
+            "/accounts/{client}/unlock": lock("Unlock an account"),
+            "/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "security": [],
+                    "responses": {"200": {"description": "The document", "content": {"application/json": {}}}}
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "apiKey": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "An API key given to `tte serve --api-keys`, with no keys given none is needed"
+                }
+            },
+            "schemas": {
+                "TxId": {
+                    "description": "An integer, or a UUID with the uuid feature",
+                    "type": ["integer", "string"]
+                },
+                "Amount": {
+                    "description": "A decimal number, best as a string to keep its precision",
+                    "type": ["string", "number"],
+                    "examples": ["1.5"]
+                },
+                "Transaction": {
+                    "description": "The CSV columns of a transaction",
+                    "type": "object",
+                    "required": ["type", "client", "tx"],
+                    "properties": {
+                        "type": {
+                            "type": "string",
+                            "examples": ["deposit", "withdrawal", "dispute", "resolve", "chargeback", "open", "close"]
+                        },
+                        "client": {"type": ["integer", "string"]},
+                        "tx": {"$ref": "#/components/schemas/TxId"},
+                        "amount": {"$ref": "#/components/schemas/Amount"},
+                        "timestamp": {"type": "string", "format": "date-time"}
+                    },
+                    "additionalProperties": {"type": ["string", "number", "null"]}
+                },
+                "Event": {
+                    "description": "What the engine decided, see the event stream",
+                    "type": "object",
+                    "required": ["event", "client", "tx"],
+                    "properties": {
+                        "event": {"type": "string", "examples": ["DepositApplied", "TransactionRejected"]},
+                        "client": {"type": "integer"},
+                        "tx": {"$ref": "#/components/schemas/TxId"},
+                        "amount": {"type": "string"},
+                        "fee": {"type": "string"},
+                        "reason": {"type": "string", "enum": names}
+                    },
+                    "additionalProperties": true
+                },
+                "Submitted": {
+                    "type": "object",
+                    "required": ["events"],
+                    "properties": {
+                        "code": {"type": "string", "enum": codes, "description": "When turned down"},
+                        "reason": {"type": "string", "enum": names, "description": "When turned down"},
+                        "events": {"type": "array", "items": {"$ref": "#/components/schemas/Event"}}
+                    }
+                },
+                "Account": {
+                    "type": "object",
+                    "required": ["client", "available", "held", "total", "locked", "open_disputes"],
+                    "properties": {
+                        "client": {"type": "integer"},
+                        "available": {"type": "string"},
+                        "held": {"type": "string"},
+                        "total": {"type": "string"},
+                        "locked": {"type": "boolean"},
+                        "open_disputes": {"type": "array", "items": {"$ref": "#/components/schemas/TxId"}}
+                    }
+                },
+                "Page": {
+                    "type": "object",
+                    "required": ["accounts", "next"],
+                    "properties": {
+                        "accounts": {"type": "array", "items": {"$ref": "#/components/schemas/Account"}},
+                        "next": {"type": ["integer", "null"], "description": "The cursor of the next page, null on the last"}
+                    }
+                },
+                "AuditEntry": {
+                    "type": "object",
+                    "required": ["client", "tx", "event"],
+                    "properties": {
+                        "client": {"type": "integer"},
+                        "tx": {"$ref": "#/components/schemas/TxId"},
+                        "event": {"description": "What was done, e.g. \"Locked\" or {\"Frozen\": \"why\"}"}
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {
+                        "code": {
+                            "type": "string",
+                            "enum": codes,
+                            "description": format!("The code of the reason a transaction was turned down:\n{}", reasons.join("\n"))
+                        },
+                        "reason": {"type": "string", "enum": names},
+                        "error": {"type": "string"}
+                    }
+                }
+            }
+        }
+    })
+}
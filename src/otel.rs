@@ -0,0 +1,232 @@
+//! OpenTelemetry traces of a running `consume`
+//!
+//! With `--otlp-endpoint`, every batch is a trace sent to an OpenTelemetry
+//! collector over OTLP/HTTP in its JSON encoding, so Jaeger, Tempo and the
+//! like show where the time goes. The `batch` span has a child for each stage:
+//! `poll`, reading from the broker, `apply`, `save` and `ack`. A sample of the
+//! transactions get a `transaction` span under `apply`, split into `validate`,
+//! decoding and admitting the message, and `engine`, applying it.
+//!
+//! A span is recorded once its stage is over, with the times it ran, and the
+//! OpenTelemetry SDK exports them in batches from a thread of its own, so a
+//! slow or missing collector holds nothing up. Spans it cannot keep up with
+//! are dropped. The collector may be `http://` or `https://`.
+use anyhow::{anyhow, Context as _, Result};
+use log::warn;
+use opentelemetry::trace::{
+    Span as _, SpanBuilder, SpanContext, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer as _, TracerProvider as _,
+};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{IdGenerator, RandomIdGenerator, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::time::{Duration, SystemTime};
+
+pub use opentelemetry::trace::SpanId;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Tracer {
+    provider: SdkTracerProvider,
+    tracer: SdkTracer,
+    sample: f64,
+}
+
+/// The spans of one batch
+pub struct Trace {
+    id: TraceId,
+    pub root: SpanId,
+    start: SystemTime,
+    sample: f64,
+    tracer: SdkTracer,
+    ids: RandomIdGenerator,
+}
+
+impl Tracer {
+    /// Exports to the collector at `endpoint`, e.g. `http://localhost:4318`,
+    /// with `sample` of the transactions, from 0 to 1, traced one by one
+    pub fn new(endpoint: &str, sample: f64) -> Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpJson)
+            .with_endpoint(traces_url(endpoint)?)
+            .with_timeout(TIMEOUT)
+            .build()
+            .context("could not set up the trace exporter")?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder_empty().with_service_name("tte").build())
+            .build();
+        Ok(Tracer {
+            tracer: provider.tracer("tte"),
+            provider,
+            sample,
+        })
+    }
+
+    /// A trace of a batch read from `start` on
+    pub fn begin(&mut self, start: SystemTime) -> Trace {
+        let ids = RandomIdGenerator::default();
+        Trace {
+            id: ids.new_trace_id(),
+            root: ids.new_span_id(),
+            start,
+            sample: self.sample,
+            tracer: self.tracer.clone(),
+            ids,
+        }
+    }
+
+    /// Ends the `batch` span of `trace`, which sends it with the next export
+    pub fn finish(&mut self, mut trace: Trace, attributes: Vec<(&'static str, Value)>) {
+        let (root, start) = (trace.root, trace.start);
+        trace.span(root, None, "batch", (start, SystemTime::now()), attributes);
+    }
+}
+
+/// Exports what is left before the consumer stops
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("could not export the last traces: {e}");
+        }
+    }
+}
+
+impl Trace {
+    /// A new span id, for a span recorded later
+    pub fn id(&mut self) -> SpanId {
+        self.ids.new_span_id()
+    }
+
+    /// Whether the next transaction is traced
+    pub fn sampled(&mut self) -> bool {
+        rand::random::<f64>() < self.sample
+    }
+
+    /// Records span `id` of `name` under `parent`, over `start` to `end`
+    pub fn span(
+        &mut self,
+        id: SpanId,
+        parent: Option<SpanId>,
+        name: &str,
+        (start, end): (SystemTime, SystemTime),
+        attributes: Vec<(&'static str, Value)>,
+    ) {
+        let parent = match parent {
+            Some(parent) => Context::new().with_remote_span_context(SpanContext::new(
+                self.id,
+                parent,
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            )),
+            None => Context::new(),
+        };
+        let span = SpanBuilder::from_name(name.to_string())
+            .with_trace_id(self.id)
+            .with_span_id(id)
+            .with_start_time(start)
+            .with_attributes(
+                attributes
+                    .into_iter()
+                    .map(|(key, value)| KeyValue::new(key, value)),
+            );
+        self.tracer
+            .build_with_context(span, &parent)
+            .end_with_timestamp(end);
+    }
+}
+
+pub fn int(value: impl Into<u64>) -> Value {
+    Value::I64(i64::try_from(value.into()).unwrap_or(i64::MAX))
+}
+
+pub fn string(value: impl ToString) -> Value {
+    Value::from(value.to_string())
+}
+
+/// The URL traces are posted to under `endpoint`
+fn traces_url(endpoint: &str) -> Result<String> {
+    if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+        return Err(anyhow!("the OTLP endpoint {endpoint} is not an HTTP URL"));
+    }
+    let base = endpoint.trim_end_matches('/');
+    Ok(if base.ends_with("/v1/traces") {
+        base.to_string()
+    } else {
+        format!("{base}/v1/traces")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_export() -> Result<()> {
+        assert_eq!(
+            traces_url("https://collector:4318/otlp/")?,
+            "https://collector:4318/otlp/v1/traces"
+        );
+        assert!(traces_url("collector:4318").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let collector = thread::spawn(move || -> Result<String> {
+            let (mut stream, _) = listener.accept()?;
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let whole = |request: &str| {
+                let (head, body) = request.split_once("\r\n\r\n")?;
+                let length = head.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })?;
+                (body.len() >= length).then_some(())
+            };
+            while whole(&String::from_utf8_lossy(&request)).is_none() {
+                let read = stream.read(&mut buf)?;
+                assert!(read > 0, "the whole trace is sent");
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+            Ok(String::from_utf8(request)?)
+        });
+        let mut tracer = Tracer::new(&endpoint, 1.0)?;
+        let start = SystemTime::now();
+        let mut trace = tracer.begin(start);
+        assert!(trace.sampled());
+        let (poll, root) = (trace.id(), trace.root);
+        trace.span(
+            poll,
+            Some(root),
+            "poll",
+            (start, start),
+            vec![("messages", int(3u64))],
+        );
+        tracer.finish(trace, Vec::new());
+        drop(tracer);
+
+        let request = collector.join().expect("the collector")?;
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1"));
+        let (_, body) = request.split_once("\r\n\r\n").expect("a body");
+        let body: serde_json::Value = serde_json::from_str(body)?;
+        let spans = &body["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        let span = |name: &str| {
+            let spans = spans.as_array().expect("spans");
+            spans.iter().find(|span| span["name"] == name).cloned()
+        };
+        let (poll, batch) = (span("poll").expect("poll"), span("batch").expect("batch"));
+        assert_eq!(poll["parentSpanId"], root.to_string());
+        assert_eq!(batch["spanId"], root.to_string());
+        assert_eq!(poll["traceId"], batch["traceId"]);
+        assert_eq!(poll["attributes"][0]["key"], "messages");
+        Ok(())
+    }
+}
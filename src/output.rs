@@ -0,0 +1,99 @@
+//! Where the results of a run go
+//!
+//! Once the transactions are applied, the report goes to every [ReportSink]
+//! and the recorded events to every [EventSink] of the run, so a run can write
+//! its CSV, upsert a database and print a summary all at once, and a new kind
+//! of output only needs an implementation. The sinks here write to any
+//! [Write]; the `tte` tool adds files, Arrow, Parquet, Postgres, webhooks
+//! and alerts.
+use crate::event::Event;
+use crate::Engine;
+use anyhow::Result;
+use std::io::Write;
+
+/// Takes the final state of a run
+pub trait ReportSink {
+    fn write_report(&mut self, engine: &Engine) -> Result<()>;
+
+    /// Whether the sink hands the results on beyond the run, e.g. to a
+    /// database, which a dry run or an interrupted one must not do
+    fn persists(&self) -> bool {
+        false
+    }
+}
+
+/// Takes the events recorded during a run, see [crate::event]
+pub trait EventSink {
+    fn write_events(&mut self, events: &[Event]) -> Result<()>;
+
+    /// Same as [ReportSink::persists]
+    fn persists(&self) -> bool {
+        false
+    }
+}
+
+/// The accounts CSV of [Engine::write_report]
+pub struct Csv<W>(pub W);
+
+impl<W: Write> ReportSink for Csv<W> {
+    fn write_report(&mut self, engine: &Engine) -> Result<()> {
+        engine.write_report(&mut self.0)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// The fees collected and the transactions rejected and of unknown types, as
+/// [Engine::write_summary] writes them
+pub struct Summary<W>(pub W);
+
+impl<W: Write> ReportSink for Summary<W> {
+    fn write_report(&mut self, engine: &Engine) -> Result<()> {
+        engine.write_summary(&mut self.0)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// The events as JSON lines, as [Engine::write_events] writes them
+pub struct JsonLines<W>(pub W);
+
+impl<W: Write> EventSink for JsonLines<W> {
+    fn write_events(&mut self, events: &[Event]) -> Result<()> {
+        for event in events {
+            serde_json::to_writer(&mut self.0, event)?;
+            writeln!(self.0)?;
+        }
+        Ok(self.0.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+
+    #[test]
+    fn test_sinks() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.record_events();
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+
+        let (mut report, mut summary) = (Csv(Vec::new()), Summary(Vec::new()));
+        let sinks: [&mut dyn ReportSink; 2] = [&mut report, &mut summary];
+        for sink in sinks {
+            assert!(!sink.persists());
+            sink.write_report(&engine)?;
+        }
+        let mut expected = Vec::new();
+        engine.write_report(&mut expected)?;
+        assert_eq!(report.0, expected);
+        assert!(String::from_utf8(summary.0)?.contains("transactions rejected: 0"));
+
+        let mut events = JsonLines(Vec::new());
+        events.write_events(engine.events())?;
+        let mut expected = Vec::new();
+        engine.write_events(&mut expected)?;
+        assert_eq!(events.0, expected);
+        Ok(())
+    }
+}
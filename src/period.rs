@@ -0,0 +1,111 @@
+//! Closing a period, such as a business day
+//!
+//! [crate::Engine::close_period] freezes the accounts as they stand into a
+//! [Period], known by an id such as `2026-10-16` that can be closed only once,
+//! and starts the next period. What is counted per period starts over: the
+//! daily withdrawal totals the risk limits go by, the activity columns of the
+//! extended report and the counts of transactions of unknown types. Balances,
+//! records, disputes, rejections and the audit trail carry on.
+use crate::snapshot::Snapshot;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io;
+
+/// The report of a closed period
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Period {
+    pub id: String,
+    pub closed_at: DateTime<Utc>,
+    /// Transactions offered to the engine within the period
+    pub transactions: u64,
+    /// How many of those were rejected
+    pub rejections: usize,
+    /// The accounts as they stood when the period closed
+    pub accounts: Snapshot,
+}
+
+impl Period {
+    pub fn write(&self, w: impl io::Write) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(w, self)?)
+    }
+}
+
+pub fn read_period(r: impl io::Read) -> Result<Period> {
+    Ok(serde_json::from_reader(io::BufReader::new(r))?)
+}
+
+/// Period ids name files, so they are kept to letters, digits, `-`, `_` and
+/// `.`, not leading
+pub fn check_id(id: &str) -> Result<()> {
+    let valid = id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    match valid && !id.is_empty() && !id.starts_with('.') {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "'{id}' is no period id, use letters, digits, '-', '_' and '.'"
+        )),
+    }
+}
+
+/// Where the engine is within its periods
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Periods {
+    /// The ids of the periods closed so far
+    pub closed: BTreeSet<String>,
+    /// When the last of them closed
+    pub last_closed_at: Option<DateTime<Utc>>,
+    /// The transactions offered and rejected when the current one started
+    pub offered: u64,
+    pub rejections: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_close_period() -> Result<()> {
+        const MONDAY: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,       100.0,     2022-03-21T09:00:00Z
+withdrawal,      1,     2,        80.0,     2022-03-21T10:00:00Z
+withdrawal,      1,     3,        10.0,     2022-03-21T11:00:00Z
+";
+        let config: Config = toml::from_str("risk.max_daily_withdrawal = \"85\"")?;
+        let mut engine = Engine::with_config(config);
+        engine.extend_report();
+        engine.replay(read_csv(MONDAY.as_bytes()), None)?;
+        let closed_at = "2022-03-21T23:59:59Z".parse()?;
+        let monday = engine.close_period("2022-03-21", closed_at)?;
+        assert_eq!(monday.transactions, 3);
+        assert_eq!(monday.rejections, 1);
+        assert_eq!(monday.accounts[&1].total, dec!(20.0));
+        assert!(engine.close_period("2022-03-21", closed_at).is_err());
+        assert_eq!(engine.last_period_closed_at(), Some(closed_at));
+
+        // Later the same day by the clock, but the limit starts over
+        let tuesday = "type,client,tx,amount,timestamp\nwithdrawal,1,4,10.0,2022-03-21T12:00:00Z\n";
+        engine.replay(read_csv(tuesday.as_bytes()), None)?;
+        assert_eq!(engine.rejections().len(), 1);
+        let mut report = Vec::new();
+        engine.write_report(&mut report)?;
+        assert!(
+            String::from_utf8(report)?.contains("1, 10.0, 0, 10.0, false, 1, 0, 10.0"),
+            "the activity counts start over"
+        );
+
+        let mut written = Vec::new();
+        monday.write(&mut written)?;
+        assert_eq!(read_period(written.as_slice())?, monday);
+        assert!(check_id("../etc").is_err());
+        assert!(check_id("").is_err());
+        Ok(())
+    }
+}
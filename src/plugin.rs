@@ -0,0 +1,216 @@
+//! Policies as WebAssembly modules
+//!
+//! A [Plugin] is a [Middleware] whose decisions are made by a WebAssembly
+//! module, so business rules can be written in any language that compiles to
+//! it and deployed without rebuilding `tte`. The module imports nothing and
+//! exports one function, given the transaction and the client's account:
+//! ```text
+//! check(type: i32, client: i64, tx: i64, amount: i64, timestamp: i64,
+//!       available: i64, held: i64, flags: i32) -> i32
+//! ```
+//! * `type` -- 0 deposit, 1 withdrawal, 2 dispute, 3 resolve, 4 chargeback,
+//!   5 open_account, 6 close_account, -1 any custom type
+//! * `tx` -- the id, or its low 64 bits if it is a UUID
+//! * `amount`, `available`, `held` -- in ten-thousandths, with `amount`
+//!   `i64::MIN` when there is none
+//! * `timestamp` -- in seconds since the Unix epoch, `i64::MIN` when there is
+//!   none
+//! * `flags` -- bit 0 set if the account exists, bit 1 if it is locked and
+//!   bit 2 if it is closed
+//!
+//! It returns 0 to pass the transaction on, -1 to skip it, or the number of a
+//! [Reason] code to reject it, e.g. 18 for `R018 refused`. A call gets a fixed
+//! amount of fuel, and one that traps or runs out is rejected as refused, so a
+//! broken plugin fails closed.
+use crate::middleware::{Context, Decision, Middleware};
+use crate::reason::Reason;
+use crate::transaction::{TransType, Transaction};
+use anyhow::{anyhow, Context as _, Result};
+use log::{error, warn};
+use rust_decimal::prelude::*;
+use std::path::Path;
+use wasmtime::{Config, Engine, Instance, Module, Store, TypedFunc};
+
+/// The instructions, roughly, a call may run
+const FUEL: u64 = 1_000_000;
+
+/// What the guest gets for a missing amount or timestamp
+const NONE: i64 = i64::MIN;
+
+type Check = TypedFunc<(i32, i64, i64, i64, i64, i64, i64, i32), i32>;
+
+/// A loaded module, see the [module docs](self) for what it exports
+pub struct Plugin {
+    name: String,
+    store: Store<()>,
+    check: Check,
+}
+
+impl Plugin {
+    /// Loads a module from a `.wasm` file, or a `.wat` one in the text format
+    pub fn load(path: &Path) -> Result<Plugin> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+        Plugin::new(path.display().to_string(), bytes)
+            .with_context(|| format!("invalid plugin {}", path.display()))
+    }
+
+    /// Loads a module from its bytes, or its text, naming it in logs by `name`
+    pub fn new(name: impl Into<String>, module: impl AsRef<[u8]>) -> Result<Plugin> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, module)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])
+            .map_err(|e| anyhow!("{e}, plugins may not import anything"))?;
+        let check = instance.get_typed_func(&mut store, "check")?;
+        Ok(Plugin {
+            name: name.into(),
+            store,
+            check,
+        })
+    }
+
+    fn call(&mut self, transaction: &Transaction, context: &Context) -> Result<i32> {
+        let trans = match &transaction.trans {
+            TransType::Deposit => 0,
+            TransType::Withdrawal => 1,
+            TransType::Dispute => 2,
+            TransType::Resolve => 3,
+            TransType::Chargeback => 4,
+            TransType::OpenAccount => 5,
+            TransType::CloseAccount => 6,
+            TransType::Other(_) => -1,
+        };
+        let (available, held, flags) = match context.account {
+            Some(account) => (
+                units(account.available()),
+                units(account.held()),
+                1 | (account.is_locked() as i32) << 1 | (account.is_closed() as i32) << 2,
+            ),
+            None => (0, 0, 0),
+        };
+        let params = (
+            trans,
+            transaction.client as i64,
+            transaction.tx as i64,
+            transaction.amount.map_or(NONE, units),
+            transaction.timestamp.map_or(NONE, |at| at.timestamp()),
+            available,
+            held,
+            flags,
+        );
+        self.store.set_fuel(FUEL)?;
+        self.check.call(&mut self.store, params)
+    }
+}
+
+/// Ten-thousandths, kept clear of [NONE]
+fn units(amount: Decimal) -> i64 {
+    (amount * Decimal::from(10_000))
+        .trunc()
+        .to_i64()
+        .unwrap_or(i64::MAX)
+        .max(NONE + 1)
+}
+
+impl Middleware for Plugin {
+    fn check(&mut self, transaction: Transaction, context: &mut Context) -> Decision {
+        match self.call(&transaction, context) {
+            Ok(0) => Decision::Continue(transaction),
+            Ok(-1) => Decision::Skip,
+            Ok(code) => Decision::Reject(format!("R{code:03}").parse().unwrap_or_else(|_| {
+                warn!(
+                    "Plugin {} returned {code}, which is no reason code",
+                    self.name
+                );
+                Reason::Refused
+            })),
+            Err(e) => {
+                error!(
+                    "Plugin {} failed on tx:{} of client:{}: {e:#}",
+                    self.name, transaction.tx, transaction.client
+                );
+                Decision::Reject(Reason::Refused)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use rust_decimal_macros::dec;
+
+    /// Skips client 9 and rejects withdrawals of more than 100, or any from a
+    /// client without an account
+    const POLICY: &str = r#"
+        (module
+          (func (export "check")
+            (param $type i32) (param $client i64) (param $tx i64) (param $amount i64)
+            (param $timestamp i64) (param $available i64) (param $held i64) (param $flags i32)
+            (result i32)
+            (if (i64.eq (local.get $client) (i64.const 9)) (then (return (i32.const -1))))
+            (if (i32.ne (local.get $type) (i32.const 1)) (then (return (i32.const 0))))
+            (if (i32.eqz (local.get $flags)) (then (return (i32.const 15))))
+            (if (i64.gt_s (local.get $amount) (i64.const 1000000)) (then (return (i32.const 18))))
+            (i32.const 0)))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+          (func (export "check")
+            (param i32 i64 i64 i64 i64 i64 i64 i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn test_plugin() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.add_middleware(Plugin::new("policy", POLICY)?);
+        let csv = "\
+type,client,tx,amount
+withdrawal,1,1,1.0
+deposit,1,2,500.0
+withdrawal,1,3,200.0
+withdrawal,1,4,50.0
+deposit,9,5,1.0
+";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts.len(), 1, "client 9 is skipped");
+        assert_eq!(accounts[0].total, dec!(450.0));
+        let rejected: Vec<_> = engine
+            .rejections()
+            .iter()
+            .map(|rejection| (rejection.tx, rejection.violation.reason()))
+            .collect();
+        assert_eq!(
+            rejected,
+            [(1, Reason::NotOpened), (3, Reason::Refused)],
+            "{rejected:?}"
+        );
+
+        let mut engine = Engine::new();
+        engine.add_middleware(Plugin::new("spin", SPIN)?);
+        engine.replay(
+            read_csv("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes()),
+            None,
+        )?;
+        assert_eq!(
+            engine.iter_accounts().count(),
+            0,
+            "out of fuel fails closed"
+        );
+        assert_eq!(engine.rejections()[0].violation.reason(), Reason::Refused);
+
+        let imports = r#"(module (import "env" "f" (func)) (func (export "check") (param i32 i64 i64 i64 i64 i64 i64 i32) (result i32) (i32.const 0)))"#;
+        assert!(Plugin::new("imports", imports).is_err());
+        assert!(Plugin::new("empty", "(module)").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! QIF exports of personal finance software, for `tte import --format qif`
+//!
+//! Records of the bank, cash, credit card and asset or liability account
+//! types become deposits for positive amounts and withdrawals for negative
+//! ones. Investment accounts and the category, class and memorized lists are
+//! skipped. The account is the name of the `!Account` block the records are
+//! in, or the one given for files that export a single account without it.
+use crate::import::Entry;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use tte::TransType;
+
+pub fn read(qif: &str, account: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut account = account.to_string();
+    // Reading an !Account block rather than transactions
+    let mut naming = false;
+    let mut cash = true;
+    let mut amount: Option<Decimal> = None;
+    let mut date = None;
+    for (number, line) in qif.lines().enumerate() {
+        let line = line.trim_end();
+        let context = || format!("line {}", number + 1);
+        if let Some(header) = line.strip_prefix('!') {
+            naming = header == "Account";
+            if let Some(kind) = header.strip_prefix("Type:") {
+                cash = matches!(kind.trim(), "Bank" | "Cash" | "CCard" | "Oth A" | "Oth L");
+            }
+            continue;
+        }
+        let Some(code) = line.chars().next() else {
+            continue;
+        };
+        let value = line[code.len_utf8()..].trim();
+        match code {
+            'N' if naming => account = value.to_string(),
+            'T' | 'U' if !naming && cash => {
+                amount = Some(parse_amount(value).with_context(context)?);
+            }
+            'D' if !naming && cash => date = Some(parse_date(value).with_context(context)?),
+            '^' if naming => naming = false,
+            '^' => {
+                if let Some(amount) = amount.take().filter(|amount| !amount.is_zero()) {
+                    entries.push(Entry {
+                        account: account.clone(),
+                        trans: if amount.is_sign_positive() {
+                            TransType::Deposit
+                        } else {
+                            TransType::Withdrawal
+                        },
+                        amount: amount.abs(),
+                        currency: None,
+                        timestamp: date,
+                    });
+                }
+                date = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Amounts may group thousands with commas, e.g. `-1,234.56`
+fn parse_amount(value: &str) -> Result<Decimal> {
+    value
+        .replace(',', "")
+        .parse()
+        .map_err(|_| anyhow!("invalid amount {value}"))
+}
+
+/// `M/D/YYYY`, or `M/D'YY` for years from 2000 on as Quicken writes them,
+/// taken as midnight UTC
+fn parse_date(value: &str) -> Result<DateTime<Utc>> {
+    let invalid = || anyhow!("invalid date {value}");
+    let (month_day, year) = value.rsplit_once(['/', '\'']).ok_or_else(invalid)?;
+    let (month, day) = month_day.split_once('/').ok_or_else(invalid)?;
+    let number = |s: &str| s.trim().parse::<u32>().map_err(|_| invalid());
+    let mut year = number(year)? as i32;
+    if year < 100 {
+        year += if value.contains('\'') || year < 70 {
+            2000
+        } else {
+            1900
+        };
+    }
+    let date = NaiveDate::from_ymd_opt(year, number(month)?, number(day)?).ok_or_else(invalid)?;
+    Ok(date.and_time(Default::default()).and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let qif = "!Type:Bank
+D03/21/2022
+T1,000.50
+PEmployer
+^
+D3/22'22
+T-20.00
+PGrocer
+^
+!Account
+NSavings
+TBank
+^
+!Type:Bank
+D3/23/22
+U5
+^
+!Type:Invst
+D3/24/22
+T-99
+^
+";
+        let entries = read(qif, "checking")?;
+        assert_eq!(entries.len(), 3, "investments are skipped");
+        assert_eq!(entries[0].account, "checking");
+        assert_eq!(entries[0].trans, TransType::Deposit);
+        assert_eq!(entries[0].amount, dec!(1000.50));
+        assert_eq!(
+            entries[0].timestamp.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2022-03-21T00:00:00+00:00")
+        );
+        assert_eq!(entries[1].trans, TransType::Withdrawal);
+        assert_eq!(entries[1].amount, dec!(20));
+        assert_eq!(
+            entries[1]
+                .timestamp
+                .map(|t| t.date_naive().to_string())
+                .as_deref(),
+            Some("2022-03-22")
+        );
+        assert_eq!(entries[2].account, "Savings");
+        assert_eq!(entries[2].amount, dec!(5));
+
+        assert!(read("!Type:Bank\nTabc\n^\n", "checking").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Simple queries over the accounts after a run, as asked at the
+//! `report --repl` prompt
+//! ```text
+//! account 42
+//! locked
+//! top 10 by total
+//! disputes open
+//! ```
+use crate::client::AccountView;
+use crate::engine::Engine;
+use crate::transaction::ClientId;
+use anyhow::{anyhow, Result};
+use std::cmp::Reverse;
+use std::io;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Query {
+    /// One client's account
+    Account(ClientId),
+    /// Every locked account
+    Locked,
+    /// The `n` accounts with the largest balance
+    Top { n: usize, by: Balance },
+    /// Every transaction under dispute, by client
+    OpenDisputes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    Available,
+    Held,
+    Total,
+}
+
+impl FromStr for Balance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "available" => Ok(Balance::Available),
+            "held" => Ok(Balance::Held),
+            "total" => Ok(Balance::Total),
+            _ => Err(anyhow!("'{s}' is not one of available, held or total")),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["account", id] => Ok(Query::Account(
+                id.parse()
+                    .map_err(|_| anyhow!("'{id}' is not a client id"))?,
+            )),
+            ["locked"] => Ok(Query::Locked),
+            ["top", n, rest @ ..] => {
+                let n = n.parse().map_err(|_| anyhow!("'{n}' is not a count"))?;
+                let by = match rest {
+                    [] => Balance::Total,
+                    ["by", balance] => balance.parse()?,
+                    _ => return Err(anyhow!("expected top N by available|held|total")),
+                };
+                Ok(Query::Top { n, by })
+            }
+            ["disputes", "open"] => Ok(Query::OpenDisputes),
+            _ => Err(anyhow!("unknown query '{s}'")),
+        }
+    }
+}
+
+impl Query {
+    /// Writes the answer as CSV, accounts in the same columns as the report
+    pub fn answer(&self, engine: &Engine, mut w: impl io::Write) -> io::Result<()> {
+        if *self == Query::OpenDisputes {
+            let mut disputes: Vec<_> = engine
+                .iter_accounts()
+                .flat_map(|view| {
+                    view.open_disputes
+                        .into_iter()
+                        .map(move |tx| (view.client, tx))
+                })
+                .collect();
+            disputes.sort();
+            writeln!(w, "client, tx")?;
+            for (client, tx) in disputes {
+                writeln!(w, "{client}, {tx}")?;
+            }
+            return Ok(());
+        }
+        let mut accounts: Vec<AccountView> = match *self {
+            Query::Account(client) => engine.account(client).into_iter().collect(),
+            Query::Locked => engine.iter_accounts().filter(|view| view.locked).collect(),
+            _ => engine.iter_accounts().collect(),
+        };
+        accounts.sort_by_key(|view| view.client);
+        if let Query::Top { n, by } = *self {
+            let balance = |view: &AccountView| match by {
+                Balance::Available => view.available,
+                Balance::Held => view.held,
+                Balance::Total => view.total,
+            };
+            // Stable, so ties stay in client order
+            accounts.sort_by_key(|view| Reverse(balance(view)));
+            accounts.truncate(n);
+        }
+        writeln!(w, "client, available, held, total, locked")?;
+        for view in accounts {
+            writeln!(
+                w,
+                "{}, {}, {}, {}, {}",
+                view.client, view.available, view.held, view.total, view.locked
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+
+    #[test]
+    fn test_queries() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+deposit,         2,     2,         7.0
+deposit,         3,     3,         1.0
+dispute,         2,     2,
+chargeback,      2,     2,
+dispute,         1,     1,
+";
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let answer = |query: &str| -> Result<String> {
+            let mut out = Vec::new();
+            query.parse::<Query>()?.answer(&engine, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        const HEADER: &str = "client, available, held, total, locked\n";
+
+        assert_eq!(
+            answer("account 3")?,
+            format!("{HEADER}3, 1.0, 0, 1.0, false\n")
+        );
+        assert_eq!(answer("account 9")?, HEADER);
+        assert_eq!(
+            answer("locked")?,
+            format!("{HEADER}2, 0.0, 0.0, 0.0, true\n")
+        );
+        assert_eq!(
+            answer("top 2 by  total")?,
+            format!("{HEADER}1, 0.0, 5.0, 5.0, false\n3, 1.0, 0, 1.0, false\n")
+        );
+        assert_eq!(answer("top 1 by held")?, answer("top 1")?);
+        assert_eq!(answer("disputes open")?, "client, tx\n1, 1\n");
+        assert!(answer("top ten").is_err());
+        assert!(answer("balance 1").is_err());
+        Ok(())
+    }
+}
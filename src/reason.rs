@@ -0,0 +1,186 @@
+//! Why a transaction changed nothing, as stable codes
+//!
+//! Every transaction the engine turns down, be it refused by a risk limit or
+//! ignored by the account, has a [Reason]. It goes by a code such as `R001`,
+//! which never changes meaning and is never reused, and by a name such as
+//! `insufficient_funds`. Logs and the rejects file show both, events and the
+//! reasons given to brokers the name or both, so automation downstream can key
+//! off either.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// A withdrawal of more than is available
+    InsufficientFunds,
+    /// A deposit or withdrawal on a locked account
+    Locked,
+    /// Anything on a closed account
+    Closed,
+    /// A withdrawal that would take the balance under the reserve
+    BelowReserve,
+    /// A deposit or withdrawal without an amount
+    MissingAmount,
+    /// A dispute, resolve or chargeback of a tx the account does not have
+    UnknownTx,
+    /// A resolve or chargeback of a tx not under dispute
+    NotDisputed,
+    /// A dispute of a tx that cannot be disputed, or not by that much
+    NotDisputable,
+    /// A dispute of a tx disputed as many times as the dispute policy allows
+    Redisputed,
+    /// A dispute that would take the available funds negative, against the
+    /// dispute policy
+    NegativeAvailable,
+    /// A close of an account with disputes open
+    OpenDisputes,
+    /// A custom type without a handler
+    UnknownType,
+    /// Over the limit on a single amount
+    MaxAmount,
+    /// Over the limit on withdrawals a day
+    MaxDailyWithdrawal,
+    /// For an account never opened, under strict onboarding
+    NotOpened,
+    /// A message that is no valid transaction
+    Malformed,
+    /// Past a client's rate limit
+    RateLimited,
+    /// Turned down by a middleware for a reason of its own
+    Refused,
+    /// An amount, or a balance it would make, beyond what the engine holds
+    Overflow,
+}
+
+impl Reason {
+    pub const ALL: [Reason; 19] = [
+        Reason::InsufficientFunds,
+        Reason::Locked,
+        Reason::Closed,
+        Reason::BelowReserve,
+        Reason::MissingAmount,
+        Reason::UnknownTx,
+        Reason::NotDisputed,
+        Reason::NotDisputable,
+        Reason::Redisputed,
+        Reason::NegativeAvailable,
+        Reason::OpenDisputes,
+        Reason::UnknownType,
+        Reason::MaxAmount,
+        Reason::MaxDailyWithdrawal,
+        Reason::NotOpened,
+        Reason::Malformed,
+        Reason::RateLimited,
+        Reason::Refused,
+        Reason::Overflow,
+    ];
+
+    /// The stable code, e.g. `R001`. New reasons get new codes at the end.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Reason::InsufficientFunds => "R001",
+            Reason::Locked => "R002",
+            Reason::Closed => "R003",
+            Reason::BelowReserve => "R004",
+            Reason::MissingAmount => "R005",
+            Reason::UnknownTx => "R006",
+            Reason::NotDisputed => "R007",
+            Reason::NotDisputable => "R008",
+            Reason::Redisputed => "R009",
+            Reason::NegativeAvailable => "R010",
+            Reason::OpenDisputes => "R011",
+            Reason::UnknownType => "R012",
+            Reason::MaxAmount => "R013",
+            Reason::MaxDailyWithdrawal => "R014",
+            Reason::NotOpened => "R015",
+            Reason::Malformed => "R016",
+            Reason::RateLimited => "R017",
+            Reason::Refused => "R018",
+            Reason::Overflow => "R019",
+        }
+    }
+
+    /// The name, e.g. `insufficient_funds`, as in the events
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reason::InsufficientFunds => "insufficient_funds",
+            Reason::Locked => "locked",
+            Reason::Closed => "closed",
+            Reason::BelowReserve => "below_reserve",
+            Reason::MissingAmount => "missing_amount",
+            Reason::UnknownTx => "unknown_tx",
+            Reason::NotDisputed => "not_disputed",
+            Reason::NotDisputable => "not_disputable",
+            Reason::Redisputed => "redisputed",
+            Reason::NegativeAvailable => "negative_available",
+            Reason::OpenDisputes => "open_disputes",
+            Reason::UnknownType => "unknown_type",
+            Reason::MaxAmount => "max_amount",
+            Reason::MaxDailyWithdrawal => "max_daily_withdrawal",
+            Reason::NotOpened => "not_opened",
+            Reason::Malformed => "malformed",
+            Reason::RateLimited => "rate_limited",
+            Reason::Refused => "refused",
+            Reason::Overflow => "overflow",
+        }
+    }
+
+    /// Whether the transaction is turned down before it reaches the account,
+    /// so that it does not show the account exists
+    pub fn before_account(&self) -> bool {
+        matches!(
+            self,
+            Reason::UnknownType
+                | Reason::MaxAmount
+                | Reason::MaxDailyWithdrawal
+                | Reason::NotOpened
+                | Reason::Malformed
+                | Reason::RateLimited
+                | Reason::Refused
+        )
+    }
+}
+
+/// The code and the name, e.g. `R001 insufficient_funds`
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.name())
+    }
+}
+
+/// Either the code or the name
+impl FromStr for Reason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Reason::ALL
+            .into_iter()
+            .find(|reason| reason.code() == s || reason.name() == s)
+            .ok_or_else(|| format!("unknown reason {s}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_reason_codes() {
+        let codes: HashSet<&str> = Reason::ALL.iter().map(Reason::code).collect();
+        assert_eq!(codes.len(), Reason::ALL.len(), "every code is its own");
+        for reason in Reason::ALL {
+            assert_eq!(reason.code().parse(), Ok(reason));
+            assert_eq!(reason.name().parse(), Ok(reason));
+            let json = serde_json::to_string(&reason).expect("a name");
+            assert_eq!(json, format!("\"{}\"", reason.name()));
+        }
+        assert_eq!(
+            Reason::InsufficientFunds.to_string(),
+            "R001 insufficient_funds"
+        );
+        assert_eq!(Reason::Locked.code(), "R002");
+    }
+}
@@ -0,0 +1,127 @@
+//! `tte consume --redis`, reading transactions from a Redis Stream
+//!
+//! Entries carry the transaction as fields named like the CSV columns:
+//! ```text
+//! XADD transactions * type deposit client 1 tx 1 amount 1.5
+//! ```
+//! They are read through a consumer group, created from the start of the
+//! stream if it does not exist yet. Entries delivered to this consumer but
+//! never acknowledged, e.g. because it crashed, are read again first.
+use crate::consume::{Message, Source};
+use anyhow::{Context, Result};
+use redis::streams::{StreamReadOptions, StreamReadReply};
+use redis::{Commands, Connection, RedisResult, Value};
+use std::collections::HashMap;
+
+/// How long a read waits for new entries before handing back control
+const BLOCK_MS: usize = 1000;
+
+pub struct RedisStream {
+    connection: Connection,
+    stream: String,
+    group: String,
+    consumer: String,
+    /// Still reading the entries left pending by an earlier run
+    pending: bool,
+}
+
+impl RedisStream {
+    pub fn open(url: &str, stream: &str, group: &str, consumer: &str) -> Result<Self> {
+        let mut connection = redis::Client::open(url)?
+            .get_connection()
+            .with_context(|| format!("could not connect to {url}"))?;
+        let created: RedisResult<()> = connection.xgroup_create_mkstream(stream, group, "0");
+        match created {
+            Err(e) if e.code() == Some("BUSYGROUP") => {}
+            result => result.with_context(|| format!("could not create group {group}"))?,
+        }
+        Ok(RedisStream {
+            connection,
+            stream: stream.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            pending: true,
+        })
+    }
+}
+
+impl Source for RedisStream {
+    fn poll(&mut self, max: usize) -> Result<Vec<Message>> {
+        let mut options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(max);
+        if !self.pending {
+            options = options.block(BLOCK_MS);
+        }
+        let from = if self.pending { "0" } else { ">" };
+        let reply: Option<StreamReadReply> =
+            self.connection
+                .xread_options(&[&self.stream], &[from], &options)?;
+        let entries: Vec<_> = reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|key| key.ids)
+            .collect();
+        if entries.is_empty() {
+            self.pending = false;
+        }
+        entries
+            .into_iter()
+            .map(|entry| {
+                let fields = entry
+                    .map
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), redis::from_redis_value(value)?)))
+                    .collect::<RedisResult<_>>()?;
+                Ok(Message {
+                    seq: seq(&entry.id),
+                    id: entry.id,
+                    fields: Ok(fields),
+                })
+            })
+            .collect()
+    }
+
+    fn ack(&mut self, messages: &[Message]) -> Result<()> {
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        let _: u64 = self.connection.xack(&self.stream, &self.group, &ids)?;
+        Ok(())
+    }
+
+    /// The lag of the group, which Redis 7 and later keep
+    fn lag(&mut self) -> Result<Option<u64>> {
+        let groups: Vec<HashMap<String, Value>> = redis::cmd("XINFO")
+            .arg("GROUPS")
+            .arg(&self.stream)
+            .query(&mut self.connection)?;
+        let field = |group: &HashMap<String, Value>, name: &str| group.get(name).cloned();
+        let lag = groups
+            .iter()
+            .find(|group| {
+                field(group, "name").and_then(|name| redis::from_redis_value(&name).ok())
+                    == Some(self.group.clone())
+            })
+            .and_then(|group| field(group, "lag"))
+            .and_then(|lag| redis::from_redis_value::<Option<u64>>(&lag).ok())
+            .flatten();
+        Ok(lag)
+    }
+}
+
+/// Entry ids are `MILLISECONDS-SEQUENCE` and increase along the stream
+fn seq(id: &str) -> Option<(u64, u64)> {
+    let (ms, n) = id.split_once('-')?;
+    Some((ms.parse().ok()?, n.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq() {
+        assert_eq!(seq("1526919030474-55"), Some((1526919030474, 55)));
+        assert!(seq("1526919030474-9") < seq("1526919030474-10"));
+        assert_eq!(seq("nonsense"), None);
+    }
+}
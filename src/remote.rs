@@ -0,0 +1,167 @@
+//! Objects in S3, Google Cloud Storage or Azure as input files and outputs
+//!
+//! `s3://bucket/key`, `gs://bucket/key` and `az://container/key` are streamed
+//! in and out without staging them on local disk. Credentials and the region
+//! come from the environment as for each cloud's own tools, e.g.
+//! `AWS_ACCESS_KEY_ID` and `AWS_REGION`.
+use crate::runtime;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::buffered::BufWriter;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path;
+use object_store::{GetOptions, GetRange, ObjectStore};
+use std::io;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+/// The schemes handled here rather than as local paths
+const SCHEMES: [&str; 3] = ["s3://", "gs://", "az://"];
+
+pub fn is_remote(path: &std::path::Path) -> bool {
+    let path = path.to_string_lossy();
+    SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+fn store(url: &str) -> Result<(Arc<dyn ObjectStore>, Path)> {
+    let (scheme, rest) = url.split_once("://").context("not a URL")?;
+    let (bucket, key) = rest
+        .split_once('/')
+        .with_context(|| format!("no object key in {url}"))?;
+    let bucket_url = format!("{scheme}://{bucket}");
+    let store: Arc<dyn ObjectStore> = match scheme {
+        "s3" => Arc::new(AmazonS3Builder::from_env().with_url(bucket_url).build()?),
+        "gs" => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(bucket_url)
+                .build()?,
+        ),
+        _ => Arc::new(
+            MicrosoftAzureBuilder::from_env()
+                .with_url(bucket_url)
+                .build()?,
+        ),
+    };
+    Ok((store, Path::parse(key)?))
+}
+
+/// Streams an object in as it is read. Seeking starts a new ranged download,
+/// which lets checkpointed runs resume on remote input.
+pub struct ObjectReader {
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+    position: u64,
+}
+
+impl ObjectReader {
+    pub fn open(url: &str) -> Result<Self> {
+        let (store, path) = store(url)?;
+        let stream = download(&store, &path, 0)?;
+        Ok(ObjectReader {
+            store,
+            path,
+            stream,
+            chunk: Bytes::new(),
+            position: 0,
+        })
+    }
+}
+
+fn download(
+    store: &Arc<dyn ObjectStore>,
+    path: &Path,
+    offset: u64,
+) -> io::Result<BoxStream<'static, object_store::Result<Bytes>>> {
+    let options = GetOptions {
+        range: (offset > 0).then_some(GetRange::Offset(offset)),
+        ..GetOptions::default()
+    };
+    let result = runtime()
+        .block_on(store.get_opts(path, options))
+        .map_err(io::Error::other)?;
+    Ok(result.into_stream())
+}
+
+impl io::Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match runtime().block_on(self.stream.next()) {
+                None => return Ok(0),
+                Some(chunk) => self.chunk = chunk.map_err(io::Error::other)?,
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl io::Seek for ObjectReader {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let io::SeekFrom::Start(offset) = pos else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "objects can only be sought from the start",
+            ));
+        };
+        self.stream = download(&self.store, &self.path, offset)?;
+        self.chunk = Bytes::new();
+        self.position = offset;
+        Ok(offset)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+/// Uploads an object in parts as it is written. Nothing appears until
+/// [ObjectWriter::finish], so a failed run never leaves a partial object.
+pub struct ObjectWriter {
+    writer: BufWriter,
+}
+
+impl ObjectWriter {
+    pub fn create(url: &str) -> Result<Self> {
+        let (store, path) = store(url)?;
+        Ok(ObjectWriter {
+            writer: BufWriter::new(store, path),
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        runtime().block_on(self.writer.shutdown())
+    }
+}
+
+impl io::Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        runtime().block_on(self.writer.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        runtime().block_on(self.writer.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_remote() {
+        assert!(is_remote("s3://bucket/in/transactions.csv".as_ref()));
+        assert!(is_remote("gs://bucket/transactions.csv".as_ref()));
+        assert!(is_remote("az://container/transactions.csv".as_ref()));
+        assert!(!is_remote("transactions.csv".as_ref()));
+        assert!(!is_remote("./s3://odd".as_ref()));
+        assert!(store("s3://bucket").is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Risk limits checked before a transaction is applied
+//!
+//! A transaction breaking a limit is not applied at all. It is recorded as a
+//! [Rejection] carrying the reason code of the limit instead.
+//! ```toml
+//! [risk]
+//! max_amount = "10000"
+//! max_daily_withdrawal = "2500"
+//!
+//! [risk.freeze]
+//! max_chargebacks = 3
+//! max_rate = "20"
+//! ```
+//!
+//! The freeze policy works the other way round. It counts the deposits and
+//! chargebacks applied to a client and locks the account once either the
+//! count or the chargeback rate goes over its threshold. Chargebacks the
+//! client ignored, e.g. of a tx never disputed, do not count, so nobody can
+//! get an account locked by sending bogus ones.
+use crate::reason::Reason;
+use crate::transaction::{ClientId, TransType, Transaction, TxId};
+use chrono::NaiveDate;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Configured limits, all off by default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskLimits {
+    /// Largest amount a single deposit or withdrawal may move
+    pub max_amount: Option<Decimal>,
+    /// Largest total a client may withdraw per calendar day (UTC).
+    /// Withdrawals without a timestamp all count towards one undated day.
+    pub max_daily_withdrawal: Option<Decimal>,
+    /// When to lock a client for its chargebacks
+    pub freeze: Option<FreezePolicy>,
+}
+
+/// Chargeback thresholds over which a client is locked
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreezePolicy {
+    /// Most chargebacks a client may have
+    pub max_chargebacks: Option<u64>,
+    /// Most chargebacks per deposit, in percent
+    pub max_rate: Option<Decimal>,
+    /// Deposits needed before the rate is considered
+    #[serde(default = "one")]
+    pub min_deposits: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+impl FreezePolicy {
+    /// Why a client with these counts has to be locked, if it does
+    fn trigger(&self, counts: &Chargebacks) -> Option<String> {
+        if self
+            .max_chargebacks
+            .is_some_and(|max| counts.chargebacks > max)
+        {
+            return Some(format!("{} chargebacks", counts.chargebacks));
+        }
+        if counts.deposits == 0 || counts.deposits < self.min_deposits {
+            return None;
+        }
+        let rate = Decimal::from(counts.chargebacks) * Decimal::ONE_HUNDRED
+            / Decimal::from(counts.deposits);
+        match self.max_rate {
+            Some(max) if rate > max => Some(format!(
+                "{} chargebacks on {} deposits",
+                counts.chargebacks, counts.deposits
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Why a transaction was refused: the limit it broke, its type having no
+/// handler, or a middleware turning it down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Violation {
+    MaxAmount,
+    DailyWithdrawal,
+    UnknownType,
+    NotOpened,
+    /// By a [crate::middleware::Middleware]
+    Refused(Reason),
+}
+
+impl Violation {
+    pub const ALL: [Violation; 4] = [
+        Violation::MaxAmount,
+        Violation::DailyWithdrawal,
+        Violation::UnknownType,
+        Violation::NotOpened,
+    ];
+
+    /// The reason the transaction is turned down for
+    pub fn reason(&self) -> Reason {
+        match self {
+            Violation::MaxAmount => Reason::MaxAmount,
+            Violation::DailyWithdrawal => Reason::MaxDailyWithdrawal,
+            Violation::UnknownType => Reason::UnknownType,
+            Violation::NotOpened => Reason::NotOpened,
+            Violation::Refused(reason) => *reason,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason())
+    }
+}
+
+/// A transaction refused by a risk limit, for its unknown type or because the
+/// account was never opened
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Rejection {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub violation: Violation,
+}
+
+/// The running totals limits are checked against
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RiskState {
+    withdrawn: HashMap<ClientId, DailyTotal>,
+    #[serde(default)]
+    chargebacks: HashMap<ClientId, Chargebacks>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Chargebacks {
+    deposits: u64,
+    chargebacks: u64,
+    frozen: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct DailyTotal {
+    day: Option<NaiveDate>,
+    amount: Decimal,
+}
+
+impl RiskState {
+    /// Takes in the running totals of clients counted by another engine
+    pub(crate) fn merge(&mut self, other: RiskState) {
+        self.withdrawn.extend(other.withdrawn);
+        self.chargebacks.extend(other.chargebacks);
+    }
+
+    /// Starts the daily withdrawal totals over, for a new period
+    pub(crate) fn start_period(&mut self) {
+        self.withdrawn.clear();
+    }
+
+    /// Checks a transaction against `limits`. It counts towards the running
+    /// totals only once applied, see [RiskState::withdrew].
+    pub fn check(&self, limits: &RiskLimits, transaction: &Transaction) -> Result<(), Violation> {
+        let Some(amount) = transaction.amount else {
+            return Ok(());
+        };
+        // The amount of a partial dispute moves nothing new
+        if transaction.trans == TransType::Dispute {
+            return Ok(());
+        }
+        if limits.max_amount.is_some_and(|max| amount > max) {
+            return Err(Violation::MaxAmount);
+        }
+        if transaction.trans != TransType::Withdrawal {
+            return Ok(());
+        }
+        let day = transaction.timestamp.map(|t| t.date_naive());
+        let withdrawn = self
+            .withdrawn
+            .get(&transaction.client)
+            .filter(|total| total.day == day)
+            .map_or(Decimal::ZERO, |total| total.amount);
+        if limits
+            .max_daily_withdrawal
+            .is_some_and(|max| withdrawn + amount > max)
+        {
+            return Err(Violation::DailyWithdrawal);
+        }
+        Ok(())
+    }
+
+    /// Counts a withdrawal the client applied towards its daily total
+    pub fn withdrew(&mut self, transaction: &Transaction) {
+        let (TransType::Withdrawal, Some(amount)) = (&transaction.trans, transaction.amount) else {
+            return;
+        };
+        let day = transaction.timestamp.map(|t| t.date_naive());
+        let total = self.withdrawn.entry(transaction.client).or_default();
+        if total.day != day {
+            *total = DailyTotal {
+                day,
+                amount: Decimal::ZERO,
+            };
+        }
+        total.amount += amount;
+    }
+
+    /// Counts a deposit or chargeback the client applied towards the freeze
+    /// policy. Returns the reason the client has to be locked the first time a
+    /// threshold is crossed.
+    pub fn freeze(&mut self, limits: &RiskLimits, transaction: &Transaction) -> Option<String> {
+        let policy = limits.freeze.as_ref()?;
+        let counts = self.chargebacks.entry(transaction.client).or_default();
+        match transaction.trans {
+            TransType::Deposit => counts.deposits += 1,
+            TransType::Chargeback => counts.chargebacks += 1,
+            _ => return None,
+        }
+        if counts.frozen {
+            return None;
+        }
+        let trigger = policy.trigger(counts)?;
+        counts.frozen = true;
+        Some(trigger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    fn withdrawal(amount: Decimal, timestamp: &str) -> Result<Transaction> {
+        let mut transaction = Transaction::new(TransType::Withdrawal, 1, 1, Some(amount));
+        transaction.timestamp = Some(timestamp.parse()?);
+        Ok(transaction)
+    }
+
+    #[test]
+    fn test_risk_limits() -> Result<()> {
+        let limits = RiskLimits {
+            max_amount: Some(dec!(100)),
+            max_daily_withdrawal: Some(dec!(150)),
+            freeze: None,
+        };
+        let mut state = RiskState::default();
+        let deposit = Transaction::new(TransType::Deposit, 1, 1, Some(dec!(100.01)));
+        assert_eq!(state.check(&limits, &deposit), Err(Violation::MaxAmount));
+
+        let morning = withdrawal(dec!(100), "2022-03-21T09:00:00Z")?;
+        let evening = withdrawal(dec!(60), "2022-03-21T21:00:00Z")?;
+        let next_day = withdrawal(dec!(60), "2022-03-22T09:00:00Z")?;
+        assert_eq!(state.check(&limits, &morning), Ok(()));
+        assert_eq!(
+            state.check(&limits, &evening),
+            Ok(()),
+            "nothing applied yet"
+        );
+        state.withdrew(&morning);
+        assert_eq!(
+            state.check(&limits, &evening),
+            Err(Violation::DailyWithdrawal)
+        );
+        assert_eq!(state.check(&limits, &next_day), Ok(()));
+        assert_eq!(
+            Violation::DailyWithdrawal.to_string(),
+            "R014 max_daily_withdrawal"
+        );
+
+        // A withdrawal refused for lack of funds withdraws nothing
+        let config: Config = toml::from_str("risk.max_daily_withdrawal = \"100\"")?;
+        let mut engine = Engine::with_config(config);
+        let csv = "\
+type,client,tx,amount
+deposit,1,1,50.0
+withdrawal,1,2,90.0
+withdrawal,1,3,20.0
+";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+        assert!(engine.rejections().is_empty());
+        assert_eq!(engine.snapshot()[&1].available, dec!(30.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_freeze_policy() -> Result<()> {
+        let limits: RiskLimits =
+            toml::from_str("freeze = { max_rate = \"50\", min_deposits = 2 }")?;
+        let mut state = RiskState::default();
+        let deposit = Transaction::new(TransType::Deposit, 1, 1, Some(dec!(1)));
+        let chargeback = Transaction::new(TransType::Chargeback, 1, 1, None);
+
+        assert_eq!(state.freeze(&limits, &deposit), None);
+        // One deposit is too few for the rate to count
+        assert_eq!(state.freeze(&limits, &chargeback), None);
+        assert_eq!(
+            state.freeze(&limits, &deposit),
+            None,
+            "1 chargeback on 2 deposits is not over 50%"
+        );
+        assert_eq!(
+            state.freeze(&limits, &chargeback),
+            Some("2 chargebacks on 2 deposits".to_string())
+        );
+        // Only the first crossing is reported
+        assert_eq!(state.freeze(&limits, &chargeback), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! Rejection rules as Rhai scripts
+//!
+//! For customization lighter than a [crate::plugin], [Rules] run a
+//! [Rhai](https://rhai.rs) script on every transaction as a [Middleware]. The
+//! script sees the transaction as the map `tx`, with `type`, `client`, `tx`,
+//! `amount` and `timestamp`, and the client's account as the map `account`,
+//! with `available`, `held`, `total`, `locked` and `closed`, or `()` before
+//! its first transaction. Amounts and number literals such as `0.9` are exact
+//! decimals. Changes to `tx`, bar its id, carry over to the transaction, and
+//! the script's value decides what becomes of it:
+//!
+//! * `()`, `true` or `"allow"` -- passes it on
+//! * `"skip"` -- skips it
+//! * `false` or `"reject"` -- rejects it as `R018 refused`
+//! * a [Reason] name or code, e.g. `"max_amount"` -- rejects it with that
+//!
+//! ```rhai
+//! if tx.type == "withdrawal" && account != () && tx.amount > account.total / 2 {
+//!     return "reject";
+//! }
+//! if tx.client == 9001 {
+//!     tx.client = 1;
+//! }
+//! ```
+//! A run of the script may take a bounded number of operations, and one that
+//! fails or runs over is rejected as refused, so a broken script fails
+//! closed.
+use crate::client::Client;
+use crate::middleware::{Context, Decision, Middleware};
+use crate::reason::Reason;
+use crate::transaction::{TransType, Transaction};
+use anyhow::{anyhow, Context as _, Result};
+use log::error;
+use rhai::{Dynamic, Map, Scope, AST};
+use rust_decimal::Decimal;
+use std::path::Path;
+
+/// How many operations a run of the script may take
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A compiled script, see the [module docs](self) for what it sees and returns
+pub struct Rules {
+    name: String,
+    engine: rhai::Engine,
+    ast: AST,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Rules> {
+        let script = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        Rules::new(path.display().to_string(), &script)
+            .with_context(|| format!("invalid rules {}", path.display()))
+    }
+
+    /// Compiles `script`, naming it in logs by `name`
+    pub fn new(name: impl Into<String>, script: &str) -> Result<Rules> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(script)?;
+        Ok(Rules {
+            name: name.into(),
+            engine,
+            ast,
+        })
+    }
+
+    fn run(&self, mut transaction: Transaction, context: &Context) -> Result<Decision> {
+        let mut scope = Scope::new();
+        scope.push("tx", to_map(&transaction));
+        scope.push("account", context.account.map_or(Dynamic::UNIT, account));
+        let verdict: Dynamic = self.engine.eval_ast_with_scope(&mut scope, &self.ast)?;
+        let tx = scope
+            .get_value::<Map>("tx")
+            .ok_or_else(|| anyhow!("tx is no longer a map"))?;
+        read_back(&mut transaction, tx)?;
+
+        if verdict.is_unit() || verdict.as_bool() == Ok(true) {
+            return Ok(Decision::Continue(transaction));
+        }
+        if verdict.as_bool() == Ok(false) {
+            return Ok(Decision::Reject(Reason::Refused));
+        }
+        let verdict = verdict
+            .into_string()
+            .map_err(|kind| anyhow!("the script returned a {kind}, not a verdict"))?;
+        Ok(match verdict.as_str() {
+            "allow" => Decision::Continue(transaction),
+            "skip" => Decision::Skip,
+            "reject" => Decision::Reject(Reason::Refused),
+            _ => Decision::Reject(verdict.parse().map_err(|e: String| anyhow!(e))?),
+        })
+    }
+}
+
+impl Middleware for Rules {
+    fn check(&mut self, transaction: Transaction, context: &mut Context) -> Decision {
+        let (client, tx) = (transaction.client, transaction.tx);
+        self.run(transaction, context).unwrap_or_else(|e| {
+            error!(
+                "Rules {} failed on tx:{tx} of client:{client}: {e:#}",
+                self.name
+            );
+            Decision::Reject(Reason::Refused)
+        })
+    }
+}
+
+fn to_map(transaction: &Transaction) -> Map {
+    let mut map = Map::new();
+    map.insert("type".into(), transaction.trans.to_string().into());
+    map.insert("client".into(), (transaction.client as i64).into());
+    map.insert("tx".into(), (transaction.tx as i64).into());
+    map.insert(
+        "amount".into(),
+        transaction
+            .amount
+            .map_or(Dynamic::UNIT, Dynamic::from_decimal),
+    );
+    map.insert(
+        "timestamp".into(),
+        transaction
+            .timestamp
+            .map_or(Dynamic::UNIT, |at| at.to_rfc3339().into()),
+    );
+    map
+}
+
+fn account(client: &Client) -> Dynamic {
+    let mut map = Map::new();
+    map.insert(
+        "available".into(),
+        Dynamic::from_decimal(client.available()),
+    );
+    map.insert("held".into(), Dynamic::from_decimal(client.held()));
+    map.insert("total".into(), Dynamic::from_decimal(client.total()));
+    map.insert("locked".into(), client.is_locked().into());
+    map.insert("closed".into(), client.is_closed().into());
+    map.into()
+}
+
+/// Takes the columns the script may change from `tx`, ignoring anything else
+/// it keeps there
+fn read_back(transaction: &mut Transaction, tx: Map) -> Result<()> {
+    for (key, value) in tx {
+        let kind = value.type_name();
+        let wrong = || anyhow!("tx.{key} is a {kind}");
+        match key.as_str() {
+            "type" => {
+                transaction.trans =
+                    TransType::from(value.into_string().map_err(|_| wrong())?.as_str())
+            }
+            "client" => {
+                let client = value.as_int().map_err(|_| wrong())?;
+                transaction.client = client.try_into().map_err(|_| wrong())?;
+            }
+            "amount" if value.is_unit() => transaction.amount = None,
+            "amount" => {
+                let amount = value
+                    .as_decimal()
+                    .or_else(|_| value.as_int().map(Decimal::from))
+                    .map_err(|_| wrong())?;
+                transaction.amount = Some(amount);
+            }
+            "timestamp" if value.is_unit() => transaction.timestamp = None,
+            "timestamp" => {
+                let at = value.into_string().map_err(|_| wrong())?;
+                transaction.timestamp = Some(at.parse().map_err(|_| wrong())?);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use rust_decimal_macros::dec;
+
+    const RULES: &str = r#"
+        if tx.client == 9 {
+            return "skip";
+        }
+        if tx.client == 9001 {
+            tx.client = 1;
+        }
+        if tx.type == "deposit" {
+            tx.amount = tx.amount * 0.9;
+        }
+        if tx.type == "withdrawal" && account != () && tx.amount > account.total / 2 {
+            return "max_amount";
+        }
+        tx.type != "bonus"
+    "#;
+
+    #[test]
+    fn test_rules() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.add_middleware(Rules::new("rules", RULES)?);
+        let csv = "\
+type,client,tx,amount
+deposit,9001,1,100.0
+withdrawal,1,2,60.0
+withdrawal,1,3,40.0
+bonus,1,4,5.0
+deposit,9,5,1.0
+";
+        engine.replay(read_csv(csv.as_bytes()), None)?;
+        let accounts: Vec<_> = engine.iter_accounts().collect();
+        assert_eq!(accounts.len(), 1, "client 9 is skipped");
+        assert_eq!(accounts[0].client, 1);
+        assert_eq!(accounts[0].total, dec!(50.0));
+        let rejected: Vec<_> = engine
+            .rejections()
+            .iter()
+            .map(|rejection| (rejection.tx, rejection.violation.reason()))
+            .collect();
+        assert_eq!(rejected, [(2, Reason::MaxAmount), (4, Reason::Refused)]);
+
+        for script in ["loop {}", "tx.amount = \"lots\"", "42"] {
+            let mut engine = Engine::new();
+            engine.add_middleware(Rules::new("broken", script)?);
+            engine.replay(
+                read_csv("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes()),
+                None,
+            )?;
+            assert_eq!(engine.rejections().len(), 1, "{script} fails closed");
+        }
+
+        assert!(Rules::new("broken", "if {").is_err());
+        Ok(())
+    }
+}
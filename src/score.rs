@@ -0,0 +1,125 @@
+//! A risk score per client, from its activity
+//!
+//! The score runs from 0 to 100 and adds up
+//! * 10 for every dispute and 25 for every chargeback offered
+//! * 5 for every transaction rejected, by a risk limit or by the account
+//! * 2 for every transaction past [BUSY_DAY] on the client's busiest day
+//!   (UTC). Transactions without a timestamp have no day and do not count.
+//!
+//! It only points at accounts worth a look, nothing is refused for it.
+use crate::transaction::{ClientId, TransType, Transaction};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Transactions a day a client may make before it adds to the score
+pub const BUSY_DAY: u64 = 10;
+
+/// What the score of one client is made of
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Activity {
+    pub disputes: u64,
+    pub chargebacks: u64,
+    pub rejections: u64,
+    /// The most transactions on any one day
+    pub busiest_day: u64,
+    day: Option<NaiveDate>,
+    today: u64,
+}
+
+impl Activity {
+    pub fn score(&self) -> u64 {
+        let score = 10 * self.disputes
+            + 25 * self.chargebacks
+            + 5 * self.rejections
+            + 2 * self.busiest_day.saturating_sub(BUSY_DAY);
+        score.min(100)
+    }
+}
+
+/// The activity of every client seen
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Scores {
+    clients: HashMap<ClientId, Activity>,
+}
+
+impl Scores {
+    /// Takes in the activity of clients counted by another engine
+    pub(crate) fn merge(&mut self, other: Scores) {
+        self.clients.extend(other.clients);
+    }
+
+    /// Counts a transaction offered to the engine, whatever becomes of it
+    pub fn observe(&mut self, transaction: &Transaction) {
+        let activity = self.clients.entry(transaction.client).or_default();
+        match transaction.trans {
+            TransType::Dispute => activity.disputes += 1,
+            TransType::Chargeback => activity.chargebacks += 1,
+            _ => {}
+        }
+        let Some(day) = transaction.timestamp.map(|t| t.date_naive()) else {
+            return;
+        };
+        if activity.day != Some(day) {
+            activity.day = Some(day);
+            activity.today = 0;
+        }
+        activity.today += 1;
+        activity.busiest_day = activity.busiest_day.max(activity.today);
+    }
+
+    /// Counts a transaction of `client` that was rejected
+    pub fn reject(&mut self, client: ClientId) {
+        self.clients.entry(client).or_default().rejections += 1;
+    }
+
+    pub fn activity(&self, client: ClientId) -> Option<&Activity> {
+        self.clients.get(&client)
+    }
+
+    /// The score of `client`, 0 if it was never seen
+    pub fn score(&self, client: ClientId) -> u64 {
+        self.activity(client).map_or(0, Activity::score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+
+    #[test]
+    fn test_risk_scores() -> Result<()> {
+        let mut data = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         5.0,     2022-03-01T10:00:00Z
+withdrawal,      1,     2,         9.0,     2022-03-01T11:00:00Z
+dispute,         1,     1,            ,     2022-03-02T10:00:00Z
+chargeback,      1,     1,            ,     2022-03-02T11:00:00Z
+"
+        .to_string();
+        for tx in 10..22 {
+            data.push_str(&format!("deposit, 2, {tx}, 1.0, 2022-03-05T10:00:00Z\n"));
+        }
+        let mut engine = Engine::new();
+        engine.score_risk();
+        engine.replay(read_csv(data.as_bytes()), None)?;
+        let scores = engine.risk_scores().expect("scoring");
+        assert_eq!(scores.score(1), 40, "a dispute, a chargeback, a rejection");
+        assert_eq!(scores.score(2), 4, "12 deposits in a day");
+        assert_eq!(scores.score(3), 0);
+
+        let mut out = Vec::new();
+        engine.write_report(&mut out)?;
+        let report = String::from_utf8(out)?;
+        assert_eq!(
+            report.lines().take(2).collect::<Vec<_>>(),
+            [
+                "client, available, held, total, locked, risk_score",
+                "1, 0.0, 0.0, 0.0, true, 40"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,987 @@
+//! `tte serve`, applying transactions posted over HTTP
+//!
+//! The engine runs on a thread of its own, fed by the connections through a
+//! bounded queue, and answers them in the order it takes their requests:
+//! ```text
+//! POST /transactions          {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+//! GET  /accounts?cursor=42&limit=100
+//! GET  /accounts/stream
+//! GET  /audit?from=0
+//! POST /accounts/42/lock?tx=900001
+//! POST /accounts/42/unlock?tx=900002
+//! GET  /openapi.json
+//! ```
+//! A transaction is a JSON object with the CSV columns, answered with the
+//! events it made, see [tte::event], with 422 and the [Reason] if the engine
+//! turned it down, or with 400 if it is no valid transaction at all.
+//!
+//! Accounts come a page at a time, in the order the clients were first seen,
+//! with the cursor of the next page while there is one, see
+//! [Engine::accounts_page]. The stream sends every account as a JSON line,
+//! asking the engine for one page after the other, so no request builds the
+//! whole report in memory. The audit trail, see [tte::AuditEntry], is
+//! streamed the same way, from the entry `from` on.
+//!
+//! Given API keys, see [read_api_keys], every request needs one as a bearer
+//! token, and its [Role] decides what it may do: submitters post transactions,
+//! auditors stream the audit trail and only admins lock and unlock accounts.
+//! Every role reads the accounts, and anyone the OpenAPI document, see
+//! [crate::openapi].
+//!
+//! Load spikes are turned away rather than buffered: requests past a token
+//! bucket rate limit, per connection or over all of them, get 429, and those
+//! finding the queue to the engine full 503, both with `Retry-After`. See
+//! [Limits].
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use csv::StringRecord;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Semaphore};
+use tte::event::Event;
+use tte::transaction::parse_tx_id;
+use tte::{AccountView, AuditEntry, ClientId, Engine, ReaderOptions, Reason, Transaction, TxId};
+
+/// How long a client may take to send the headers of a request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most bytes of request line and headers read, the least hyper allows
+const MAX_REQUEST: usize = 8192;
+
+/// The most bytes of a transaction
+const MAX_BODY: usize = 4096;
+
+/// Connections served at once, any more are turned away
+const MAX_CONNECTIONS: usize = 256;
+
+/// Accounts on a page unless the request asks for fewer
+const DEFAULT_LIMIT: usize = 100;
+
+/// The most accounts on a page, and the pages the stream is sent in
+const MAX_LIMIT: usize = 1000;
+
+/// How often the server looks whether it is to stop
+const POLL: Duration = Duration::from_millis(100);
+
+type Body = BoxBody<Bytes, io::Error>;
+
+/// Where a page goes, with the cursor of the next one if there is more
+type PageReply<T, C> = oneshot::Sender<Result<(Vec<T>, Option<C>)>>;
+
+/// How much load the server takes before it turns requests away
+pub struct Limits {
+    /// Requests waiting for the engine, any more are answered with 503
+    pub queue: usize,
+    /// Requests a second on one connection, any more are answered with 429
+    pub rate: Option<u32>,
+    /// Requests a second over all connections, any more are answered with 429
+    pub global_rate: Option<u32>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            queue: 1024,
+            rate: None,
+            global_rate: None,
+        }
+    }
+}
+
+/// What the holder of an API key may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Posts transactions
+    Submitter,
+    /// Streams the audit trail
+    Auditor,
+    /// Locks and unlocks accounts, and posts transactions
+    Admin,
+}
+
+/// The role of every API key
+pub type ApiKeys = HashMap<String, Role>;
+
+#[derive(Deserialize)]
+struct ApiKey {
+    key: String,
+    role: Role,
+}
+
+/// Reads API keys from CSV with `key` and `role` columns, the role one of
+/// `submitter`, `auditor` or `admin`
+pub fn read_api_keys(r: impl io::Read) -> Result<ApiKeys> {
+    let mut keys = ApiKeys::new();
+    for key in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(r)
+        .into_deserialize()
+    {
+        let ApiKey { key, role } = key?;
+        if key.is_empty() {
+            return Err(anyhow!("an API key is empty"));
+        }
+        keys.insert(key, role);
+    }
+    Ok(keys)
+}
+
+/// A token bucket holding up to a second's worth of requests, so a client
+/// may send that many at once before it is held to the rate
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    filled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32) -> Self {
+        Bucket {
+            rate: f64::from(rate.max(1)),
+            tokens: f64::from(rate.max(1)),
+            filled: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or tells how long until there is one
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.filled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.filled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// What every connection shares
+struct Shared {
+    jobs: SyncSender<Job>,
+    rate: Option<u32>,
+    global: Option<Mutex<Bucket>>,
+    keys: Option<ApiKeys>,
+}
+
+impl Shared {
+    /// Whether the bearer of the request may take `route`, without keys
+    /// anyone may
+    fn authorize<B>(&self, request: &Request<B>, route: &Route) -> Result<(), (StatusCode, &str)> {
+        let (Some(keys), Some(roles)) = (&self.keys, route.roles()) else {
+            return Ok(());
+        };
+        let role = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|key| keys.get(key.trim()))
+            .ok_or((StatusCode::UNAUTHORIZED, "no valid API key"))?;
+        match roles.contains(role) {
+            true => Ok(()),
+            false => Err((StatusCode::FORBIDDEN, "not allowed for this API key")),
+        }
+    }
+
+    /// Takes a token from the bucket of the connection, then from the global
+    /// one, or tells how long until the request could be taken
+    fn admit(&self, connection: Option<&Mutex<Bucket>>) -> Result<(), Duration> {
+        for bucket in [connection, self.global.as_ref()].into_iter().flatten() {
+            bucket.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+        }
+        Ok(())
+    }
+}
+
+/// What a connection asks of the engine, with where the answer goes
+enum Job {
+    Submit(Transaction, oneshot::Sender<Result<Vec<Event>>>),
+    Accounts {
+        cursor: Option<ClientId>,
+        limit: usize,
+        reply: PageReply<AccountView, ClientId>,
+    },
+    Audit {
+        from: usize,
+        limit: usize,
+        reply: PageReply<AuditEntry, usize>,
+    },
+    Lock {
+        client: ClientId,
+        tx: TxId,
+        locked: bool,
+        reply: oneshot::Sender<Result<Option<AccountView>>>,
+    },
+}
+
+/// Where a request goes
+enum Route {
+    Submit,
+    Accounts,
+    StreamAccounts,
+    Audit,
+    Lock(ClientId, bool),
+    OpenApi,
+}
+
+impl Route {
+    /// The route of `path`, 404 if none, 405 if not for `method`
+    fn find(method: &Method, path: &str) -> Result<Route, StatusCode> {
+        let route = match path {
+            "/transactions" => Route::Submit,
+            "/accounts" => Route::Accounts,
+            "/accounts/stream" => Route::StreamAccounts,
+            "/audit" => Route::Audit,
+            "/openapi.json" => Route::OpenApi,
+            _ => {
+                let (client, action) = path
+                    .strip_prefix("/accounts/")
+                    .and_then(|rest| rest.split_once('/'))
+                    .ok_or(StatusCode::NOT_FOUND)?;
+                let client = client.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+                match action {
+                    "lock" => Route::Lock(client, true),
+                    "unlock" => Route::Lock(client, false),
+                    _ => return Err(StatusCode::NOT_FOUND),
+                }
+            }
+        };
+        let expected = match route {
+            Route::Submit | Route::Lock(..) => Method::POST,
+            Route::Accounts | Route::StreamAccounts | Route::Audit | Route::OpenApi => Method::GET,
+        };
+        match *method == expected {
+            true => Ok(route),
+            false => Err(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    /// The roles that may take the route, none when it needs no API key
+    fn roles(&self) -> Option<&'static [Role]> {
+        Some(match self {
+            Route::Submit => &[Role::Submitter, Role::Admin],
+            Route::Accounts | Route::StreamAccounts => {
+                &[Role::Submitter, Role::Auditor, Role::Admin]
+            }
+            Route::Audit => &[Role::Auditor],
+            Route::Lock(..) => &[Role::Admin],
+            Route::OpenApi => return None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Page {
+    accounts: Vec<AccountView>,
+    next: Option<ClientId>,
+}
+
+#[derive(Serialize)]
+struct Submitted<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    events: &'a [Event],
+}
+
+#[derive(Serialize)]
+struct Failure<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    error: &'a str,
+}
+
+/// A running server
+pub struct Server {
+    pub addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Engine>>,
+}
+
+impl Server {
+    /// Serves `engine` on `addr` from threads of its own, e.g. port 0 for any,
+    /// to the holders of `keys` if given and to anyone otherwise
+    pub fn start(
+        mut engine: Engine,
+        addr: &str,
+        limits: Limits,
+        keys: Option<ApiKeys>,
+    ) -> Result<Self> {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("could not listen on {addr}"))?;
+        listener.set_nonblocking(true)?;
+        let bound = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        engine.record_events();
+        let (jobs, queue) = mpsc::sync_channel(limits.queue);
+        let engine = thread::spawn(move || run(engine, queue));
+        let shared = Arc::new(Shared {
+            jobs,
+            rate: limits.rate,
+            global: limits.global_rate.map(|rate| Mutex::new(Bucket::new(rate))),
+            keys,
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            runtime.block_on(async {
+                match TcpListener::from_std(listener) {
+                    Ok(listener) => accept(listener, shared, &stopping).await,
+                    Err(e) => warn!("serve: {e}"),
+                }
+            });
+            // Ends the connections still open, and with them the queue
+            drop(runtime);
+            engine
+                .join()
+                .map_err(|_| anyhow!("the engine thread panicked"))
+        });
+        Ok(Server {
+            addr: bound,
+            stop,
+            thread,
+        })
+    }
+
+    /// Stops taking requests and hands back the engine once it has applied
+    /// those it took
+    pub fn stop(self) -> Result<Engine> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("the server thread panicked"))?
+    }
+}
+
+/// Works through the queue until every connection is gone
+fn run(mut engine: Engine, queue: Receiver<Job>) -> Engine {
+    for job in queue {
+        match job {
+            Job::Submit(transaction, reply) => {
+                let applied = engine.apply(transaction);
+                let events = engine.take_events();
+                let _ = reply.send(applied.map(|()| events));
+            }
+            Job::Accounts {
+                cursor,
+                limit,
+                reply,
+            } => {
+                let _ = reply.send(engine.accounts_page(cursor, limit));
+            }
+            Job::Audit { from, limit, reply } => {
+                let trail = engine.audit_trail();
+                let entries = trail.get(from..).unwrap_or_default();
+                let page = entries.iter().take(limit).cloned().collect();
+                let next = (entries.len() > limit).then_some(from + limit);
+                let _ = reply.send(Ok((page, next)));
+            }
+            Job::Lock {
+                client,
+                tx,
+                locked,
+                reply,
+            } => {
+                let done = match locked {
+                    true => engine.lock(client, tx),
+                    false => engine.unlock(client, tx),
+                };
+                let _ = reply.send(done.map(|()| engine.account(client)));
+            }
+        }
+    }
+    engine
+}
+
+async fn accept(listener: TcpListener, shared: Arc<Shared>, stop: &AtomicBool) {
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match tokio::time::timeout(POLL, listener.accept()).await {
+            Ok(Ok((stream, _))) => stream,
+            Ok(Err(e)) => {
+                warn!("serve: {e}");
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let Ok(permit) = Arc::clone(&connections).try_acquire_owned() else {
+            warn!("serve: {MAX_CONNECTIONS} connections at once already");
+            continue;
+        };
+        let shared = Arc::clone(&shared);
+        let bucket = shared
+            .rate
+            .map(|rate| Arc::new(Mutex::new(Bucket::new(rate))));
+        tokio::spawn(async move {
+            let service = service_fn(|request| {
+                let shared = Arc::clone(&shared);
+                let bucket = bucket.clone();
+                async move {
+                    let answer = match shared.admit(bucket.as_deref()) {
+                        Ok(()) => answer(request, &shared).await,
+                        Err(wait) => retry_after(
+                            failure(StatusCode::TOO_MANY_REQUESTS, None, "rate limited"),
+                            wait,
+                        ),
+                    };
+                    Ok::<_, Infallible>(answer)
+                }
+            });
+            let connection = http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(READ_TIMEOUT)
+                .max_buf_size(MAX_REQUEST)
+                .serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = connection.await {
+                warn!("serve: {e}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn answer(request: Request<Incoming>, shared: &Shared) -> Response<Body> {
+    let route = match Route::find(request.method(), request.uri().path()) {
+        Ok(route) => route,
+        Err(status) => {
+            let error = status.canonical_reason().unwrap_or_default();
+            return failure(status, None, &error.to_lowercase());
+        }
+    };
+    if let Err((status, error)) = shared.authorize(&request, &route) {
+        return failure(status, None, error);
+    }
+    let query = request.uri().query().map(str::to_string);
+    let jobs = &shared.jobs;
+    let answer = match route {
+        Route::Submit => submit(request, jobs).await,
+        Route::Accounts => accounts(query.as_deref(), jobs).await,
+        Route::StreamAccounts => Ok(stream(jobs.clone(), None, |cursor, reply| Job::Accounts {
+            cursor,
+            limit: MAX_LIMIT,
+            reply,
+        })),
+        Route::Audit => Ok(audit(query.as_deref(), jobs)),
+        Route::Lock(client, locked) => lock(client, locked, query.as_deref(), jobs).await,
+        Route::OpenApi => Ok(json(StatusCode::OK, &crate::openapi::document())),
+    };
+    answer.unwrap_or_else(|failure| failure)
+}
+
+async fn submit(
+    request: Request<Incoming>,
+    jobs: &SyncSender<Job>,
+) -> Result<Response<Body>, Response<Body>> {
+    let body = match tokio::time::timeout(
+        READ_TIMEOUT,
+        Limited::new(request.into_body(), MAX_BODY).collect(),
+    )
+    .await
+    {
+        Ok(Ok(body)) => body.to_bytes(),
+        Ok(Err(e)) if e.is::<LengthLimitError>() => {
+            return Err(failure(StatusCode::PAYLOAD_TOO_LARGE, None, &e.to_string()))
+        }
+        Ok(Err(e)) => return Err(failure(StatusCode::BAD_REQUEST, None, &e.to_string())),
+        Err(_) => {
+            return Err(failure(
+                StatusCode::REQUEST_TIMEOUT,
+                None,
+                "the transaction took too long to send",
+            ))
+        }
+    };
+    let transaction =
+        decode(&body).map_err(|e| failure(StatusCode::BAD_REQUEST, Some(Reason::Malformed), &e))?;
+    let tx = transaction.tx;
+    let events = ask(jobs, |reply| Job::Submit(transaction, reply))
+        .await?
+        .map_err(|e| failure(StatusCode::INTERNAL_SERVER_ERROR, None, &format!("{e:#}")))?;
+    let reason = events.iter().find_map(|event| match event {
+        Event::TransactionRejected {
+            tx: rejected,
+            reason,
+            ..
+        } if *rejected == tx => reason.parse::<Reason>().ok(),
+        _ => None,
+    });
+    let status = match reason {
+        Some(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        None => StatusCode::OK,
+    };
+    let submitted = Submitted {
+        code: reason.map(|reason| reason.code()),
+        reason: reason.map(|reason| reason.name()),
+        events: &events,
+    };
+    Ok(json(status, &submitted))
+}
+
+async fn accounts(
+    query: Option<&str>,
+    jobs: &SyncSender<Job>,
+) -> Result<Response<Body>, Response<Body>> {
+    let (cursor, limit) =
+        page_query(query).map_err(|e| failure(StatusCode::BAD_REQUEST, None, &e))?;
+    let (accounts, next) = ask(jobs, |reply| Job::Accounts {
+        cursor,
+        limit,
+        reply,
+    })
+    .await?
+    .map_err(|e| failure(StatusCode::BAD_REQUEST, None, &e.to_string()))?;
+    Ok(json(StatusCode::OK, &Page { accounts, next }))
+}
+
+fn audit(query: Option<&str>, jobs: &SyncSender<Job>) -> Response<Body> {
+    let from = match parameters(query).as_slice() {
+        [] => Some(0),
+        [("from", from)] => from.parse().ok(),
+        _ => None,
+    };
+    let Some(from) = from else {
+        let error = "from must be the index of an audit entry, and nothing else given";
+        return failure(StatusCode::BAD_REQUEST, None, error);
+    };
+    stream(jobs.clone(), Some(from), |from, reply| Job::Audit {
+        from: from.unwrap_or_default(),
+        limit: MAX_LIMIT,
+        reply,
+    })
+}
+
+async fn lock(
+    client: ClientId,
+    locked: bool,
+    query: Option<&str>,
+    jobs: &SyncSender<Job>,
+) -> Result<Response<Body>, Response<Body>> {
+    let tx = match parameters(query).as_slice() {
+        [("tx", tx)] => parse_tx_id(tx),
+        _ => None,
+    };
+    let tx = tx.ok_or_else(|| {
+        let error = "tx must be the id the operation goes by, and nothing else given";
+        failure(StatusCode::BAD_REQUEST, None, error)
+    })?;
+    let account = ask(jobs, |reply| Job::Lock {
+        client,
+        tx,
+        locked,
+        reply,
+    })
+    .await?
+    .map_err(|e| failure(StatusCode::NOT_FOUND, None, &e.to_string()))?;
+    Ok(json(StatusCode::OK, &account))
+}
+
+/// The items of one page after the other as JSON lines, from `cursor` on,
+/// each page asked for by `job`. The body ends in an error, which cuts the
+/// response short, if a page cannot be had.
+fn stream<T, C>(
+    jobs: SyncSender<Job>,
+    mut cursor: Option<C>,
+    job: fn(Option<C>, PageReply<T, C>) -> Job,
+) -> Response<Body>
+where
+    T: Serialize + Send + Sync + 'static,
+    C: Copy + Send + Sync + 'static,
+{
+    let (lines, receiver) = tokio::sync::mpsc::channel(MAX_LIMIT);
+    tokio::spawn(async move {
+        loop {
+            let (page, next) = match ask(&jobs, |reply| job(cursor, reply)).await {
+                Ok(Ok(page)) => page,
+                Ok(Err(e)) => {
+                    let _ = lines.send(Err(io::Error::other(e))).await;
+                    return;
+                }
+                // The queue is full, or the server stopping
+                Err(_) => {
+                    let e = io::Error::other("the engine could not be asked for the next page");
+                    let _ = lines.send(Err(e)).await;
+                    return;
+                }
+            };
+            for item in &page {
+                let line = serde_json::to_string(item).unwrap_or_default() + "\n";
+                if lines.send(Ok(Bytes::from(line))).await.is_err() {
+                    return;
+                }
+            }
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return,
+            }
+        }
+    });
+    let mut response = Response::new(Lines(receiver).boxed());
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        "application/x-ndjson".parse().expect("a valid header"),
+    );
+    response
+}
+
+/// A body sent as its lines come
+struct Lines(tokio::sync::mpsc::Receiver<io::Result<Bytes>>);
+
+impl hyper::body::Body for Lines {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        self.0
+            .poll_recv(cx)
+            .map(|line| line.map(|line| line.map(Frame::data)))
+    }
+}
+
+/// Queues `job` for the engine and waits for its answer, or answers 503
+/// straight away when the queue is full
+async fn ask<T>(
+    jobs: &SyncSender<Job>,
+    job: impl FnOnce(oneshot::Sender<T>) -> Job,
+) -> Result<T, Response<Body>> {
+    let (reply, answer) = oneshot::channel();
+    if let Err(e) = jobs.try_send(job(reply)) {
+        let error = match e {
+            TrySendError::Full(_) => "too many requests waiting for the engine",
+            TrySendError::Disconnected(_) => "the engine has stopped",
+        };
+        let response = failure(StatusCode::SERVICE_UNAVAILABLE, None, error);
+        return Err(retry_after(response, Duration::from_secs(1)));
+    }
+    answer.await.map_err(|_| {
+        failure(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            None,
+            "the engine dropped the request",
+        )
+    })
+}
+
+/// The transaction of a JSON object with the CSV columns, read the way a CSV
+/// row is
+fn decode(body: &[u8]) -> Result<Transaction, String> {
+    let fields = crate::json_fields(body)?;
+    let headers: StringRecord = fields.iter().map(|(name, _)| name.trim()).collect();
+    let record: StringRecord = fields.iter().map(|(_, value)| value.trim()).collect();
+    ReaderOptions::default()
+        .decode(&record, &headers)
+        .map_err(|e| e.to_string())
+}
+
+/// The names and values of a query such as `cursor=42&limit=100`
+fn parameters(query: Option<&str>) -> Vec<(&str, &str)> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect()
+}
+
+/// The cursor and limit of `cursor=42&limit=100`, both optional
+fn page_query(query: Option<&str>) -> Result<(Option<ClientId>, usize), String> {
+    let mut cursor = None;
+    let mut limit = DEFAULT_LIMIT;
+    for (name, value) in parameters(query) {
+        match name {
+            "cursor" => {
+                cursor = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid cursor {value}"))?,
+                )
+            }
+            "limit" => {
+                limit = value
+                    .parse()
+                    .ok()
+                    .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                    .ok_or_else(|| format!("the limit must be from 1 to {MAX_LIMIT}"))?
+            }
+            _ => return Err(format!("unknown parameter {name}")),
+        }
+    }
+    Ok((cursor, limit))
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_string(body).unwrap_or_default() + "\n";
+    let mut response = Response::new(
+        Full::new(Bytes::from(body))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        "application/json".parse().expect("a valid header"),
+    );
+    response
+}
+
+/// Asks the client to come back after `wait`, in whole seconds
+fn retry_after(mut response: Response<Body>, wait: Duration) -> Response<Body> {
+    let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+    response.headers_mut().insert(
+        RETRY_AFTER,
+        seconds.to_string().parse().expect("a valid header"),
+    );
+    response
+}
+
+fn failure(status: StatusCode, reason: Option<Reason>, error: &str) -> Response<Body> {
+    let failure = Failure {
+        code: reason.map(|reason| reason.code()),
+        reason: reason.map(|reason| reason.name()),
+        error,
+    };
+    json(status, &failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> Result<String> {
+        request_as(addr, None, method, path, body)
+    }
+
+    fn request_as(
+        addr: SocketAddr,
+        key: Option<&str>,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        let authorization = key
+            .map(|key| format!("Authorization: Bearer {key}\r\n"))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             {authorization}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let server = Server::start(Engine::new(), "127.0.0.1:0", Limits::default(), None)?;
+        let addr = server.addr;
+        for client in 1..=3 {
+            let deposit = format!(
+                r#"{{"type": "deposit", "client": {client}, "tx": {client}, "amount": "1.5"}}"#
+            );
+            let response = request(addr, "POST", "/transactions", &deposit)?;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+            assert!(
+                response.contains(r#""event":"DepositApplied""#),
+                "{response}"
+            );
+        }
+        let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 4, "amount": 2}"#;
+        let response = request(addr, "POST", "/transactions", withdrawal)?;
+        assert!(response.starts_with("HTTP/1.1 422"), "{response}");
+        assert!(response.contains(r#""code":"R001","reason":"insufficient_funds""#));
+        let response = request(addr, "POST", "/transactions", r#"{"type": "deposit"}"#)?;
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        assert!(response.contains(r#""code":"R016""#));
+
+        let response = request(addr, "GET", "/accounts?limit=2", "")?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.contains(r#""client":2,"#) && !response.contains(r#""client":3,"#));
+        assert!(response.contains(r#""next":2"#), "{response}");
+        let response = request(addr, "GET", "/accounts?cursor=2&limit=2", "")?;
+        assert!(response.contains(r#""client":3,"#) && response.contains(r#""next":null"#));
+        assert!(request(addr, "GET", "/accounts?cursor=9", "")?.starts_with("HTTP/1.1 400"));
+        assert!(request(addr, "GET", "/accounts?limit=0", "")?.starts_with("HTTP/1.1 400"));
+        let response = request(addr, "GET", "/accounts/stream", "")?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert_eq!(response.matches(r#""client":"#).count(), 3, "{response}");
+        assert!(request(addr, "GET", "/nope", "")?.starts_with("HTTP/1.1 404"));
+
+        let engine = server.stop()?;
+        assert_eq!(engine.iter_accounts().count(), 3);
+        assert_eq!(
+            engine.account(1).map(|account| account.total),
+            Some("1.5".parse()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits() -> Result<()> {
+        let limits = Limits {
+            global_rate: Some(1),
+            ..Limits::default()
+        };
+        let server = Server::start(Engine::new(), "127.0.0.1:0", limits, None)?;
+        assert!(request(server.addr, "GET", "/accounts", "")?.starts_with("HTTP/1.1 200 OK"));
+        let response = request(server.addr, "GET", "/accounts", "")?;
+        assert!(response.starts_with("HTTP/1.1 429"), "{response}");
+        assert!(response.contains("retry-after: 1\r\n"), "{response}");
+        server.stop()?;
+
+        let mut bucket = Bucket::new(2);
+        assert!(bucket.take().is_ok() && bucket.take().is_ok());
+        assert!(bucket
+            .take()
+            .is_err_and(|wait| wait <= Duration::from_millis(500)));
+
+        let (jobs, _queue) = mpsc::sync_channel(1);
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let page = |reply| Job::Accounts {
+            cursor: None,
+            limit: 1,
+            reply,
+        };
+        jobs.try_send(page(oneshot::channel().0))?;
+        let full = runtime.block_on(ask(&jobs, page)).map(|_| ());
+        let response = full.expect_err("the queue is full");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_roles() -> Result<()> {
+        const KEYS: &str = "\
+key,            role
+submit-key,     submitter
+audit-key,      auditor
+admin-key,      admin
+";
+        let keys = read_api_keys(KEYS.as_bytes())?;
+        assert!(read_api_keys("key,role\nk,owner\n".as_bytes()).is_err());
+        let server = Server::start(Engine::new(), "127.0.0.1:0", Limits::default(), Some(keys))?;
+        let addr = server.addr;
+        let send = |key, method, path: &str, body| -> Result<String> {
+            let response = request_as(addr, key, method, path, body)?;
+            Ok(response[..12].to_string())
+        };
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5"}"#;
+        assert_eq!(
+            send(None, "POST", "/transactions", deposit)?,
+            "HTTP/1.1 401"
+        );
+        assert_eq!(send(Some("nope"), "GET", "/accounts", "")?, "HTTP/1.1 401");
+        assert_eq!(
+            send(Some("audit-key"), "POST", "/transactions", deposit)?,
+            "HTTP/1.1 403"
+        );
+        assert_eq!(
+            send(Some("submit-key"), "POST", "/transactions", deposit)?,
+            "HTTP/1.1 200"
+        );
+        assert_eq!(
+            send(Some("submit-key"), "GET", "/accounts", "")?,
+            "HTTP/1.1 200"
+        );
+        assert_eq!(
+            send(Some("audit-key"), "GET", "/accounts", "")?,
+            "HTTP/1.1 200"
+        );
+
+        let lock = "/accounts/1/lock?tx=900001";
+        assert_eq!(send(Some("submit-key"), "POST", lock, "")?, "HTTP/1.1 403");
+        assert_eq!(send(Some("audit-key"), "POST", lock, "")?, "HTTP/1.1 403");
+        let locked = request_as(addr, Some("admin-key"), "POST", lock, "")?;
+        assert!(locked.starts_with("HTTP/1.1 200 OK"), "{locked}");
+        assert!(locked.contains(r#""locked":true"#), "{locked}");
+        let missing = "/accounts/2/lock?tx=900002";
+        assert_eq!(
+            send(Some("admin-key"), "POST", missing, "")?,
+            "HTTP/1.1 404"
+        );
+        assert_eq!(
+            send(Some("admin-key"), "POST", "/accounts/1/lock", "")?,
+            "HTTP/1.1 400"
+        );
+        assert_eq!(send(Some("admin-key"), "GET", lock, "")?, "HTTP/1.1 405");
+        let unlock = "/accounts/1/unlock?tx=900003";
+        assert_eq!(send(Some("admin-key"), "POST", unlock, "")?, "HTTP/1.1 200");
+
+        assert_eq!(
+            send(Some("admin-key"), "GET", "/audit", "")?,
+            "HTTP/1.1 403"
+        );
+        let trail = request_as(addr, Some("audit-key"), "GET", "/audit?from=1", "")?;
+        assert!(trail.starts_with("HTTP/1.1 200 OK"), "{trail}");
+        assert!(!trail.contains(r#""event":"Locked""#), "{trail}");
+        assert!(
+            trail.contains(r#"{"client":1,"tx":900003,"event":"Unlocked"}"#),
+            "{trail}"
+        );
+
+        let engine = server.stop()?;
+        assert_eq!(engine.audit_trail().len(), 2);
+        assert!(!engine.account(1).expect("client 1").locked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_openapi() -> Result<()> {
+        let keys = read_api_keys("key,role\nk,admin\n".as_bytes())?;
+        let server = Server::start(Engine::new(), "127.0.0.1:0", Limits::default(), Some(keys))?;
+        let response = request(server.addr, "GET", "/openapi.json", "")?;
+        server.stop()?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "without a key");
+        let (_, body) = response.split_once("\r\n\r\n").expect("a body");
+        let document: serde_json::Value = serde_json::from_str(body)?;
+        let paths = document["paths"].as_object().expect("paths");
+        for (path, operations) in paths {
+            for method in operations.as_object().expect("operations").keys() {
+                let method = method.to_uppercase().parse()?;
+                let path = path.replace("{client}", "1");
+                assert!(Route::find(&method, &path).is_ok(), "{method} {path}");
+            }
+        }
+        assert_eq!(paths.len(), 7, "every route");
+        let codes = &document["components"]["schemas"]["Error"]["properties"]["code"]["enum"];
+        assert_eq!(codes.as_array().map(Vec::len), Some(Reason::ALL.len()));
+        assert_eq!(codes[0], "R001");
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! The net movement of funds per client over a batch, for settling with an
+//! upstream processor
+//!
+//! Only what the engine applied counts: deposits in, withdrawals and
+//! chargebacks out. Fees stay within the books and disputes only hold funds,
+//! so neither moves anything.
+//!
+//! The [Position] is where the engine stands as a whole: what it owes its
+//! clients, how much of that disputes hold, and what chargebacks have cost.
+use crate::client::Client;
+use crate::event::Event;
+use crate::transaction::ClientId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Movement {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub chargebacks: Decimal,
+}
+
+impl Movement {
+    /// What the client is owed, or owes when negative
+    pub fn net(&self) -> Decimal {
+        self.deposits - self.withdrawals - self.chargebacks
+    }
+}
+
+/// The movement of every client with any applied deposit, withdrawal or
+/// chargeback among `events`
+pub fn movements(events: &[Event]) -> BTreeMap<ClientId, Movement> {
+    let mut movements: BTreeMap<ClientId, Movement> = BTreeMap::new();
+    for event in events {
+        match event {
+            Event::DepositApplied { client, amount, .. } => {
+                movements.entry(*client).or_default().deposits += amount
+            }
+            Event::WithdrawalApplied { client, amount, .. } => {
+                movements.entry(*client).or_default().withdrawals += amount
+            }
+            Event::ChargebackApplied { client, amount, .. } => {
+                movements.entry(*client).or_default().chargebacks += amount
+            }
+            _ => {}
+        }
+    }
+    movements
+}
+
+/// The engine-wide settlement position, see [crate::Engine::position]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Position {
+    /// The total of every account, what the clients are owed
+    pub liabilities: Decimal,
+    /// The part of that held by open disputes
+    pub held: Decimal,
+    /// Everything chargebacks took back so far
+    pub chargeback_losses: Decimal,
+}
+
+impl Position {
+    pub(crate) fn add(&mut self, client: &Client) {
+        self.liabilities += client.total();
+        self.held += client.held();
+    }
+
+    pub(crate) fn remove(&mut self, client: &Client) {
+        self.liabilities -= client.total();
+        self.held -= client.held();
+    }
+
+    /// Writes the position as lines of the summary
+    /// ```text
+    /// customer liabilities: 3.5
+    /// held: 0
+    /// chargeback losses: 2.0
+    /// ```
+    pub fn write(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(w, "customer liabilities: {}", self.liabilities.round_dp(4))?;
+        writeln!(w, "held: {}", self.held.round_dp(4))?;
+        writeln!(
+            w,
+            "chargeback losses: {}",
+            self.chargeback_losses.round_dp(4)
+        )
+    }
+}
+
+/// Writes the movements, ordered by client
+/// ```text
+/// client, deposits, withdrawals, chargebacks, net
+/// 1, 5.0, 1.5, 0, 3.5
+/// ```
+pub fn write_movements(
+    mut w: impl io::Write,
+    movements: &BTreeMap<ClientId, Movement>,
+) -> io::Result<()> {
+    writeln!(w, "client, deposits, withdrawals, chargebacks, net")?;
+    for (client, movement) in movements {
+        writeln!(
+            w,
+            "{client}, {}, {}, {}, {}",
+            movement.deposits.round_dp(4),
+            movement.withdrawals.round_dp(4),
+            movement.chargebacks.round_dp(4),
+            movement.net().round_dp(4)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_movements() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.0
+deposit,         2,     2,         2.0
+withdrawal,      1,     3,         1.5
+withdrawal,      1,     4,        50.0
+dispute,         2,     2,
+chargeback,      2,     2,
+dispute,         1,     1,
+";
+        let mut engine = Engine::new();
+        engine.record_events();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let mut out = Vec::new();
+        write_movements(&mut out, &movements(engine.events()))?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client, deposits, withdrawals, chargebacks, net\n\
+             1, 5.0, 1.5, 0, 3.5\n\
+             2, 2.0, 0, 2.0, 0.0\n",
+            "the refused withdrawal and the open dispute move nothing"
+        );
+
+        let position = engine.position();
+        assert_eq!(position.liabilities, dec!(3.5));
+        assert_eq!(position.held, dec!(5.0), "client 1 disputes its deposit");
+        assert_eq!(position.chargeback_losses, dec!(2.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! `tte shard`, spreading a run over worker processes by client
+//!
+//! The input is split once into a file per shard, which only takes finding
+//! the client of each row, and every worker is this program running `report`
+//! on one of them. All of a client's transactions meet in one worker, so the
+//! workers need nothing from each other and their reports merge into one by
+//! client.
+use anyhow::{anyhow, bail, Context, Result};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use tte::transaction::{read_schema, SCHEMA_MARKER};
+use tte::{ClientId, ReaderOptions};
+
+/// The share `index` of `count` of the clients a consumer or worker applies
+/// transactions for, written `INDEX/COUNT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    pub fn owns(&self, client: ClientId) -> bool {
+        client % self.count == self.index
+    }
+}
+
+impl FromStr for Shard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let shard = s
+            .split_once('/')
+            .and_then(|(index, count)| {
+                Some(Shard {
+                    index: index.parse().ok()?,
+                    count: count.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| anyhow!("'{s}' is not INDEX/COUNT"))?;
+        if shard.index >= shard.count {
+            return Err(anyhow!(
+                "shard {} of {} does not exist",
+                shard.index,
+                shard.count
+            ));
+        }
+        Ok(shard)
+    }
+}
+
+/// Writes the rows of `csv` into a comma separated file per shard in `dir`,
+/// each with the header line and the schema the input declares, and returns
+/// the files
+pub fn split(
+    csv: impl io::Read,
+    options: &ReaderOptions,
+    dir: &Path,
+    count: u64,
+) -> Result<Vec<PathBuf>> {
+    let mut input = BufReader::new(csv);
+    let schema = read_schema(&mut input)?;
+    let mut rdr = options.schema_reader(input, schema);
+    let headers = rdr.byte_headers()?.clone();
+    let client = headers
+        .iter()
+        .position(|header| header == b"client")
+        .context("the input has no client column")?;
+    let files: Vec<PathBuf> = (0..count)
+        .map(|index| dir.join(format!("shard-{index}.csv")))
+        .collect();
+    let mut writers = files
+        .iter()
+        .map(|file| {
+            let mut file = File::create(file)?;
+            if let Some(schema) = schema {
+                writeln!(file, "{SCHEMA_MARKER}{schema}")?;
+            }
+            let mut writer = csv::Writer::from_writer(file);
+            writer.write_byte_record(&headers)?;
+            Ok(writer)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for record in rdr.byte_records() {
+        let record = record?;
+        // A row without a valid client goes to the first worker to report
+        let shard = std::str::from_utf8(record.get(client).unwrap_or_default())
+            .ok()
+            .and_then(|client| client.parse::<ClientId>().ok())
+            .map_or(0, |client| client % count);
+        writers[shard as usize].write_byte_record(&record)?;
+    }
+    for mut writer in writers {
+        writer.flush()?;
+    }
+    Ok(files)
+}
+
+/// Runs a worker of `report` with `args` on each of `files` at once and
+/// merges their reports
+pub fn run(files: &[PathBuf], args: &[OsString]) -> Result<String> {
+    let exe = std::env::current_exe().context("could not find the tte executable")?;
+    let workers = files
+        .iter()
+        .enumerate()
+        .map(|(index, file)| {
+            Command::new(&exe)
+                .arg("report")
+                .args(args)
+                .arg(file)
+                .stdout(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("could not start worker {index}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut reports = Vec::with_capacity(workers.len());
+    for (index, worker) in workers.into_iter().enumerate() {
+        let output = worker.wait_with_output()?;
+        if !output.status.success() {
+            bail!("worker {index} failed: {}", output.status);
+        }
+        reports.push(String::from_utf8(output.stdout)?);
+    }
+    merge(&reports)
+}
+
+/// Checks the sharded report against the one a single engine wrote for the
+/// same input, which has to be the same line for line
+pub fn verify(sharded: &str, single: &str) -> Result<()> {
+    let mut lines = sharded.lines().zip(single.lines());
+    if let Some((number, (sharded, single))) = (1..).zip(&mut lines).find(|(_, (a, b))| a != b) {
+        bail!(
+            "line {number} of the sharded report is '{sharded}' but '{single}' run in one process"
+        );
+    }
+    let (sharded, single) = (sharded.lines().count(), single.lines().count());
+    if sharded != single {
+        bail!("the sharded report has {sharded} lines but {single} run in one process");
+    }
+    Ok(())
+}
+
+/// One report of the clients each of `reports` owns, ordered by client.
+/// Accounts seeded with `--initial-accounts` are in every worker's report, so
+/// only the owner's row is kept.
+fn merge(reports: &[String]) -> Result<String> {
+    let count = reports.len() as u64;
+    let mut header = None;
+    let mut rows = Vec::new();
+    for (index, report) in (0..).zip(reports) {
+        let shard = Shard { index, count };
+        let mut lines = report.lines();
+        let Some(first) = lines.next() else {
+            continue;
+        };
+        header.get_or_insert(first);
+        for line in lines {
+            let client: ClientId = line
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid report line {line}"))?;
+            if shard.owns(client) {
+                rows.push((client, line));
+            }
+        }
+    }
+    rows.sort_by_key(|(client, _)| *client);
+    let mut merged = String::new();
+    for line in header
+        .into_iter()
+        .chain(rows.into_iter().map(|(_, line)| line))
+    {
+        merged.push_str(line);
+        merged.push('\n');
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let header = "client, available, held, total, locked";
+        let reports = [
+            format!("{header}\n2, 1, 0, 1, false\n3, 5, 0, 5, false\n4, 2, 0, 2, true\n"),
+            format!("{header}\n1, 7, 0, 7, false\n3, 5, 0, 5, false\n"),
+        ];
+        assert_eq!(
+            merge(&reports)?,
+            format!("{header}\n1, 7, 0, 7, false\n2, 1, 0, 1, false\n3, 5, 0, 5, false\n4, 2, 0, 2, true\n"),
+            "client 3, seeded in both, is kept once"
+        );
+
+        let dir = std::env::temp_dir().join(format!("tte-shard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let options = ReaderOptions {
+            delimiter: b';',
+            ..ReaderOptions::default()
+        };
+        let input =
+            "type; client; tx; amount\ndeposit; 1; 1; 1,5\ndeposit; 2; 2; 2\ndeposit; 3; 3; 3\n";
+        let files = split(input.as_bytes(), &options, &dir, 2)?;
+        assert_eq!(
+            std::fs::read_to_string(&files[0])?,
+            "type,client,tx,amount\ndeposit,2,2,2\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&files[1])?,
+            "type,client,tx,amount\ndeposit,1,1,\"1,5\"\ndeposit,3,3,3\n"
+        );
+        std::fs::remove_dir_all(&dir)?;
+
+        let report = format!("{header}\n1, 7, 0, 7, false\n");
+        assert!(verify(&report, &report).is_ok());
+        assert!(verify(&report, &format!("{header}\n1, 7, 0, 7, true\n")).is_err());
+        assert!(verify(&report, header).is_err());
+
+        assert!("2/2".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+        assert_eq!("0/1".parse::<Shard>()?, Shard { index: 0, count: 1 });
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! `report --sink`, writing the final balances straight into a database
+//!
+//! Only Postgres is supported. Every client's balances are upserted into a
+//! table with the report's columns, which must have a unique `client`:
+//! ```sql
+//! CREATE TABLE accounts (
+//!     client bigint PRIMARY KEY,
+//!     available numeric NOT NULL,
+//!     held numeric NOT NULL,
+//!     total numeric NOT NULL,
+//!     locked boolean NOT NULL
+//! );
+//! ```
+//! The connection is encrypted whenever the server offers TLS, checked
+//! against the system's trusted certificates, and `sslmode=require` in the URL
+//! refuses servers that do not.
+use anyhow::{bail, Context, Result};
+use postgres::types::ToSql;
+use postgres::Client;
+use rustls::{ClientConfig, RootCertStore};
+use std::sync::Arc;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tte::output::ReportSink;
+use tte::{Engine, Snapshot};
+
+/// Rows upserted per statement and transaction
+const BATCH: usize = 1000;
+
+/// `--sink postgres://...`, upserting the report into `table`
+pub struct Postgres {
+    pub url: String,
+    pub table: String,
+}
+
+impl ReportSink for Postgres {
+    fn write_report(&mut self, engine: &Engine) -> Result<()> {
+        write_postgres(&self.url, &self.table, &engine.snapshot())
+    }
+
+    fn persists(&self) -> bool {
+        true
+    }
+}
+
+/// Upserts every account in `snapshot` into `table` of the database at `url`,
+/// one transaction per batch of rows. A failed run leaves the batches before
+/// it in place, which a rerun simply overwrites.
+pub fn write_postgres(url: &str, table: &str, snapshot: &Snapshot) -> Result<()> {
+    check_table(table)?;
+    let mut db = Client::connect(url, tls()?).context("could not connect to the sink")?;
+    let rows: Vec<[String; 5]> = snapshot
+        .iter()
+        .map(|(client, account)| {
+            [
+                client.to_string(),
+                account.available.to_string(),
+                account.held.to_string(),
+                account.total.to_string(),
+                account.locked.to_string(),
+            ]
+        })
+        .collect();
+    for batch in rows.chunks(BATCH) {
+        let params: Vec<&(dyn ToSql + Sync)> = batch
+            .iter()
+            .flatten()
+            .map(|value| value as &(dyn ToSql + Sync))
+            .collect();
+        let mut transaction = db.transaction()?;
+        transaction
+            .execute(&upsert(table, batch.len()), &params)
+            .with_context(|| format!("could not upsert into {table}"))?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn tls() -> Result<MakeRustlsConnect> {
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    let (added, _) = roots.add_parsable_certificates(native.certs);
+    if added == 0 {
+        log::warn!("no trusted certificates found, TLS connections will fail");
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
+
+/// Table names end up in the SQL text, so only plain, optionally schema
+/// qualified, identifiers are accepted
+fn check_table(table: &str) -> Result<()> {
+    let plain = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if !table.split('.').all(plain) || table.split('.').count() > 2 {
+        bail!("'{table}' is not a valid table name");
+    }
+    Ok(())
+}
+
+/// The upsert of `rows` accounts. Values are bound as text and cast, which
+/// keeps the amounts exact.
+fn upsert(table: &str, rows: usize) -> String {
+    let values: Vec<String> = (0..rows)
+        .map(|row| {
+            let p = row * 5;
+            format!(
+                "(${}::text::bigint, ${}::text::numeric, ${}::text::numeric, ${}::text::numeric, ${}::text::boolean)",
+                p + 1,
+                p + 2,
+                p + 3,
+                p + 4,
+                p + 5
+            )
+        })
+        .collect();
+    format!(
+        "INSERT INTO {table} (client, available, held, total, locked) VALUES {} \
+         ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, \
+         held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked",
+        values.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert() {
+        assert_eq!(
+            upsert("reporting.accounts", 2),
+            "INSERT INTO reporting.accounts (client, available, held, total, locked) VALUES \
+             ($1::text::bigint, $2::text::numeric, $3::text::numeric, $4::text::numeric, $5::text::boolean), \
+             ($6::text::bigint, $7::text::numeric, $8::text::numeric, $9::text::numeric, $10::text::boolean) \
+             ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, \
+             held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked"
+        );
+        assert!(check_table("accounts").is_ok());
+        assert!(check_table("reporting.accounts_2022").is_ok());
+        assert!(check_table("accounts; DROP TABLE x").is_err());
+        assert!(check_table("a.b.c").is_err());
+        assert!(check_table("").is_err());
+        assert!(tls().is_ok());
+    }
+}
@@ -0,0 +1,193 @@
+//! Account snapshots, i.e. the accounts report read back in as data
+//!
+//! A [Snapshot] is what the engine reports at the end of a run and is also
+//! what `tte reconcile` reads from an expected accounts file, so the two can be
+//! compared client by client.
+use crate::transaction::ClientId;
+use anyhow::Result;
+use csv::Trim;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+
+/// The balances of a single client as they appear in the accounts report
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Account {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// All client accounts keyed and ordered by client id
+pub type Snapshot = BTreeMap<ClientId, Account>;
+
+#[derive(Deserialize)]
+struct Row {
+    client: ClientId,
+    #[serde(flatten)]
+    account: Account,
+}
+
+/// Reads an accounts report in the format written by
+/// [crate::Engine::write_report], skipping lines starting with `#` such as the
+/// mark of an incomplete report
+pub fn read_snapshot(csv: impl io::Read) -> Result<Snapshot> {
+    let rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(csv);
+    let mut snapshot = Snapshot::new();
+    for result in rdr.into_deserialize() {
+        let row: Row = result?;
+        snapshot.insert(row.client, row.account);
+    }
+    Ok(snapshot)
+}
+
+/// A single per-client difference between two snapshots. It displays as a
+/// change going from the left snapshot to the right one.
+/// ```text
+/// client 2: held 0 -> 2
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    /// The client only exists in the left snapshot
+    OnlyLeft(ClientId),
+    /// The client only exists in the right snapshot
+    OnlyRight(ClientId),
+    /// A field of the client differs between the two snapshots
+    Changed {
+        client: ClientId,
+        field: &'static str,
+        left: String,
+        right: String,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::OnlyLeft(client) => write!(f, "client {client}: removed"),
+            Difference::OnlyRight(client) => write!(f, "client {client}: added"),
+            Difference::Changed {
+                client,
+                field,
+                left,
+                right,
+            } => write!(f, "client {client}: {field} {left} -> {right}"),
+        }
+    }
+}
+
+/// Compares two snapshots and returns every difference ordered by client id.
+/// Amounts are compared by value so `2.0` and `2` are equal.
+pub fn compare(left: &Snapshot, right: &Snapshot) -> Vec<Difference> {
+    let clients: BTreeSet<&ClientId> = left.keys().chain(right.keys()).collect();
+    let mut differences = Vec::new();
+    for &client in clients {
+        match (left.get(&client), right.get(&client)) {
+            (Some(l), Some(r)) => {
+                let mut changed = |field, left: String, right: String| {
+                    differences.push(Difference::Changed {
+                        client,
+                        field,
+                        left,
+                        right,
+                    })
+                };
+                if l.available != r.available {
+                    changed(
+                        "available",
+                        l.available.to_string(),
+                        r.available.to_string(),
+                    );
+                }
+                if l.held != r.held {
+                    changed("held", l.held.to_string(), r.held.to_string());
+                }
+                if l.total != r.total {
+                    changed("total", l.total.to_string(), r.total.to_string());
+                }
+                if l.locked != r.locked {
+                    changed("locked", l.locked.to_string(), r.locked.to_string());
+                }
+            }
+            (Some(_), None) => differences.push(Difference::OnlyLeft(client)),
+            (None, Some(_)) => differences.push(Difference::OnlyRight(client)),
+            (None, None) => unreachable!(),
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const EXPECTED: &str = "\
+client, available, held, total, locked
+     1,       1.5,  0.0,   1.5,  false
+     2,       2.0,  0.0,   2.0,  false
+     3,         0,    0,     0,   true
+";
+
+    #[test]
+    fn test_read_snapshot() -> Result<()> {
+        let snapshot = read_snapshot(EXPECTED.as_bytes())?;
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(
+            snapshot[&1],
+            Account {
+                available: dec!(1.5),
+                held: dec!(0),
+                total: dec!(1.5),
+                locked: false,
+            }
+        );
+        assert!(snapshot[&3].locked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let left = read_snapshot(EXPECTED.as_bytes())?;
+        let right = read_snapshot(
+            "\
+client, available, held, total, locked
+1, 1.5, 0, 1.5, false
+2, 0, 2, 2, false
+4, 1, 0, 1, false
+"
+            .as_bytes(),
+        )?;
+        assert_eq!(
+            compare(&left, &right),
+            vec![
+                Difference::Changed {
+                    client: 2,
+                    field: "available",
+                    left: "2".to_string(),
+                    right: "0".to_string(),
+                },
+                Difference::Changed {
+                    client: 2,
+                    field: "held",
+                    left: "0".to_string(),
+                    right: "2".to_string(),
+                },
+                Difference::OnlyLeft(3),
+                Difference::OnlyRight(4),
+            ]
+        );
+        assert!(compare(&left, &left).is_empty());
+        assert_eq!(
+            compare(&left, &right)[1].to_string(),
+            "client 2: held 0 -> 2"
+        );
+        Ok(())
+    }
+}
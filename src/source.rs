@@ -0,0 +1,214 @@
+//! Where the transactions of a run come from
+//!
+//! The engine takes its transactions a batch at a time from a
+//! [TransactionSource] (see [crate::Engine::replay_source]), so a new kind of
+//! input only needs an implementation here. A CSV file, standard input or a
+//! URL is read by [crate::transaction::Transactions], and [Generator] makes
+//! transactions up for load tests and demos.
+use crate::transaction::{ClientId, TransType, Transaction, Transactions, TxId};
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::Decimal;
+use std::io;
+use std::str::FromStr;
+
+/// How many transactions a CSV source hands over at once
+const BATCH: usize = 1024;
+
+/// Transactions, a batch at a time
+pub trait TransactionSource {
+    /// The next transactions in order, or none once the source is exhausted
+    fn next_batch(&mut self) -> Result<Vec<Transaction>>;
+}
+
+impl<R: io::Read> TransactionSource for Transactions<R> {
+    fn next_batch(&mut self) -> Result<Vec<Transaction>> {
+        let mut batch = Vec::with_capacity(BATCH);
+        for result in self.by_ref().take(BATCH) {
+            batch.push(result?);
+        }
+        Ok(batch)
+    }
+}
+
+/// Made up transactions, the same for the same seed
+///
+/// Mostly deposits and withdrawals spread over the clients, with now and then
+/// a dispute of an earlier transaction, resolved or charged back right after. Written as a URI, with every part optional:
+/// ```text
+/// generate://?count=100000&clients=50&seed=7
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generator {
+    /// How many transactions to make, disputes and all
+    pub count: u64,
+    pub clients: ClientId,
+    pub seed: u64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            count: 1000,
+            clients: 10,
+            seed: 1,
+        }
+    }
+}
+
+impl Generator {
+    pub const SCHEME: &'static str = "generate://";
+
+    /// The transactions, a batch at a time
+    pub fn source(&self) -> Generated {
+        Generated {
+            spec: self.clone(),
+            rng: Rng::new(self.seed),
+            made: 0,
+            disputed: None,
+        }
+    }
+}
+
+/// A [Generator] spelled as in its docs
+impl FromStr for Generator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let query = s
+            .strip_prefix(Generator::SCHEME)
+            .ok_or_else(|| anyhow!("{s} does not start with {}", Generator::SCHEME))?;
+        let mut generator = Generator::default();
+        for pair in query.trim_start_matches('?').split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("{pair} is not key=value"))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("{key}={value} is not a number"))
+            };
+            match key {
+                "count" => generator.count = number()?,
+                "clients" => generator.clients = number()?,
+                "seed" => generator.seed = number()?,
+                _ => bail!("{key} is not count, clients or seed"),
+            }
+        }
+        if generator.clients == 0 {
+            bail!("clients=0 leaves nobody to make transactions for");
+        }
+        Ok(generator)
+    }
+}
+
+/// The transactions of a [Generator]
+pub struct Generated {
+    spec: Generator,
+    rng: Rng,
+    made: u64,
+    /// The transaction under dispute, if any, to settle next
+    disputed: Option<(ClientId, TxId)>,
+}
+
+impl Generated {
+    fn make(&mut self) -> Transaction {
+        self.made += 1;
+        let tx = TxId::from(self.made);
+        if let Some((client, disputed)) = self.disputed.take() {
+            let trans = match self.rng.below(10) {
+                0 => TransType::Chargeback,
+                _ => TransType::Resolve,
+            };
+            return Transaction::new(trans, client, disputed, None);
+        }
+        let client = 1 + self.rng.below(self.spec.clients);
+        match self.rng.below(1000) {
+            0..=4 if self.made > 1 => {
+                // Maybe the tx of a dispute or of another client, which the
+                // engine ignores like any such mistake in a real file
+                let earlier = TxId::from(1 + self.rng.below(self.made - 1));
+                self.disputed = Some((client, earlier));
+                Transaction::new(TransType::Dispute, client, earlier, None)
+            }
+            0..=299 => Transaction::new(TransType::Withdrawal, client, tx, Some(self.amount())),
+            _ => Transaction::new(TransType::Deposit, client, tx, Some(self.amount())),
+        }
+    }
+
+    /// From 0.0001 to 1000.0000
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(1 + self.rng.below(10_000_000) as i64, 4)
+    }
+}
+
+impl TransactionSource for Generated {
+    fn next_batch(&mut self) -> Result<Vec<Transaction>> {
+        let left = self.spec.count - self.made;
+        let len = left.min(BATCH as u64) as usize;
+        Ok((0..len).map(|_| self.make()).collect())
+    }
+}
+
+/// A xorshift generator, so the same seed makes the same transactions
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// From 0 to below `max`
+    fn below(&mut self, max: u64) -> u64 {
+        self.next() % max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+
+    #[test]
+    fn test_sources() -> Result<()> {
+        let generator: Generator = "generate://?count=2500&clients=3&seed=7".parse()?;
+        assert_eq!(generator.count, 2500);
+        assert!("generate://?clients=0".parse::<Generator>().is_err());
+        assert!("generate://?speed=7".parse::<Generator>().is_err());
+
+        let mut source = generator.source();
+        let mut all = Vec::new();
+        loop {
+            let batch = source.next_batch()?;
+            if batch.is_empty() {
+                break;
+            }
+            all.extend(batch);
+        }
+        assert_eq!(all.len(), 2500);
+        assert!(all.iter().all(|t| (1..=3).contains(&t.client)));
+        let mut again = generator.source();
+        assert_eq!(again.next_batch()?, all[..BATCH]);
+
+        let mut engine = Engine::new();
+        engine.replay_source(&mut generator.source(), None, || false)?;
+        assert_eq!(engine.iter_accounts().count(), 3);
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+        let mut transactions = read_csv(csv.as_bytes());
+        assert_eq!(transactions.next_batch()?.len(), 2);
+        assert!(transactions.next_batch()?.is_empty());
+        Ok(())
+    }
+}
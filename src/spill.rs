@@ -0,0 +1,193 @@
+//! Tx records moved out of memory under a cap
+//!
+//! Every deposit and withdrawal is remembered in case a dispute refers to it,
+//! and on a big file those records are most of what the engine holds. A
+//! [Spill] keeps only so many of them in memory and moves the least recently
+//! referenced out to files in a temporary directory, from where a dispute,
+//! resolve or chargeback loads its record back.
+//!
+//! The files are spread over [BUCKETS] by tx id and only ever appended to, so
+//! a lookup reads one bucket and takes the last line for the transaction.
+use crate::amount::{from_decimal, to_decimal};
+use crate::client::Clients;
+use crate::transaction::{parse_tx_id, ClientId, TxId};
+use anyhow::Result;
+use log::debug;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What one record is reckoned to take in memory, map overhead included
+pub const RECORD_BYTES: u64 = 64;
+
+/// How many files the spilled records are spread over
+pub const BUCKETS: u64 = 256;
+
+pub struct Spill {
+    dir: PathBuf,
+    max_records: usize,
+    in_memory: usize,
+    /// Records in memory, least recently referenced first. A record
+    /// referenced again is queued again, and [Spill::touched] tells which of
+    /// its entries counts.
+    recent: VecDeque<(ClientId, TxId, u64)>,
+    touched: HashMap<(ClientId, TxId), u64>,
+    seq: u64,
+}
+
+impl Spill {
+    /// A spill keeping the records in memory under `max_memory` bytes, going
+    /// by [RECORD_BYTES], in a directory of its own that is removed on drop
+    pub fn new(max_memory: u64) -> io::Result<Self> {
+        static SPILLS: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "tte-spill-{}-{}",
+            std::process::id(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(Spill {
+            dir,
+            max_records: (max_memory / RECORD_BYTES).max(1) as usize,
+            in_memory: 0,
+            recent: VecDeque::new(),
+            touched: HashMap::new(),
+            seq: 0,
+        })
+    }
+
+    /// Counts a record just added to the client's, spilling others if that
+    /// takes it over the cap
+    pub(crate) fn added(
+        &mut self,
+        clients: &mut Clients,
+        client: ClientId,
+        tx: TxId,
+    ) -> Result<()> {
+        self.in_memory += 1;
+        self.recent.push_back((client, tx, self.seq));
+        self.enforce(clients)
+    }
+
+    /// Makes sure the record of `tx` is in memory if it was ever spilled, and
+    /// counts it as referenced
+    pub(crate) fn load(&mut self, clients: &mut Clients, client: ClientId, tx: TxId) -> Result<()> {
+        let Some(account) = clients.get_mut(client) else {
+            return Ok(());
+        };
+        if !account.has_record(tx) {
+            let Some(amount) = self.find(client, tx)? else {
+                return Ok(());
+            };
+            debug!("  reloading spilled record tx:{tx}");
+            account.add_record(tx, from_decimal(amount))?;
+            self.in_memory += 1;
+        }
+        self.seq += 1;
+        self.recent.push_back((client, tx, self.seq));
+        self.touched.insert((client, tx), self.seq);
+        self.enforce(clients)
+    }
+
+    /// Spills the least recently referenced records once there are too many,
+    /// down to nine tenths of the cap so it does not happen on every record
+    fn enforce(&mut self, clients: &mut Clients) -> Result<()> {
+        if self.in_memory <= self.max_records {
+            return Ok(());
+        }
+        let target = self.max_records - self.max_records / 10;
+        let mut buckets: HashMap<TxId, String> = HashMap::new();
+        while self.in_memory > target {
+            let Some((client, tx, seq)) = self.recent.pop_front() else {
+                break;
+            };
+            if self
+                .touched
+                .get(&(client, tx))
+                .is_some_and(|&latest| latest != seq)
+            {
+                continue;
+            }
+            self.touched.remove(&(client, tx));
+            let Some(amount) = clients.get_mut(client).and_then(|c| c.take_record(tx)) else {
+                continue;
+            };
+            self.in_memory -= 1;
+            let line = buckets.entry(bucket(tx)).or_default();
+            let _ = writeln!(line, "{client},{tx},{}", to_decimal(amount));
+        }
+        debug!("  spilling records into {} buckets", buckets.len());
+        for (bucket, lines) in buckets {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(bucket))?
+                .write_all(lines.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The amount last spilled for `tx` of `client`
+    fn find(&self, client: ClientId, tx: TxId) -> io::Result<Option<Decimal>> {
+        let lines = match fs::read_to_string(self.path(bucket(tx))) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let amount = lines.lines().rev().find_map(|line| {
+            let mut fields = line.split(',');
+            let matches = fields.next()?.parse::<ClientId>().ok()? == client
+                && parse_tx_id(fields.next()?)? == tx;
+            matches.then(|| fields.next()?.parse().ok()).flatten()
+        });
+        Ok(amount)
+    }
+
+    fn path(&self, bucket: TxId) -> PathBuf {
+        self.dir.join(format!("{bucket}.csv"))
+    }
+}
+
+fn bucket(tx: TxId) -> TxId {
+    tx % TxId::from(BUCKETS)
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::read_csv;
+    use crate::Engine;
+
+    #[test]
+    fn test_spill() -> Result<()> {
+        let mut data = String::from("type,client,tx,amount\n");
+        for tx in 1..=50 {
+            data.push_str(&format!("deposit,{},{tx},{tx}.5\n", tx % 3));
+        }
+        data.push_str(
+            "dispute,1,1,\nchargeback,1,1,\ndispute,2,20,\ndispute,0,48,\nresolve,0,48,\n",
+        );
+        let mut plain = Engine::new();
+        plain.replay(read_csv(data.as_bytes()), None)?;
+
+        let mut engine = Engine::new();
+        engine.spill_records(10 * RECORD_BYTES)?;
+        engine.replay(read_csv(data.as_bytes()), None)?;
+        assert_eq!(engine.snapshot(), plain.snapshot());
+        let spill = engine.spill.as_ref().expect("spilling");
+        assert!(spill.in_memory <= spill.max_records);
+        assert!(spill.find(2, 20)?.is_some(), "reloaded, but still on disk");
+        assert_eq!(spill.find(2, 999)?, None);
+        Ok(())
+    }
+}
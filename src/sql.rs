@@ -0,0 +1,130 @@
+//! `tte sql`, SQL queries over the results of a run
+//!
+//! The final accounts, and optionally the transactions read, are loaded into
+//! an in-memory SQLite database as the tables
+//! `accounts(client, available, held, total, locked)` and
+//! `transactions(type, client, tx, amount, timestamp)`. `locked` is 0 or 1 so
+//! `WHERE locked` works. Amounts are SQLite reals, fine for exploring but not
+//! for exact sums.
+use anyhow::Result;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use std::io;
+use tte::{Engine, Transaction};
+
+const SCHEMA: &str = "
+CREATE TABLE accounts (
+    client INTEGER PRIMARY KEY,
+    available REAL,
+    held REAL,
+    total REAL,
+    locked INTEGER
+);
+CREATE TABLE transactions (
+    type TEXT,
+    client INTEGER,
+    tx INTEGER,
+    amount REAL,
+    timestamp TEXT
+);
+";
+
+/// Loads the accounts of `engine` and the given `transactions` into a new
+/// in-memory database. Ids and amounts go in as text and are converted by the
+/// column types, which leaves UUID tx ids as text.
+pub fn load(engine: &Engine, transactions: &[Transaction]) -> Result<Connection> {
+    let mut db = Connection::open_in_memory()?;
+    db.execute_batch(SCHEMA)?;
+    let batch = db.transaction()?;
+    {
+        let mut insert = batch.prepare("INSERT INTO accounts VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for view in engine.iter_accounts() {
+            insert.execute((
+                view.client.to_string(),
+                view.available.to_string(),
+                view.held.to_string(),
+                view.total.to_string(),
+                view.locked,
+            ))?;
+        }
+        let mut insert = batch.prepare("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for transaction in transactions {
+            insert.execute((
+                transaction.trans.as_str(),
+                transaction.client.to_string(),
+                transaction.tx.to_string(),
+                transaction.amount.map(|amount| amount.to_string()),
+                transaction
+                    .timestamp
+                    .map(|timestamp| timestamp.to_rfc3339()),
+            ))?;
+        }
+    }
+    batch.commit()?;
+    Ok(db)
+}
+
+/// Runs `sql` and writes the rows it returns as CSV with a header line
+pub fn query(db: &Connection, sql: &str, mut w: impl io::Write) -> Result<()> {
+    let mut statement = db.prepare(sql)?;
+    writeln!(w, "{}", statement.column_names().join(", "))?;
+    let columns = statement.column_count();
+    let mut rows = statement.query(())?;
+    while let Some(row) = rows.next()? {
+        let fields = (0..columns)
+            .map(|i| {
+                Ok(match row.get_ref(i)? {
+                    ValueRef::Null => String::new(),
+                    ValueRef::Integer(i) => i.to_string(),
+                    ValueRef::Real(f) => f.to_string(),
+                    ValueRef::Text(text) | ValueRef::Blob(text) => {
+                        String::from_utf8_lossy(text).into_owned()
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        writeln!(w, "{}", fields.join(", "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tte::read_csv;
+
+    #[test]
+    fn test_sql() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         5.5
+deposit,         2,     2,         7.0
+dispute,         2,     2,
+chargeback,      2,     2,
+";
+        let transactions = read_csv(DATA.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+        let mut engine = Engine::new();
+        engine.replay(read_csv(DATA.as_bytes()), None)?;
+        let db = load(&engine, &transactions)?;
+        let answer = |sql: &str| -> Result<String> {
+            let mut out = Vec::new();
+            query(&db, sql, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        assert_eq!(
+            answer("SELECT client, total FROM accounts WHERE locked")?,
+            "client, total\n2, 0\n"
+        );
+        assert_eq!(
+            answer("SELECT client, available FROM accounts WHERE NOT locked")?,
+            "client, available\n1, 5.5\n"
+        );
+        assert_eq!(
+            answer("SELECT type, count(*) AS n FROM transactions GROUP BY type ORDER BY type")?,
+            "type, n\nchargeback, 1\ndeposit, 2\ndispute, 1\n"
+        );
+        assert!(answer("SELECT * FROM nowhere").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! `report --output-template`, rendering the report with a Tera template
+//!
+//! The template sees `accounts`, each with `client`, `available`, `held`,
+//! `total` and `locked`, in client order, and `totals` with the sums of the
+//! balances. Amounts are strings so they stay exact. Nothing is escaped, since
+//! the output is text rather than HTML.
+//!
+//! Besides the Tera built-ins there is `pad(width, fill=" ", align="right")`
+//! for fixed-width records. A value longer than its width is an error rather
+//! than cut short.
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use tera::Tera;
+use tte::Engine;
+
+const NAME: &str = "report";
+
+pub struct Template {
+    tera: Tera,
+}
+
+#[derive(Serialize)]
+struct Account {
+    client: u64,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+#[derive(Default)]
+struct Totals {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+impl Template {
+    /// Compiles a template, so mistakes show before the run rather than after
+    pub fn new(source: &str) -> Result<Self> {
+        let mut tera = Tera::default();
+        tera.autoescape_on(Vec::new());
+        tera.register_filter("pad", pad);
+        tera.add_raw_template(NAME, source)
+            .context("invalid template")?;
+        Ok(Template { tera })
+    }
+
+    pub fn render(&self, engine: &Engine, w: &mut dyn io::Write) -> io::Result<()> {
+        let mut totals = Totals::default();
+        let accounts: Vec<Account> = engine
+            .snapshot()
+            .into_iter()
+            .map(|(client, account)| {
+                totals.available += account.available;
+                totals.held += account.held;
+                totals.total += account.total;
+                Account {
+                    client,
+                    available: account.available.to_string(),
+                    held: account.held.to_string(),
+                    total: account.total.to_string(),
+                    locked: account.locked,
+                }
+            })
+            .collect();
+        let mut context = tera::Context::new();
+        context.insert("accounts", &accounts);
+        context.insert(
+            "totals",
+            &HashMap::from([
+                ("available", totals.available.to_string()),
+                ("held", totals.held.to_string()),
+                ("total", totals.total.to_string()),
+            ]),
+        );
+        self.tera
+            .render_to(NAME, &context, w)
+            .map_err(io::Error::other)
+    }
+}
+
+fn pad(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    let width = args
+        .get("width")
+        .and_then(Value::as_u64)
+        .ok_or("pad needs a width")? as usize;
+    let fill = match args.get("fill").and_then(Value::as_str) {
+        None => ' ',
+        Some(fill) => {
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(fill), None) => fill,
+                _ => return Err("pad fills with a single character".into()),
+            }
+        }
+    };
+    let length = text.chars().count();
+    if length > width {
+        return Err(format!("'{text}' does not fit in {width} characters").into());
+    }
+    let padding: String = std::iter::repeat_n(fill, width - length).collect();
+    Ok(Value::String(
+        match args.get("align").and_then(Value::as_str) {
+            None | Some("right") => format!("{padding}{text}"),
+            Some("left") => format!("{text}{padding}"),
+            Some(align) => return Err(format!("pad cannot align {align}").into()),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tte::read_csv;
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.replay(
+            read_csv("type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,10,2,20.25\n".as_bytes()),
+            None,
+        )?;
+        let template = Template::new(
+            "{% for a in accounts %}{{ a.client | pad(width=4, fill=\"0\") }}\
+             {{ a.total | pad(width=8) }}{{ a.locked | pad(width=6, align=\"left\") }}|\n\
+             {% endfor %}total {{ totals.total }}\n",
+        )?;
+        let mut out = Vec::new();
+        template.render(&engine, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "0002     1.5false |\n0010   20.25false |\ntotal 21.75\n"
+        );
+
+        let too_narrow =
+            Template::new("{% for a in accounts %}{{ a.total | pad(width=3) }}{% endfor %}")?;
+        assert!(too_narrow.render(&engine, &mut Vec::new()).is_err());
+        assert!(Template::new("{% for %}").is_err());
+        Ok(())
+    }
+}
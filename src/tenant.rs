@@ -0,0 +1,132 @@
+//! Accounts kept apart per tenant
+//!
+//! [Tenants] holds an [Engine] for every tenant, all with the same policies,
+//! so one process can serve many tenants without their clients meeting:
+//! client 1 of one tenant and client 1 of another are different accounts, and
+//! a dispute never finds a transaction of another tenant.
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::snapshot::Snapshot;
+use crate::transaction::Transaction;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io;
+
+/// The placeholder for the tenant in a file name pattern
+pub const PLACEHOLDER: &str = "{tenant}";
+
+#[derive(Default)]
+pub struct Tenants {
+    config: Config,
+    engines: BTreeMap<String, Engine>,
+}
+
+impl Tenants {
+    /// Tenants whose engines apply the policies in `config`
+    pub fn new(config: Config) -> Self {
+        Tenants {
+            config,
+            engines: BTreeMap::new(),
+        }
+    }
+
+    /// The engine of `tenant`, started on first use
+    pub fn engine(&mut self, tenant: &str) -> &mut Engine {
+        if !self.engines.contains_key(tenant) {
+            let engine = Engine::with_config(self.config.clone());
+            self.engines.insert(tenant.to_string(), engine);
+        }
+        self.engines.get_mut(tenant).expect("just inserted")
+    }
+
+    pub fn apply(&mut self, tenant: &str, transaction: Transaction) -> Result<()> {
+        self.engine(tenant).apply(transaction)
+    }
+
+    /// Every tenant with its engine, in tenant order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Engine)> {
+        self.engines
+            .iter()
+            .map(|(tenant, engine)| (tenant.as_str(), engine))
+    }
+
+    /// The accounts of every tenant
+    pub fn snapshots(&self) -> BTreeMap<&str, Snapshot> {
+        self.iter()
+            .map(|(tenant, engine)| (tenant, engine.snapshot()))
+            .collect()
+    }
+
+    /// Writes the accounts report of every tenant as one, led by a `tenant`
+    /// column
+    /// ```text
+    /// tenant, client, available, held, total, locked
+    /// acme, 1, 1.5, 0, 1.5, false
+    /// globex, 1, 20, 0, 20, false
+    /// ```
+    pub fn write_report(&self, mut w: impl io::Write) -> io::Result<()> {
+        // Every engine has the same config, and so the same columns
+        let columns = self
+            .engines
+            .values()
+            .next()
+            .map(Engine::extra_columns)
+            .unwrap_or_default();
+        writeln!(w, "tenant, client, available, held, total, locked{columns}")?;
+        for (tenant, engine) in self.iter() {
+            engine.write_report_rows(&mut w, &format!("{tenant}, "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The tenant a file name holds according to `pattern`, e.g. `acme` for
+/// `acme_2022-03-21.csv` and `{tenant}_2022-03-21.csv`, or `None` if the name
+/// does not fit the pattern
+pub fn tenant_from_name<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let (prefix, suffix) = pattern.split_once(PLACEHOLDER)?;
+    let tenant = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    (!tenant.is_empty()).then_some(tenant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransType;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_tenants() -> Result<()> {
+        let mut tenants = Tenants::default();
+        let deposit = |tx| Transaction::new(TransType::Deposit, 1, tx, Some(Decimal::new(15, 1)));
+        tenants.apply("globex", deposit(1))?;
+        tenants.apply("acme", deposit(1))?;
+        tenants.apply("acme", Transaction::new(TransType::Dispute, 1, 1, None))?;
+        assert_eq!(
+            tenants.snapshots()["globex"][&1].held,
+            Decimal::ZERO,
+            "the dispute is acme's"
+        );
+        let mut out = Vec::new();
+        tenants.write_report(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "tenant, client, available, held, total, locked\n\
+             acme, 1, 0.0, 1.5, 1.5, false\n\
+             globex, 1, 1.5, 0, 1.5, false\n"
+        );
+
+        let pattern = "{tenant}_transactions.csv";
+        assert_eq!(
+            tenant_from_name(pattern, "acme_transactions.csv"),
+            Some("acme")
+        );
+        assert_eq!(tenant_from_name(pattern, "_transactions.csv"), None);
+        assert_eq!(tenant_from_name(pattern, "acme.csv"), None);
+        assert_eq!(
+            tenant_from_name("transactions.csv", "transactions.csv"),
+            None
+        );
+        Ok(())
+    }
+}
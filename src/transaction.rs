@@ -0,0 +1,1157 @@
+//! Transaction records and the CSV reader that produces them
+use crate::scan::Scanner;
+use chrono::{DateTime, Utc};
+use csv::{StringRecord, Trim};
+use memchr::memchr;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+
+/// Client account identifier. Historically a `u16`, which is still enforced in
+/// legacy mode (see [ReaderOptions::legacy_ids]).
+pub type ClientId = u64;
+
+/// Transaction identifier
+#[cfg(not(feature = "uuid"))]
+pub type TxId = u64;
+
+/// Transaction identifier. With the `uuid` feature the `tx` column may also
+/// hold a UUID such as `67e55044-10b1-426f-9247-bb680e5fe0c8`, which is kept as
+/// its 128 bit value so numeric and UUID ids share one key type.
+#[cfg(feature = "uuid")]
+pub type TxId = u128;
+
+/// Parses a `tx` id as written in the input
+#[cfg(not(feature = "uuid"))]
+pub fn parse_tx_id(raw: &str) -> Option<TxId> {
+    raw.parse().ok()
+}
+
+/// Parses a `tx` id as written in the input, either an integer or a UUID
+#[cfg(feature = "uuid")]
+pub fn parse_tx_id(raw: &str) -> Option<TxId> {
+    raw.parse()
+        .ok()
+        .or_else(|| uuid::Uuid::parse_str(raw).ok().map(|id| id.as_u128()))
+}
+
+#[cfg(feature = "uuid")]
+pub(crate) fn deserialize_tx<'de, D>(deserializer: D) -> Result<TxId, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+    parse_tx_id(&raw)
+        .ok_or_else(|| serde::de::Error::custom(format!("tx {raw} is not an integer or UUID")))
+}
+
+/// The `type` column. Any type other than the five built in ones is kept by
+/// name as [TransType::Other] and is only applied by a handler registered for
+/// it, see [crate::handler].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TransType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Opens the client's account, see [crate::Config::strict_onboarding]
+    OpenAccount,
+    /// Closes the client's account to any further transaction
+    CloseAccount,
+    Other(String),
+}
+
+impl TransType {
+    /// The name as written in the `type` column
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransType::Deposit => "deposit",
+            TransType::Withdrawal => "withdrawal",
+            TransType::Dispute => "dispute",
+            TransType::Resolve => "resolve",
+            TransType::Chargeback => "chargeback",
+            TransType::OpenAccount => "open_account",
+            TransType::CloseAccount => "close_account",
+            TransType::Other(name) => name,
+        }
+    }
+}
+
+impl TransType {
+    /// Only accepts the canonical lowercase names, anything else is kept as
+    /// [TransType::Other]. See [ReaderOptions::strict_types].
+    pub fn strict(name: &str) -> TransType {
+        match TransType::from(name) {
+            trans if trans.as_str() == name => trans,
+            _ => TransType::Other(name.to_string()),
+        }
+    }
+}
+
+/// Reads a type name ignoring case, `_`, `-` and spaces, and accepting a few
+/// common aliases such as `withdraw`. Unknown names are kept as written.
+impl From<&str> for TransType {
+    fn from(name: &str) -> Self {
+        let key: String = name
+            .chars()
+            .filter(|c| !matches!(c, '_' | '-' | ' '))
+            .collect::<String>()
+            .to_lowercase();
+        match key.as_str() {
+            "deposit" => TransType::Deposit,
+            "withdrawal" | "withdraw" | "withdrawl" => TransType::Withdrawal,
+            "dispute" => TransType::Dispute,
+            "resolve" => TransType::Resolve,
+            "chargeback" => TransType::Chargeback,
+            "openaccount" => TransType::OpenAccount,
+            "closeaccount" => TransType::CloseAccount,
+            _ => TransType::Other(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TransType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for TransType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        if name.is_empty() {
+            return Err(serde::de::Error::custom("missing transaction type"));
+        }
+        Ok(TransType::from(name.as_str()))
+    }
+}
+
+/// [Transaction] is a struct used by [serde] and [csv] to deserialize the
+/// input CSV data into fields that can be acted upon.
+///
+/// The optional `timestamp` column holds an RFC 3339 time and is only needed
+/// for point-in-time reports (`report --as-of <timestamp>`).
+///
+/// Transactions the engine makes up itself, such as accrued interest, are
+/// serialized back out with [write_csv] so they can join the input.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub trans: TransType,
+    pub client: ClientId,
+    #[cfg_attr(feature = "uuid", serde(deserialize_with = "deserialize_tx"))]
+    pub tx: TxId,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl Transaction {
+    pub fn new(
+        trans: TransType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+    ) -> Transaction {
+        Transaction {
+            trans,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+        }
+    }
+}
+
+/// Parses an amount from its exact decimal text rather than going through a
+/// float, rejecting scientific notation such as `1e10`
+pub fn parse_amount(raw: &str) -> Result<Decimal, String> {
+    if raw.contains(['e', 'E']) {
+        return Err(format!("amount {raw} is in scientific notation"));
+    }
+    Decimal::from_str(raw).map_err(|e| format!("amount {raw} is invalid: {e}"))
+}
+
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(raw) if !raw.is_empty() => parse_amount(&raw)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// The most fractional digits an amount may carry unless configured otherwise
+pub const DEFAULT_MAX_PRECISION: u32 = 4;
+
+/// Errors produced while reading transactions
+#[derive(Debug)]
+pub enum ReadError {
+    /// The row could not be read or deserialized
+    Csv(csv::Error),
+    /// The amount has more fractional digits than allowed
+    Precision {
+        line: u64,
+        amount: Decimal,
+        max: u32,
+    },
+    /// The client id is out of range for legacy `u16` ids
+    LegacyClientId { line: u64, client: ClientId },
+    /// The row has a different number of fields than the header, as found by
+    /// [crate::scan]
+    Fields {
+        line: u64,
+        found: usize,
+        expected: usize,
+    },
+    /// The file declares a [Schema] this version cannot read, or lacks the
+    /// columns of the one it declares
+    Schema(String),
+    /// A row of a v2 file is in another currency than the rows before it
+    Currency {
+        line: u64,
+        found: String,
+        expected: String,
+    },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Csv(e) => write!(f, "{e}"),
+            ReadError::Precision { line, amount, max } => write!(
+                f,
+                "line {line}: amount {amount} has more than {max} decimal places"
+            ),
+            ReadError::LegacyClientId { line, client } => write!(
+                f,
+                "line {line}: client {client} is out of range for legacy u16 ids"
+            ),
+            ReadError::Fields {
+                line,
+                found,
+                expected,
+            } => write!(f, "line {line}: found {found} fields, expected {expected}"),
+            ReadError::Schema(message) => write!(f, "{message}"),
+            ReadError::Currency {
+                line,
+                found,
+                expected,
+            } => write!(
+                f,
+                "line {line}: amount in {found}, the file is in {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<csv::Error> for ReadError {
+    fn from(e: csv::Error) -> Self {
+        ReadError::Csv(e)
+    }
+}
+
+/// The column order assumed for files without a header line
+pub const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// What the first line of a file declaring its [Schema] starts with
+pub const SCHEMA_MARKER: &str = "#schema=";
+
+/// The version of the transactions CSV layout
+///
+/// A file declares it on a first line such as `#schema=v2`, which is
+/// otherwise skipped like any line starting with `#` in such a file. Files
+/// without one are v1, the `type,client,tx,amount` columns with an optional
+/// `timestamp`. v2 adds the `currency` and `timestamp` columns, both required.
+/// Balances have no currency, so every row of a v2 file that names one must
+/// name the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Schema {
+    #[default]
+    V1,
+    V2,
+}
+
+impl Schema {
+    /// The columns of the schema, in the order assumed without a header line
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Schema::V1 => &DEFAULT_COLUMNS,
+            Schema::V2 => &["type", "client", "tx", "amount", "currency", "timestamp"],
+        }
+    }
+
+    /// Fails unless `headers` name every column v2 requires. v1 files are left
+    /// to fail row by row, as they always have.
+    pub fn check(&self, headers: &StringRecord) -> Result<(), ReadError> {
+        if *self == Schema::V1 {
+            return Ok(());
+        }
+        let missing: Vec<&str> = self
+            .columns()
+            .iter()
+            .copied()
+            .filter(|column| !headers.iter().any(|h| h == *column))
+            .collect();
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(ReadError::Schema(format!(
+                "schema {self} needs the column(s) {}",
+                missing.join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schema::V1 => write!(f, "v1"),
+            Schema::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+impl FromStr for Schema {
+    type Err = ReadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(Schema::V1),
+            "v2" => Ok(Schema::V2),
+            _ => Err(ReadError::Schema(format!(
+                "schema {s} is not supported, only v1 and v2 are"
+            ))),
+        }
+    }
+}
+
+/// The [Schema] declared by the first line of `input`, which is left unread
+/// for the reader to skip, or None if the file declares none
+pub fn read_schema(input: &mut impl BufRead) -> Result<Option<Schema>, ReadError> {
+    let buf = input.fill_buf().map_err(csv::Error::from)?;
+    let Some(rest) = buf.strip_prefix(SCHEMA_MARKER.as_bytes()) else {
+        return Ok(None);
+    };
+    let end = memchr(b'\n', rest).unwrap_or(rest.len());
+    String::from_utf8_lossy(&rest[..end])
+        .trim()
+        .parse()
+        .map(Some)
+}
+
+/// Holds a v2 file to the one currency its `currency` column first names
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Currency {
+    column: Option<usize>,
+    seen: Option<String>,
+}
+
+impl Currency {
+    pub(crate) fn new(headers: &StringRecord, schema: Schema) -> Self {
+        Currency {
+            column: match schema {
+                Schema::V1 => None,
+                Schema::V2 => headers.iter().position(|h| h == "currency"),
+            },
+            seen: None,
+        }
+    }
+
+    /// Fails if `record` names another currency than the rows before it. Rows
+    /// without one, such as disputes, are in any.
+    pub(crate) fn check(&mut self, record: &StringRecord) -> Result<(), ReadError> {
+        let Some(found) = self
+            .column
+            .and_then(|i| record.get(i))
+            .filter(|currency| !currency.is_empty())
+        else {
+            return Ok(());
+        };
+        match &self.seen {
+            None => self.seen = Some(found.to_string()),
+            Some(expected) if expected != found => {
+                return Err(ReadError::Currency {
+                    line: record.position().map_or(0, |p| p.line()),
+                    found: found.to_string(),
+                    expected: expected.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// How the transactions CSV is laid out
+///
+/// By default the first line is a header and columns are matched to
+/// [Transaction] fields by name. Files without a header, or with columns named
+/// differently, can instead give the column names by position. Columns named
+/// anything other than a [Transaction] field are ignored.
+///
+/// The delimiter, quoting and decimal separator can be changed for exports
+/// such as the semicolon delimited, decimal comma files common in Europe.
+#[derive(Debug, Clone)]
+pub struct ReaderOptions {
+    /// Whether the first line is a header line
+    pub has_headers: bool,
+    /// Column names by position, overriding any header line
+    pub columns: Option<Vec<String>>,
+    /// The field delimiter
+    pub delimiter: u8,
+    /// The quote character
+    pub quote: u8,
+    /// Whether quotes are special at all. When false they are kept verbatim.
+    pub quoting: bool,
+    /// Amounts use a comma as the decimal separator, e.g. `"1,50"`
+    pub decimal_comma: bool,
+    /// Reject amounts with more fractional digits than this. Trailing zeros
+    /// are not counted. `None` accepts any precision.
+    pub max_precision: Option<u32>,
+    /// Reject client ids that do not fit the legacy `u16` range
+    pub legacy_ids: bool,
+    /// Only take the canonical lowercase type names, e.g. `deposit` but not
+    /// `Deposit` or `withdraw`. Other spellings are unknown types.
+    pub strict_types: bool,
+    /// Read a plain `type,client,tx,amount` file with [crate::scan]. Other
+    /// files are read as usual.
+    pub fast_parse: bool,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions {
+            has_headers: true,
+            columns: None,
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            decimal_comma: false,
+            max_precision: Some(DEFAULT_MAX_PRECISION),
+            legacy_ids: false,
+            strict_types: false,
+            fast_parse: false,
+        }
+    }
+}
+
+impl ReaderOptions {
+    /// Builds a [csv::Reader] configured with these options
+    pub fn reader<R: io::Read>(&self, csv: R) -> csv::Reader<R> {
+        self.schema_reader(csv, None)
+    }
+
+    /// Same as [ReaderOptions::reader] for a file that declared `schema`, as
+    /// found by [read_schema], skipping the lines starting with `#`
+    pub fn schema_reader<R: io::Read>(&self, csv: R, schema: Option<Schema>) -> csv::Reader<R> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quoting(self.quoting)
+            .comment(schema.map(|_| b'#'))
+            .from_reader(csv);
+        let columns = match (&self.columns, self.has_headers) {
+            (Some(columns), true) => {
+                // Skip past the header line before replacing it. A read error
+                // here resurfaces on the first record read.
+                let _ = rdr.byte_headers();
+                StringRecord::from(columns.clone())
+            }
+            (Some(columns), false) => StringRecord::from(columns.clone()),
+            (None, false) => StringRecord::from(schema.unwrap_or_default().columns().to_vec()),
+            (None, true) => return rdr,
+        };
+        rdr.set_headers(columns);
+        rdr
+    }
+
+    /// Deserializes a single record read by [ReaderOptions::reader] into a
+    /// [Transaction], first rewriting the amount into the canonical format
+    pub fn decode(
+        &self,
+        record: &StringRecord,
+        headers: &StringRecord,
+    ) -> Result<Transaction, ReadError> {
+        self.decode_projected(record, headers, Columns::find(headers).as_ref())
+    }
+
+    /// Same as [ReaderOptions::decode], trying the fields at `columns` by hand
+    /// before going through [serde]
+    fn decode_projected(
+        &self,
+        record: &StringRecord,
+        headers: &StringRecord,
+        columns: Option<&Columns>,
+    ) -> Result<Transaction, ReadError> {
+        let transaction = match columns
+            .and_then(|columns| columns.fields(record))
+            .and_then(|fields| self.project(&fields))
+        {
+            Some(transaction) => transaction,
+            None => self.deserialize(record, headers)?,
+        };
+        let raw_type = match self.strict_types {
+            true => headers
+                .iter()
+                .position(|h| h == "type")
+                .and_then(|i| record.get(i)),
+            false => None,
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        self.check(transaction, raw_type, line)
+    }
+
+    /// Applies the options that can refuse a transaction once read, taking
+    /// `raw_type` as written for [ReaderOptions::strict_types]
+    pub(crate) fn check(
+        &self,
+        mut transaction: Transaction,
+        raw_type: Option<&str>,
+        line: u64,
+    ) -> Result<Transaction, ReadError> {
+        if let Some(raw) = raw_type.filter(|_| self.strict_types) {
+            transaction.trans = TransType::strict(raw);
+        }
+        if self.legacy_ids && transaction.client > ClientId::from(u16::MAX) {
+            return Err(ReadError::LegacyClientId {
+                line,
+                client: transaction.client,
+            });
+        }
+        match (transaction.amount, self.max_precision) {
+            (Some(amount), Some(max)) if amount.normalize().scale() > max => {
+                Err(ReadError::Precision { line, amount, max })
+            }
+            _ => Ok(transaction),
+        }
+    }
+
+    /// The transaction in `fields`, or None for serde to find out what is
+    /// wrong with it. The amount of a dispute, resolve or chargeback is
+    /// usually empty: it is not looked at for a resolve or chargeback, which
+    /// have no use for it, and only parsed for a partial dispute. Serde is
+    /// given the same rows without it, see [ReaderOptions::deserialize].
+    pub(crate) fn project(&self, fields: &Fields) -> Option<Transaction> {
+        let trans = match fields.trans {
+            "" => return None,
+            name => TransType::from(name),
+        };
+        let client = fields.client.parse().ok()?;
+        let tx = parse_tx_id(fields.tx)?;
+        let amount = match (&trans, fields.amount) {
+            (TransType::Resolve | TransType::Chargeback, _) | (_, "") => None,
+            (_, raw) if self.decimal_comma => Some(parse_amount(&raw.replace(',', ".")).ok()?),
+            (_, raw) => Some(parse_amount(raw).ok()?),
+        };
+        let timestamp = match fields.timestamp {
+            None | Some("") => None,
+            Some(raw) => Some(raw.parse().ok()?),
+        };
+        Some(Transaction {
+            trans,
+            client,
+            tx,
+            amount,
+            timestamp,
+        })
+    }
+
+    /// Deserializes `record` with serde, leaving out the amount of a resolve
+    /// or chargeback as [ReaderOptions::project] does, so a row reads the
+    /// same whichever decodes it
+    fn deserialize(
+        &self,
+        record: &StringRecord,
+        headers: &StringRecord,
+    ) -> csv::Result<Transaction> {
+        let column = |name| headers.iter().position(|h| h == name);
+        let unused = column("type")
+            .and_then(|i| record.get(i))
+            .map(|raw| TransType::from(raw.trim()))
+            .is_some_and(|trans| matches!(trans, TransType::Resolve | TransType::Chargeback));
+        match column("amount") {
+            Some(amount) if unused || self.decimal_comma => {
+                let mut fixed: StringRecord = record
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| match i == amount {
+                        true if unused => String::new(),
+                        true => field.replace(',', "."),
+                        false => field.to_string(),
+                    })
+                    .collect();
+                fixed.set_position(record.position().cloned());
+                fixed.deserialize(Some(headers))
+            }
+            _ => record.deserialize(Some(headers)),
+        }
+    }
+}
+
+/// Where the [Transaction] fields are in a record, for decoding them without
+/// going through [serde]
+#[derive(Debug, Clone)]
+struct Columns {
+    trans: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+    /// None unless `headers` name every field but the optional timestamp
+    fn find(headers: &StringRecord) -> Option<Columns> {
+        let column = |name| headers.iter().position(|h| h == name);
+        Some(Columns {
+            trans: column("type")?,
+            client: column("client")?,
+            tx: column("tx")?,
+            amount: column("amount")?,
+            timestamp: column("timestamp"),
+        })
+    }
+
+    fn fields<'r>(&self, record: &'r StringRecord) -> Option<Fields<'r>> {
+        Some(Fields {
+            trans: record.get(self.trans)?,
+            client: record.get(self.client)?,
+            tx: record.get(self.tx)?,
+            amount: record.get(self.amount)?,
+            timestamp: self.timestamp.and_then(|i| record.get(i)),
+        })
+    }
+}
+
+/// The raw, trimmed [Transaction] fields of one row
+pub(crate) struct Fields<'a> {
+    pub trans: &'a str,
+    pub client: &'a str,
+    pub tx: &'a str,
+    pub amount: &'a str,
+    pub timestamp: Option<&'a str>,
+}
+
+/// Iterator over the [Transaction]s of a CSV file. See [read_csv_with].
+pub struct Transactions<R> {
+    source: Source<BufReader<R>>,
+    headers: StringRecord,
+    columns: Option<Columns>,
+    /// A failure reading the header line, returned as the first item
+    header_error: Option<ReadError>,
+    /// The file declares a schema it does not follow, so nothing is read
+    unreadable: bool,
+    currency: Currency,
+    options: ReaderOptions,
+}
+
+/// Where the rows come from, the [csv] reader or the quicker [Scanner]
+enum Source<R> {
+    Csv(csv::StringRecordsIntoIter<R>),
+    Scan(Scanner<R>),
+}
+
+impl<R: io::Read> Iterator for Transactions<R> {
+    type Item = Result<Transaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.header_error.take() {
+            self.unreadable = matches!(e, ReadError::Schema(_));
+            return Some(Err(e));
+        }
+        if self.unreadable {
+            return None;
+        }
+        let records = match &mut self.source {
+            Source::Csv(records) => records,
+            Source::Scan(scanner) => return scanner.next(),
+        };
+        let record = match records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if let Err(e) = self.currency.check(&record) {
+            return Some(Err(e));
+        }
+        Some(
+            self.options
+                .decode_projected(&record, &self.headers, self.columns.as_ref()),
+        )
+    }
+}
+
+impl<R: io::Read> Transactions<R> {
+    /// Where the next transaction starts in the input
+    pub fn input_position(&self) -> csv::Position {
+        match &self.source {
+            Source::Csv(records) => records.reader().position().clone(),
+            Source::Scan(scanner) => scanner.position().clone(),
+        }
+    }
+}
+
+impl<R: io::Read + io::Seek> Transactions<R> {
+    /// Carries on reading from `position`, as returned by
+    /// [Transactions::input_position] on the same input
+    pub fn seek(&mut self, position: csv::Position) -> csv::Result<()> {
+        match &mut self.source {
+            Source::Csv(records) => records.reader_mut().seek(position),
+            Source::Scan(scanner) => Ok(scanner.seek(position)?),
+        }
+    }
+}
+
+/// Writes transactions as CSV with a `type,client,tx,amount,timestamp` header,
+/// readable again by [read_csv]
+pub fn write_csv(w: impl io::Write, transactions: &[Transaction]) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    for transaction in transactions {
+        wtr.serialize(transaction)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn read_csv<R: io::Read>(csv: R) -> Transactions<R> {
+    read_csv_with(csv, &ReaderOptions::default())
+}
+
+/// Same as [read_csv] but with the CSV layout given by `options`
+///
+/// A file declaring a [Schema] on its first line is read as that schema. One
+/// declaring a schema it cannot read fails on the first item.
+pub fn read_csv_with<R: io::Read>(csv: R, options: &ReaderOptions) -> Transactions<R> {
+    let mut input = BufReader::new(csv);
+    let schema = match read_schema(&mut input) {
+        Ok(schema) => schema,
+        Err(e) => {
+            return Transactions {
+                source: Source::Csv(options.reader(input).into_records()),
+                headers: StringRecord::new(),
+                columns: None,
+                header_error: Some(e),
+                unreadable: false,
+                currency: Currency::default(),
+                options: options.clone(),
+            }
+        }
+    };
+    if options.fast_parse && schema.is_none() {
+        match Scanner::start(input, options) {
+            Ok(scanner) => {
+                return Transactions {
+                    source: Source::Scan(scanner),
+                    headers: StringRecord::from(DEFAULT_COLUMNS.to_vec()),
+                    columns: None,
+                    header_error: None,
+                    unreadable: false,
+                    currency: Currency::default(),
+                    options: options.clone(),
+                }
+            }
+            Err(unread) => input = unread,
+        }
+    }
+    let mut rdr = options.schema_reader(input, schema);
+    let schema = schema.unwrap_or_default();
+    let (headers, header_error) = match rdr.headers() {
+        Ok(headers) => (headers.clone(), schema.check(headers).err()),
+        Err(e) => (StringRecord::new(), Some(e.into())),
+    };
+    Transactions {
+        source: Source::Csv(rdr.into_records()),
+        columns: Columns::find(&headers),
+        currency: Currency::new(&headers, schema),
+        headers,
+        header_error,
+        unreadable: false,
+        options: options.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+    use std::ffi::OsString;
+
+    const DATA_SPACES: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         1.0
+deposit,         2,     2,         2.0
+deposit,         1,     3,         2.0
+withdrawal,      1,     4,         1.5
+withdrawal,      2,     5,         3.0
+";
+
+    const DATA_NO_SPACES: &str = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+deposit,1,3,2.0
+withdrawal,1,4,1.5
+withdrawal,2,5,3.0
+";
+
+    #[test]
+    fn test_parse_csv_spaces() {
+        read_csv(DATA_SPACES.as_bytes());
+    }
+
+    #[test]
+    fn test_parse_csv_no_spaces() {
+        read_csv(DATA_NO_SPACES.as_bytes());
+    }
+
+    #[test]
+    fn test_parse_csv_file() {
+        let _ = OsString::from_str("transactions.csv").unwrap();
+    }
+
+    #[test]
+    fn test_csv_to_transactions() -> Result<()> {
+        let mut transactions = read_csv(DATA_SPACES.as_bytes());
+
+        if let Some(result) = transactions.next() {
+            let record: Transaction = result?;
+            assert_eq!(
+                record,
+                Transaction {
+                    trans: TransType::Deposit,
+                    client: 1,
+                    tx: 1,
+                    amount: Some(dec!(1.0)),
+                    timestamp: None,
+                }
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_no_header() -> Result<()> {
+        let options = ReaderOptions {
+            has_headers: false,
+            ..Default::default()
+        };
+        let data = DATA_NO_SPACES.split_once('\n').unwrap().1;
+        let records =
+            read_csv_with(data.as_bytes(), &options).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[3],
+            Transaction::new(TransType::Withdrawal, 1, 4, Some(dec!(1.5)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_custom_columns() -> Result<()> {
+        const DATA: &str = "\
+id,     amount,   kind,       account,  memo
+1,         1.0,   deposit,    7,        payday
+2,            ,   dispute,    7,        oops
+";
+        let columns = ["tx", "amount", "type", "client", "memo"];
+        let options = ReaderOptions {
+            columns: Some(columns.iter().map(|c| c.to_string()).collect()),
+            ..Default::default()
+        };
+        let records =
+            read_csv_with(DATA.as_bytes(), &options).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(
+            records,
+            vec![
+                Transaction::new(TransType::Deposit, 7, 1, Some(dec!(1.0))),
+                Transaction::new(TransType::Dispute, 7, 2, None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_semicolon_decimal_comma() -> Result<()> {
+        const DATA: &str = "\
+type;client;tx;amount
+deposit;1;1;\"1,50\"
+withdrawal;1;2;0,2500
+dispute;1;1;
+";
+        let options = ReaderOptions {
+            delimiter: b';',
+            decimal_comma: true,
+            ..Default::default()
+        };
+        let records =
+            read_csv_with(DATA.as_bytes(), &options).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(
+            records,
+            vec![
+                Transaction::new(TransType::Deposit, 1, 1, Some(dec!(1.5))),
+                Transaction::new(TransType::Withdrawal, 1, 2, Some(dec!(0.25))),
+                Transaction::new(TransType::Dispute, 1, 1, None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_no_quoting() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+'deposit',1,1,1.0
+";
+        let options = ReaderOptions {
+            quote: b'\'',
+            ..Default::default()
+        };
+        let records =
+            read_csv_with(DATA.as_bytes(), &options).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(records[0].trans, TransType::Deposit);
+
+        let options = ReaderOptions {
+            quote: b'\'',
+            quoting: false,
+            ..Default::default()
+        };
+        // The quotes are kept, so the type is no longer one of the built in ones
+        let records =
+            read_csv_with(DATA.as_bytes(), &options).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(records[0].trans, TransType::Other("'deposit'".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_type_aliases() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+Deposit,1,1,1.0
+WITHDRAW,1,2,1.0
+charge_back,1,1,
+";
+        let types = |options: &ReaderOptions| -> Result<Vec<TransType>> {
+            Ok(read_csv_with(DATA.as_bytes(), options)
+                .map(|result| result.map(|transaction| transaction.trans))
+                .collect::<Result<_, _>>()?)
+        };
+        assert_eq!(
+            types(&ReaderOptions::default())?,
+            vec![
+                TransType::Deposit,
+                TransType::Withdrawal,
+                TransType::Chargeback
+            ]
+        );
+        let strict = ReaderOptions {
+            strict_types: true,
+            ..Default::default()
+        };
+        assert_eq!(types(&strict)?[0], TransType::Other("Deposit".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_strict_amounts() {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,1,1.12340
+deposit,1,2,1e10
+deposit,1,3,0.00001
+";
+        let mut records = read_csv(DATA.as_bytes());
+        assert_eq!(records.next().unwrap().unwrap().amount, Some(dec!(1.1234)));
+        let e = records.next().unwrap().unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("amount 1e10 is in scientific notation"));
+        assert_eq!(
+            records.next().unwrap().unwrap_err().to_string(),
+            "line 4: amount 0.00001 has more than 4 decimal places"
+        );
+
+        let options = ReaderOptions {
+            max_precision: Some(5),
+            ..Default::default()
+        };
+        let records = read_csv_with(DATA.as_bytes(), &options);
+        assert_eq!(records.filter(Result::is_ok).count(), 2);
+    }
+
+    #[test]
+    fn test_csv_client_ids() {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,65535,1,1.0
+deposit,65536,2,1.0
+deposit,18446744073709551615,3,1.0
+";
+        let records = read_csv(DATA.as_bytes());
+        assert_eq!(records.filter(Result::is_ok).count(), 3);
+
+        let options = ReaderOptions {
+            legacy_ids: true,
+            ..Default::default()
+        };
+        let mut records = read_csv_with(DATA.as_bytes(), &options);
+        assert_eq!(records.next().unwrap().unwrap().client, 65535);
+        assert_eq!(
+            records.next().unwrap().unwrap_err().to_string(),
+            "line 3: client 65536 is out of range for legacy u16 ids"
+        );
+    }
+
+    #[test]
+    fn test_csv_large_tx_ids() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,4294967296,1.0
+dispute,1,4294967296,
+";
+        let records = read_csv(DATA.as_bytes()).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(records[0].tx, 4_294_967_296);
+        assert_eq!(records[1].tx, records[0].tx);
+        Ok(())
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_csv_uuid_tx_ids() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,67e55044-10b1-426f-9247-bb680e5fe0c8,1.0
+deposit,1,7,1.0
+";
+        let records = read_csv(DATA.as_bytes()).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(records[0].tx, 0x67e55044_10b1_426f_9247_bb680e5fe0c8);
+        assert_eq!(records[1].tx, 7);
+        assert!(
+            read_csv("type,client,tx,amount\ndeposit,1,nope,1.0\n".as_bytes())
+                .next()
+                .unwrap()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_timestamp_column() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,         1.0,     2022-03-21T10:00:00Z
+dispute,         1,     1,            ,     2022-03-21T11:30:00Z
+";
+        let records = read_csv(DATA.as_bytes()).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(
+            records[0].timestamp,
+            Some("2022-03-21T10:00:00Z".parse::<DateTime<Utc>>()?)
+        );
+        assert_eq!(records[1].amount, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_projected_rows() -> Result<()> {
+        const DATA: &str = "\
+timestamp,                 amount,   tx, client,  type
+2022-03-21T10:00:00+02:00,    1.5,    1,      1,  Deposit
+,                                ,    1,      1,  dispute
+,                             0.5,    1,      1,  dispute
+,                       not money,    1,      1,  resolve
+,                                ,    1,      1,  chargeback
+,                             2.0,    2,      1,  withdraw
+,                             2.0,    3,      1,
+,                             1e3,    4,      1,  deposit
+";
+        let options = ReaderOptions::default();
+        let mut rdr = options.reader(DATA.as_bytes());
+        let headers = rdr.headers()?.clone();
+        let columns = Columns::find(&headers).expect("every column");
+        for record in rdr.records() {
+            let record = record?;
+            let projected = columns
+                .fields(&record)
+                .and_then(|fields| options.project(&fields));
+            let line = record.position().map(|p| p.line());
+            match options.deserialize(&record, &headers) {
+                Ok(transaction) => assert_eq!(projected, Some(transaction), "line {line:?}"),
+                Err(_) => assert_eq!(projected, None, "line {line:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_schema() -> Result<()> {
+        const V2: &str = "\
+#schema=v2
+type,       client,     tx,     amount,  currency,  timestamp
+deposit,         1,     1,         1.0,       EUR,  2022-03-21T10:00:00Z
+# a comment
+dispute,         1,     1,            ,          ,  2022-03-21T11:30:00Z
+deposit,         1,     2,         1.0,       USD,  2022-03-21T12:00:00Z
+";
+        let mut records = read_csv(V2.as_bytes());
+        let deposit = records.next().expect("a deposit")?;
+        assert!(deposit.timestamp.is_some());
+        assert_eq!(records.next().expect("a dispute")?.amount, None);
+        assert_eq!(
+            records.next().expect("a deposit").unwrap_err().to_string(),
+            "line 6: amount in USD, the file is in EUR"
+        );
+
+        let missing = "#schema=v2\ntype,client,tx,amount\ndeposit,1,1,1.0\n";
+        let mut records = read_csv(missing.as_bytes());
+        assert_eq!(
+            records.next().expect("an error").unwrap_err().to_string(),
+            "schema v2 needs the column(s) currency, timestamp"
+        );
+        assert!(records.next().is_none());
+
+        let mut records = read_csv("#schema=v3\ntype,client,tx,amount\n".as_bytes());
+        assert_eq!(
+            records.next().expect("an error").unwrap_err().to_string(),
+            "schema v3 is not supported, only v1 and v2 are"
+        );
+        assert!(records.next().is_none());
+
+        let v1 = "#schema=v1\ntype,client,tx,amount\ndeposit,1,1,1.0\n";
+        assert_eq!(read_csv(v1.as_bytes()).count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_csv_round_trip() -> Result<()> {
+        let mut deposit = Transaction::new(TransType::Deposit, 1, 7, Some(dec!(0.25)));
+        deposit.timestamp = Some("2022-03-31T23:59:59Z".parse()?);
+        let transactions = vec![deposit, Transaction::new(TransType::Dispute, 1, 7, None)];
+
+        let mut out = Vec::new();
+        write_csv(&mut out, &transactions)?;
+        assert_eq!(
+            String::from_utf8(out.clone())?,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,7,0.25,2022-03-31T23:59:59Z\n\
+             dispute,1,7,,\n"
+        );
+        let read = read_csv(out.as_slice()).collect::<Result<Vec<Transaction>, _>>()?;
+        assert_eq!(read, transactions);
+        Ok(())
+    }
+}
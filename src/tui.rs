@@ -0,0 +1,265 @@
+//! `tte tui`, a terminal dashboard watching a run
+//!
+//! Transactions are applied in short slices between redraws so the screen
+//! stays live on large files. Typing digits looks up a client, `q` or Esc
+//! quits, also before the input is exhausted.
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tte::transaction::ReadError;
+use tte::{AccountView, ClientId, Engine, Transaction, TxId};
+
+/// How long to apply transactions before drawing again
+const SLICE: Duration = Duration::from_millis(50);
+/// How often the top accounts are ranked again, as that visits every client
+const RANK_EVERY: Duration = Duration::from_secs(1);
+const TOP: usize = 10;
+const RECENT: usize = 10;
+
+#[derive(Default)]
+struct Dashboard {
+    processed: u64,
+    elapsed: Duration,
+    /// Transactions per second over the last slice
+    rate: f64,
+    top: Vec<AccountView>,
+    /// Most recent first
+    recently_locked: VecDeque<(ClientId, TxId)>,
+    locked: HashSet<ClientId>,
+    search: String,
+    status: String,
+}
+
+impl Dashboard {
+    /// Notes a client whose account got locked by the last transaction
+    fn observe(&mut self, engine: &Engine, client: ClientId, tx: TxId) {
+        let locked = engine.account(client).is_some_and(|view| view.locked);
+        if locked && self.locked.insert(client) {
+            self.recently_locked.push_front((client, tx));
+            self.recently_locked.truncate(RECENT);
+        }
+    }
+
+    fn rank(&mut self, engine: &Engine) {
+        let mut accounts: Vec<AccountView> = engine.iter_accounts().collect();
+        accounts.sort_by(|a, b| b.total.cmp(&a.total).then(a.client.cmp(&b.client)));
+        accounts.truncate(TOP);
+        self.top = accounts;
+    }
+
+    fn render(&self, engine: &Engine, frame: &mut Frame) {
+        let [stats, middle, detail] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(TOP as u16 + 3),
+            Constraint::Length(8),
+        ])
+        .areas(frame.area());
+        let [top, locked] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(middle);
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                "processed: {}   rate: {:.0} tx/s   elapsed: {:.1}s   {}",
+                self.processed,
+                self.rate,
+                self.elapsed.as_secs_f64(),
+                self.status
+            ))
+            .block(Block::bordered().title("Throughput")),
+            stats,
+        );
+        self.render_top(frame, top);
+        let items = self
+            .recently_locked
+            .iter()
+            .map(|(client, tx)| format!("client {client} at tx {tx}"));
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("Recently locked")),
+            locked,
+        );
+        self.render_detail(engine, frame, detail);
+    }
+
+    fn render_top(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.top.iter().map(|view| {
+            Row::new(vec![
+                view.client.to_string(),
+                view.available.to_string(),
+                view.held.to_string(),
+                view.total.to_string(),
+                view.locked.to_string(),
+            ])
+        });
+        let widths = [Constraint::Ratio(1, 5); 5];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(Row::new(vec![
+                    "client",
+                    "available",
+                    "held",
+                    "total",
+                    "locked",
+                ]))
+                .block(Block::bordered().title("Top accounts by total")),
+            area,
+        );
+    }
+
+    fn render_detail(&self, engine: &Engine, frame: &mut Frame, area: Rect) {
+        let text = match self.search.parse::<ClientId>() {
+            Err(_) => "type a client id".to_string(),
+            Ok(client) => match engine.account(client) {
+                None => format!("no client {client}"),
+                Some(view) => format!(
+                    "available: {}\nheld: {}\ntotal: {}\nlocked: {}\nopen disputes: {:?}",
+                    view.available, view.held, view.total, view.locked, view.open_disputes
+                ),
+            },
+        };
+        let title = format!("Client {}", self.search);
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    /// Handles pending key presses, returning false to quit
+    fn keys(&mut self, wait: Duration) -> Result<bool> {
+        if !event::poll(wait)? {
+            return Ok(true);
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                return Ok(true);
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(false)
+                }
+                KeyCode::Char(c) if c.is_ascii_digit() => self.search.push(c),
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Runs `transactions` through `engine` while showing the dashboard, until
+/// the user quits
+pub fn run(
+    mut engine: Engine,
+    mut transactions: impl Iterator<Item = Result<Transaction, ReadError>>,
+) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let mut dashboard = Dashboard {
+        status: "running".to_string(),
+        ..Dashboard::default()
+    };
+    let started = Instant::now();
+    let mut ranked = started - RANK_EVERY;
+    let mut running = true;
+    let result = loop {
+        if running {
+            let slice = Instant::now();
+            let before = dashboard.processed;
+            while slice.elapsed() < SLICE {
+                let Some(result) = transactions.next() else {
+                    running = false;
+                    dashboard.status = "done, q to quit".to_string();
+                    break;
+                };
+                let applied = result.map_err(anyhow::Error::from).and_then(|transaction| {
+                    let (client, tx) = (transaction.client, transaction.tx);
+                    engine.apply(transaction).map(|()| (client, tx))
+                });
+                match applied {
+                    Ok((client, tx)) => {
+                        dashboard.processed += 1;
+                        dashboard.observe(&engine, client, tx);
+                    }
+                    Err(e) => {
+                        running = false;
+                        dashboard.status = format!("stopped: {e}");
+                        break;
+                    }
+                }
+            }
+            dashboard.elapsed = started.elapsed();
+            dashboard.rate = (dashboard.processed - before) as f64 / slice.elapsed().as_secs_f64();
+        }
+        if !running || ranked.elapsed() >= RANK_EVERY {
+            dashboard.rank(&engine);
+            ranked = Instant::now();
+        }
+        if let Err(e) = terminal.draw(|frame| dashboard.render(&engine, frame)) {
+            break Err(e.into());
+        }
+        let wait = if running {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(250)
+        };
+        match dashboard.keys(wait) {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use tte::read_csv;
+
+    #[test]
+    fn test_dashboard_render() -> Result<()> {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,7.0
+dispute,2,2,
+chargeback,2,2,
+";
+        let mut engine = Engine::new();
+        let mut dashboard = Dashboard {
+            search: "1".to_string(),
+            ..Dashboard::default()
+        };
+        for transaction in read_csv(DATA.as_bytes()) {
+            let transaction = transaction?;
+            let (client, tx) = (transaction.client, transaction.tx);
+            engine.apply(transaction)?;
+            dashboard.observe(&engine, client, tx);
+        }
+        dashboard.rank(&engine);
+        assert_eq!(dashboard.top[0].client, 1);
+        assert_eq!(dashboard.recently_locked, [(2, 2)]);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30))?;
+        terminal.draw(|frame| dashboard.render(&engine, frame))?;
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("client 2 at tx 2"));
+        assert!(screen.contains("available: 5.0"));
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! Reading an input file through io_uring on Linux
+//!
+//! Built with the `io-uring` feature. [UringFile] keeps a few large reads of
+//! the file queued in the kernel while what was already read is parsed and
+//! applied, rather than making one read system call after another as the csv
+//! parser runs dry.
+use io_uring::{opcode, types, IoUring};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+/// Reads kept queued
+const DEPTH: usize = 4;
+/// Bytes per read
+const CHUNK: usize = 1 << 20;
+
+/// A ring with a read per slot at most
+struct Ring(IoUring);
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        IoUring::new(entries).map(Ring)
+    }
+
+    /// Queues a read of `len` bytes of `file` at `offset` into `buf`, to
+    /// complete with `tag`
+    ///
+    /// # Safety
+    ///
+    /// `buf` has to stay valid until the read completes
+    unsafe fn read(
+        &mut self,
+        file: &File,
+        buf: *mut u8,
+        len: usize,
+        offset: u64,
+        tag: u64,
+    ) -> io::Result<()> {
+        let read = opcode::Read::new(types::Fd(file.as_raw_fd()), buf, len as u32)
+            .offset(offset)
+            .build()
+            .user_data(tag);
+        // SAFETY: the caller keeps the buffer until the read completes
+        unsafe { self.0.submission().push(&read) }
+            .map_err(|_| io::Error::other("the io_uring submission queue is full"))?;
+        self.0.submit()?;
+        Ok(())
+    }
+
+    /// Waits for the next completed read, and gives its tag and result
+    fn complete(&mut self) -> io::Result<(u64, i32)> {
+        loop {
+            if let Some(done) = self.0.completion().next() {
+                return Ok((done.user_data(), done.result()));
+            }
+            self.0.submit_and_wait(1)?;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// A read into the buffer is queued
+    Queued,
+    /// The buffer holds what there is of its part of the file
+    Ready,
+}
+
+/// One of the buffers, holding [CHUNK] bytes of the file from `offset`
+struct Slot {
+    buf: Box<[u8]>,
+    offset: u64,
+    filled: usize,
+    state: State,
+}
+
+/// A file read ahead through io_uring, [DEPTH] chunks at a time
+pub struct UringFile {
+    file: File,
+    ring: Ring,
+    slots: Vec<Slot>,
+    /// The slot being read from, and how much of it was
+    current: usize,
+    consumed: usize,
+    /// Where the next chunk to queue starts
+    next: u64,
+}
+
+impl UringFile {
+    /// Fails if io_uring is not there, e.g. on an old kernel or where
+    /// seccomp turns it off
+    pub fn open(path: &Path) -> io::Result<UringFile> {
+        let mut file = UringFile {
+            file: File::open(path)?,
+            ring: Ring::new(DEPTH as u32)?,
+            slots: (0..DEPTH)
+                .map(|_| Slot {
+                    buf: vec![0; CHUNK].into_boxed_slice(),
+                    offset: 0,
+                    filled: 0,
+                    state: State::Ready,
+                })
+                .collect(),
+            current: 0,
+            consumed: 0,
+            next: 0,
+        };
+        file.restart(0)?;
+        Ok(file)
+    }
+
+    /// Queues every slot afresh, reading on from `offset`
+    fn restart(&mut self, offset: u64) -> io::Result<()> {
+        self.drain()?;
+        self.next = offset;
+        self.current = 0;
+        self.consumed = 0;
+        for i in 0..DEPTH {
+            self.queue(i)?;
+        }
+        Ok(())
+    }
+
+    /// Queues slot `i` for the next chunk
+    fn queue(&mut self, i: usize) -> io::Result<()> {
+        let slot = &mut self.slots[i];
+        slot.offset = self.next;
+        slot.filled = 0;
+        slot.state = State::Queued;
+        self.next += CHUNK as u64;
+        self.submit(i)
+    }
+
+    /// Submits a read of what is missing from slot `i`
+    fn submit(&mut self, i: usize) -> io::Result<()> {
+        let slot = &mut self.slots[i];
+        let buf = slot.buf[slot.filled..].as_mut_ptr();
+        let len = CHUNK - slot.filled;
+        let offset = slot.offset + slot.filled as u64;
+        // SAFETY: the buffer is only dropped or reused once no read into it
+        // is queued, see UringFile::drain
+        unsafe { self.ring.read(&self.file, buf, len, offset, i as u64) }
+    }
+
+    /// Waits for slot `i` to be read
+    fn wait(&mut self, i: usize) -> io::Result<()> {
+        while self.slots[i].state == State::Queued {
+            self.settle()?;
+        }
+        Ok(())
+    }
+
+    /// Takes one completed read, queuing the rest again if it came up short
+    /// before the end of the file
+    fn settle(&mut self) -> io::Result<()> {
+        let (tag, res) = self.ring.complete()?;
+        let i = tag as usize;
+        let slot = &mut self.slots[i];
+        match res {
+            0 => slot.state = State::Ready,
+            1.. => {
+                slot.filled += res as usize;
+                match slot.filled == CHUNK {
+                    true => slot.state = State::Ready,
+                    false => self.submit(i)?,
+                }
+            }
+            _ => {
+                let e = io::Error::from_raw_os_error(-res);
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                ) {
+                    self.submit(i)?;
+                } else {
+                    slot.state = State::Ready;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until no read is queued, or the ring itself fails
+    fn drain(&mut self) -> io::Result<()> {
+        let queued = |file: &Self| {
+            file.slots
+                .iter()
+                .filter(|s| s.state == State::Queued)
+                .count()
+        };
+        let mut result = Ok(());
+        while queued(self) > 0 {
+            let before = queued(self);
+            if let Err(e) = self.settle() {
+                if queued(self) == before {
+                    return Err(e);
+                }
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+impl Read for UringFile {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.wait(self.current)?;
+            let slot = &self.slots[self.current];
+            if self.consumed < slot.filled {
+                let len = out.len().min(slot.filled - self.consumed);
+                out[..len].copy_from_slice(&slot.buf[self.consumed..self.consumed + len]);
+                self.consumed += len;
+                return Ok(len);
+            }
+            if slot.filled < CHUNK {
+                // The end of the file
+                return Ok(0);
+            }
+            self.queue(self.current)?;
+            self.current = (self.current + 1) % DEPTH;
+            self.consumed = 0;
+        }
+    }
+}
+
+impl Seek for UringFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.slots[self.current].offset + self.consumed as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        let target = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        if target != position {
+            self.restart(target)?;
+        }
+        Ok(target)
+    }
+}
+
+impl Drop for UringFile {
+    fn drop(&mut self) {
+        // The kernel may still be writing into the buffers
+        let _ = self.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    #[ignore = "needs io_uring, which containers and seccomp profiles often turn off"]
+    fn test_uring_file() -> Result<()> {
+        let data: Vec<u8> = (0..CHUNK * 5 / 2).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("tte-uring-{}", std::process::id()));
+        std::fs::write(&path, &data)?;
+        let mut file = UringFile::open(&path)?;
+        let mut read = Vec::new();
+        file.read_to_end(&mut read)?;
+        assert!(read == data, "the whole file in order");
+
+        file.seek(SeekFrom::Start(CHUNK as u64 + 7))?;
+        let mut some = [0; 10];
+        file.read_exact(&mut some)?;
+        assert_eq!(some, data[CHUNK + 7..CHUNK + 17]);
+        assert_eq!(file.stream_position()?, CHUNK as u64 + 17);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! Input validation without applying any transactions
+//!
+//! [validate] reads a transactions file row by row and reports every problem
+//! it can find, tagged with the line number, instead of stopping at the first
+//! one or silently skipping rows like the engine does.
+use crate::transaction::{
+    read_schema, ClientId, Currency, ReadError, ReaderOptions, TransType, Transaction, TxId,
+};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The row is suspicious but the engine can still process it
+    Warning,
+    /// The row cannot be processed as intended
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found in the input
+/// ```text
+/// line 7: error: dispute references unknown tx 12
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub line: u64,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// Schema checks every row of a transactions file and returns the issues
+/// found, in input order. Only IO failures are returned as an `Err`.
+pub fn validate(csv: impl io::Read, options: &ReaderOptions) -> Result<Vec<Issue>> {
+    let mut input = BufReader::new(csv);
+    let schema = match read_schema(&mut input) {
+        Ok(schema) => schema,
+        Err(e) => {
+            let message = error_message(&e);
+            return Ok(vec![Issue {
+                line: 1,
+                severity: Severity::Error,
+                message,
+            }]);
+        }
+    };
+    let mut rdr = options.schema_reader(input, schema);
+    let schema = schema.unwrap_or_default();
+    // Precision is checked here instead so the row is still validated
+    let max_precision = options.max_precision;
+    let options = &ReaderOptions {
+        max_precision: None,
+        ..options.clone()
+    };
+    let mut issues = Vec::new();
+    let mut issue = |line, severity, message: String| {
+        issues.push(Issue {
+            line,
+            severity,
+            message,
+        })
+    };
+
+    let headers = rdr.headers()?.clone();
+    let missing: Vec<&str> = schema
+        .columns()
+        .iter()
+        .copied()
+        .filter(|column| !headers.iter().any(|h| h == *column))
+        .collect();
+    if !missing.is_empty() {
+        let line = rdr.position().line().saturating_sub(1).max(1);
+        issue(
+            line,
+            Severity::Error,
+            format!("missing required column(s): {}", missing.join(", ")),
+        );
+        return Ok(issues);
+    }
+    let amount_column = headers.iter().position(|h| h == "amount");
+    let mut currency = Currency::new(&headers, schema);
+
+    // tx id -> owning client of every deposit and withdrawal seen so far
+    let mut seen: HashMap<TxId, ClientId> = HashMap::new();
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                issue(line, Severity::Error, e.to_string());
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        if let Err(ReadError::Currency {
+            found, expected, ..
+        }) = currency.check(&record)
+        {
+            issue(
+                line,
+                Severity::Error,
+                format!("amount in {found}, the file is in {expected}"),
+            );
+        }
+        let transaction: Transaction = match options.decode(&record, &headers) {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                issue(
+                    line,
+                    Severity::Error,
+                    format!("invalid row: {}", error_message(&e)),
+                );
+                continue;
+            }
+        };
+        let raw_amount = amount_column.and_then(|i| record.get(i)).unwrap_or("");
+
+        match &transaction.trans {
+            TransType::Deposit | TransType::Withdrawal => match transaction.amount {
+                None => issue(line, Severity::Error, "missing amount".to_string()),
+                Some(amount) => {
+                    if amount.is_sign_negative() {
+                        issue(
+                            line,
+                            Severity::Error,
+                            format!("negative amount {raw_amount}"),
+                        );
+                    }
+                    match max_precision {
+                        Some(max) if amount.normalize().scale() > max => issue(
+                            line,
+                            Severity::Error,
+                            format!("amount {raw_amount} has more than {max} decimal places"),
+                        ),
+                        _ => {}
+                    }
+                    if seen.insert(transaction.tx, transaction.client).is_some() {
+                        issue(
+                            line,
+                            Severity::Error,
+                            format!("duplicate tx {}", transaction.tx),
+                        );
+                    }
+                }
+            },
+            TransType::Other(name) => issue(
+                line,
+                Severity::Warning,
+                format!("unknown transaction type {name} will be rejected"),
+            ),
+            TransType::Dispute | TransType::Resolve | TransType::Chargeback => {
+                let kind = transaction.trans.to_string();
+                // Only a dispute takes an amount, to hold part of the tx. The
+                // others are not even given theirs, so go by the raw field.
+                if !raw_amount.is_empty() && transaction.trans != TransType::Dispute {
+                    issue(
+                        line,
+                        Severity::Warning,
+                        format!("{kind} has an amount which will be ignored"),
+                    );
+                }
+                match seen.get(&transaction.tx) {
+                    None => issue(
+                        line,
+                        Severity::Error,
+                        format!("{kind} references unknown tx {}", transaction.tx),
+                    ),
+                    Some(&owner) if owner != transaction.client => issue(
+                        line,
+                        Severity::Error,
+                        format!(
+                            "{kind} by client {} references tx {} of client {owner}",
+                            transaction.client, transaction.tx
+                        ),
+                    ),
+                    Some(_) => {}
+                }
+            }
+            TransType::OpenAccount | TransType::CloseAccount => {
+                if transaction.amount.is_some() {
+                    issue(
+                        line,
+                        Severity::Warning,
+                        format!("{} has an amount which will be ignored", transaction.trans),
+                    );
+                }
+            }
+        }
+    }
+    Ok(issues)
+}
+
+/// Pulls the human readable part out of a read error, dropping the position
+/// information that is already reported as the line number
+fn error_message(e: &ReadError) -> String {
+    match e {
+        ReadError::Csv(e) => match e.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => e.to_string(),
+        },
+        ReadError::Precision { .. }
+        | ReadError::LegacyClientId { .. }
+        | ReadError::Fields { .. }
+        | ReadError::Schema(_)
+        | ReadError::Currency { .. } => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_clean() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         1.0
+withdrawal,      1,     2,      0.5001
+dispute,         1,     1,
+resolve,         1,     1,
+";
+        assert!(validate(DATA.as_bytes(), &ReaderOptions::default())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_missing_columns() -> Result<()> {
+        const DATA: &str = "\
+type,client,amount
+deposit,1,1.0
+";
+        let issues = validate(DATA.as_bytes(), &ReaderOptions::default())?;
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "line 1: error: missing required column(s): tx"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_rows() -> Result<()> {
+        const DATA: &str = "\
+type,       client,     tx,     amount
+deposit,         1,     1,         1.0
+deposit,         1,     2,
+refund,          1,     3,         1.0
+deposit,         1,     4,     1.00001
+deposit,         1,     6,        1e10
+deposit,        -1,     5,         1.0
+withdrawal,      2,     1,         1.0
+dispute,         1,     9,
+chargeback,      3,     4,        2.0
+";
+        let issues = validate(DATA.as_bytes(), &ReaderOptions::default())?;
+        let lines: Vec<(u64, Severity)> = issues.iter().map(|i| (i.line, i.severity)).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (3, Severity::Error),
+                (4, Severity::Warning),
+                (5, Severity::Error),
+                (6, Severity::Error),
+                (7, Severity::Error),
+                (8, Severity::Error),
+                (9, Severity::Error),
+                (10, Severity::Warning),
+                (10, Severity::Error),
+            ]
+        );
+        assert_eq!(
+            issues[1].message,
+            "unknown transaction type refund will be rejected"
+        );
+        assert_eq!(
+            issues[3].message,
+            "invalid row: amount 1e10 is in scientific notation"
+        );
+        assert_eq!(
+            issues[8].message,
+            "chargeback by client 3 references tx 4 of client 1"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! `--webhook`, telling other systems about chargebacks and locked accounts
+//!
+//! Every URL is sent a POST with a JSON array of the `ChargebackApplied` and
+//! `AccountFrozen` events, as they appear in the event stream: once at the end
+//! of a `report` run, and after every batch `consume` applies. A chargeback
+//! locks the account, so the two cover every lock. Servers that are down or
+//! overloaded are retried with a growing wait.
+//!
+//! With a secret in the `TTE_WEBHOOK_SECRET` environment variable, each request
+//! carries `X-Tte-Signature: sha256=<hex>`, the HMAC-SHA256 of the body, which
+//! receivers check to know the notification is genuine.
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::thread;
+use std::time::Duration;
+use tte::event::Event;
+use tte::output::EventSink;
+
+/// Attempts at each URL before giving up
+const RETRIES: u32 = 5;
+const SECRET_VAR: &str = "TTE_WEBHOOK_SECRET";
+const SIGNATURE_HEADER: &str = "X-Tte-Signature";
+
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<String>) -> Self {
+        Webhooks {
+            urls,
+            secret: std::env::var(SECRET_VAR).ok(),
+        }
+    }
+
+    /// Posts the chargebacks and locks among `events` to every URL. A URL that
+    /// fails does not keep the others from being sent to.
+    pub fn notify(&self, events: &[Event]) -> Result<()> {
+        let events: Vec<&Event> = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    Event::ChargebackApplied { .. } | Event::AccountFrozen { .. }
+                )
+            })
+            .collect();
+        if events.is_empty() {
+            return Ok(());
+        }
+        let body = serde_json::to_string(&events)?;
+        let signature = self.secret.as_ref().map(|secret| sign(secret, &body));
+        let mut failed = Vec::new();
+        for url in &self.urls {
+            if let Err(e) = post(url, &body, signature.as_deref()) {
+                log::error!("webhook {url}: {e}");
+                failed.push(url.as_str());
+            }
+        }
+        match failed.as_slice() {
+            [] => Ok(()),
+            failed => Err(anyhow!("could not notify {}", failed.join(", "))),
+        }
+    }
+}
+
+impl EventSink for Webhooks {
+    fn write_events(&mut self, events: &[Event]) -> Result<()> {
+        self.notify(events)
+    }
+
+    fn persists(&self) -> bool {
+        true
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+/// Sends `body`, retrying what may pass: a dropped connection, a 429 or a
+/// server error
+pub fn post(url: &str, body: &str, signature: Option<&str>) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let mut request = ureq::post(url).set("Content-Type", "application/json");
+        if let Some(signature) = signature {
+            request = request.set(SIGNATURE_HEADER, signature);
+        }
+        match request.send_string(body) {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(status, _))
+                if (status == 429 || status >= 500) && attempt < RETRIES => {}
+            Err(ureq::Error::Transport(_)) if attempt < RETRIES => {}
+            Err(e) => return Err(e.into()),
+        }
+        log::warn!("webhook {url}: attempt {attempt} failed, retrying");
+        thread::sleep(Duration::from_millis(100 << attempt));
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_notify() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/hooks", listener.local_addr()?);
+        let server = thread::spawn(move || -> io::Result<Vec<(String, String)>> {
+            let mut requests = Vec::new();
+            for (i, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream?;
+                let mut reader = BufReader::new(&stream);
+                let (mut signature, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line)?;
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                            signature = value.to_string();
+                        } else if name.eq_ignore_ascii_case("content-length") {
+                            length = value.parse().unwrap_or_default();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                requests.push((signature, String::from_utf8_lossy(&body).into_owned()));
+                let status = if i == 0 {
+                    "503 Service Unavailable"
+                } else {
+                    "204 No Content"
+                };
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n")?;
+            }
+            Ok(requests)
+        });
+
+        let webhooks = Webhooks {
+            urls: vec![url],
+            secret: Some("secret".to_string()),
+        };
+        let events = [
+            Event::DepositApplied {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(50, 1),
+                fee: Decimal::ZERO,
+            },
+            Event::ChargebackApplied {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(50, 1),
+            },
+        ];
+        webhooks.notify(&events)?;
+        webhooks.notify(&events[..1])?;
+        let body = r#"[{"event":"ChargebackApplied","client":1,"tx":1,"amount":"5.0"}]"#;
+        let signature = "sha256=2554c5c3965d6c301131a4342b55ff464e8288d10c60e730032e93614981c3fb";
+        let expected = (signature.to_string(), body.to_string());
+        assert_eq!(
+            server.join().unwrap()?,
+            [expected.clone(), expected],
+            "retried after the 503, and nothing sent without a chargeback"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! `tte whatif`, what a single proposed transaction would do
+//!
+//! Support tooling asks what a transaction would do before it is sent. The
+//! engine is loaded from a saved state, a checkpoint or a consumer's state
+//! file, the transaction applied to it and the account shown before and after,
+//! with the outcome. The state file is never written, so nothing the proposal
+//! does is kept.
+use anyhow::Result;
+use csv::{StringRecord, Trim};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{self, Write};
+use tte::event::Event;
+use tte::{AccountView, Engine, ReaderOptions, Reason, Transaction};
+
+/// The engine in a checkpoint or a consumer's state, which both keep it under
+/// `engine`
+#[derive(Deserialize)]
+struct Saved {
+    engine: Engine,
+}
+
+pub fn read_engine(r: impl io::Read) -> Result<Engine> {
+    let saved: Saved = serde_json::from_reader(io::BufReader::new(r))?;
+    Ok(saved.engine)
+}
+
+/// A transaction written as a CSV row without a header, e.g.
+/// `withdrawal,42,9999,500.0`, maybe with a timestamp after the amount
+pub fn proposed(row: &str) -> Result<Transaction, String> {
+    const COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+    let mut record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_reader(row.as_bytes())
+        .records()
+        .next()
+        .ok_or("no transaction given")?
+        .map_err(|e| e.to_string())?;
+    if record.len() > COLUMNS.len() {
+        return Err(format!(
+            "{} columns, but only type, client, tx, amount and timestamp are read",
+            record.len()
+        ));
+    }
+    // A dispute, resolve or chargeback may leave the amount out
+    while record.len() < 4 {
+        record.push_field("");
+    }
+    let headers: StringRecord = COLUMNS[..record.len()].iter().collect();
+    ReaderOptions::default()
+        .decode(&record, &headers)
+        .map_err(|e| e.to_string())
+}
+
+/// The account a transaction touched, before and after
+pub struct Projection {
+    pub before: Option<AccountView>,
+    pub after: Option<AccountView>,
+    /// What the engine recorded of the transaction, none if it was skipped
+    pub events: Vec<Event>,
+}
+
+/// Applies `transaction` to `engine`, which should be thrown away after
+pub fn what_if(engine: &mut Engine, transaction: Transaction) -> Result<Projection> {
+    engine.record_events();
+    let seen = engine.events().len();
+    let client = engine
+        .merged_into(transaction.client)
+        .unwrap_or(transaction.client);
+    let before = engine.account(client);
+    engine.apply(transaction)?;
+    let events = engine.events()[seen..].to_vec();
+    let after = engine.account(client);
+    Ok(Projection {
+        before,
+        after,
+        events,
+    })
+}
+
+impl Projection {
+    /// Why the transaction changed nothing, if it did not
+    pub fn rejected(&self) -> Option<Reason> {
+        self.events.iter().find_map(|event| match event {
+            Event::TransactionRejected { reason, .. } => reason.parse().ok(),
+            _ => None,
+        })
+    }
+
+    fn outcome(&self) -> &'static str {
+        match (self.events.is_empty(), self.rejected()) {
+            (true, _) => "skipped",
+            (false, Some(_)) => "rejected",
+            (false, None) => "applied",
+        }
+    }
+
+    /// Writes the outcome and the account as text
+    /// ```text
+    /// outcome: rejected, R001 insufficient_funds
+    /// client, available, held, total, locked
+    /// before: 42, 100.0, 0, 100.0, false
+    /// after: 42, 100.0, 0, 100.0, false
+    /// ```
+    pub fn write_text(&self, mut w: impl Write) -> io::Result<()> {
+        match self.rejected() {
+            Some(reason) => writeln!(w, "outcome: rejected, {reason}")?,
+            None => writeln!(w, "outcome: {}", self.outcome())?,
+        }
+        writeln!(w, "client, available, held, total, locked")?;
+        for (label, view) in [("before", &self.before), ("after", &self.after)] {
+            match view {
+                Some(view) => writeln!(
+                    w,
+                    "{label}: {}, {}, {}, {}, {}",
+                    view.client, view.available, view.held, view.total, view.locked
+                )?,
+                None => writeln!(w, "{label}: no account")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the outcome, the account and the events as one JSON object
+    pub fn write_json(&self, mut w: impl Write) -> Result<()> {
+        let view = |view: &Option<AccountView>| {
+            view.as_ref().map(|view| {
+                json!({
+                    "client": view.client,
+                    "available": view.available,
+                    "held": view.held,
+                    "total": view.total,
+                    "locked": view.locked,
+                })
+            })
+        };
+        let reason = self.rejected();
+        let projection = json!({
+            "outcome": self.outcome(),
+            "code": reason.map(|reason| reason.code()),
+            "reason": reason.map(|reason| reason.name()),
+            "before": view(&self.before),
+            "after": view(&self.after),
+            "events": self.events,
+        });
+        serde_json::to_writer_pretty(&mut w, &projection)?;
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tte::checkpoint::write_checkpoint;
+    use tte::read_csv;
+
+    #[test]
+    fn test_what_if() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.replay(
+            read_csv("type,client,tx,amount\ndeposit,42,1,100.0\n".as_bytes()),
+            None,
+        )?;
+        let mut state = Vec::new();
+        write_checkpoint(&mut state, &engine, &csv::Position::new())?;
+
+        let tx = |row: &str| proposed(row).map_err(anyhow::Error::msg);
+        let mut sandbox = read_engine(state.as_slice())?;
+        let projection = what_if(&mut sandbox, tx("withdrawal, 42, 9999, 500.0")?)?;
+        assert_eq!(projection.rejected(), Some(Reason::InsufficientFunds));
+        let mut text = Vec::new();
+        projection.write_text(&mut text)?;
+        assert_eq!(
+            String::from_utf8(text)?,
+            "outcome: rejected, R001 insufficient_funds
+client, available, held, total, locked
+before: 42, 100.0, 0, 100.0, false
+after: 42, 100.0, 0, 100.0, false
+"
+        );
+
+        let mut sandbox = read_engine(state.as_slice())?;
+        let projection = what_if(&mut sandbox, tx("dispute,42,1")?)?;
+        let mut json = Vec::new();
+        projection.write_json(&mut json)?;
+        let json: serde_json::Value = serde_json::from_slice(&json)?;
+        assert_eq!(json["outcome"], "applied");
+        assert_eq!(json["after"]["held"], "100.0");
+
+        let projection = what_if(&mut sandbox, tx("deposit,7,2,1.0")?)?;
+        assert!(projection.before.is_none());
+        assert_eq!(projection.after.map(|view| view.total), Some(1.into()));
+        assert!(proposed("deposit,7,2,1.0,2022-03-21T10:00:00Z,extra").is_err());
+        assert!(proposed("").is_err());
+        Ok(())
+    }
+}
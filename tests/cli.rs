@@ -0,0 +1,24 @@
+//! Runs of the `tte` binary, for what only shows from the outside: exit
+//! statuses and what ends up on standard output
+use std::process::Command;
+use tte::snapshot::read_snapshot;
+
+#[test]
+fn test_out_of_time() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/basic/input.csv"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_tte"))
+        .args(["report", "--max-duration", "0s", input])
+        .output()
+        .expect("tte runs");
+    assert_eq!(output.status.code(), Some(124));
+    let report = String::from_utf8(output.stdout).expect("UTF-8");
+    assert!(
+        report.ends_with("\n# incomplete: out of time\n"),
+        "the report is marked:\n{report}"
+    );
+    let snapshot = read_snapshot(report.as_bytes()).expect("still an accounts report");
+    assert!(snapshot.is_empty(), "no time to apply anything");
+}
@@ -0,0 +1,56 @@
+//! Golden-file tests
+//!
+//! Every directory in `tests/fixtures` is a case: its `input.csv` is run
+//! through an engine, configured by its `config.toml` if there is one, and
+//! the report has to be its `expected.csv` exactly. A regression case from the
+//! field is added as a new directory, without touching any code.
+use std::fs;
+use std::path::Path;
+use tte::{read_csv, Config, Engine};
+
+fn report(case: &Path) -> Result<String, String> {
+    let config = match fs::read_to_string(case.join("config.toml")) {
+        Ok(config) => toml::from_str(&config).map_err(|e| e.to_string())?,
+        Err(_) => Config::default(),
+    };
+    let input = fs::read(case.join("input.csv")).map_err(|e| e.to_string())?;
+    let mut engine = Engine::with_config(config);
+    engine
+        .replay(read_csv(input.as_slice()), None)
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    engine.write_report(&mut out).map_err(|e| e.to_string())?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_fixtures() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut cases: Vec<_> = fs::read_dir(&fixtures)
+        .expect("tests/fixtures")
+        .map(|entry| entry.expect("fixture").path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no fixtures in {}", fixtures.display());
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        let name = case.file_name().unwrap_or_default().to_string_lossy();
+        let expected = match fs::read_to_string(case.join("expected.csv")) {
+            Ok(expected) => expected,
+            Err(e) => {
+                failures.push(format!("{name}: expected.csv: {e}"));
+                continue;
+            }
+        };
+        match report(case) {
+            Ok(actual) if actual == expected => {}
+            Ok(actual) => failures.push(format!(
+                "{name}:\n--- expected\n{expected}+++ actual\n{actual}"
+            )),
+            Err(e) => failures.push(format!("{name}: {e}")),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,74 @@
+//! Snapshot tests of the formatted outputs
+//!
+//! The outputs below are kept in `tests/snapshots` and any change to them
+//! fails until it is reviewed and accepted with `cargo insta review`.
+use tte::analysis::analyze;
+use tte::{read_csv, Config, Engine};
+
+/// A dispute resolved and disputed again before the chargeback locks the
+/// account, a deposit to the locked account, and a refused withdrawal
+const DISPUTE_CHAIN: &str = "\
+type,       client,     tx,     amount,     timestamp
+deposit,         1,     1,        10.0,     2022-03-21T09:00:00Z
+deposit,         1,     2,         5.0,     2022-03-21T09:30:00Z
+dispute,         1,     1,            ,     2022-03-21T10:00:00Z
+resolve,         1,     1,            ,     2022-03-21T11:00:00Z
+dispute,         1,     1,            ,     2022-03-22T10:00:00Z
+chargeback,      1,     1,            ,     2022-03-22T11:00:00Z
+deposit,         1,     3,         1.0,     2022-03-22T12:00:00Z
+deposit,         2,     4,         3.0,     2022-03-21T09:00:00Z
+withdrawal,      2,     5,         4.0,     2022-03-21T10:00:00Z
+dispute,         2,     4,         1.0,     2022-03-21T11:00:00Z
+deposit,         3,     6,     12345.6789,
+";
+
+fn replay(engine: &mut Engine) {
+    engine
+        .replay(read_csv(DISPUTE_CHAIN.as_bytes()), None)
+        .expect("valid input");
+}
+
+fn written(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+    let mut out = Vec::new();
+    write(&mut out).expect("written");
+    String::from_utf8(out).expect("UTF-8")
+}
+
+#[test]
+fn test_report() {
+    let mut engine = Engine::new();
+    replay(&mut engine);
+    insta::assert_snapshot!("report", written(|w| engine.write_report(w)));
+}
+
+#[test]
+fn test_report_every_column() {
+    let config: Config = toml::from_str(
+        "disputes.negative_available = \"clamp\"\n\
+         [[credit]]\nclient = 2\nlimit = \"1\"",
+    )
+    .expect("valid config");
+    let mut engine = Engine::with_config(config);
+    engine.score_risk();
+    engine.extend_report();
+    replay(&mut engine);
+    insta::assert_snapshot!("report_every_column", written(|w| engine.write_report(w)));
+}
+
+#[test]
+fn test_events() {
+    let mut engine = Engine::new();
+    engine.record_events();
+    replay(&mut engine);
+    insta::assert_snapshot!("events", written(|w| engine.write_events(w)));
+}
+
+#[test]
+fn test_analysis() {
+    let analysis = analyze(read_csv(DISPUTE_CHAIN.as_bytes()), 20).expect("valid input");
+    insta::assert_snapshot!("analysis_table", written(|w| analysis.write_table(w)));
+    insta::assert_snapshot!(
+        "analysis_json",
+        serde_json::to_string_pretty(&analysis).expect("serialized")
+    );
+}
@@ -0,0 +1,25 @@
+//! Generates the C header from the `extern "C"` functions in `src/lib.rs`
+//! into `OUT_DIR`. The checked-in `include/tte.h` is only rewritten when
+//! `TTE_FFI_UPDATE_HEADER` is set, so a build never touches the source tree,
+//! and a test checks that it is current.
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Unable to read cbindgen.toml");
+    let header = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate the C header");
+    header.write_to_file(out_dir.join("tte.h"));
+    if env::var_os("TTE_FFI_UPDATE_HEADER").is_some() {
+        header.write_to_file(crate_dir.join("include/tte.h"));
+    }
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=TTE_FFI_UPDATE_HEADER");
+}
@@ -0,0 +1,73 @@
+#ifndef TTE_H
+#define TTE_H
+
+/* Generated by cbindgen from tte-ffi/src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// How many recent idempotency keys an engine remembers
+#define TTE_IDEMPOTENCY_KEYS 1024
+
+// An engine instance. Opaque to C callers.
+typedef struct TteEngine TteEngine;
+
+// Creates a new engine with no accounts. Free it with [tte_engine_free].
+struct TteEngine *tte_engine_new(void);
+
+// Applies a transaction given as a JSON object, or an array of them, e.g.
+// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+//
+// Returns the number of transactions applied, or -1 if the JSON was invalid,
+// in which case nothing is applied and [tte_engine_last_error] says why.
+//
+// # Safety
+//
+// `engine` must come from [tte_engine_new] and `json` must be a valid NUL
+// terminated string.
+int tte_engine_process_json(struct TteEngine *engine, const char *json);
+
+// Same as [tte_engine_process_json] but deduplicated by an idempotency `key`.
+// A submission reusing a key seen among the last [TTE_IDEMPOTENCY_KEYS] is not
+// applied again. It returns the original outcome instead, including the
+// original error.
+//
+// # Safety
+//
+// `engine` must come from [tte_engine_new] and `key` and `json` must be valid
+// NUL terminated strings.
+int tte_engine_process_json_once(struct TteEngine *engine, const char *key, const char *json);
+
+// Returns the accounts report as a CSV string in the same format as the
+// `tte` command line tool. Free it with [tte_string_free].
+//
+// # Safety
+//
+// `engine` must come from [tte_engine_new].
+char *tte_engine_accounts_csv(const struct TteEngine *engine);
+
+// Returns the error message of the last failed call on this engine, or NULL.
+// The string is owned by the engine and valid until the next call on it.
+//
+// # Safety
+//
+// `engine` must come from [tte_engine_new].
+const char *tte_engine_last_error(const struct TteEngine *engine);
+
+// Frees a string returned by [tte_engine_accounts_csv]
+//
+// # Safety
+//
+// `s` must come from [tte_engine_accounts_csv] and not already be freed.
+void tte_string_free(char *s);
+
+// Frees an engine created by [tte_engine_new]
+//
+// # Safety
+//
+// `engine` must come from [tte_engine_new] and not already be freed.
+void tte_engine_free(struct TteEngine *engine);
+
+#endif  /* TTE_H */
@@ -0,0 +1,332 @@
+//! TTE FFI
+//!
+//! A C API for embedding the transaction engine in-process. The header in
+//! `include/tte.h` is generated by the build script, see `build.rs`.
+//!
+//! ```c
+//! TteEngine *engine = tte_engine_new();
+//! if (tte_engine_process_json(engine, "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}") < 0) {
+//!     fprintf(stderr, "%s\n", tte_engine_last_error(engine));
+//! }
+//! char *csv = tte_engine_accounts_csv(engine);
+//! printf("%s", csv);
+//! tte_string_free(csv);
+//! tte_engine_free(engine);
+//! ```
+//!
+//! Callers that may retry a submission, e.g. a network front end, can pass an
+//! idempotency key with [tte_engine_process_json_once] so a retry is not
+//! applied twice.
+use lru::LruCache;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::Value;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::num::NonZeroUsize;
+use std::ptr;
+use tte::transaction::{parse_amount, ClientId, TxId, DEFAULT_MAX_PRECISION};
+use tte::{Engine, TransType, Transaction};
+
+/// How many recent idempotency keys an engine remembers
+pub const TTE_IDEMPOTENCY_KEYS: usize = 1024;
+
+/// An engine instance. Opaque to C callers.
+pub struct TteEngine {
+    engine: Engine,
+    last_error: Option<CString>,
+    /// Outcome of each recent submission by idempotency key
+    outcomes: LruCache<String, Result<usize, String>>,
+}
+
+/// A transaction as submitted in JSON. The amount may be a JSON string, which
+/// is exact, or a number.
+#[derive(Deserialize)]
+struct JsonTransaction {
+    #[serde(rename = "type")]
+    trans: TransType,
+    client: ClientId,
+    tx: TxId,
+    #[serde(default)]
+    amount: Option<Value>,
+}
+
+/// Either a single transaction object or an array of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Batch {
+    One(JsonTransaction),
+    Many(Vec<JsonTransaction>),
+}
+
+fn amount(value: Option<Value>) -> Result<Option<Decimal>, String> {
+    let amount = match value {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(raw)) => parse_amount(&raw)?,
+        Some(Value::Number(n)) => parse_amount(&n.to_string())?,
+        Some(other) => return Err(format!("amount {other} is not a number")),
+    };
+    if amount.normalize().scale() > DEFAULT_MAX_PRECISION {
+        return Err(format!(
+            "amount {amount} has more than {DEFAULT_MAX_PRECISION} decimal places"
+        ));
+    }
+    Ok(Some(amount))
+}
+
+/// Parses the whole batch up front so that a bad transaction applies nothing
+fn parse(json: &str) -> Result<Vec<Transaction>, String> {
+    let batch = match serde_json::from_str(json).map_err(|e| e.to_string())? {
+        Batch::One(transaction) => vec![transaction],
+        Batch::Many(transactions) => transactions,
+    };
+    batch
+        .into_iter()
+        .map(|t| Ok(Transaction::new(t.trans, t.client, t.tx, amount(t.amount)?)))
+        .collect()
+}
+
+impl TteEngine {
+    fn process_json(&mut self, json: &str) -> Result<usize, String> {
+        let transactions = parse(json)?;
+        let count = transactions.len();
+        for transaction in transactions {
+            self.engine.apply(transaction).map_err(|e| e.to_string())?;
+        }
+        Ok(count)
+    }
+
+    fn process_json_once(&mut self, key: &str, json: &str) -> Result<usize, String> {
+        if let Some(outcome) = self.outcomes.get(key) {
+            return outcome.clone();
+        }
+        let outcome = self.process_json(json);
+        self.outcomes.put(key.to_string(), outcome.clone());
+        outcome
+    }
+
+    /// Records the outcome of a call as the C return value and last error
+    fn finish(&mut self, result: Result<usize, String>) -> c_int {
+        match result {
+            Ok(count) => {
+                self.last_error = None;
+                c_int::try_from(count).unwrap_or(c_int::MAX)
+            }
+            Err(e) => {
+                self.last_error = CString::new(e).ok();
+                -1
+            }
+        }
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    match s.is_null() {
+        true => Err(format!("{name} is NULL")),
+        false => CStr::from_ptr(s).to_str().map_err(|e| e.to_string()),
+    }
+}
+
+/// Creates a new engine with no accounts. Free it with [tte_engine_free].
+#[no_mangle]
+pub extern "C" fn tte_engine_new() -> *mut TteEngine {
+    Box::into_raw(Box::new(TteEngine {
+        engine: Engine::new(),
+        last_error: None,
+        outcomes: LruCache::new(NonZeroUsize::new(TTE_IDEMPOTENCY_KEYS).unwrap()),
+    }))
+}
+
+/// Applies a transaction given as a JSON object, or an array of them, e.g.
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+///
+/// Returns the number of transactions applied, or -1 if the JSON was invalid,
+/// in which case nothing is applied and [tte_engine_last_error] says why.
+///
+/// # Safety
+///
+/// `engine` must come from [tte_engine_new] and `json` must be a valid NUL
+/// terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tte_engine_process_json(
+    engine: *mut TteEngine,
+    json: *const c_char,
+) -> c_int {
+    let Some(engine) = engine.as_mut() else {
+        return -1;
+    };
+    let result = to_str(json, "json").and_then(|json| engine.process_json(json));
+    engine.finish(result)
+}
+
+/// Same as [tte_engine_process_json] but deduplicated by an idempotency `key`.
+/// A submission reusing a key seen among the last [TTE_IDEMPOTENCY_KEYS] is not
+/// applied again. It returns the original outcome instead, including the
+/// original error.
+///
+/// # Safety
+///
+/// `engine` must come from [tte_engine_new] and `key` and `json` must be valid
+/// NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn tte_engine_process_json_once(
+    engine: *mut TteEngine,
+    key: *const c_char,
+    json: *const c_char,
+) -> c_int {
+    let Some(engine) = engine.as_mut() else {
+        return -1;
+    };
+    let result = to_str(key, "key")
+        .and_then(|key| to_str(json, "json").and_then(|json| engine.process_json_once(key, json)));
+    engine.finish(result)
+}
+
+/// Returns the accounts report as a CSV string in the same format as the
+/// `tte` command line tool. Free it with [tte_string_free].
+///
+/// # Safety
+///
+/// `engine` must come from [tte_engine_new].
+#[no_mangle]
+pub unsafe extern "C" fn tte_engine_accounts_csv(engine: *const TteEngine) -> *mut c_char {
+    let Some(engine) = engine.as_ref() else {
+        return ptr::null_mut();
+    };
+    let mut csv = Vec::new();
+    match engine.engine.write_report(&mut csv) {
+        Ok(()) => CString::new(csv).map_or(ptr::null_mut(), CString::into_raw),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Returns the error message of the last failed call on this engine, or NULL.
+/// The string is owned by the engine and valid until the next call on it.
+///
+/// # Safety
+///
+/// `engine` must come from [tte_engine_new].
+#[no_mangle]
+pub unsafe extern "C" fn tte_engine_last_error(engine: *const TteEngine) -> *const c_char {
+    match engine.as_ref().and_then(|e| e.last_error.as_ref()) {
+        Some(e) => e.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Frees a string returned by [tte_engine_accounts_csv]
+///
+/// # Safety
+///
+/// `s` must come from [tte_engine_accounts_csv] and not already be freed.
+#[no_mangle]
+pub unsafe extern "C" fn tte_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Frees an engine created by [tte_engine_new]
+///
+/// # Safety
+///
+/// `engine` must come from [tte_engine_new] and not already be freed.
+#[no_mangle]
+pub unsafe extern "C" fn tte_engine_free(engine: *mut TteEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn accounts(engine: *const TteEngine) -> String {
+        let csv = tte_engine_accounts_csv(engine);
+        let s = CStr::from_ptr(csv).to_str().unwrap().to_string();
+        tte_string_free(csv);
+        s
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        assert_eq!(
+            include_str!("../include/tte.h"),
+            include_str!(concat!(env!("OUT_DIR"), "/tte.h")),
+            "regenerate it with TTE_FFI_UPDATE_HEADER=1 cargo build -p tte-ffi"
+        );
+    }
+
+    #[test]
+    fn test_process_json() {
+        unsafe {
+            let engine = tte_engine_new();
+            let one = c"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"2.5\"}";
+            assert_eq!(tte_engine_process_json(engine, one.as_ptr()), 1);
+            let many = c"[
+                {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 1.25},
+                {\"type\": \"deposit\", \"client\": 2, \"tx\": 3, \"amount\": 4},
+                {\"type\": \"dispute\", \"client\": 2, \"tx\": 3}
+            ]";
+            assert_eq!(tte_engine_process_json(engine, many.as_ptr()), 3);
+            assert!(tte_engine_last_error(engine).is_null());
+            assert_eq!(
+                accounts(engine),
+                "client, available, held, total, locked\n\
+                 1, 1.25, 0, 1.25, false\n\
+                 2, 0, 4, 4, false\n"
+            );
+            tte_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_process_json_errors() {
+        unsafe {
+            let engine = tte_engine_new();
+            let bad = c"[
+                {\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.0\"},
+                {\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": \"1e3\"}
+            ]";
+            assert_eq!(tte_engine_process_json(engine, bad.as_ptr()), -1);
+            let error = CStr::from_ptr(tte_engine_last_error(engine));
+            assert_eq!(
+                error.to_str().unwrap(),
+                "amount 1e3 is in scientific notation"
+            );
+            // Nothing from the failed batch was applied
+            assert_eq!(accounts(engine), "client, available, held, total, locked\n");
+
+            assert_eq!(tte_engine_process_json(engine, c"not json".as_ptr()), -1);
+            assert_eq!(tte_engine_process_json(engine, ptr::null()), -1);
+            assert_eq!(tte_engine_process_json(ptr::null_mut(), ptr::null()), -1);
+            tte_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_process_json_once() {
+        unsafe {
+            let engine = tte_engine_new();
+            let deposit = c"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"2.5\"}";
+            let bad = c"{\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": \"1e3\"}";
+            for _ in 0..2 {
+                assert_eq!(
+                    tte_engine_process_json_once(engine, c"a".as_ptr(), deposit.as_ptr()),
+                    1
+                );
+                assert_eq!(
+                    tte_engine_process_json_once(engine, c"b".as_ptr(), bad.as_ptr()),
+                    -1
+                );
+                assert!(!tte_engine_last_error(engine).is_null());
+            }
+            assert_eq!(
+                accounts(engine),
+                "client, available, held, total, locked\n\
+                 1, 2.5, 0, 2.5, false\n"
+            );
+            tte_engine_free(engine);
+        }
+    }
+}
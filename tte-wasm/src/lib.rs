@@ -0,0 +1,74 @@
+//! TTE WASM
+//!
+//! Exposes the transaction engine to JavaScript so it can run in a browser
+//! based demo or playground. Build it with `wasm-pack`
+//!
+//! ```bash
+//! wasm-pack build tte-wasm --target web
+//! ```
+//!
+//! ```js
+//! import init, { process_csv } from "./pkg/tte_wasm.js";
+//! await init();
+//! const accounts = JSON.parse(process_csv(csvText));
+//! ```
+use serde::Serialize;
+use tte::snapshot::Account;
+use tte::transaction::ClientId;
+use tte::{read_csv, Engine};
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize)]
+struct Row<'a> {
+    client: ClientId,
+    #[serde(flatten)]
+    account: &'a Account,
+}
+
+/// Runs a transactions CSV through a fresh engine and returns the accounts as
+/// a JSON array ordered by client id. Amounts are strings to keep them exact.
+/// ```json
+/// [{"client": 1, "available": "1.5", "held": "0", "total": "1.5", "locked": false}]
+/// ```
+pub fn accounts_json(csv: &str) -> anyhow::Result<String> {
+    let mut engine = Engine::new();
+    engine.replay(read_csv(csv.as_bytes()), None)?;
+    let snapshot = engine.snapshot();
+    let rows: Vec<Row> = snapshot
+        .iter()
+        .map(|(&client, account)| Row { client, account })
+        .collect();
+    Ok(serde_json::to_string(&rows)?)
+}
+
+/// JavaScript entry point for [accounts_json]. Errors are thrown as JS errors.
+#[wasm_bindgen]
+pub fn process_csv(text: &str) -> Result<String, JsError> {
+    accounts_json(text).map_err(|e| JsError::new(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accounts_json() {
+        const DATA: &str = "\
+type,client,tx,amount
+deposit,2,1,2.0
+deposit,1,2,1.5
+dispute,2,1,
+";
+        assert_eq!(
+            accounts_json(DATA).unwrap(),
+            r#"[{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false},"#
+                .to_string()
+                + r#"{"client":2,"available":"0.0","held":"2.0","total":"2.0","locked":false}]"#
+        );
+    }
+
+    #[test]
+    fn test_accounts_json_error() {
+        assert!(accounts_json("type,client,tx,amount\ndeposit,1,1,abc\n").is_err());
+    }
+}